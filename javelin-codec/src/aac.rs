@@ -1,7 +1,7 @@
-mod adts;
 mod packet;
 
 
+pub mod adts;
 pub mod config;
 
 
@@ -1,6 +1,7 @@
 use bytes::{Bytes, BytesMut, BufMut};
+use crate::{Error, Result};
 use super::{
-    config::AudioSpecificConfiguration,
+    config::{AudioObjectType, AudioSpecificConfiguration},
 };
 
 
@@ -49,7 +50,7 @@ impl AudioDataTransportStream {
     const PROTECTION_FLAG: u8 = 0x01;
 
     pub fn new(payload: Bytes, asc: AudioSpecificConfiguration) -> Self {
-        assert!(payload.len() <= (std::u16::MAX & 0x1FFF) as usize);
+        assert!(payload.len() <= 0x1FFF);
 
         let profile = (asc.object_type as u8) - 1;
 
@@ -62,6 +63,71 @@ impl AudioDataTransportStream {
             payload,
         }
     }
+
+    /// Reads the frame at the start of the buffer and advances the buffer past it.
+    /// Returns `None` if the buffer does not hold a complete frame.
+    pub fn try_from_bytes(bytes: &mut Bytes) -> Result<Option<Self>> {
+        if bytes.len() < 7 {
+            return Ok(None);
+        }
+
+        let header = &bytes[..7];
+
+        if u16::from(header[0]) << 8 | u16::from(header[1] & 0xF0) != Self::SYNCWORD {
+            return Err(Error::ParseError("Invalid ADTS sync word".into()));
+        }
+
+        let version = if header[1] & 0x08 == 0 { Version::Mpeg4 } else { Version::Mpeg2 };
+        let protection_absent = header[1] & Self::PROTECTION_FLAG != 0;
+        let profile = header[2] >> 6;
+        let sampling_frequency_index = (header[2] >> 2) & 0x0F;
+        let channel_configuration = ((header[2] & 0x01) << 2) | (header[3] >> 6);
+        let frame_length = (usize::from(header[3] & 0x03) << 11)
+            | (usize::from(header[4]) << 3)
+            | usize::from(header[5] >> 5);
+
+        let header_length = if protection_absent { 7 } else { 9 };
+
+        if frame_length < header_length {
+            return Err(Error::ParseError(format!("Invalid ADTS frame length {}", frame_length)));
+        }
+
+        if bytes.len() < frame_length {
+            return Ok(None);
+        }
+
+        let mut frame = bytes.split_to(frame_length);
+        let crc = if protection_absent {
+            None
+        } else {
+            Some(format!("{:02x}{:02x}", frame[7], frame[8]))
+        };
+
+        Ok(Some(Self {
+            version,
+            profile,
+            sampling_frequency_index,
+            channel_configuration,
+            crc,
+            payload: frame.split_off(header_length),
+        }))
+    }
+
+    /// The configuration RTMP and MP4 carry instead of per frame headers
+    pub fn audio_specific_configuration(&self) -> Result<AudioSpecificConfiguration> {
+        Ok(AudioSpecificConfiguration {
+            object_type: AudioObjectType::try_from_u8(self.profile + 1)?,
+            sampling_frequency_index: self.sampling_frequency_index,
+            channel_configuration: self.channel_configuration,
+            frame_length_flag: false,
+            depends_on_core_coder: false,
+            extension_flag: false,
+        })
+    }
+
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
 }
 
 impl Into<Bytes> for AudioDataTransportStream {
@@ -95,7 +161,7 @@ impl Into<Bytes> for AudioDataTransportStream {
         tmp.put_u8(channel_configuration2 | frame_length1);
 
         // Frame length cont. (11 bits) and buffer fullness (5 bits)
-        let frame_length2 = ((frame_length & 0x7FF) << 5) as u16;
+        let frame_length2 = (frame_length & 0x7FF) << 5;
         tmp.put_u16_be(frame_length2 | 0b0000_0000_0001_1111);
 
         // Buffer fullness cont. (6 bits) and number of AAC frames minus one (2 bits = 0)
@@ -138,4 +204,32 @@ mod tests {
 
         assert_eq!(expected[..], actual[..]);
     }
+
+    #[test]
+    fn can_parse_frames() {
+        let mut bytes = Bytes::from_static(&[
+            0b1111_1111, 0b1111_0001, 0b0101_0000, 0b1000_0000,
+            0b0000_0001, 0b0111_1111, 0b1111_1100,
+            0b0100_1110, 0b0010_1111, 0b1001_0011, 0b1111_0010,
+            // start of the next frame
+            0b1111_1111, 0b1111_0001,
+        ]);
+
+        let adts = AudioDataTransportStream::try_from_bytes(&mut bytes).unwrap().unwrap();
+        assert_eq!(adts.payload()[..], [0b0100_1110, 0b0010_1111, 0b1001_0011, 0b1111_0010]);
+
+        let asc = adts.audio_specific_configuration().unwrap();
+        assert_eq!(asc.object_type, AudioObjectType::AacLowComplexity);
+        assert_eq!(asc.sampling_frequency(), Some(44100));
+        assert_eq!(asc.channel_configuration, 2);
+
+        assert!(AudioDataTransportStream::try_from_bytes(&mut bytes).unwrap().is_none());
+        assert_eq!(bytes.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_sync_word() {
+        let mut bytes = Bytes::from_static(&[0xFF, 0x00, 0x50, 0x80, 0x01, 0x7F, 0xFC]);
+        assert!(AudioDataTransportStream::try_from_bytes(&mut bytes).is_err());
+    }
 }
@@ -49,6 +49,10 @@ pub struct AudioSpecificConfiguration {
 }
 
 impl AudioSpecificConfiguration {
+    const SAMPLING_FREQUENCIES: [u32; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+
     pub fn try_from_buf<B>(buf: &mut B) -> Result<Self>
         where B: Buf
     {
@@ -72,6 +76,28 @@ impl AudioSpecificConfiguration {
             extension_flag,
         })
     }
+
+    /// Sampling frequency in Hz, `None` for reserved or explicitly coded frequencies
+    pub fn sampling_frequency(&self) -> Option<u32> {
+        Self::SAMPLING_FREQUENCIES.get(self.sampling_frequency_index as usize).cloned()
+    }
+
+    /// Codec parameter of RFC 6381, as used by the `CODECS` attribute of HLS playlists
+    pub fn codec_string(&self) -> String {
+        format!("mp4a.40.{}", self.object_type.clone() as u8)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 2] {
+        let object_type = self.object_type.clone() as u8;
+        let flags = (self.frame_length_flag as u8) << 2
+            | (self.depends_on_core_coder as u8) << 1
+            | self.extension_flag as u8;
+
+        [
+            (object_type << 3) | (self.sampling_frequency_index >> 1),
+            (self.sampling_frequency_index << 7) | (self.channel_configuration << 3) | flags,
+        ]
+    }
 }
 
 
@@ -100,4 +126,13 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn can_be_converted_into_bytes() {
+        let raw = [0b0001_0010, 0b0001_0000];
+        let asc = AudioSpecificConfiguration::try_from_buf(&mut (&raw[..]).into_buf()).unwrap();
+
+        assert_eq!(asc.to_bytes(), raw);
+        assert_eq!(asc.sampling_frequency(), Some(44100));
+    }
 }
@@ -70,6 +70,11 @@ impl Packet {
         self.payload.clone().into()
     }
 
+    /// The raw AAC frame without ADTS header
+    pub fn payload(&self) -> Bytes {
+        self.payload.payload().clone()
+    }
+
     pub fn presentation_timestamp(&self) -> u64 {
         self.timestamp
     }
@@ -1,9 +1,16 @@
-mod nal;
 mod packet;
 mod bitstream;
 
 
+pub mod annexb;
 pub mod dcr;
+pub mod frame;
+pub mod nal;
+pub mod pps;
+pub mod rbsp;
+pub mod slice;
+pub mod sps;
 
 
 pub use self::packet::Packet;
+pub(crate) use self::packet::FrameType;
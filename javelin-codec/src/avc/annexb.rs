@@ -0,0 +1,149 @@
+use bytes::{Bytes, BufMut};
+use super::{
+    dcr::DecoderConfigurationRecord,
+    nal::{self, UnitType},
+};
+use crate::{Error, Result};
+
+
+/// Splits an Annex-B byte stream at its three or four byte start codes into NAL units.
+///
+/// Leading data before the first start code and trailing zeros of units are dropped.
+pub fn split(data: &Bytes) -> Vec<Bytes> {
+    let mut nal_units = Vec::new();
+    let mut start = None;
+    let mut zeros = 0;
+
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == 0x01 && zeros >= 2 {
+            if let Some(start) = start {
+                push_nal_unit(&mut nal_units, data.slice(start, i - zeros));
+            }
+            start = Some(i + 1);
+        }
+
+        zeros = if *byte == 0x00 { zeros + 1 } else { 0 };
+    }
+
+    if let Some(start) = start {
+        push_nal_unit(&mut nal_units, data.slice_from(start));
+    }
+
+    nal_units
+}
+
+fn push_nal_unit(nal_units: &mut Vec<Bytes>, mut nal_unit: Bytes) {
+    let trailing_zeros = nal_unit.iter().rev().take_while(|byte| **byte == 0x00).count();
+    nal_unit.truncate(nal_unit.len() - trailing_zeros);
+
+    if !nal_unit.is_empty() {
+        nal_units.push(nal_unit);
+    }
+}
+
+
+/// Access unit of an Annex-B byte stream, as carried by MPEG transport streams
+#[derive(Debug, Clone)]
+pub struct AccessUnit {
+    pub sps: Vec<nal::Unit>,
+    pub pps: Vec<nal::Unit>,
+    /// Units other than parameter sets and access unit delimiters, without start codes
+    pub nal_units: Vec<Bytes>,
+    pub keyframe: bool,
+}
+
+impl AccessUnit {
+    pub fn try_from_bytes(data: &Bytes) -> Result<Self> {
+        let mut sps = Vec::new();
+        let mut pps = Vec::new();
+        let mut nal_units = Vec::new();
+        let mut keyframe = false;
+
+        for nal_unit in split(data) {
+            if nal_unit[0] & 0x80 != 0 {
+                return Err(Error::ParseError("Forbidden bit of NAL unit header is set".to_string()));
+            }
+
+            match nal_unit[0] & 0x1F {
+                t if t == UnitType::SequenceParameterSet as u8 => sps.push(nal::Unit::try_from_bytes(nal_unit)?),
+                t if t == UnitType::PictureParameterSet as u8 => pps.push(nal::Unit::try_from_bytes(nal_unit)?),
+                t if t == UnitType::AccessUnitDelimiter as u8 => (),
+                t => {
+                    keyframe |= t == UnitType::IdrPicture as u8;
+                    nal_units.push(nal_unit);
+                },
+            }
+        }
+
+        Ok(Self { sps, pps, nal_units, keyframe })
+    }
+
+    /// Configuration record of the parameter sets, if the access unit carries both
+    pub fn decoder_configuration_record(&self) -> Option<DecoderConfigurationRecord> {
+        DecoderConfigurationRecord::from_parameter_sets(self.sps.clone(), self.pps.clone()).ok()
+    }
+
+    /// NAL units with 4 byte length prefixes, as carried by FLV video tags
+    pub fn to_avcc_bytes(&self) -> Bytes {
+        let size = self.nal_units.iter().map(|nal_unit| nal_unit.len() + 4).sum();
+        let mut tmp = Vec::with_capacity(size);
+
+        for nal_unit in &self.nal_units {
+            tmp.put_u32_be(nal_unit.len() as u32);
+            tmp.put_slice(nal_unit);
+        }
+
+        Bytes::from(tmp)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCESS_UNIT: &[u8] = &[
+        0x00, 0x00, 0x00, 0x01, 0x09, 0xF0,
+        0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x1F,
+        0x00, 0x00, 0x01, 0x68, 0xEE, 0x3C,
+        0x00, 0x00, 0x01, 0x65, 0x88, 0x84, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn can_split_at_start_codes() {
+        let nal_units = split(&Bytes::from_static(ACCESS_UNIT));
+
+        assert_eq!(nal_units.len(), 4);
+        assert_eq!(nal_units[0][..], [0x09, 0xF0]);
+        assert_eq!(nal_units[1][..], [0x67, 0x64, 0x00, 0x1F]);
+        assert_eq!(nal_units[2][..], [0x68, 0xEE, 0x3C]);
+        assert_eq!(nal_units[3][..], [0x65, 0x88, 0x84]);
+    }
+
+    #[test]
+    fn extracts_parameter_sets() {
+        let access_unit = AccessUnit::try_from_bytes(&Bytes::from_static(ACCESS_UNIT)).unwrap();
+        assert!(access_unit.keyframe);
+        assert_eq!(access_unit.nal_units.len(), 1);
+
+        let dcr = access_unit.decoder_configuration_record().unwrap();
+        assert_eq!(dcr.to_bytes()[..], [
+            0x01, 0x64, 0x00, 0x1F, 0xFF,
+            0xE1, 0x00, 0x04, 0x67, 0x64, 0x00, 0x1F,
+            0x01, 0x00, 0x03, 0x68, 0xEE, 0x3C,
+        ]);
+    }
+
+    #[test]
+    fn can_be_converted_into_avcc_bytes() {
+        let data = Bytes::from_static(&[0x00, 0x00, 0x01, 0x41, 0x9A, 0x00, 0x00, 0x01, 0x01, 0x9E]);
+        let access_unit = AccessUnit::try_from_bytes(&data).unwrap();
+
+        assert!(!access_unit.keyframe);
+        assert!(access_unit.decoder_configuration_record().is_none());
+        assert_eq!(access_unit.to_avcc_bytes()[..], [
+            0x00, 0x00, 0x00, 0x02, 0x41, 0x9A,
+            0x00, 0x00, 0x00, 0x02, 0x01, 0x9E,
+        ]);
+    }
+}
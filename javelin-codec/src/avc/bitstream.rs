@@ -2,6 +2,7 @@ use log::{warn, debug};
 use bytes::{Bytes, BytesMut, IntoBuf};
 use super::{
     dcr::DecoderConfigurationRecord,
+    frame,
     nal,
 };
 use crate::{utils, Error, Result};
@@ -27,7 +28,15 @@ impl<'a> Bitstream {
         let mut nal_units = Vec::new();
 
         while buf.has_remaining() {
+            if buf.remaining() < dcr.nalu_size as usize {
+                return Err(Error::NotEnoughData);
+            }
+
             let nalu_length = utils::try_bytes_as_usize_be(dcr.nalu_size as usize, &mut buf)?;
+            if buf.remaining() < nalu_length {
+                return Err(Error::NotEnoughData);
+            }
+
             let nalu_data: Bytes = buf.by_ref().take(nalu_length).collect();
             nal_units.push(nal::Unit::try_from_bytes(nalu_data)?)
         };
@@ -39,6 +48,10 @@ impl<'a> Bitstream {
         Ok(Self { nal_units, dcr })
     }
 
+    pub fn nal_units(&self) -> &[nal::Unit] {
+        &self.nal_units
+    }
+
     pub fn try_as_bytes(&self) -> Result<Bytes> {
         use self::nal::UnitType;
 
@@ -47,6 +60,9 @@ impl<'a> Bitstream {
         let mut sps_and_pps_appended = false;
         let nalus = self.nal_units.clone();
         let dcr = &self.dcr;
+        let recovery_point = self.nal_units.iter()
+            .filter(|nalu| nalu.kind == UnitType::SupplementaryEnhancementInformation)
+            .any(frame::has_recovery_point);
 
         for nalu in nalus {
             match &nalu.kind {
@@ -56,19 +72,15 @@ impl<'a> Bitstream {
                     continue;
                 },
                 | UnitType::NonIdrPicture
-                | UnitType::SupplementaryEnhancementInformation => {
-                    if !aud_appended {
-                        tmp.extend(Self::ACCESS_UNIT_DELIMITER);
-                        aud_appended = true;
-                    }
-                },
-                UnitType::IdrPicture => {
+                | UnitType::SupplementaryEnhancementInformation
+                | UnitType::IdrPicture => {
                     if !aud_appended {
                         tmp.extend(Self::ACCESS_UNIT_DELIMITER);
                         aud_appended = true;
                     }
 
-                    if !sps_and_pps_appended {
+                    // Decoding can start with IDR pictures and at recovery points, both need the parameter sets
+                    if (nalu.kind == UnitType::IdrPicture || recovery_point) && !sps_and_pps_appended {
                         if let Some(sps) = dcr.sps.first() {
                             tmp.extend(Self::DELIMITER2);
                             let unit: Bytes = sps.clone().into();
@@ -99,6 +111,28 @@ impl<'a> Bitstream {
 
         Ok(tmp.freeze())
     }
+
+    pub fn to_avcc_bytes(&self) -> Bytes {
+        use bytes::BufMut;
+        use self::nal::UnitType;
+
+        let mut tmp = Vec::new();
+
+        for nalu in &self.nal_units {
+            match &nalu.kind {
+                | UnitType::SequenceParameterSet
+                | UnitType::PictureParameterSet
+                | UnitType::AccessUnitDelimiter => continue,
+                _ => (),
+            }
+
+            let nalu_data: Bytes = nalu.clone().into();
+            tmp.put_u32_be(nalu_data.len() as u32);
+            tmp.put_slice(&nalu_data);
+        }
+
+        Bytes::from(tmp)
+    }
 }
 
 #[cfg(feature = "try_from")]
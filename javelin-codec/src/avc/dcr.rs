@@ -1,4 +1,4 @@
-use bytes::{Bytes, Buf};
+use bytes::{Bytes, Buf, BufMut};
 use super::nal;
 use crate::{Error, Result};
 
@@ -21,7 +21,7 @@ use crate::{Error, Result};
 /// 16   | PPS Length
 /// var  | PPS
 ///
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DecoderConfigurationRecord {
     pub version: u8,
     pub profile_indication: u8,
@@ -53,17 +53,17 @@ impl DecoderConfigurationRecord {
         let sps_count = buf.get_u8() & 0x1F;
         let mut sps = Vec::new();
         for _ in 0..sps_count {
-            let sps_length = buf.get_u16_be() as usize;
-            let tmp: Bytes = buf.by_ref().take(sps_length).collect();
-            sps.push(nal::Unit::try_from_bytes(tmp)?);
+            sps.push(Self::read_unit(buf)?);
+        }
+
+        if buf.remaining() < 1 {
+            return Err(Error::NotEnoughData);
         }
 
         let pps_count = buf.get_u8();
         let mut pps = Vec::new();
         for _ in 0..pps_count {
-            let pps_length = buf.get_u16_be() as usize;
-            let tmp: Bytes = buf.by_ref().take(pps_length).collect();
-            pps.push(nal::Unit::try_from_bytes(tmp)?);
+            pps.push(Self::read_unit(buf)?);
         }
 
         Ok(Self {
@@ -76,4 +76,134 @@ impl DecoderConfigurationRecord {
             pps,
         })
     }
+
+    /// Reads a parameter set with its 16 bit length prefix
+    fn read_unit<B>(buf: &mut B) -> Result<nal::Unit>
+        where B: Buf
+    {
+        if buf.remaining() < 2 {
+            return Err(Error::NotEnoughData);
+        }
+
+        let unit_length = buf.get_u16_be() as usize;
+        if buf.remaining() < unit_length {
+            return Err(Error::NotEnoughData);
+        }
+
+        let tmp: Bytes = buf.by_ref().take(unit_length).collect();
+        nal::Unit::try_from_bytes(tmp)
+    }
+
+    /// Creates a record for NAL units with 4 byte length prefixes.
+    ///
+    /// Profile and level are taken from the first sequence parameter set.
+    /// The record has room for up to 31 sequence and 255 picture parameter sets.
+    pub fn from_parameter_sets(sps: Vec<nal::Unit>, pps: Vec<nal::Unit>) -> Result<Self> {
+        if sps.len() > 0x1F || pps.len() > 0xFF {
+            return Err(Error::from(format!("Too many parameter sets, got {} SPS and {} PPS", sps.len(), pps.len())));
+        }
+
+        let (profile_indication, profile_compatability, level_indication) = match sps.first() {
+            Some(first) if first.data.len() >= 3 => (first.data[0], first.data[1], first.data[2]),
+            Some(_) => return Err(Error::NotEnoughData),
+            None => return Err(Error::from("Sequence parameter set missing")),
+        };
+
+        if pps.is_empty() {
+            return Err(Error::from("Picture parameter set missing"));
+        }
+
+        Ok(Self {
+            version: 1,
+            profile_indication,
+            profile_compatability,
+            level_indication,
+            nalu_size: 4,
+            sps,
+            pps,
+        })
+    }
+
+    /// Writes the record as carried by FLV sequence headers and MP4 `avcC` boxes.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut tmp = Vec::new();
+
+        tmp.put_u8(self.version);
+        tmp.put_u8(self.profile_indication);
+        tmp.put_u8(self.profile_compatability);
+        tmp.put_u8(self.level_indication);
+        tmp.put_u8(0xFC | (self.nalu_size - 1));
+
+        tmp.put_u8(0xE0 | self.sps.len() as u8);
+        for sps in &self.sps {
+            let unit: Bytes = sps.clone().into();
+            tmp.put_u16_be(unit.len() as u16);
+            tmp.put_slice(&unit);
+        }
+
+        tmp.put_u8(self.pps.len() as u8);
+        for pps in &self.pps {
+            let unit: Bytes = pps.clone().into();
+            tmp.put_u16_be(unit.len() as u16);
+            tmp.put_slice(&unit);
+        }
+
+        Bytes::from(tmp)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::IntoBuf;
+    use super::*;
+
+    const RECORD: &[u8] = &[
+        0x01, 0x64, 0x00, 0x1F, 0xFF,
+        0xE1, 0x00, 0x04, 0x67, 0x64, 0x00, 0x1F,
+        0x01, 0x00, 0x03, 0x68, 0xEE, 0x3C,
+    ];
+
+    #[test]
+    fn can_be_written_back() {
+        let dcr = DecoderConfigurationRecord::try_from_buf(&mut RECORD.into_buf()).unwrap();
+        assert_eq!(dcr.to_bytes()[..], RECORD[..]);
+    }
+
+    #[test]
+    fn can_be_created_from_parameter_sets() {
+        let sps = nal::Unit::try_from_bytes(Bytes::from_static(&[0x67, 0x64, 0x00, 0x1F])).unwrap();
+        let pps = nal::Unit::try_from_bytes(Bytes::from_static(&[0x68, 0xEE, 0x3C])).unwrap();
+
+        let dcr = DecoderConfigurationRecord::from_parameter_sets(vec![sps], vec![pps]).unwrap();
+        assert_eq!(dcr.profile_indication, 0x64);
+        assert_eq!(dcr.level_indication, 0x1F);
+        assert_eq!(dcr.nalu_size, 4);
+        assert_eq!(dcr.to_bytes()[..], RECORD[..]);
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        for len in 0..RECORD.len() {
+            let record = &RECORD[..len];
+            assert!(DecoderConfigurationRecord::try_from_buf(&mut record.into_buf()).is_err(), "accepted {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn limits_parameter_sets() {
+        let sps = nal::Unit::try_from_bytes(Bytes::from_static(&[0x67, 0x64, 0x00, 0x1F])).unwrap();
+        let pps = nal::Unit::try_from_bytes(Bytes::from_static(&[0x68, 0xEE, 0x3C])).unwrap();
+
+        assert!(DecoderConfigurationRecord::from_parameter_sets(vec![sps.clone(); 31], vec![pps.clone(); 255]).is_ok());
+        assert!(DecoderConfigurationRecord::from_parameter_sets(vec![sps.clone(); 32], vec![pps.clone()]).is_err());
+        assert!(DecoderConfigurationRecord::from_parameter_sets(vec![sps], vec![pps; 256]).is_err());
+    }
+
+    #[test]
+    fn requires_both_parameter_sets() {
+        let sps = nal::Unit::try_from_bytes(Bytes::from_static(&[0x67, 0x64, 0x00, 0x1F])).unwrap();
+        assert!(DecoderConfigurationRecord::from_parameter_sets(vec![sps], Vec::new()).is_err());
+        assert!(DecoderConfigurationRecord::from_parameter_sets(Vec::new(), Vec::new()).is_err());
+    }
 }
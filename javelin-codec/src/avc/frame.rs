@@ -0,0 +1,224 @@
+use super::{
+    dcr::DecoderConfigurationRecord,
+    nal::{self, UnitType},
+    pps::PictureParameterSet,
+    rbsp,
+    slice::{SliceHeader, SliceType},
+    sps::SequenceParameterSet,
+};
+use crate::Result;
+
+
+/// Parsed parameter sets of a decoder configuration record
+#[derive(Debug, Clone, Default)]
+pub struct ParameterSets {
+    pub sps: Vec<SequenceParameterSet>,
+    pub pps: Vec<PictureParameterSet>,
+}
+
+impl ParameterSets {
+    pub fn try_from_dcr(dcr: &DecoderConfigurationRecord) -> Result<Self> {
+        let sps = dcr.sps.iter().map(SequenceParameterSet::try_from_unit).collect::<Result<_>>()?;
+        let pps = dcr.pps.iter().map(PictureParameterSet::try_from_unit).collect::<Result<_>>()?;
+
+        Ok(Self { sps, pps })
+    }
+}
+
+
+/// What a frame is according to its slices, regardless of the frame type of its container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Type of the first slice
+    pub slice_type: SliceType,
+    pub idr: bool,
+    /// Set if a recovery point SEI message precedes the slices
+    pub recovery_point: bool,
+    /// Whether other frames can refer to this one
+    pub reference: bool,
+    pub frame_num: u32,
+    max_frame_num: u32,
+    gaps_in_frame_num_allowed: bool,
+}
+
+impl FrameInfo {
+    /// Classifies the NAL units of an access unit, `None` if it has no coded slice.
+    pub fn try_from_units(units: &[nal::Unit], parameter_sets: &ParameterSets) -> Result<Option<Self>> {
+        let recovery_point = units.iter()
+            .filter(|unit| unit.kind == UnitType::SupplementaryEnhancementInformation)
+            .any(has_recovery_point);
+
+        let unit = match units.iter().find(|unit| unit.kind == UnitType::IdrPicture || unit.kind == UnitType::NonIdrPicture) {
+            Some(unit) => unit,
+            None => return Ok(None),
+        };
+
+        let header = SliceHeader::try_from_unit(unit, &parameter_sets.sps, &parameter_sets.pps)?;
+
+        // The slice header could only be parsed if both parameter sets are known
+        let sps = parameter_sets.pps.iter()
+            .find(|pps| pps.id == header.pps_id)
+            .and_then(|pps| parameter_sets.sps.iter().find(|sps| sps.id == pps.sps_id))
+            .expect("BUG: parameter sets of slice missing");
+
+        Ok(Some(Self {
+            slice_type: header.slice_type,
+            idr: header.idr_pic_id.is_some(),
+            recovery_point,
+            reference: unit.ref_idc != 0,
+            frame_num: header.frame_num,
+            max_frame_num: sps.max_frame_num(),
+            gaps_in_frame_num_allowed: sps.gaps_in_frame_num_allowed,
+        }))
+    }
+
+    /// Whether decoding can start with this frame
+    pub fn is_random_access(&self) -> bool {
+        self.idr || (self.recovery_point && self.slice_type.is_intra())
+    }
+}
+
+
+/// Follows `frame_num` across frames to notice lost or misordered frames.
+#[derive(Debug, Default)]
+pub struct FrameNumbers {
+    prev_ref_frame_num: Option<u32>,
+}
+
+impl FrameNumbers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the frame follows the previous reference frame in decoding order.
+    pub fn check(&mut self, frame: &FrameInfo) -> bool {
+        let valid = match self.prev_ref_frame_num {
+            _ if frame.idr => frame.frame_num == 0,
+            _ if frame.gaps_in_frame_num_allowed => true,
+            Some(prev) => frame.frame_num == prev || frame.frame_num == (prev + 1) % frame.max_frame_num,
+            None => true,
+        };
+
+        if frame.reference {
+            self.prev_ref_frame_num = Some(frame.frame_num);
+        }
+
+        valid
+    }
+}
+
+
+/// Whether a SEI unit carries a recovery point message.
+pub(super) fn has_recovery_point(unit: &nal::Unit) -> bool {
+    const RECOVERY_POINT: u32 = 6;
+
+    let data = rbsp::remove_emulation_prevention(&unit.data);
+    let mut data = &data[..];
+
+    // Every message starts with its type and size, both coded as runs of 0xFF plus a last byte
+    fn read_value(data: &mut &[u8]) -> Option<u32> {
+        let mut value = 0u32;
+        loop {
+            let (byte, rest) = data.split_first()?;
+            *data = rest;
+            value = value.saturating_add(u32::from(*byte));
+            if *byte != 0xFF {
+                return Some(value);
+            }
+        }
+    }
+
+    // Stops at the trailing bits of the payload
+    while !data.is_empty() && data != [0x80] {
+        let (payload_type, payload_size) = match (read_value(&mut data), read_value(&mut data)) {
+            (Some(payload_type), Some(payload_size)) => (payload_type, payload_size as usize),
+            _ => return false,
+        };
+
+        if payload_type == RECOVERY_POINT {
+            return true;
+        }
+
+        if payload_size > data.len() {
+            return false;
+        }
+        data = &data[payload_size..];
+    }
+
+    false
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use super::*;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xC0, 0x1E, 0xE5, 0x40, 0x5A, 0x12, 0x24];
+    const PPS: &[u8] = &[0x68, 0xEB, 0x83, 0xCB, 0x20];
+
+    fn unit(data: &'static [u8]) -> nal::Unit {
+        nal::Unit::try_from_bytes(Bytes::from_static(data)).unwrap()
+    }
+
+    fn parameter_sets() -> ParameterSets {
+        ParameterSets {
+            sps: vec![SequenceParameterSet::try_from_unit(&unit(SPS)).unwrap()],
+            pps: vec![PictureParameterSet::try_from_unit(&unit(PPS)).unwrap()],
+        }
+    }
+
+    #[test]
+    fn detects_idr_frames() {
+        // first_mb 0, slice type 7 (I), pps 0, frame_num 0, field_pic 0, idr_pic_id 0, poc lsb 0
+        let units = [unit(&[0x65, 0x88, 0x82, 0x01])];
+        let frame = FrameInfo::try_from_units(&units, &parameter_sets()).unwrap().unwrap();
+
+        assert_eq!(frame.slice_type, SliceType::I);
+        assert!(frame.idr);
+        assert!(frame.reference);
+        assert!(frame.is_random_access());
+    }
+
+    #[test]
+    fn detects_recovery_points() {
+        // Recovery point SEI, then an I slice of a non-IDR picture with frame_num 1
+        let units = [
+            unit(&[0x06, 0x06, 0x01, 0xC4, 0x80]),
+            unit(&[0x41, 0x88, 0x88, 0x0A]),
+        ];
+        let frame = FrameInfo::try_from_units(&units, &parameter_sets()).unwrap().unwrap();
+
+        assert_eq!(frame.slice_type, SliceType::I);
+        assert!(!frame.idr);
+        assert!(frame.recovery_point);
+        assert_eq!(frame.frame_num, 1);
+        assert!(frame.is_random_access());
+    }
+
+    #[test]
+    fn checks_frame_numbers() {
+        let parameter_sets = parameter_sets();
+        let mut frame_numbers = FrameNumbers::new();
+
+        // IDR, then a P frame with frame_num 1 and a B frame without reference with frame_num 3
+        let idr = [unit(&[0x65, 0x88, 0x82, 0x01])];
+        let p = [unit(&[0x41, 0x9A, 0x20, 0x28])];
+        let b = [unit(&[0x01, 0x9E, 0x60, 0x48])];
+
+        for (units, valid) in [(&idr, true), (&p, true), (&b, false)].iter() {
+            let frame = FrameInfo::try_from_units(*units, &parameter_sets).unwrap().unwrap();
+            assert_eq!(frame_numbers.check(&frame), *valid);
+        }
+
+        let b = FrameInfo::try_from_units(&b, &parameter_sets).unwrap().unwrap();
+        assert_eq!(b.slice_type, SliceType::B);
+        assert!(!b.reference);
+    }
+
+    #[test]
+    fn ignores_units_without_slices() {
+        let units = [unit(SPS), unit(PPS)];
+        assert!(FrameInfo::try_from_units(&units, &parameter_sets()).unwrap().is_none());
+    }
+}
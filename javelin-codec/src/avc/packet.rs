@@ -3,6 +3,7 @@ use bytes::{Bytes, Buf, IntoBuf};
 use super::{
     dcr::DecoderConfigurationRecord,
     bitstream::Bitstream,
+    frame::{FrameInfo, ParameterSets},
 };
 use crate::{
     SharedState,
@@ -34,7 +35,7 @@ impl From<u8> for PacketType {
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum FrameType {
+pub(crate) enum FrameType {
     Keyframe,
     InterFrame,
     DisposableInterFrame,
@@ -78,15 +79,27 @@ pub struct Packet {
 }
 
 impl Packet {
+    const CODEC_ID: u8 = 7;
+
     pub fn try_from_buf<B>(bytes: B, timestamp: u64, shared: &SharedState) -> Result<Self>
         where B: IntoBuf
     {
         let mut buf = bytes.into_buf();
 
+        if buf.remaining() < 5 {
+            return Err(Error::NotEnoughData);
+        }
+
         let tmp = buf.get_u8();
         let frame_type = FrameType::from(tmp >> 4);
         let codec_id = tmp & 0x0F;
-        assert!(codec_id == 7);
+        // Enhanced RTMP packets use the codec ID bits for the packet type and name the codec by FourCC
+        if tmp & 0x80 != 0 {
+            return Err(Error::UnsupportedCodec("Enhanced RTMP video".to_string()));
+        }
+        if codec_id != Self::CODEC_ID {
+            return Err(Error::UnsupportedCodec(format!("FLV video codec {}", codec_id)));
+        }
 
         let tmp = buf.get_u32_be();
         let packet_type = PacketType::from((tmp >> 24) as u8);
@@ -114,6 +127,11 @@ impl Packet {
         self.nal_units.try_as_bytes()
     }
 
+    /// NAL units with 4 byte length prefixes, without parameter sets and delimiters
+    pub fn to_avcc_bytes(&self) -> Bytes {
+        self.nal_units.to_avcc_bytes()
+    }
+
     pub fn is_sequence_header(&self) -> bool {
         self.packet_type == PacketType::SequenceHeader
     }
@@ -122,11 +140,65 @@ impl Packet {
         self.frame_type == FrameType::Keyframe || self.frame_type == FrameType::GeneratedKeyframe
     }
 
+    /// Classifies the frame by its slices instead of the frame type, `None` if it has no slices
+    pub fn frame_info(&self, parameter_sets: &ParameterSets) -> Result<Option<FrameInfo>> {
+        FrameInfo::try_from_units(self.nal_units.nal_units(), parameter_sets)
+    }
+
     pub fn presentation_timestamp(&self) -> u64 {
         self.timestamp + (self.composition_time as u64)
     }
 
+    pub fn composition_time(&self) -> u32 {
+        self.composition_time
+    }
+
     pub fn timestamp(&self) -> u64 {
         self.timestamp
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEQUENCE_HEADER: &[u8] = &[
+        0x17, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x64, 0x00, 0x1F, 0xFF,
+        0xE1, 0x00, 0x04, 0x67, 0x64, 0x00, 0x1F,
+        0x01, 0x00, 0x03, 0x68, 0xEE, 0x3C,
+    ];
+
+    #[test]
+    fn rejects_truncated_units() {
+        let shared = SharedState::new();
+        Packet::try_from_buf(SEQUENCE_HEADER, 0, &shared).unwrap();
+
+        // Length prefix cut short and unit shorter than its prefix
+        let data: &[&[u8]] = &[
+            &[0x17, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+            &[0x17, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x65, 0x88],
+        ];
+
+        for data in data {
+            match Packet::try_from_buf(*data, 0, &shared) {
+                Err(Error::NotEnoughData) => (),
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_other_codecs() {
+        let shared = SharedState::new();
+
+        // VP6 and Enhanced RTMP HEVC
+        for data in [&[0x14, 0x00, 0x00, 0x00, 0x00][..], &[0x90, b'h', b'v', b'c', b'1'][..]].iter() {
+            match Packet::try_from_buf(*data, 0, &shared) {
+                Err(Error::UnsupportedCodec(_)) => (),
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use super::{
+    nal::{self, UnitType},
+    rbsp::{self, BitReader},
+};
+use crate::{Error, Result};
+
+
+/// Picture parameter set of a H.264 bitstream
+///
+/// Parsing stops before the optional fields following `redundant_pic_cnt_present_flag`,
+/// which need the sequence parameter set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PictureParameterSet {
+    pub id: u32,
+    pub sps_id: u32,
+    pub entropy_coding_mode: bool,
+    pub bottom_field_pic_order_in_frame_present: bool,
+    pub num_slice_groups: u32,
+    pub slice_group_map_type: u32,
+    pub slice_group_change_rate: u32,
+    pub num_ref_idx_l0_default_active: u32,
+    pub num_ref_idx_l1_default_active: u32,
+    pub weighted_pred: bool,
+    pub weighted_bipred_idc: u32,
+    pub pic_init_qp: i32,
+    pub pic_init_qs: i32,
+    pub chroma_qp_index_offset: i32,
+    pub deblocking_filter_control_present: bool,
+    pub constrained_intra_pred: bool,
+    pub redundant_pic_cnt_present: bool,
+}
+
+impl PictureParameterSet {
+    pub fn try_from_unit(unit: &nal::Unit) -> Result<Self> {
+        if unit.kind != UnitType::PictureParameterSet {
+            return Err(Error::ParseError(format!("Expected picture parameter set, got {:?}", unit.kind)));
+        }
+
+        Self::try_from_rbsp(&rbsp::remove_emulation_prevention(&unit.data))
+    }
+
+    /// Parses the payload following the NAL unit header, without emulation prevention bytes.
+    pub fn try_from_rbsp(data: &[u8]) -> Result<Self> {
+        let mut reader = BitReader::new(data);
+
+        let id = reader.read_ue_bounded(255, "pic_parameter_set_id")?;
+        let sps_id = reader.read_ue_bounded(31, "seq_parameter_set_id")?;
+        let entropy_coding_mode = reader.read_bit()?;
+        let bottom_field_pic_order_in_frame_present = reader.read_bit()?;
+        let num_slice_groups = reader.read_ue_bounded(7, "num_slice_groups_minus1")? + 1;
+        let mut slice_group_map_type = 0;
+        let mut slice_group_change_rate = 0;
+
+        if num_slice_groups > 1 {
+            slice_group_map_type = reader.read_ue_bounded(6, "slice_group_map_type")?;
+
+            match slice_group_map_type {
+                0 => {
+                    for _ in 0..num_slice_groups {
+                        reader.read_ue()?; // run_length_minus1
+                    }
+                },
+                2 => {
+                    for _ in 1..num_slice_groups {
+                        reader.read_ue()?; // top_left
+                        reader.read_ue()?; // bottom_right
+                    }
+                },
+                3..=5 => {
+                    reader.skip_bits(1)?; // slice_group_change_direction_flag
+                    slice_group_change_rate = reader.read_ue()?.saturating_add(1);
+                },
+                6 => {
+                    let pic_size_in_map_units = reader.read_ue()?.saturating_add(1) as usize;
+                    // Ceil(Log2(num_slice_groups)) bits per slice_group_id
+                    let bits = 32 - (num_slice_groups - 1).leading_zeros() as usize;
+                    reader.skip_bits(pic_size_in_map_units.saturating_mul(bits))?;
+                },
+                _ => (),
+            }
+        }
+
+        let num_ref_idx_l0_default_active = reader.read_ue_bounded(31, "num_ref_idx_l0_default_active_minus1")? + 1;
+        let num_ref_idx_l1_default_active = reader.read_ue_bounded(31, "num_ref_idx_l1_default_active_minus1")? + 1;
+        let weighted_pred = reader.read_bit()?;
+        let weighted_bipred_idc = reader.read_bits(2)?;
+        let pic_init_qp = reader.read_se()?.saturating_add(26);
+        let pic_init_qs = reader.read_se()?.saturating_add(26);
+        let chroma_qp_index_offset = reader.read_se()?;
+        let deblocking_filter_control_present = reader.read_bit()?;
+        let constrained_intra_pred = reader.read_bit()?;
+        let redundant_pic_cnt_present = reader.read_bit()?;
+
+        Ok(Self {
+            id,
+            sps_id,
+            entropy_coding_mode,
+            bottom_field_pic_order_in_frame_present,
+            num_slice_groups,
+            slice_group_map_type,
+            slice_group_change_rate,
+            num_ref_idx_l0_default_active,
+            num_ref_idx_l1_default_active,
+            weighted_pred,
+            weighted_bipred_idc,
+            pic_init_qp,
+            pic_init_qs,
+            chroma_qp_index_offset,
+            deblocking_filter_control_present,
+            constrained_intra_pred,
+            redundant_pic_cnt_present,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use super::*;
+
+    #[test]
+    fn can_parse_picture_parameter_set() {
+        let unit = nal::Unit::try_from_bytes(Bytes::from_static(&[0x68, 0xEB, 0x83, 0xCB, 0x20])).unwrap();
+        let pps = PictureParameterSet::try_from_unit(&unit).unwrap();
+
+        assert_eq!((pps.id, pps.sps_id), (0, 0));
+        assert!(pps.entropy_coding_mode);
+        assert_eq!(pps.num_slice_groups, 1);
+        assert_eq!(pps.num_ref_idx_l0_default_active, 3);
+        assert_eq!(pps.num_ref_idx_l1_default_active, 1);
+        assert_eq!(pps.pic_init_qp, 23);
+        assert_eq!(pps.chroma_qp_index_offset, -2);
+        assert!(pps.deblocking_filter_control_present);
+        assert!(!pps.redundant_pic_cnt_present);
+    }
+}
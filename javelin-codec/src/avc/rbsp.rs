@@ -0,0 +1,147 @@
+use crate::{Error, Result};
+
+
+/// Removes the emulation prevention bytes of a NAL unit payload.
+///
+/// Encoders insert `0x03` after two zero bytes so that the payload never contains
+/// a start code, the raw byte sequence payload (RBSP) is the payload without them.
+pub fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for byte in data {
+        if *byte == 0x03 && zeros >= 2 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if *byte == 0x00 { zeros + 1 } else { 0 };
+        rbsp.push(*byte);
+    }
+
+    rbsp
+}
+
+
+/// Reads the fields of a raw byte sequence payload, most significant bit first.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn bits_left(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool> {
+        if self.bits_left() == 0 {
+            return Err(Error::NotEnoughData);
+        }
+
+        let byte = self.data[self.position / 8];
+        let bit = (byte >> (7 - self.position % 8)) & 0x01;
+        self.position += 1;
+
+        Ok(bit == 1)
+    }
+
+    /// Reads an unsigned integer of up to 32 bits, `u(n)` in the specification
+    pub fn read_bits(&mut self, count: u8) -> Result<u32> {
+        assert!(count <= 32);
+
+        if self.bits_left() < count as usize {
+            return Err(Error::NotEnoughData);
+        }
+
+        let mut value = 0u64;
+        for _ in 0..count {
+            value = (value << 1) | u64::from(self.read_bit()?);
+        }
+
+        Ok(value as u32)
+    }
+
+    pub fn skip_bits(&mut self, count: usize) -> Result<()> {
+        if self.bits_left() < count {
+            return Err(Error::NotEnoughData);
+        }
+
+        self.position += count;
+        Ok(())
+    }
+
+    /// Reads an unsigned exp-Golomb code, `ue(v)` in the specification
+    pub fn read_ue(&mut self) -> Result<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(Error::ParseError("Exp-Golomb code exceeds 32 bits".to_string()));
+            }
+        }
+
+        let suffix = u64::from(self.read_bits(leading_zeros)?);
+        Ok(((1u64 << leading_zeros) - 1 + suffix) as u32)
+    }
+
+    /// Reads an unsigned exp-Golomb code and fails if it exceeds `max`
+    pub fn read_ue_bounded(&mut self, max: u32, name: &str) -> Result<u32> {
+        match self.read_ue()? {
+            value if value <= max => Ok(value),
+            value => Err(Error::ParseError(format!("Invalid {} {}", name, value))),
+        }
+    }
+
+    /// Reads a signed exp-Golomb code, `se(v)` in the specification
+    pub fn read_se(&mut self) -> Result<i32> {
+        let value = i64::from(self.read_ue()?);
+
+        let value = if value % 2 == 0 { -(value / 2) } else { (value + 1) / 2 };
+        Ok(value as i32)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_emulation_prevention_bytes() {
+        let data = [0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x03];
+        assert_eq!(remove_emulation_prevention(&data), [0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03]);
+    }
+
+    #[test]
+    fn can_read_exp_golomb_codes() {
+        // 1, 010, 011, 00100, 00101 and 0001000
+        let data = [0b1010_0110, 0b0100_0010, 0b1000_1000];
+        let mut reader = BitReader::new(&data);
+
+        assert_eq!(reader.read_ue().unwrap(), 0);
+        assert_eq!(reader.read_ue().unwrap(), 1);
+        assert_eq!(reader.read_se().unwrap(), -1);
+        assert_eq!(reader.read_se().unwrap(), 2);
+        assert_eq!(reader.read_ue().unwrap(), 4);
+        assert_eq!(reader.read_ue().unwrap(), 7);
+        assert_eq!(reader.bits_left(), 0);
+        assert!(reader.read_ue().is_err());
+    }
+
+    #[test]
+    fn can_read_fixed_length_fields() {
+        let data = [0xAB, 0xCD, 0xEF, 0x12, 0x34];
+        let mut reader = BitReader::new(&data);
+
+        assert_eq!(reader.read_bits(4).unwrap(), 0xA);
+        assert_eq!(reader.read_bits(32).unwrap(), 0xBCDE_F123);
+        reader.skip_bits(2).unwrap();
+        assert_eq!(reader.read_bits(2).unwrap(), 0b00);
+        assert!(reader.read_bits(1).is_err());
+    }
+}
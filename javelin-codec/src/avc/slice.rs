@@ -0,0 +1,119 @@
+use super::{
+    nal::{self, UnitType},
+    pps::PictureParameterSet,
+    rbsp::{self, BitReader},
+    sps::SequenceParameterSet,
+};
+use crate::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceType {
+    P,
+    B,
+    I,
+    SP,
+    SI,
+}
+
+impl SliceType {
+    fn try_from(value: u32) -> Result<Self> {
+        if value > 9 {
+            return Err(Error::ParseError(format!("Invalid slice type {}", value)));
+        }
+
+        // Values above 4 signal that all slices of the picture have the same type
+        let val = match value % 5 {
+            0 => SliceType::P,
+            1 => SliceType::B,
+            2 => SliceType::I,
+            3 => SliceType::SP,
+            _ => SliceType::SI,
+        };
+
+        Ok(val)
+    }
+
+    /// Whether the slice can be decoded without other pictures
+    pub fn is_intra(self) -> bool {
+        self == SliceType::I || self == SliceType::SI
+    }
+}
+
+
+/// Start of a slice header, up to the picture order count
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceHeader {
+    pub first_mb_in_slice: u32,
+    pub slice_type: SliceType,
+    pub pps_id: u32,
+    pub frame_num: u32,
+    pub field_pic: bool,
+    pub bottom_field: bool,
+    /// Only present in slices of IDR pictures
+    pub idr_pic_id: Option<u32>,
+    pub pic_order_cnt_lsb: Option<u32>,
+}
+
+impl SliceHeader {
+    /// Slice headers are short, the rest of the slice data is not needed
+    const MAX_HEADER_SIZE: usize = 64;
+
+    /// Parses the header of a coded slice with the parameter sets it refers to.
+    pub fn try_from_unit(unit: &nal::Unit, sps: &[SequenceParameterSet], pps: &[PictureParameterSet]) -> Result<Self> {
+        let idr = match unit.kind {
+            UnitType::IdrPicture => true,
+            UnitType::NonIdrPicture => false,
+            _ => return Err(Error::ParseError(format!("Expected coded slice, got {:?}", unit.kind))),
+        };
+
+        let data = &unit.data[..unit.data.len().min(Self::MAX_HEADER_SIZE)];
+        let data = rbsp::remove_emulation_prevention(data);
+        let mut reader = BitReader::new(&data);
+
+        let first_mb_in_slice = reader.read_ue()?;
+        let slice_type = SliceType::try_from(reader.read_ue()?)?;
+        let pps_id = reader.read_ue()?;
+
+        let pps = pps.iter()
+            .find(|pps| pps.id == pps_id)
+            .ok_or_else(|| Error::ParseError(format!("Unknown picture parameter set {}", pps_id)))?;
+        let sps = sps.iter()
+            .find(|sps| sps.id == pps.sps_id)
+            .ok_or_else(|| Error::ParseError(format!("Unknown sequence parameter set {}", pps.sps_id)))?;
+
+        if sps.separate_colour_plane {
+            reader.skip_bits(2)?; // colour_plane_id
+        }
+
+        let frame_num = reader.read_bits(sps.log2_max_frame_num as u8)?;
+
+        let mut field_pic = false;
+        let mut bottom_field = false;
+        if !sps.frame_mbs_only {
+            field_pic = reader.read_bit()?;
+            if field_pic {
+                bottom_field = reader.read_bit()?;
+            }
+        }
+
+        let idr_pic_id = if idr { Some(reader.read_ue()?) } else { None };
+
+        let pic_order_cnt_lsb = if sps.pic_order_cnt_type == 0 {
+            Some(reader.read_bits(sps.log2_max_pic_order_cnt_lsb as u8)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            first_mb_in_slice,
+            slice_type,
+            pps_id,
+            frame_num,
+            field_pic,
+            bottom_field,
+            idr_pic_id,
+            pic_order_cnt_lsb,
+        })
+    }
+}
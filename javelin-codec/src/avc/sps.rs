@@ -0,0 +1,419 @@
+use super::{
+    nal::{self, UnitType},
+    rbsp::{self, BitReader},
+};
+use crate::{Error, Result};
+
+
+/// Timing of the video usability information
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingInfo {
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub fixed_frame_rate: bool,
+}
+
+
+/// Sequence parameter set of a H.264 bitstream
+///
+/// Only the fields needed to describe the stream and to parse slice headers are kept,
+/// scaling matrices and HRD parameters are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceParameterSet {
+    pub profile_idc: u8,
+    /// `constraint_set0_flag` to `constraint_set5_flag` and the two reserved bits
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub id: u32,
+    pub chroma_format_idc: u32,
+    pub separate_colour_plane: bool,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+    pub log2_max_frame_num: u32,
+    pub pic_order_cnt_type: u32,
+    pub log2_max_pic_order_cnt_lsb: u32,
+    pub delta_pic_order_always_zero: bool,
+    pub max_num_ref_frames: u32,
+    pub gaps_in_frame_num_allowed: bool,
+    pub pic_width_in_mbs: u32,
+    pub pic_height_in_map_units: u32,
+    pub frame_mbs_only: bool,
+    pub mb_adaptive_frame_field: bool,
+    /// Left, right, top and bottom offsets in crop units
+    pub frame_cropping: Option<(u32, u32, u32, u32)>,
+    pub timing_info: Option<TimingInfo>,
+    /// From the bitstream restrictions of the video usability information
+    pub max_num_reorder_frames: Option<u32>,
+}
+
+impl SequenceParameterSet {
+    /// Upper bound of the width and height in macroblocks, well above what any level allows
+    const MAX_MBS: u32 = 4096;
+
+    pub fn try_from_unit(unit: &nal::Unit) -> Result<Self> {
+        if unit.kind != UnitType::SequenceParameterSet {
+            return Err(Error::ParseError(format!("Expected sequence parameter set, got {:?}", unit.kind)));
+        }
+
+        Self::try_from_rbsp(&rbsp::remove_emulation_prevention(&unit.data))
+    }
+
+    /// Parses the payload following the NAL unit header, without emulation prevention bytes.
+    pub fn try_from_rbsp(data: &[u8]) -> Result<Self> {
+        let mut reader = BitReader::new(data);
+
+        let profile_idc = reader.read_bits(8)? as u8;
+        let constraint_flags = reader.read_bits(8)? as u8;
+        let level_idc = reader.read_bits(8)? as u8;
+        let id = reader.read_ue()?;
+
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        let mut bit_depth_luma = 8;
+        let mut bit_depth_chroma = 8;
+
+        if Self::has_chroma_format(profile_idc) {
+            chroma_format_idc = reader.read_ue_bounded(3, "chroma_format_idc")?;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = reader.read_bit()?;
+            }
+            bit_depth_luma = reader.read_ue_bounded(6, "bit_depth_luma_minus8")? + 8;
+            bit_depth_chroma = reader.read_ue_bounded(6, "bit_depth_chroma_minus8")? + 8;
+            reader.skip_bits(1)?; // qpprime_y_zero_transform_bypass_flag
+
+            if reader.read_bit()? {
+                let count = if chroma_format_idc == 3 { 12 } else { 8 };
+                for i in 0..count {
+                    if reader.read_bit()? {
+                        skip_scaling_list(&mut reader, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        let log2_max_frame_num = reader.read_ue_bounded(12, "log2_max_frame_num_minus4")? + 4;
+        let pic_order_cnt_type = reader.read_ue()?;
+        let mut log2_max_pic_order_cnt_lsb = 0;
+        let mut delta_pic_order_always_zero = false;
+
+        match pic_order_cnt_type {
+            0 => log2_max_pic_order_cnt_lsb = reader.read_ue_bounded(12, "log2_max_pic_order_cnt_lsb_minus4")? + 4,
+            1 => {
+                delta_pic_order_always_zero = reader.read_bit()?;
+                reader.read_se()?; // offset_for_non_ref_pic
+                reader.read_se()?; // offset_for_top_to_bottom_field
+                let cycle_length = reader.read_ue()?;
+                for _ in 0..cycle_length {
+                    reader.read_se()?; // offset_for_ref_frame
+                }
+            },
+            2 => (),
+            _ => return Err(Error::ParseError(format!("Invalid picture order count type {}", pic_order_cnt_type))),
+        }
+
+        let max_num_ref_frames = reader.read_ue()?;
+        let gaps_in_frame_num_allowed = reader.read_bit()?;
+        let pic_width_in_mbs = reader.read_ue_bounded(Self::MAX_MBS, "pic_width_in_mbs_minus1")? + 1;
+        let pic_height_in_map_units = reader.read_ue_bounded(Self::MAX_MBS, "pic_height_in_map_units_minus1")? + 1;
+
+        let frame_mbs_only = reader.read_bit()?;
+        let mb_adaptive_frame_field = !frame_mbs_only && reader.read_bit()?;
+        reader.skip_bits(1)?; // direct_8x8_inference_flag
+
+        let frame_cropping = if reader.read_bit()? {
+            Some((reader.read_ue()?, reader.read_ue()?, reader.read_ue()?, reader.read_ue()?))
+        } else {
+            None
+        };
+
+        let mut sps = Self {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            id,
+            chroma_format_idc,
+            separate_colour_plane,
+            bit_depth_luma,
+            bit_depth_chroma,
+            log2_max_frame_num,
+            pic_order_cnt_type,
+            log2_max_pic_order_cnt_lsb,
+            delta_pic_order_always_zero,
+            max_num_ref_frames,
+            gaps_in_frame_num_allowed,
+            pic_width_in_mbs,
+            pic_height_in_map_units,
+            frame_mbs_only,
+            mb_adaptive_frame_field,
+            frame_cropping,
+            timing_info: None,
+            max_num_reorder_frames: None,
+        };
+
+        if reader.read_bit()? {
+            sps.read_vui_parameters(&mut reader)?;
+        }
+
+        Ok(sps)
+    }
+
+    fn read_vui_parameters(&mut self, reader: &mut BitReader) -> Result<()> {
+        const EXTENDED_SAR: u32 = 255;
+
+        if reader.read_bit()? && reader.read_bits(8)? == EXTENDED_SAR {
+            reader.skip_bits(32)?; // sar_width and sar_height
+        }
+
+        if reader.read_bit()? {
+            reader.skip_bits(1)?; // overscan_appropriate_flag
+        }
+
+        if reader.read_bit()? {
+            reader.skip_bits(4)?; // video_format and video_full_range_flag
+            if reader.read_bit()? {
+                reader.skip_bits(24)?; // colour primaries, transfer characteristics and matrix coefficients
+            }
+        }
+
+        if reader.read_bit()? {
+            reader.read_ue()?; // chroma_sample_loc_type_top_field
+            reader.read_ue()?; // chroma_sample_loc_type_bottom_field
+        }
+
+        if reader.read_bit()? {
+            self.timing_info = Some(TimingInfo {
+                num_units_in_tick: reader.read_bits(32)?,
+                time_scale: reader.read_bits(32)?,
+                fixed_frame_rate: reader.read_bit()?,
+            });
+        }
+
+        let nal_hrd_parameters = reader.read_bit()?;
+        if nal_hrd_parameters {
+            skip_hrd_parameters(reader)?;
+        }
+
+        let vcl_hrd_parameters = reader.read_bit()?;
+        if vcl_hrd_parameters {
+            skip_hrd_parameters(reader)?;
+        }
+
+        if nal_hrd_parameters || vcl_hrd_parameters {
+            reader.skip_bits(1)?; // low_delay_hrd_flag
+        }
+
+        reader.skip_bits(1)?; // pic_struct_present_flag
+
+        if reader.read_bit()? {
+            reader.skip_bits(1)?; // motion_vectors_over_pic_boundaries_flag
+            reader.read_ue()?; // max_bytes_per_pic_denom
+            reader.read_ue()?; // max_bits_per_mb_denom
+            reader.read_ue()?; // log2_max_mv_length_horizontal
+            reader.read_ue()?; // log2_max_mv_length_vertical
+            self.max_num_reorder_frames = Some(reader.read_ue()?);
+            reader.read_ue()?; // max_dec_frame_buffering
+        }
+
+        Ok(())
+    }
+
+    fn has_chroma_format(profile_idc: u8) -> bool {
+        matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135)
+    }
+
+    /// `ChromaArrayType` of the specification, zero for monochrome or separately coded planes
+    pub fn chroma_array_type(&self) -> u32 {
+        if self.separate_colour_plane { 0 } else { self.chroma_format_idc }
+    }
+
+    /// Width and height of a crop unit in luma samples
+    fn crop_units(&self) -> (u32, u32) {
+        let field_factor = if self.frame_mbs_only { 1 } else { 2 };
+
+        match self.chroma_array_type() {
+            0 => (1, field_factor),
+            1 => (2, 2 * field_factor),
+            2 => (2, field_factor),
+            _ => (1, field_factor),
+        }
+    }
+
+    /// `frame_num` wraps around at this value
+    pub fn max_frame_num(&self) -> u32 {
+        1 << self.log2_max_frame_num
+    }
+
+    /// Width of the decoded frames in pixels, after cropping
+    pub fn width(&self) -> u32 {
+        let width = self.pic_width_in_mbs * 16;
+        let (crop_unit_x, _) = self.crop_units();
+
+        match self.frame_cropping {
+            Some((left, right, _, _)) => width.saturating_sub(crop_unit_x.saturating_mul(left.saturating_add(right))),
+            None => width,
+        }
+    }
+
+    /// Height of the decoded frames in pixels, after cropping
+    pub fn height(&self) -> u32 {
+        let field_factor = if self.frame_mbs_only { 1 } else { 2 };
+        let height = field_factor * self.pic_height_in_map_units * 16;
+        let (_, crop_unit_y) = self.crop_units();
+
+        match self.frame_cropping {
+            Some((_, _, top, bottom)) => height.saturating_sub(crop_unit_y.saturating_mul(top.saturating_add(bottom))),
+            None => height,
+        }
+    }
+
+    /// Frames per second, if the stream signals its timing
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.timing_info.as_ref()
+            .filter(|timing| timing.num_units_in_tick > 0 && timing.time_scale > 0)
+            // Every frame lasts two ticks, one per field
+            .map(|timing| f64::from(timing.time_scale) / (2.0 * f64::from(timing.num_units_in_tick)))
+    }
+
+    /// Frames that can precede another one in decoding order but follow it in output order
+    pub fn max_reorder_frames(&self) -> Option<u32> {
+        const CONSTRAINT_SET3: u8 = 0x10;
+
+        if self.max_num_reorder_frames.is_some() {
+            return self.max_num_reorder_frames;
+        }
+
+        // Intra profiles and baseline streams have no B-frames
+        match self.profile_idc {
+            44 | 66 => Some(0),
+            86 | 100 | 110 | 122 | 244 if self.constraint_flags & CONSTRAINT_SET3 != 0 => Some(0),
+            _ => None,
+        }
+    }
+
+    pub fn profile_name(&self) -> &'static str {
+        const CONSTRAINT_SET1: u8 = 0x40;
+
+        match self.profile_idc {
+            66 if self.constraint_flags & CONSTRAINT_SET1 != 0 => "Constrained Baseline",
+            66 => "Baseline",
+            77 => "Main",
+            88 => "Extended",
+            100 => "High",
+            110 => "High 10",
+            122 => "High 4:2:2",
+            244 => "High 4:4:4 Predictive",
+            44 => "CAVLC 4:4:4 Intra",
+            83 | 86 => "Scalable",
+            118 | 128 => "Multiview",
+            _ => "Unknown",
+        }
+    }
+
+    /// Level as in `3.1`, level 1b is reported as `1b`
+    pub fn level_name(&self) -> String {
+        const CONSTRAINT_SET3: u8 = 0x10;
+
+        match (self.profile_idc, self.level_idc) {
+            (66, 11) | (77, 11) | (88, 11) if self.constraint_flags & CONSTRAINT_SET3 != 0 => "1b".to_string(),
+            (_, 9) => "1b".to_string(),
+            (_, level) => format!("{}.{}", level / 10, level % 10),
+        }
+    }
+
+    pub fn chroma_format_name(&self) -> &'static str {
+        match self.chroma_format_idc {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            _ => "4:4:4",
+        }
+    }
+
+    /// Codec parameter of RFC 6381, as used by the `CODECS` attribute of HLS playlists
+    pub fn codec_string(&self) -> String {
+        format!("avc1.{:02x}{:02x}{:02x}", self.profile_idc, self.constraint_flags, self.level_idc)
+    }
+}
+
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Result<()> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta_scale = reader.read_se()?;
+            next_scale = (last_scale + delta_scale + 256) % 256;
+        }
+
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Ok(())
+}
+
+fn skip_hrd_parameters(reader: &mut BitReader) -> Result<()> {
+    let cpb_count = reader.read_ue()? + 1;
+    reader.skip_bits(8)?; // bit_rate_scale and cpb_size_scale
+
+    for _ in 0..cpb_count {
+        reader.read_ue()?; // bit_rate_value_minus1
+        reader.read_ue()?; // cpb_size_value_minus1
+        reader.skip_bits(1)?; // cbr_flag
+    }
+
+    // Lengths of the removal and output delays and the time offset
+    reader.skip_bits(20)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use super::*;
+
+    fn parse(data: &'static [u8]) -> SequenceParameterSet {
+        let unit = nal::Unit::try_from_bytes(Bytes::from_static(data)).unwrap();
+        SequenceParameterSet::try_from_unit(&unit).unwrap()
+    }
+
+    #[test]
+    fn can_parse_high_profile_with_vui() {
+        let sps = parse(&[
+            0x67, 0x64, 0x00, 0x28, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0xC0, 0x44, 0x00,
+            0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xCA, 0x3C, 0x22, 0x11, 0x65, 0x80,
+        ]);
+
+        assert_eq!(sps.profile_name(), "High");
+        assert_eq!(sps.level_name(), "4.0");
+        assert_eq!(sps.chroma_format_name(), "4:2:0");
+        assert_eq!(sps.bit_depth_luma, 8);
+        assert_eq!(sps.log2_max_pic_order_cnt_lsb, 6);
+        assert_eq!(sps.max_num_ref_frames, 4);
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!(sps.frame_rate(), Some(25.0));
+        assert_eq!(sps.max_reorder_frames(), Some(2));
+        assert_eq!(sps.codec_string(), "avc1.640028");
+    }
+
+    #[test]
+    fn can_parse_interlaced_baseline_without_vui() {
+        let sps = parse(&[0x67, 0x42, 0xC0, 0x1E, 0xE5, 0x40, 0x5A, 0x12, 0x24]);
+
+        assert_eq!(sps.profile_name(), "Constrained Baseline");
+        assert_eq!(sps.level_name(), "3.0");
+        assert!(!sps.frame_mbs_only);
+        assert_eq!(sps.log2_max_pic_order_cnt_lsb, 8);
+        assert_eq!((sps.width(), sps.height()), (720, 576));
+        assert_eq!(sps.frame_rate(), None);
+        assert_eq!(sps.max_reorder_frames(), Some(0));
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let unit = nal::Unit::try_from_bytes(Bytes::from_static(&[0x67, 0x64, 0x00, 0x28, 0xAC])).unwrap();
+        assert!(SequenceParameterSet::try_from_unit(&unit).is_err());
+    }
+}
@@ -13,6 +13,7 @@ pub enum Error {
     DecoderConfigurationRecordMissing,
     AudioSpecificConfigurationMissing,
     UnsupportedConfigurationRecordVersion(u8),
+    UnsupportedCodec(String),
 }
 
 impl From<io::Error> for Error {
@@ -0,0 +1,136 @@
+mod reader;
+mod writer;
+mod script;
+mod demuxer;
+
+
+pub use self::{
+    reader::Reader,
+    demuxer::Demuxer,
+    writer::Writer,
+    script::{Metadata, Value},
+};
+
+
+use bytes::{Bytes, BufMut};
+use crate::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagKind {
+    Audio,
+    Video,
+    Script,
+}
+
+impl TagKind {
+    pub fn try_from_u8(value: u8) -> Option<Self> {
+        match value {
+            8 => Some(TagKind::Audio),
+            9 => Some(TagKind::Video),
+            18 => Some(TagKind::Script),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            TagKind::Audio => 8,
+            TagKind::Video => 9,
+            TagKind::Script => 18,
+        }
+    }
+}
+
+
+/// Bits | Description
+/// ---- | -----------
+/// 24   | Signature ("FLV")
+/// 8    | Version (1)
+/// 5    | Reserved
+/// 1    | Has audio
+/// 1    | Reserved
+/// 1    | Has video
+/// 32   | Header size (9)
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub has_audio: bool,
+    pub has_video: bool,
+}
+
+impl Header {
+    pub const SIZE: usize = 9;
+    pub const SIGNATURE: &'static [u8] = b"FLV";
+
+    /// Encodes the header followed by the size of the (non-existent) previous tag,
+    /// which is how every file or stream starts.
+    pub fn to_bytes(self) -> Bytes {
+        let mut flags = 0;
+        if self.has_audio {
+            flags |= 0b0000_0100;
+        }
+        if self.has_video {
+            flags |= 0b0000_0001;
+        }
+
+        let mut buf = Vec::with_capacity(Self::SIZE + 4);
+        buf.put_slice(Self::SIGNATURE);
+        buf.put_u8(1);
+        buf.put_u8(flags);
+        buf.put_u32_be(Self::SIZE as u32);
+        buf.put_u32_be(0);
+
+        Bytes::from(buf)
+    }
+}
+
+
+/// Bits | Description
+/// ---- | -----------
+/// 8    | Tag type
+/// 24   | Data size
+/// 24   | Timestamp
+/// 8    | Timestamp extension (upper 8 bits)
+/// 24   | Stream ID (0)
+/// var  | Data
+/// 32   | Previous tag size
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub kind: TagKind,
+    pub timestamp: u32,
+    pub data: Bytes,
+}
+
+impl Tag {
+    pub const HEADER_SIZE: usize = 11;
+    /// Largest payload a tag can hold
+    pub const MAX_DATA_SIZE: usize = 0x00FF_FFFF;
+
+    pub fn new(kind: TagKind, timestamp: u32, data: Bytes) -> Self {
+        Self { kind, timestamp, data }
+    }
+
+    /// Encodes the tag followed by its size, as it appears in a file or stream.
+    pub fn to_bytes(&self) -> Result<Bytes> {
+        let data_size = self.data.len();
+
+        if data_size > Self::MAX_DATA_SIZE {
+            return Err(Error::Custom(format!("FLV tag data of {} bytes is too large", data_size)));
+        }
+
+        let tag_size = Self::HEADER_SIZE + data_size;
+
+        let mut buf = Vec::with_capacity(tag_size + 4);
+        buf.put_u8(self.kind.as_u8());
+        buf.put_uint_be(data_size as u64, 3);
+        buf.put_uint_be(u64::from(self.timestamp & 0x00FF_FFFF), 3);
+        buf.put_u8((self.timestamp >> 24) as u8);
+        buf.put_uint_be(0, 3);
+        buf.put_slice(&self.data);
+        buf.put_u32_be(tag_size as u32);
+
+        Ok(Bytes::from(buf))
+    }
+}
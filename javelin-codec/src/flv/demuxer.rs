@@ -0,0 +1,164 @@
+use std::io::Cursor;
+use bytes::{Buf, BytesMut};
+use crate::{Error, Result};
+use super::{Header, Tag, TagKind};
+
+
+/// Splits an FLV stream that arrives in chunks of any size into tags
+#[derive(Default)]
+pub struct Demuxer {
+    buf: BytesMut,
+    header: Option<Header>,
+}
+
+impl Demuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The file header, once enough data has been pushed to read it
+    pub fn header(&self) -> Option<Header> {
+        self.header
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Whether data of an incomplete tag is left over
+    pub fn has_remaining(&self) -> bool {
+        !self.buf.is_empty()
+    }
+
+    /// Returns the next complete tag, or `None` until more data was pushed.
+    /// Tags of unknown types are skipped.
+    pub fn next_tag(&mut self) -> Result<Option<Tag>> {
+        if self.header.is_none() && !self.read_header()? {
+            return Ok(None);
+        }
+
+        loop {
+            if self.buf.len() < Tag::HEADER_SIZE {
+                return Ok(None);
+            }
+
+            let mut cursor = Cursor::new(&self.buf[..Tag::HEADER_SIZE]);
+            let tag_type = cursor.get_u8();
+            let data_size = cursor.get_uint_be(3) as usize;
+            let timestamp = cursor.get_uint_be(3) as u32;
+            let timestamp_extension = cursor.get_u8();
+
+            let size = Tag::HEADER_SIZE + data_size + 4;
+            if self.buf.len() < size {
+                return Ok(None);
+            }
+
+            let mut tag = self.buf.split_to(size);
+            tag.advance(Tag::HEADER_SIZE);
+            tag.truncate(data_size);
+
+            // The upper bits are reserved for filtering and encryption
+            let kind = match TagKind::try_from_u8(tag_type & 0x1F) {
+                Some(kind) => kind,
+                None => continue,
+            };
+
+            let timestamp = (u32::from(timestamp_extension) << 24) | timestamp;
+
+            return Ok(Some(Tag::new(kind, timestamp, tag.freeze())));
+        }
+    }
+
+    /// Reads and validates the file header, returns whether enough data was available
+    fn read_header(&mut self) -> Result<bool> {
+        if self.buf.len() < Header::SIZE {
+            return Ok(false);
+        }
+
+        if &self.buf[..3] != Header::SIGNATURE {
+            return Err(Error::ParseError("Invalid FLV signature".into()));
+        }
+
+        let mut cursor = Cursor::new(&self.buf[4..Header::SIZE]);
+        let flags = cursor.get_u8();
+        let header_size = cursor.get_u32_be() as usize;
+
+        if header_size < Header::SIZE {
+            return Err(Error::ParseError(format!("Invalid FLV header size {}", header_size)));
+        }
+
+        // Followed by the size of the (non-existent) previous tag
+        if self.buf.len() < header_size + 4 {
+            return Ok(false);
+        }
+
+        self.buf.advance(header_size + 4);
+        self.header = Some(Header {
+            has_audio: flags & 0b0000_0100 != 0,
+            has_video: flags & 0b0000_0001 != 0,
+        });
+
+        Ok(true)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use super::*;
+
+    const STREAM: &[u8] = &[
+        b'F', b'L', b'V', 0x01, 0b0000_0101, 0x00, 0x00, 0x00, 0x09,
+        0x00, 0x00, 0x00, 0x00,
+        // audio tag
+        0x08, 0x00, 0x00, 0x02, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+        0xAF, 0x01,
+        0x00, 0x00, 0x00, 0x0D,
+        // unknown tag
+        0x0F, 0x00, 0x00, 0x01, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00,
+        0xFF,
+        0x00, 0x00, 0x00, 0x0C,
+        // video tag with extended timestamp
+        0x09, 0x00, 0x00, 0x01, 0x00, 0x00, 0x20, 0x01, 0x00, 0x00, 0x00,
+        0x17,
+        0x00, 0x00, 0x00, 0x0C,
+    ];
+
+    #[test]
+    fn demuxes_stream_pushed_byte_by_byte() {
+        let mut demuxer = Demuxer::new();
+        let mut tags = Vec::new();
+
+        for byte in STREAM {
+            demuxer.push(&[*byte]);
+            while let Some(tag) = demuxer.next_tag().unwrap() {
+                tags.push(tag);
+            }
+        }
+
+        assert_eq!(demuxer.header(), Some(Header { has_audio: true, has_video: true }));
+        assert_eq!(tags, vec![
+            Tag::new(TagKind::Audio, 16, Bytes::from_static(&[0xAF, 0x01])),
+            Tag::new(TagKind::Video, 0x0100_0020, Bytes::from_static(&[0x17])),
+        ]);
+        assert!(!demuxer.has_remaining());
+    }
+
+    #[test]
+    fn keeps_incomplete_tags() {
+        let mut demuxer = Demuxer::new();
+        demuxer.push(&STREAM[..20]);
+
+        assert!(demuxer.next_tag().unwrap().is_none());
+        assert!(demuxer.has_remaining());
+    }
+
+    #[test]
+    fn rejects_invalid_signature() {
+        let mut demuxer = Demuxer::new();
+        demuxer.push(b"FLX\x01\x05\x00\x00\x00\x09\x00\x00\x00\x00");
+
+        assert!(demuxer.next_tag().is_err());
+    }
+}
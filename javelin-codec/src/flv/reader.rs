@@ -0,0 +1,163 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use bytes::Bytes;
+use byteorder::{ReadBytesExt, BigEndian};
+use crate::{Error, Result};
+use super::{Header, Tag, TagKind};
+
+
+/// Reads FLV tags one at a time
+pub struct Reader<R> {
+    inner: R,
+    header: Header,
+    /// Offset of the next tag from the start of the input
+    position: u64,
+}
+
+impl<R> Reader<R>
+    where R: Read
+{
+    /// Reads and validates the file header
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut signature = [0; 3];
+        inner.read_exact(&mut signature)?;
+
+        if signature != Header::SIGNATURE {
+            return Err(Error::ParseError("Invalid FLV signature".into()));
+        }
+
+        let _version = inner.read_u8()?;
+        let flags = inner.read_u8()?;
+        let header_size = inner.read_u32::<BigEndian>()? as usize;
+
+        if header_size < Header::SIZE {
+            return Err(Error::ParseError(format!("Invalid FLV header size {}", header_size)));
+        }
+
+        // Skip anything the header might have been extended with
+        io::copy(&mut inner.by_ref().take((header_size - Header::SIZE) as u64), &mut io::sink())?;
+
+        let _previous_tag_size = inner.read_u32::<BigEndian>()?;
+
+        let header = Header {
+            has_audio: flags & 0b0000_0100 != 0,
+            has_video: flags & 0b0000_0001 != 0,
+        };
+
+        Ok(Self { inner, header, position: header_size as u64 + 4 })
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    /// Offset of the next tag, which can be returned to with `seek`
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the next tag, or `None` once the end of the input is reached.
+    /// Tags of unknown types are skipped.
+    pub fn read_tag(&mut self) -> Result<Option<Tag>> {
+        loop {
+            let tag_type = match self.inner.read_u8() {
+                Ok(tag_type) => tag_type,
+                Err(ref why) if why.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(why) => return Err(why.into()),
+            };
+
+            let data_size = self.inner.read_u24::<BigEndian>()? as usize;
+            let timestamp = self.inner.read_u24::<BigEndian>()?;
+            let timestamp_extension = self.inner.read_u8()?;
+            let _stream_id = self.inner.read_u24::<BigEndian>()?;
+
+            let mut data = vec![0; data_size];
+            self.inner.read_exact(&mut data)?;
+            let _previous_tag_size = self.inner.read_u32::<BigEndian>()?;
+
+            self.position += (Tag::HEADER_SIZE + data_size + 4) as u64;
+
+            // The upper bits are reserved for filtering and encryption
+            let kind = match TagKind::try_from_u8(tag_type & 0x1F) {
+                Some(kind) => kind,
+                None => continue,
+            };
+
+            let timestamp = (u32::from(timestamp_extension) << 24) | timestamp;
+
+            return Ok(Some(Tag::new(kind, timestamp, Bytes::from(data))));
+        }
+    }
+}
+
+impl<R> Reader<R>
+    where R: Read + Seek
+{
+    /// Continues reading at a position previously returned by `position`
+    pub fn seek(&mut self, position: u64) -> Result<()> {
+        self.inner.seek(SeekFrom::Start(position))?;
+        self.position = position;
+        Ok(())
+    }
+}
+
+impl<R> Iterator for Reader<R>
+    where R: Read
+{
+    type Item = Result<Tag>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_tag().transpose()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &[u8] = &[
+        b'F', b'L', b'V', 0x01, 0b0000_0101, 0x00, 0x00, 0x00, 0x09,
+        0x00, 0x00, 0x00, 0x00,
+        // audio tag
+        0x08, 0x00, 0x00, 0x02, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+        0xAF, 0x01,
+        0x00, 0x00, 0x00, 0x0D,
+        // video tag with extended timestamp
+        0x09, 0x00, 0x00, 0x01, 0x00, 0x00, 0x20, 0x01, 0x00, 0x00, 0x00,
+        0x17,
+        0x00, 0x00, 0x00, 0x0C,
+    ];
+
+    #[test]
+    fn reads_header_and_tags() {
+        let mut reader = Reader::new(FILE).unwrap();
+
+        assert_eq!(reader.header(), Header { has_audio: true, has_video: true });
+
+        let audio = reader.read_tag().unwrap().unwrap();
+        assert_eq!(audio, Tag::new(TagKind::Audio, 16, Bytes::from_static(&[0xAF, 0x01])));
+
+        let video = reader.read_tag().unwrap().unwrap();
+        assert_eq!(video, Tag::new(TagKind::Video, 0x0100_0020, Bytes::from_static(&[0x17])));
+
+        assert!(reader.read_tag().unwrap().is_none());
+    }
+
+    #[test]
+    fn can_seek_to_tag_positions() {
+        let mut reader = Reader::new(io::Cursor::new(FILE)).unwrap();
+        reader.read_tag().unwrap();
+
+        let position = reader.position();
+        let video = reader.read_tag().unwrap();
+        assert_eq!(reader.position(), FILE.len() as u64);
+
+        reader.seek(position).unwrap();
+        assert_eq!(reader.read_tag().unwrap(), video);
+    }
+
+    #[test]
+    fn rejects_invalid_signature() {
+        assert!(Reader::new(&b"FLX\x01\x05\x00\x00\x00\x09\x00\x00\x00\x00"[..]).is_err());
+    }
+}
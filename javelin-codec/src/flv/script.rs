@@ -0,0 +1,353 @@
+use bytes::{Buf, BufMut, Bytes, IntoBuf};
+use crate::{Error, Result};
+use super::{Tag, TagKind};
+
+
+/// AMF0 encoded value of a script tag
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, Value)>),
+    Null,
+    Undefined,
+    EcmaArray(Vec<(String, Value)>),
+    StrictArray(Vec<Value>),
+    /// Milliseconds since the epoch
+    Date(f64),
+}
+
+impl Value {
+    const NUMBER: u8 = 0x00;
+    const BOOLEAN: u8 = 0x01;
+    const STRING: u8 = 0x02;
+    const OBJECT: u8 = 0x03;
+    const NULL: u8 = 0x05;
+    const UNDEFINED: u8 = 0x06;
+    const ECMA_ARRAY: u8 = 0x08;
+    const OBJECT_END: u8 = 0x09;
+    const STRICT_ARRAY: u8 = 0x0A;
+    const DATE: u8 = 0x0B;
+    const LONG_STRING: u8 = 0x0C;
+
+    pub fn read<B>(buf: &mut B) -> Result<Self>
+        where B: Buf
+    {
+        let value = match read_u8(buf)? {
+            Self::NUMBER => Value::Number(read_f64(buf)?),
+            Self::BOOLEAN => Value::Boolean(read_u8(buf)? != 0),
+            Self::STRING => Value::String(read_string(buf)?),
+            Self::OBJECT => Value::Object(read_properties(buf)?),
+            Self::NULL => Value::Null,
+            Self::UNDEFINED => Value::Undefined,
+            Self::ECMA_ARRAY => {
+                // The count is only a hint, the properties are terminated like those of an object
+                ensure(buf, 4)?;
+                buf.get_u32_be();
+                Value::EcmaArray(read_properties(buf)?)
+            },
+            Self::STRICT_ARRAY => {
+                ensure(buf, 4)?;
+                let count = buf.get_u32_be();
+                let mut values = Vec::new();
+                for _ in 0..count {
+                    values.push(Self::read(buf)?);
+                }
+                Value::StrictArray(values)
+            },
+            Self::DATE => {
+                let value = read_f64(buf)?;
+                ensure(buf, 2)?;
+                buf.get_i16_be(); // time zone, unused
+                Value::Date(value)
+            },
+            Self::LONG_STRING => {
+                ensure(buf, 4)?;
+                let length = buf.get_u32_be() as usize;
+                Value::String(read_utf8(buf, length)?)
+            },
+            marker => return Err(Error::ParseError(format!("Unsupported AMF0 marker {:#04x}", marker))),
+        };
+
+        Ok(value)
+    }
+
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Number(value) => {
+                buf.put_u8(Self::NUMBER);
+                buf.put_f64_be(*value);
+            },
+            Value::Boolean(value) => {
+                buf.put_u8(Self::BOOLEAN);
+                buf.put_u8(*value as u8);
+            },
+            Value::String(value) if value.len() > u16::MAX as usize => {
+                buf.put_u8(Self::LONG_STRING);
+                buf.put_u32_be(value.len() as u32);
+                buf.put_slice(value.as_bytes());
+            },
+            Value::String(value) => {
+                buf.put_u8(Self::STRING);
+                write_string(buf, value);
+            },
+            Value::Object(properties) => {
+                buf.put_u8(Self::OBJECT);
+                write_properties(buf, properties);
+            },
+            Value::Null => buf.put_u8(Self::NULL),
+            Value::Undefined => buf.put_u8(Self::UNDEFINED),
+            Value::EcmaArray(properties) => {
+                buf.put_u8(Self::ECMA_ARRAY);
+                buf.put_u32_be(properties.len() as u32);
+                write_properties(buf, properties);
+            },
+            Value::StrictArray(values) => {
+                buf.put_u8(Self::STRICT_ARRAY);
+                buf.put_u32_be(values.len() as u32);
+                for value in values {
+                    value.write(buf);
+                }
+            },
+            Value::Date(value) => {
+                buf.put_u8(Self::DATE);
+                buf.put_f64_be(*value);
+                buf.put_i16_be(0);
+            },
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+
+/// Properties of an `onMetaData` script tag, in the order they were read
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    properties: Vec<(String, Value)>,
+}
+
+impl Metadata {
+    const NAME: &'static str = "onMetaData";
+    /// Prefix of metadata sent by RTMP publishers
+    const SET_DATA_FRAME: &'static str = "@setDataFrame";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the body of a script tag or RTMP data message,
+    /// returns `None` if it does not contain metadata.
+    pub fn try_from_bytes<B>(bytes: B) -> Result<Option<Self>>
+        where B: IntoBuf
+    {
+        let mut buf = bytes.into_buf();
+
+        let mut name = Value::read(&mut buf)?;
+        if name.as_str() == Some(Self::SET_DATA_FRAME) {
+            name = Value::read(&mut buf)?;
+        }
+
+        if name.as_str() != Some(Self::NAME) {
+            return Ok(None);
+        }
+
+        let properties = match Value::read(&mut buf)? {
+            Value::EcmaArray(properties) | Value::Object(properties) => properties,
+            _ => return Err(Error::ParseError("Metadata is not an object".into())),
+        };
+
+        Ok(Some(Self { properties }))
+    }
+
+    /// Returns `None` for tags other than script tags containing metadata
+    pub fn try_from_tag(tag: &Tag) -> Result<Option<Self>> {
+        if tag.kind != TagKind::Script {
+            return Ok(None);
+        }
+
+        Self::try_from_bytes(&tag.data)
+    }
+
+    /// Body of a script tag, also used for RTMP data messages
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = Vec::new();
+        Value::String(Self::NAME.to_string()).write(&mut buf);
+        Value::EcmaArray(self.properties.clone()).write(&mut buf);
+        Bytes::from(buf)
+    }
+
+    pub fn to_tag(&self, timestamp: u32) -> Tag {
+        Tag::new(TagKind::Script, timestamp, self.to_bytes())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.properties.iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Replaces the value of an existing property or appends a new one.
+    pub fn set<K>(&mut self, key: K, value: Value)
+        where K: Into<String>
+    {
+        let key = key.into();
+
+        match self.properties.iter_mut().find(|(name, _)| *name == key) {
+            Some(property) => property.1 = value,
+            None => self.properties.push((key, value)),
+        }
+    }
+
+    pub fn number(&self, key: &str) -> Option<f64> {
+        self.get(key).and_then(Value::as_number)
+    }
+
+    pub fn boolean(&self, key: &str) -> Option<bool> {
+        self.get(key).and_then(Value::as_bool)
+    }
+
+    pub fn string(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(Value::as_str)
+    }
+
+    pub fn properties(&self) -> &[(String, Value)] {
+        &self.properties
+    }
+}
+
+
+fn ensure<B: Buf>(buf: &B, length: usize) -> Result<()> {
+    if buf.remaining() < length {
+        return Err(Error::NotEnoughData);
+    }
+    Ok(())
+}
+
+fn read_u8<B: Buf>(buf: &mut B) -> Result<u8> {
+    ensure(buf, 1)?;
+    Ok(buf.get_u8())
+}
+
+fn read_f64<B: Buf>(buf: &mut B) -> Result<f64> {
+    ensure(buf, 8)?;
+    Ok(buf.get_f64_be())
+}
+
+fn read_utf8<B: Buf>(buf: &mut B, length: usize) -> Result<String> {
+    ensure(buf, length)?;
+    let bytes: Vec<u8> = buf.by_ref().take(length).collect();
+    String::from_utf8(bytes).map_err(|_| Error::ParseError("Invalid UTF-8 string".into()))
+}
+
+fn read_string<B: Buf>(buf: &mut B) -> Result<String> {
+    ensure(buf, 2)?;
+    let length = buf.get_u16_be() as usize;
+    read_utf8(buf, length)
+}
+
+/// Reads properties up to the object end marker
+fn read_properties<B: Buf>(buf: &mut B) -> Result<Vec<(String, Value)>> {
+    let mut properties = Vec::new();
+
+    loop {
+        let key = read_string(buf)?;
+
+        if key.is_empty() {
+            if read_u8(buf)? == Value::OBJECT_END {
+                return Ok(properties);
+            }
+            return Err(Error::ParseError("Expected end of AMF0 object".into()));
+        }
+
+        properties.push((key, Value::read(buf)?));
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    buf.put_u16_be(value.len() as u16);
+    buf.put_slice(value.as_bytes());
+}
+
+fn write_properties(buf: &mut Vec<u8>, properties: &[(String, Value)]) {
+    for (key, value) in properties {
+        write_string(buf, key);
+        value.write(buf);
+    }
+
+    write_string(buf, "");
+    buf.put_u8(Value::OBJECT_END);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_round_trip() {
+        let mut metadata = Metadata::new();
+        metadata.set("width", Value::Number(1280.0));
+        metadata.set("stereo", Value::Boolean(true));
+        metadata.set("encoder", Value::String("obs-output module".into()));
+        metadata.set("keyframes", Value::Object(vec![
+            ("times".into(), Value::StrictArray(vec![Value::Number(0.0), Value::Number(2.0)])),
+        ]));
+        metadata.set("width", Value::Number(1920.0));
+
+        let tag = metadata.to_tag(0);
+        let read = Metadata::try_from_tag(&tag).unwrap().unwrap();
+
+        assert_eq!(read, metadata);
+        assert_eq!(read.number("width"), Some(1920.0));
+        assert_eq!(read.boolean("stereo"), Some(true));
+        assert_eq!(read.string("encoder"), Some("obs-output module"));
+    }
+
+    #[test]
+    fn reads_metadata_of_rtmp_publishers() {
+        let mut buf = Vec::new();
+        Value::String("@setDataFrame".into()).write(&mut buf);
+        Value::String("onMetaData".into()).write(&mut buf);
+        Value::Object(vec![("framerate".into(), Value::Number(30.0))]).write(&mut buf);
+
+        let metadata = Metadata::try_from_bytes(buf).unwrap().unwrap();
+
+        assert_eq!(metadata.number("framerate"), Some(30.0));
+    }
+
+    #[test]
+    fn ignores_other_script_data() {
+        let mut buf = Vec::new();
+        Value::String("onCuePoint".into()).write(&mut buf);
+        Value::Null.write(&mut buf);
+
+        assert!(Metadata::try_from_bytes(buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = Metadata::new().to_bytes();
+        assert!(Metadata::try_from_bytes(&data[..data.len() - 2]).is_err());
+    }
+}
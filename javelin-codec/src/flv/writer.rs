@@ -0,0 +1,68 @@
+use std::io::Write;
+use crate::Result;
+use super::{Header, Tag};
+
+
+/// Writes FLV tags one at a time
+pub struct Writer<W> {
+    inner: W,
+}
+
+impl<W> Writer<W>
+    where W: Write
+{
+    /// Writes the file header
+    pub fn new(mut inner: W, header: Header) -> Result<Self> {
+        inner.write_all(&header.to_bytes())?;
+        Ok(Self { inner })
+    }
+
+    /// Writes a tag, returns the number of bytes written.
+    pub fn write_tag(&mut self, tag: &Tag) -> Result<usize> {
+        let bytes = tag.to_bytes()?;
+        self.inner.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use super::*;
+    use crate::flv::{Reader, TagKind};
+
+    #[test]
+    fn written_tags_can_be_read() {
+        let header = Header { has_audio: true, has_video: true };
+        let tags = vec![
+            Tag::new(TagKind::Script, 0, Bytes::from_static(&[0x02, 0x00])),
+            Tag::new(TagKind::Audio, 16, Bytes::from_static(&[0xAF, 0x01])),
+            Tag::new(TagKind::Video, 0x0100_0020, Bytes::from_static(&[0x17])),
+        ];
+
+        let mut writer = Writer::new(Vec::new(), header).unwrap();
+        let mut size = Header::SIZE + 4;
+        for tag in &tags {
+            size += writer.write_tag(tag).unwrap();
+        }
+
+        let file = writer.into_inner();
+        assert_eq!(file.len(), size);
+
+        let reader = Reader::new(&file[..]).unwrap();
+        assert_eq!(reader.header(), header);
+
+        let read = reader.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(read, tags);
+    }
+}
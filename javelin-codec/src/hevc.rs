@@ -0,0 +1,10 @@
+mod packet;
+mod bitstream;
+
+
+pub mod dcr;
+pub mod nal;
+pub mod sps;
+
+
+pub use self::packet::Packet;
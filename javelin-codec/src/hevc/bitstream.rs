@@ -0,0 +1,158 @@
+use log::warn;
+use bytes::{Bytes, BytesMut, IntoBuf};
+use super::{
+    dcr::DecoderConfigurationRecord,
+    nal::{self, UnitType},
+};
+use crate::{utils, Error, Result};
+
+
+#[derive(Debug)]
+pub struct Bitstream {
+    dcr: DecoderConfigurationRecord,
+    nal_units: Vec<nal::Unit>,
+}
+
+impl<'a> Bitstream {
+    const DELIMITER1: &'a [u8] = &[0x00, 0x00, 0x01];
+    const DELIMITER2: &'a [u8] = &[0x00, 0x00, 0x00, 0x01];
+    /// Access unit delimiter allowing I, P and B slices
+    const ACCESS_UNIT_DELIMITER: &'a [u8] = &[0x00, 0x00, 0x00, 0x01, 0x46, 0x01, 0x50];
+
+    pub fn try_from_buf<B>(bytes: B, dcr: DecoderConfigurationRecord) -> Result<Self>
+        where B: IntoBuf,
+    {
+        use bytes::Buf;
+
+        let mut buf = bytes.into_buf();
+        let mut nal_units = Vec::new();
+
+        while buf.has_remaining() {
+            if buf.remaining() < dcr.nalu_size as usize {
+                return Err(Error::NotEnoughData);
+            }
+
+            let nalu_length = utils::try_bytes_as_usize_be(dcr.nalu_size as usize, &mut buf)?;
+            if buf.remaining() < nalu_length {
+                return Err(Error::NotEnoughData);
+            }
+
+            let nalu_data: Bytes = buf.by_ref().take(nalu_length).collect();
+            nal_units.push(nal::Unit::try_from_bytes(nalu_data)?)
+        };
+
+        if buf.has_remaining() {
+            warn!("{} bytes remaining in buffer", buf.remaining());
+        }
+
+        Ok(Self { nal_units, dcr })
+    }
+
+    /// Whether the access unit holds an IRAP picture
+    pub fn is_random_access(&self) -> bool {
+        self.nal_units.iter().any(|nalu| nalu.kind.is_irap())
+    }
+
+    /// Converts the access unit to Annex-B, starting with an access unit delimiter.
+    ///
+    /// Parameter sets of the bitstream are dropped, those of the decoder configuration
+    /// record are put in front of IRAP pictures instead.
+    pub fn try_as_bytes(&self) -> Result<Bytes> {
+        let mut tmp = BytesMut::new();
+        let mut parameter_sets_appended = false;
+        let dcr = &self.dcr;
+
+        tmp.extend(Self::ACCESS_UNIT_DELIMITER);
+
+        for nalu in &self.nal_units {
+            match nalu.kind {
+                | UnitType::VideoParameterSet
+                | UnitType::SequenceParameterSet
+                | UnitType::PictureParameterSet
+                | UnitType::AccessUnitDelimiter => continue,
+                kind if kind.is_irap() && !parameter_sets_appended => {
+                    for unit in dcr.vps.iter().chain(&dcr.sps).chain(&dcr.pps) {
+                        tmp.extend(Self::DELIMITER2);
+                        let unit: Bytes = unit.clone().into();
+                        tmp.extend(unit);
+                    }
+
+                    parameter_sets_appended = true;
+                },
+                _ => (),
+            }
+
+            tmp.extend(Self::DELIMITER1);
+            let nalu_data: Bytes = nalu.clone().into();
+            tmp.extend(nalu_data);
+        }
+
+        Ok(tmp.freeze())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::IntoBuf;
+    use super::*;
+
+    const RECORD: &[u8] = &[
+        0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78,
+        0xF0, 0x00, 0xFC, 0xFD, 0xF8, 0xF8, 0x00, 0x00, 0x0F, 0x03,
+        0xA0, 0x00, 0x01, 0x00, 0x04, 0x40, 0x01, 0x0C, 0x01,
+        0xA1, 0x00, 0x01, 0x00, 0x04, 0x42, 0x01, 0x01, 0x01,
+        0xA2, 0x00, 0x01, 0x00, 0x04, 0x44, 0x01, 0xC1, 0x72,
+    ];
+
+    fn dcr() -> DecoderConfigurationRecord {
+        DecoderConfigurationRecord::try_from_buf(&mut RECORD.into_buf()).unwrap()
+    }
+
+    #[test]
+    fn puts_parameter_sets_before_irap_pictures() {
+        // Prefix SEI and an IDR slice
+        let data = Bytes::from_static(&[
+            0x00, 0x00, 0x00, 0x03, 0x4E, 0x01, 0x05,
+            0x00, 0x00, 0x00, 0x04, 0x26, 0x01, 0xAF, 0x06,
+        ]);
+        let bitstream = Bitstream::try_from_buf(data, dcr()).unwrap();
+
+        assert!(bitstream.is_random_access());
+        assert_eq!(bitstream.try_as_bytes().unwrap()[..], [
+            0x00, 0x00, 0x00, 0x01, 0x46, 0x01, 0x50,
+            0x00, 0x00, 0x01, 0x4E, 0x01, 0x05,
+            0x00, 0x00, 0x00, 0x01, 0x40, 0x01, 0x0C, 0x01,
+            0x00, 0x00, 0x00, 0x01, 0x42, 0x01, 0x01, 0x01,
+            0x00, 0x00, 0x00, 0x01, 0x44, 0x01, 0xC1, 0x72,
+            0x00, 0x00, 0x01, 0x26, 0x01, 0xAF, 0x06,
+        ][..]);
+    }
+
+    #[test]
+    fn rejects_truncated_units() {
+        // Length prefix cut short and unit shorter than its prefix
+        for data in [&[0x00, 0x00, 0x00][..], &[0x00, 0x00, 0x00, 0x04, 0x26, 0x01, 0xAF][..]].iter() {
+            match Bitstream::try_from_buf(*data, dcr()) {
+                Err(Error::NotEnoughData) => (),
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_other_pictures_alone() {
+        // Trailing picture that repeats the picture parameter set
+        let data = Bytes::from_static(&[
+            0x00, 0x00, 0x00, 0x04, 0x44, 0x01, 0xC1, 0x72,
+            0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0xD0,
+        ]);
+        let bitstream = Bitstream::try_from_buf(data, dcr()).unwrap();
+
+        assert!(!bitstream.is_random_access());
+        assert_eq!(bitstream.try_as_bytes().unwrap()[..], [
+            0x00, 0x00, 0x00, 0x01, 0x46, 0x01, 0x50,
+            0x00, 0x00, 0x01, 0x02, 0x01, 0xD0,
+        ][..]);
+    }
+}
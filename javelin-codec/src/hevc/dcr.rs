@@ -0,0 +1,243 @@
+use bytes::{Bytes, Buf, BufMut};
+use super::{
+    nal::{self, UnitType},
+    sps::ProfileTierLevel,
+};
+use crate::{Error, Result};
+
+
+/// HEVC decoder configuration record (`HEVCDecoderConfigurationRecord`)
+///
+/// Bits | Name
+/// ---- | ----
+/// 8    | Version
+/// 2    | General Profile Space
+/// 1    | General Tier
+/// 5    | General Profile IDC
+/// 32   | General Profile Compatibility Flags
+/// 48   | General Constraint Indicator Flags
+/// 8    | General Level IDC
+/// 4    | Reserved
+/// 12   | Min Spatial Segmentation IDC
+/// 6    | Reserved
+/// 2    | Parallelism Type
+/// 6    | Reserved
+/// 2    | Chroma Format
+/// 5    | Reserved
+/// 3    | Bit Depth Luma - 8
+/// 5    | Reserved
+/// 3    | Bit Depth Chroma - 8
+/// 16   | Average Frame Rate
+/// 2    | Constant Frame Rate
+/// 3    | Temporal Layer Count
+/// 1    | Temporal ID Nested
+/// 2    | NALU Length
+/// 8    | Array Count
+/// var  | Arrays of VPS, SPS, PPS and SEI units
+///
+/// Each array starts with the completeness flag, a reserved bit, the unit type,
+/// the unit count and then the units with 16 bit length prefixes.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderConfigurationRecord {
+    pub version: u8,
+    pub profile_tier_level: ProfileTierLevel,
+    pub min_spatial_segmentation_idc: u16,
+    pub parallelism_type: u8,
+    pub chroma_format: u8,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+    /// Frames per 256 seconds, zero if unknown
+    pub avg_frame_rate: u16,
+    pub constant_frame_rate: u8,
+    pub num_temporal_layers: u8,
+    pub temporal_id_nested: bool,
+    pub nalu_size: u8,
+    pub vps: Vec<nal::Unit>,
+    pub sps: Vec<nal::Unit>,
+    pub pps: Vec<nal::Unit>,
+}
+
+impl DecoderConfigurationRecord {
+    pub fn try_from_buf<B>(buf: &mut B) -> Result<Self>
+        where B: Buf
+    {
+        if buf.remaining() < 23 {
+            return Err(Error::NotEnoughData)
+        }
+
+        let version = buf.get_u8();
+        if version != 1 {
+            return Err(Error::UnsupportedConfigurationRecordVersion(version));
+        }
+
+        let tmp = buf.get_u8();
+        let profile_tier_level = ProfileTierLevel {
+            profile_space: tmp >> 6,
+            tier: (tmp >> 5) & 0x01 == 1,
+            profile_idc: tmp & 0x1F,
+            profile_compatibility_flags: buf.get_u32_be(),
+            constraint_indicator_flags: u64::from(buf.get_u16_be()) << 32 | u64::from(buf.get_u32_be()),
+            level_idc: buf.get_u8(),
+        };
+
+        let min_spatial_segmentation_idc = buf.get_u16_be() & 0x0FFF;
+        let parallelism_type = buf.get_u8() & 0x03;
+        let chroma_format = buf.get_u8() & 0x03;
+        let bit_depth_luma = (buf.get_u8() & 0x07) + 8;
+        let bit_depth_chroma = (buf.get_u8() & 0x07) + 8;
+        let avg_frame_rate = buf.get_u16_be();
+
+        let tmp = buf.get_u8();
+        let constant_frame_rate = tmp >> 6;
+        let num_temporal_layers = (tmp >> 3) & 0x07;
+        let temporal_id_nested = (tmp >> 2) & 0x01 == 1;
+        let nalu_size = (tmp & 0x03) + 1;
+
+        let mut vps = Vec::new();
+        let mut sps = Vec::new();
+        let mut pps = Vec::new();
+
+        let array_count = buf.get_u8();
+        for _ in 0..array_count {
+            if buf.remaining() < 3 {
+                return Err(Error::NotEnoughData);
+            }
+
+            // Arrays of other units, e.g. SEI messages, are not needed to decode the stream
+            let unit_type = buf.get_u8() & 0x3F;
+            let unit_count = buf.get_u16_be();
+
+            for _ in 0..unit_count {
+                if buf.remaining() < 2 {
+                    return Err(Error::NotEnoughData);
+                }
+
+                let unit_length = buf.get_u16_be() as usize;
+                if buf.remaining() < unit_length {
+                    return Err(Error::NotEnoughData);
+                }
+
+                let tmp: Bytes = buf.by_ref().take(unit_length).collect();
+                match unit_type {
+                    32 => vps.push(nal::Unit::try_from_bytes(tmp)?),
+                    33 => sps.push(nal::Unit::try_from_bytes(tmp)?),
+                    34 => pps.push(nal::Unit::try_from_bytes(tmp)?),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(Self {
+            version,
+            profile_tier_level,
+            min_spatial_segmentation_idc,
+            parallelism_type,
+            chroma_format,
+            bit_depth_luma,
+            bit_depth_chroma,
+            avg_frame_rate,
+            constant_frame_rate,
+            num_temporal_layers,
+            temporal_id_nested,
+            nalu_size,
+            vps,
+            sps,
+            pps,
+        })
+    }
+
+    /// Writes the record as carried by Enhanced RTMP sequence headers and MP4 `hvcC` boxes.
+    ///
+    /// The arrays are marked complete, as all parameter sets of the stream are in the record.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut tmp = Vec::new();
+        let ptl = &self.profile_tier_level;
+
+        tmp.put_u8(self.version);
+        tmp.put_u8(ptl.profile_space << 6 | (ptl.tier as u8) << 5 | ptl.profile_idc);
+        tmp.put_u32_be(ptl.profile_compatibility_flags);
+        tmp.put_u16_be((ptl.constraint_indicator_flags >> 32) as u16);
+        tmp.put_u32_be(ptl.constraint_indicator_flags as u32);
+        tmp.put_u8(ptl.level_idc);
+        tmp.put_u16_be(0xF000 | self.min_spatial_segmentation_idc);
+        tmp.put_u8(0xFC | self.parallelism_type);
+        tmp.put_u8(0xFC | self.chroma_format);
+        tmp.put_u8(0xF8 | (self.bit_depth_luma - 8));
+        tmp.put_u8(0xF8 | (self.bit_depth_chroma - 8));
+        tmp.put_u16_be(self.avg_frame_rate);
+        tmp.put_u8(
+            self.constant_frame_rate << 6
+            | self.num_temporal_layers << 3
+            | (self.temporal_id_nested as u8) << 2
+            | (self.nalu_size - 1)
+        );
+
+        let arrays = [
+            (UnitType::VideoParameterSet, &self.vps),
+            (UnitType::SequenceParameterSet, &self.sps),
+            (UnitType::PictureParameterSet, &self.pps),
+        ];
+
+        tmp.put_u8(arrays.iter().filter(|(_, units)| !units.is_empty()).count() as u8);
+        for (unit_type, units) in arrays.iter().filter(|(_, units)| !units.is_empty()) {
+            tmp.put_u8(0x80 | u8::from(*unit_type));
+            tmp.put_u16_be(units.len() as u16);
+
+            for unit in units.iter() {
+                let unit: Bytes = unit.clone().into();
+                tmp.put_u16_be(unit.len() as u16);
+                tmp.put_slice(&unit);
+            }
+        }
+
+        Bytes::from(tmp)
+    }
+
+    pub fn codec_string(&self) -> String {
+        self.profile_tier_level.codec_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::IntoBuf;
+    use super::*;
+
+    const RECORD: &[u8] = &[
+        0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78,
+        0xF0, 0x00, 0xFC, 0xFD, 0xF8, 0xF8, 0x00, 0x00, 0x0F, 0x03,
+        0xA0, 0x00, 0x01, 0x00, 0x04, 0x40, 0x01, 0x0C, 0x01,
+        0xA1, 0x00, 0x01, 0x00, 0x04, 0x42, 0x01, 0x01, 0x01,
+        0xA2, 0x00, 0x01, 0x00, 0x04, 0x44, 0x01, 0xC1, 0x72,
+    ];
+
+    #[test]
+    fn can_parse_record() {
+        let dcr = DecoderConfigurationRecord::try_from_buf(&mut RECORD.into_buf()).unwrap();
+
+        assert_eq!(dcr.profile_tier_level.profile_idc, 1);
+        assert_eq!(dcr.profile_tier_level.level_idc, 120);
+        assert_eq!(dcr.chroma_format, 1);
+        assert_eq!((dcr.bit_depth_luma, dcr.bit_depth_chroma), (8, 8));
+        assert_eq!(dcr.num_temporal_layers, 1);
+        assert!(dcr.temporal_id_nested);
+        assert_eq!(dcr.nalu_size, 4);
+        assert_eq!((dcr.vps.len(), dcr.sps.len(), dcr.pps.len()), (1, 1, 1));
+        assert_eq!(dcr.pps[0].kind, UnitType::PictureParameterSet);
+        assert_eq!(dcr.codec_string(), "hvc1.1.6.L120.B0");
+    }
+
+    #[test]
+    fn can_be_written_back() {
+        let dcr = DecoderConfigurationRecord::try_from_buf(&mut RECORD.into_buf()).unwrap();
+        assert_eq!(dcr.to_bytes()[..], RECORD[..]);
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let record = &RECORD[..RECORD.len() - 1];
+        assert!(DecoderConfigurationRecord::try_from_buf(&mut record.into_buf()).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use bytes::{Bytes, BytesMut};
+use crate::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub enum UnitType {
+    TrailingN = 0,
+    TrailingR = 1,
+    TemporalSubLayerAccessN = 2,
+    TemporalSubLayerAccessR = 3,
+    StepwiseTemporalSubLayerAccessN = 4,
+    StepwiseTemporalSubLayerAccessR = 5,
+    RandomAccessDecodableLeadingN = 6,
+    RandomAccessDecodableLeadingR = 7,
+    RandomAccessSkippedLeadingN = 8,
+    RandomAccessSkippedLeadingR = 9,
+    BrokenLinkAccessWithLeadingPictures = 16,
+    BrokenLinkAccessWithDecodableLeadingPictures = 17,
+    BrokenLinkAccessWithoutLeadingPictures = 18,
+    IdrWithDecodableLeadingPictures = 19,
+    IdrWithoutLeadingPictures = 20,
+    CleanRandomAccess = 21,
+    VideoParameterSet = 32,
+    SequenceParameterSet = 33,
+    PictureParameterSet = 34,
+    AccessUnitDelimiter = 35,
+    SequenceEnd = 36,
+    BitstreamEnd = 37,
+    FillerData = 38,
+    PrefixSupplementaryEnhancementInformation = 39,
+    SuffixSupplementaryEnhancementInformation = 40,
+}
+
+impl UnitType {
+    fn try_from(value: u8) -> Result<Self> {
+        let val = match value {
+            0 => UnitType::TrailingN,
+            1 => UnitType::TrailingR,
+            2 => UnitType::TemporalSubLayerAccessN,
+            3 => UnitType::TemporalSubLayerAccessR,
+            4 => UnitType::StepwiseTemporalSubLayerAccessN,
+            5 => UnitType::StepwiseTemporalSubLayerAccessR,
+            6 => UnitType::RandomAccessDecodableLeadingN,
+            7 => UnitType::RandomAccessDecodableLeadingR,
+            8 => UnitType::RandomAccessSkippedLeadingN,
+            9 => UnitType::RandomAccessSkippedLeadingR,
+            16 => UnitType::BrokenLinkAccessWithLeadingPictures,
+            17 => UnitType::BrokenLinkAccessWithDecodableLeadingPictures,
+            18 => UnitType::BrokenLinkAccessWithoutLeadingPictures,
+            19 => UnitType::IdrWithDecodableLeadingPictures,
+            20 => UnitType::IdrWithoutLeadingPictures,
+            21 => UnitType::CleanRandomAccess,
+            32 => UnitType::VideoParameterSet,
+            33 => UnitType::SequenceParameterSet,
+            34 => UnitType::PictureParameterSet,
+            35 => UnitType::AccessUnitDelimiter,
+            36 => UnitType::SequenceEnd,
+            37 => UnitType::BitstreamEnd,
+            38 => UnitType::FillerData,
+            39 => UnitType::PrefixSupplementaryEnhancementInformation,
+            40 => UnitType::SuffixSupplementaryEnhancementInformation,
+            10..=15 | 22..=31 | 41..=47 => {
+                return Err(Error::ParseError(format!("Reserved NAL unit type {}", value)));
+            },
+            _ => {
+                return Err(Error::ParseError(format!("Unspecified NAL unit type {}", value)));
+            },
+        };
+
+        Ok(val)
+    }
+
+    /// Whether the unit holds a slice of a coded picture
+    pub fn is_vcl(self) -> bool {
+        u8::from(self) < 32
+    }
+
+    /// Whether the unit holds a slice of an intra random access point (IRAP) picture,
+    /// decoding can start with these pictures.
+    pub fn is_irap(self) -> bool {
+        (16..=23).contains(&u8::from(self))
+    }
+
+    pub fn is_idr(self) -> bool {
+        self == UnitType::IdrWithDecodableLeadingPictures || self == UnitType::IdrWithoutLeadingPictures
+    }
+}
+
+impl From<UnitType> for u8 {
+    fn from(kind: UnitType) -> Self {
+        kind as u8
+    }
+}
+
+
+/// Network Abstraction Layer Unit (aka NALU) of a H.265 bitstream.
+///
+/// Bits | Name
+/// ---- | ----
+/// 1    | Forbidden Zero Bit
+/// 6    | Unit Type
+/// 6    | Layer ID
+/// 3    | Temporal ID + 1
+/// var  | Payload
+///
+#[derive(Clone, PartialEq, Eq)]
+pub struct Unit {
+    pub kind: UnitType,
+    pub layer_id: u8,
+    pub temporal_id: u8,
+    pub data: Bytes, // Raw Byte Sequence Payload (RBSP)
+}
+
+impl Unit {
+    pub fn try_from_bytes(bytes: Bytes) -> Result<Self> {
+        if bytes.len() < 2 {
+            return Err(Error::NotEnoughData);
+        }
+
+        let header = u16::from(bytes[0]) << 8 | u16::from(bytes[1]);
+        if header >> 15 != 0 {
+            return Err(Error::ParseError("Forbidden zero bit of NAL unit is set".to_string()));
+        }
+
+        let kind = UnitType::try_from((header >> 9) as u8 & 0x3F)?;
+        let layer_id = (header >> 3) as u8 & 0x3F;
+        let temporal_id = match header as u8 & 0x07 {
+            0 => return Err(Error::ParseError("Invalid temporal ID of NAL unit".to_string())),
+            temporal_id_plus1 => temporal_id_plus1 - 1,
+        };
+        let data = bytes.slice_from(2);
+
+        Ok(Self { kind, layer_id, temporal_id, data })
+    }
+}
+
+impl From<Unit> for Bytes {
+    fn from(unit: Unit) -> Self {
+        use bytes::BufMut;
+
+        let mut tmp = BytesMut::with_capacity(unit.data.len() + 2);
+
+        let header = u16::from(u8::from(unit.kind)) << 9 | u16::from(unit.layer_id) << 3 | u16::from(unit.temporal_id + 1);
+        tmp.put_u16_be(header);
+        tmp.put(unit.data);
+
+        tmp.freeze()
+    }
+}
+
+impl fmt::Debug for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Unit")
+            .field("kind", &self.kind)
+            .field("layer_id", &self.layer_id)
+            .field("temporal_id", &self.temporal_id)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_unit_header() {
+        let bytes = Bytes::from_static(&[0x26, 0x01, 0xAF, 0x06]);
+        let unit = Unit::try_from_bytes(bytes.clone()).unwrap();
+
+        assert_eq!(unit.kind, UnitType::IdrWithDecodableLeadingPictures);
+        assert_eq!((unit.layer_id, unit.temporal_id), (0, 0));
+        assert!(unit.kind.is_irap() && unit.kind.is_idr());
+        assert_eq!(unit.data[..], [0xAF, 0x06]);
+
+        let written: Bytes = unit.into();
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn rejects_reserved_unit_types() {
+        assert!(Unit::try_from_bytes(Bytes::from_static(&[0x2C, 0x01])).is_err());
+        assert!(Unit::try_from_bytes(Bytes::from_static(&[0x02, 0x00])).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use log::debug;
+use bytes::{Bytes, Buf, IntoBuf};
+use super::{
+    dcr::DecoderConfigurationRecord,
+    bitstream::Bitstream,
+};
+use crate::{
+    avc::FrameType,
+    SharedState,
+    Error,
+    Result,
+};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PacketType {
+    SequenceStart,
+    CodedFrames,
+    SequenceEnd,
+    /// Coded frames without composition time
+    CodedFramesX,
+    Metadata,
+    Mpeg2TsSequenceStart,
+    Unknown(u8)
+}
+
+impl From<u8> for PacketType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => PacketType::SequenceStart,
+            1 => PacketType::CodedFrames,
+            2 => PacketType::SequenceEnd,
+            3 => PacketType::CodedFramesX,
+            4 => PacketType::Metadata,
+            5 => PacketType::Mpeg2TsSequenceStart,
+            _ => PacketType::Unknown(value),
+        }
+    }
+}
+
+
+/// HEVC encoded byte chunk of Enhanced RTMP
+///
+/// Bits | Name
+/// ---- | ----
+/// 1    | Extended Header, always set
+/// 3    | Frame Type
+/// 4    | Packet Type
+/// 32   | FourCC, `hvc1`
+/// 24   | Composition Time (signed), only if the packet type is `CodedFrames`
+/// var  | [Decoder Configuration Record](dcr/struct.DecoderConfigurationRecord.html)
+/// var  | [NALU](nal/struct.Unit.html)
+///
+#[derive(Debug)]
+pub struct Packet {
+    frame_type: FrameType,
+    packet_type: PacketType,
+    composition_time: i32,
+    nal_units: Bitstream,
+    timestamp: u64,
+}
+
+impl Packet {
+    pub const FOURCC: &'static [u8] = b"hvc1";
+
+    pub fn try_from_buf<B>(bytes: B, timestamp: u64, shared: &SharedState) -> Result<Self>
+        where B: IntoBuf
+    {
+        let mut buf = bytes.into_buf();
+
+        if buf.remaining() < 5 {
+            return Err(Error::NotEnoughData);
+        }
+
+        let tmp = buf.get_u8();
+        if tmp & 0x80 == 0 {
+            return Err(Error::ParseError("Expected video packet with extended header".to_string()));
+        }
+        let frame_type = FrameType::from((tmp >> 4) & 0x07);
+        let packet_type = PacketType::from(tmp & 0x0F);
+
+        let mut fourcc = [0; 4];
+        buf.copy_to_slice(&mut fourcc);
+        if fourcc != Self::FOURCC {
+            return Err(Error::UnsupportedCodec(String::from_utf8_lossy(&fourcc).into_owned()));
+        }
+
+        let composition_time = match packet_type {
+            PacketType::CodedFrames if buf.remaining() >= 3 => buf.get_int_be(3) as i32,
+            PacketType::CodedFrames => return Err(Error::NotEnoughData),
+            _ => 0,
+        };
+
+        if packet_type == PacketType::SequenceStart {
+            debug!("Received HEVC sequence header");
+            let mut dcr = shared.hevc_dcr.write();
+            *dcr = Some(DecoderConfigurationRecord::try_from_buf(&mut buf)?);
+        }
+
+        let dcr = shared.hevc_dcr.read().clone().ok_or(Error::DecoderConfigurationRecordMissing)?;
+        let nal_units = match packet_type {
+            PacketType::CodedFrames | PacketType::CodedFramesX => Bitstream::try_from_buf(buf, dcr)?,
+            _ => Bitstream::try_from_buf(Bytes::new(), dcr)?,
+        };
+
+        Ok(Self {
+            frame_type,
+            packet_type,
+            composition_time,
+            nal_units,
+            timestamp,
+        })
+    }
+
+    /// Access unit in Annex-B format, with parameter sets in front of IRAP pictures
+    pub fn try_as_bytes(&self) -> Result<Bytes> {
+        self.nal_units.try_as_bytes()
+    }
+
+    pub fn is_sequence_header(&self) -> bool {
+        self.packet_type == PacketType::SequenceStart
+    }
+
+    pub fn has_coded_frames(&self) -> bool {
+        self.packet_type == PacketType::CodedFrames || self.packet_type == PacketType::CodedFramesX
+    }
+
+    pub fn is_keyframe(&self) -> bool {
+        self.frame_type == FrameType::Keyframe || self.frame_type == FrameType::GeneratedKeyframe
+    }
+
+    /// Whether decoding can start with this frame according to its NAL unit types
+    pub fn is_random_access(&self) -> bool {
+        self.nal_units.is_random_access()
+    }
+
+    /// Frames with a negative composition time are presented no earlier than zero
+    pub fn presentation_timestamp(&self) -> u64 {
+        (self.timestamp as i64 + i64::from(self.composition_time)).max(0) as u64
+    }
+
+    pub fn composition_time(&self) -> i32 {
+        self.composition_time
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEQUENCE_START: &[u8] = &[
+        0x90, b'h', b'v', b'c', b'1',
+        0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78,
+        0xF0, 0x00, 0xFC, 0xFD, 0xF8, 0xF8, 0x00, 0x00, 0x0F, 0x03,
+        0xA0, 0x00, 0x01, 0x00, 0x04, 0x40, 0x01, 0x0C, 0x01,
+        0xA1, 0x00, 0x01, 0x00, 0x04, 0x42, 0x01, 0x01, 0x01,
+        0xA2, 0x00, 0x01, 0x00, 0x04, 0x44, 0x01, 0xC1, 0x72,
+    ];
+
+    #[test]
+    fn can_parse_enhanced_rtmp_packets() {
+        let shared = SharedState::new();
+
+        let packet = Packet::try_from_buf(SEQUENCE_START, 0, &shared).unwrap();
+        assert!(packet.is_sequence_header());
+        assert!(shared.hevc_dcr.read().is_some());
+
+        // Keyframe with a composition time of 40ms and an IDR slice
+        let data: &[u8] = &[
+            0x91, b'h', b'v', b'c', b'1', 0x00, 0x00, 0x28,
+            0x00, 0x00, 0x00, 0x04, 0x26, 0x01, 0xAF, 0x06,
+        ];
+        let packet = Packet::try_from_buf(data, 1000, &shared).unwrap();
+        assert!(packet.has_coded_frames());
+        assert!(packet.is_keyframe() && packet.is_random_access());
+        assert_eq!(packet.presentation_timestamp(), 1040);
+
+        // Inter frame without composition time
+        let data: &[u8] = &[
+            0xA3, b'h', b'v', b'c', b'1',
+            0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0xD0,
+        ];
+        let packet = Packet::try_from_buf(data, 1080, &shared).unwrap();
+        assert!(!packet.is_keyframe() && !packet.is_random_access());
+        assert_eq!(packet.presentation_timestamp(), 1080);
+    }
+
+    #[test]
+    fn reads_negative_composition_times() {
+        let shared = SharedState::new();
+        Packet::try_from_buf(SEQUENCE_START, 0, &shared).unwrap();
+
+        // Inter frame with a composition time of -40ms
+        let data: &[u8] = &[
+            0xA1, b'h', b'v', b'c', b'1', 0xFF, 0xFF, 0xD8,
+            0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0xD0,
+        ];
+        let packet = Packet::try_from_buf(data, 1000, &shared).unwrap();
+        assert_eq!(packet.composition_time(), -40);
+        assert_eq!(packet.presentation_timestamp(), 960);
+
+        let packet = Packet::try_from_buf(data, 20, &shared).unwrap();
+        assert_eq!(packet.presentation_timestamp(), 0);
+    }
+
+    #[test]
+    fn rejects_other_codecs() {
+        let shared = SharedState::new();
+        let data: &[u8] = &[0x90, b'a', b'v', b'0', b'1', 0x81];
+
+        match Packet::try_from_buf(data, 0, &shared) {
+            Err(Error::UnsupportedCodec(fourcc)) => assert_eq!(fourcc, "av01"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
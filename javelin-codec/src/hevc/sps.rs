@@ -0,0 +1,260 @@
+use super::nal::{self, UnitType};
+use crate::{
+    avc::rbsp::{self, BitReader},
+    Error,
+    Result,
+};
+
+
+/// General profile, tier and level of a H.265 bitstream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileTierLevel {
+    pub profile_space: u8,
+    pub tier: bool,
+    pub profile_idc: u8,
+    pub profile_compatibility_flags: u32,
+    /// The 48 bits following the compatibility flags
+    pub constraint_indicator_flags: u64,
+    pub level_idc: u8,
+}
+
+impl ProfileTierLevel {
+    fn try_from_reader(reader: &mut BitReader, max_sub_layers: u8) -> Result<Self> {
+        let profile_space = reader.read_bits(2)? as u8;
+        let tier = reader.read_bit()?;
+        let profile_idc = reader.read_bits(5)? as u8;
+        let profile_compatibility_flags = reader.read_bits(32)?;
+        let constraint_indicator_flags = u64::from(reader.read_bits(16)?) << 32 | u64::from(reader.read_bits(32)?);
+        let level_idc = reader.read_bits(8)? as u8;
+
+        // Sub-layers are skipped, only the general values describe the whole stream
+        let sub_layers = usize::from(max_sub_layers - 1);
+        let mut present = Vec::with_capacity(sub_layers);
+        for _ in 0..sub_layers {
+            let profile_present = reader.read_bit()?;
+            let level_present = reader.read_bit()?;
+            present.push((profile_present, level_present));
+        }
+
+        if sub_layers > 0 {
+            reader.skip_bits((8 - sub_layers) * 2)?; // reserved_zero_2bits
+        }
+
+        for (profile_present, level_present) in present {
+            if profile_present {
+                reader.skip_bits(88)?;
+            }
+            if level_present {
+                reader.skip_bits(8)?;
+            }
+        }
+
+        Ok(Self {
+            profile_space,
+            tier,
+            profile_idc,
+            profile_compatibility_flags,
+            constraint_indicator_flags,
+            level_idc,
+        })
+    }
+
+    /// Codec as named in the `CODECS` attribute of HLS playlists, e.g. `hvc1.1.6.L93.B0`
+    pub fn codec_string(&self) -> String {
+        let profile_space = ["", "A", "B", "C"][usize::from(self.profile_space & 0x03)];
+        let tier = if self.tier { 'H' } else { 'L' };
+
+        // The compatibility flags are written in reverse bit order
+        let mut codec = format!("hvc1.{}{}.{:X}.{}{}",
+            profile_space,
+            self.profile_idc,
+            self.profile_compatibility_flags.reverse_bits(),
+            tier,
+            self.level_idc);
+
+        // Constraint bytes follow one by one, trailing zero bytes are left out
+        let constraints = &self.constraint_indicator_flags.to_be_bytes()[2..];
+        let len = constraints.iter().rposition(|byte| *byte != 0).map_or(0, |pos| pos + 1);
+        for byte in &constraints[..len] {
+            codec.push_str(&format!(".{:X}", byte));
+        }
+
+        codec
+    }
+}
+
+
+/// Sequence parameter set of a H.265 bitstream
+///
+/// Parsing stops after the bit depths, which covers everything needed to describe the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceParameterSet {
+    pub vps_id: u8,
+    pub max_sub_layers: u8,
+    pub temporal_id_nesting: bool,
+    pub profile_tier_level: ProfileTierLevel,
+    pub id: u32,
+    pub chroma_format_idc: u32,
+    pub separate_colour_plane: bool,
+    pub pic_width_in_luma_samples: u32,
+    pub pic_height_in_luma_samples: u32,
+    /// Left, right, top and bottom offset in chroma samples
+    pub conformance_window: Option<(u32, u32, u32, u32)>,
+    pub bit_depth_luma: u32,
+    pub bit_depth_chroma: u32,
+}
+
+impl SequenceParameterSet {
+    pub fn try_from_unit(unit: &nal::Unit) -> Result<Self> {
+        if unit.kind != UnitType::SequenceParameterSet {
+            return Err(Error::ParseError(format!("Expected sequence parameter set, got {:?}", unit.kind)));
+        }
+
+        Self::try_from_rbsp(&rbsp::remove_emulation_prevention(&unit.data))
+    }
+
+    /// Parses the payload following the NAL unit header, without emulation prevention bytes.
+    pub fn try_from_rbsp(data: &[u8]) -> Result<Self> {
+        let mut reader = BitReader::new(data);
+
+        let vps_id = reader.read_bits(4)? as u8;
+        let max_sub_layers = match reader.read_bits(3)? as u8 + 1 {
+            8 => return Err(Error::ParseError("Invalid sps_max_sub_layers_minus1 7".to_string())),
+            max_sub_layers => max_sub_layers,
+        };
+        let temporal_id_nesting = reader.read_bit()?;
+        let profile_tier_level = ProfileTierLevel::try_from_reader(&mut reader, max_sub_layers)?;
+
+        let id = reader.read_ue_bounded(15, "sps_seq_parameter_set_id")?;
+        let chroma_format_idc = reader.read_ue_bounded(3, "chroma_format_idc")?;
+        let separate_colour_plane = chroma_format_idc == 3 && reader.read_bit()?;
+        let pic_width_in_luma_samples = reader.read_ue()?;
+        let pic_height_in_luma_samples = reader.read_ue()?;
+
+        let conformance_window = if reader.read_bit()? {
+            Some((reader.read_ue()?, reader.read_ue()?, reader.read_ue()?, reader.read_ue()?))
+        } else {
+            None
+        };
+
+        let bit_depth_luma = reader.read_ue_bounded(8, "bit_depth_luma_minus8")? + 8;
+        let bit_depth_chroma = reader.read_ue_bounded(8, "bit_depth_chroma_minus8")? + 8;
+
+        Ok(Self {
+            vps_id,
+            max_sub_layers,
+            temporal_id_nesting,
+            profile_tier_level,
+            id,
+            chroma_format_idc,
+            separate_colour_plane,
+            pic_width_in_luma_samples,
+            pic_height_in_luma_samples,
+            conformance_window,
+            bit_depth_luma,
+            bit_depth_chroma,
+        })
+    }
+
+    /// Conformance window offsets are coded in chroma samples
+    fn crop_units(&self) -> (u32, u32) {
+        match self.chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        }
+    }
+
+    /// Width in pixels after cropping
+    pub fn width(&self) -> u32 {
+        let (crop_unit_x, _) = self.crop_units();
+
+        match self.conformance_window {
+            Some((left, right, _, _)) => {
+                let crop = left.saturating_add(right).saturating_mul(crop_unit_x);
+                self.pic_width_in_luma_samples.saturating_sub(crop)
+            },
+            None => self.pic_width_in_luma_samples,
+        }
+    }
+
+    /// Height in pixels after cropping
+    pub fn height(&self) -> u32 {
+        let (_, crop_unit_y) = self.crop_units();
+
+        match self.conformance_window {
+            Some((_, _, top, bottom)) => {
+                let crop = top.saturating_add(bottom).saturating_mul(crop_unit_y);
+                self.pic_height_in_luma_samples.saturating_sub(crop)
+            },
+            None => self.pic_height_in_luma_samples,
+        }
+    }
+
+    pub fn profile_name(&self) -> &'static str {
+        match self.profile_tier_level.profile_idc {
+            1 => "Main",
+            2 => "Main 10",
+            3 => "Main Still Picture",
+            4 => "Format Range Extensions",
+            5 => "High Throughput",
+            9 => "Screen Content Coding Extensions",
+            _ => "Unknown",
+        }
+    }
+
+    /// Level as written in the specification, e.g. `4.1`
+    pub fn level_name(&self) -> String {
+        // general_level_idc is 30 times the level
+        let level_idc = u32::from(self.profile_tier_level.level_idc);
+        format!("{}.{}", level_idc / 30, level_idc % 30 / 3)
+    }
+
+    pub fn chroma_format_name(&self) -> &'static str {
+        match self.chroma_format_idc {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            _ => "4:4:4",
+        }
+    }
+
+    pub fn codec_string(&self) -> String {
+        self.profile_tier_level.codec_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use super::*;
+
+    /// 1920x1088 Main profile at level 4 with a conformance window cropping 8 rows
+    const SPS: &[u8] = &[
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0xB0, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x03, 0x00, 0x78, 0xA0, 0x03, 0xC0, 0x80, 0x11, 0x07, 0xCB, 0x96,
+    ];
+
+    #[test]
+    fn can_parse_sequence_parameter_set() {
+        let unit = nal::Unit::try_from_bytes(Bytes::from_static(SPS)).unwrap();
+        let sps = SequenceParameterSet::try_from_unit(&unit).unwrap();
+
+        assert_eq!(sps.profile_tier_level, ProfileTierLevel {
+            profile_space: 0,
+            tier: false,
+            profile_idc: 1,
+            profile_compatibility_flags: 0x6000_0000,
+            constraint_indicator_flags: 0xB000_0000_0000,
+            level_idc: 120,
+        });
+        assert_eq!(sps.chroma_format_idc, 1);
+        assert_eq!((sps.pic_width_in_luma_samples, sps.pic_height_in_luma_samples), (1920, 1088));
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!((sps.bit_depth_luma, sps.bit_depth_chroma), (8, 8));
+        assert_eq!(sps.profile_name(), "Main");
+        assert_eq!(sps.level_name(), "4.0");
+        assert_eq!(sps.codec_string(), "hvc1.1.6.L120.B0");
+    }
+}
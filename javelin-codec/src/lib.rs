@@ -1,6 +1,10 @@
 pub(crate) mod utils;
 pub mod avc;
+pub mod hevc;
 pub mod aac;
+pub mod flv;
+pub mod mp4;
+pub mod mpegts;
 pub mod error;
 
 
@@ -17,6 +21,7 @@ pub use self::error::{Error, Result};
 pub struct SharedState {
     pub dcr: Arc<RwLock<Option<DecoderConfigurationRecord>>>,
     pub asc: Arc<RwLock<Option<AudioSpecificConfiguration>>>,
+    pub hevc_dcr: Arc<RwLock<Option<hevc::dcr::DecoderConfigurationRecord>>>,
 }
 
 impl SharedState {
@@ -24,6 +29,7 @@ impl SharedState {
         Self {
             dcr: Arc::new(RwLock::new(None)),
             asc: Arc::new(RwLock::new(None)),
+            hevc_dcr: Arc::new(RwLock::new(None)),
         }
     }
 }
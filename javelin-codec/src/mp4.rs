@@ -0,0 +1,31 @@
+mod boxes;
+mod writer;
+
+
+pub use self::writer::Writer;
+
+
+use crate::{
+    avc::dcr::DecoderConfigurationRecord,
+    aac::config::AudioSpecificConfiguration,
+};
+
+
+/// Ticks per second of all tracks, same as FLV and RTMP timestamps
+pub const TIMESCALE: u32 = 1000;
+
+
+/// Configuration of the H.264 track
+#[derive(Debug, Clone)]
+pub struct VideoTrack {
+    pub dcr: DecoderConfigurationRecord,
+    pub width: u16,
+    pub height: u16,
+}
+
+
+/// Configuration of the AAC track
+#[derive(Debug, Clone)]
+pub struct AudioTrack {
+    pub asc: AudioSpecificConfiguration,
+}
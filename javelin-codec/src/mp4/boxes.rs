@@ -0,0 +1,338 @@
+use bytes::{Bytes, BufMut};
+use byteorder::{ByteOrder, BigEndian};
+use crate::DecoderConfigurationRecord;
+use super::{AudioTrack, VideoTrack, TIMESCALE};
+
+
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Sample depends on others and is not a sync sample
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x0101_0000;
+/// Sample does not depend on others
+const SAMPLE_FLAGS_SYNC: u32 = 0x0200_0000;
+
+
+/// A sample as stored in a fragment
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub data: Bytes,
+    pub decode_time: u64,
+    pub duration: u32,
+    pub composition_offset: u32,
+    pub is_sync: bool,
+}
+
+
+/// Samples of a single track in a fragment
+pub struct TrackFragment<'a> {
+    pub track_id: u32,
+    pub samples: &'a [Sample],
+}
+
+
+pub enum Track<'a> {
+    Video(u32, &'a VideoTrack),
+    Audio(u32, &'a AudioTrack),
+}
+
+impl<'a> Track<'a> {
+    fn id(&self) -> u32 {
+        match self {
+            Track::Video(id, _) | Track::Audio(id, _) => *id,
+        }
+    }
+}
+
+
+/// Writes a box, the size is filled in once the content has been written.
+fn write_box<F>(buf: &mut Vec<u8>, kind: &[u8; 4], content: F)
+    where F: FnOnce(&mut Vec<u8>)
+{
+    let start = buf.len();
+    buf.put_u32_be(0);
+    buf.put_slice(kind);
+    content(buf);
+
+    let size = (buf.len() - start) as u32;
+    BigEndian::write_u32(&mut buf[start..start + 4], size);
+}
+
+fn write_full_box<F>(buf: &mut Vec<u8>, kind: &[u8; 4], version: u8, flags: u32, content: F)
+    where F: FnOnce(&mut Vec<u8>)
+{
+    write_box(buf, kind, |buf| {
+        buf.put_u32_be((u32::from(version) << 24) | (flags & 0x00FF_FFFF));
+        content(buf);
+    })
+}
+
+fn write_matrix(buf: &mut Vec<u8>) {
+    for value in &UNITY_MATRIX {
+        buf.put_u32_be(*value);
+    }
+}
+
+
+/// File type and movie header, all samples follow in fragments
+pub fn init_segment(tracks: &[Track]) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    write_box(&mut buf, b"ftyp", |buf| {
+        buf.put_slice(b"isom");
+        buf.put_u32_be(0x200);
+        buf.put_slice(b"isom");
+        buf.put_slice(b"iso6");
+        buf.put_slice(b"avc1");
+        buf.put_slice(b"mp41");
+    });
+
+    write_box(&mut buf, b"moov", |buf| {
+        let next_track_id = tracks.iter().map(Track::id).max().unwrap_or(0) + 1;
+
+        write_full_box(buf, b"mvhd", 0, 0, |buf| {
+            buf.put_u32_be(0); // creation time
+            buf.put_u32_be(0); // modification time
+            buf.put_u32_be(TIMESCALE);
+            buf.put_u32_be(0); // duration, unknown for fragmented files
+            buf.put_u32_be(0x0001_0000); // rate
+            buf.put_u16_be(0x0100); // volume
+            buf.put_slice(&[0; 10]);
+            write_matrix(buf);
+            buf.put_slice(&[0; 24]);
+            buf.put_u32_be(next_track_id);
+        });
+
+        for track in tracks {
+            write_trak(buf, track);
+        }
+
+        write_box(buf, b"mvex", |buf| {
+            for track in tracks {
+                write_full_box(buf, b"trex", 0, 0, |buf| {
+                    buf.put_u32_be(track.id());
+                    buf.put_u32_be(1); // sample description index
+                    buf.put_u32_be(0); // default duration
+                    buf.put_u32_be(0); // default size
+                    buf.put_u32_be(0); // default flags
+                });
+            }
+        });
+    });
+
+    buf
+}
+
+fn write_trak(buf: &mut Vec<u8>, track: &Track) {
+    let (width, height, volume) = match track {
+        Track::Video(_, video) => (video.width, video.height, 0),
+        Track::Audio(..) => (0, 0, 0x0100),
+    };
+
+    write_box(buf, b"trak", |buf| {
+        // Track enabled and in movie
+        write_full_box(buf, b"tkhd", 0, 0x03, |buf| {
+            buf.put_u32_be(0); // creation time
+            buf.put_u32_be(0); // modification time
+            buf.put_u32_be(track.id());
+            buf.put_u32_be(0);
+            buf.put_u32_be(0); // duration
+            buf.put_slice(&[0; 8]);
+            buf.put_u16_be(0); // layer
+            buf.put_u16_be(0); // alternate group
+            buf.put_u16_be(volume);
+            buf.put_u16_be(0);
+            write_matrix(buf);
+            buf.put_u32_be(u32::from(width) << 16);
+            buf.put_u32_be(u32::from(height) << 16);
+        });
+
+        write_box(buf, b"mdia", |buf| {
+            write_full_box(buf, b"mdhd", 0, 0, |buf| {
+                buf.put_u32_be(0); // creation time
+                buf.put_u32_be(0); // modification time
+                buf.put_u32_be(TIMESCALE);
+                buf.put_u32_be(0); // duration
+                buf.put_u16_be(0x55C4); // language 'und'
+                buf.put_u16_be(0);
+            });
+
+            let (handler, name): (&[u8], &[u8]) = match track {
+                Track::Video(..) => (b"vide", b"VideoHandler\0"),
+                Track::Audio(..) => (b"soun", b"SoundHandler\0"),
+            };
+
+            write_full_box(buf, b"hdlr", 0, 0, |buf| {
+                buf.put_u32_be(0);
+                buf.put_slice(handler);
+                buf.put_slice(&[0; 12]);
+                buf.put_slice(name);
+            });
+
+            write_box(buf, b"minf", |buf| {
+                match track {
+                    Track::Video(..) => write_full_box(buf, b"vmhd", 0, 0x01, |buf| {
+                        buf.put_slice(&[0; 8]);
+                    }),
+                    Track::Audio(..) => write_full_box(buf, b"smhd", 0, 0, |buf| {
+                        buf.put_u32_be(0);
+                    }),
+                }
+
+                write_box(buf, b"dinf", |buf| {
+                    write_full_box(buf, b"dref", 0, 0, |buf| {
+                        buf.put_u32_be(1);
+                        // Media data is in the same file
+                        write_full_box(buf, b"url ", 0, 0x01, |_| ());
+                    });
+                });
+
+                write_stbl(buf, track);
+            });
+        });
+    });
+}
+
+/// Only holds the sample description, the samples are described by the fragments
+fn write_stbl(buf: &mut Vec<u8>, track: &Track) {
+    write_box(buf, b"stbl", |buf| {
+        write_full_box(buf, b"stsd", 0, 0, |buf| {
+            buf.put_u32_be(1);
+
+            match track {
+                Track::Video(_, video) => write_avc1(buf, video),
+                Track::Audio(id, audio) => write_mp4a(buf, *id, audio),
+            }
+        });
+
+        write_full_box(buf, b"stts", 0, 0, |buf| buf.put_u32_be(0));
+        write_full_box(buf, b"stsc", 0, 0, |buf| buf.put_u32_be(0));
+        write_full_box(buf, b"stsz", 0, 0, |buf| {
+            buf.put_u32_be(0);
+            buf.put_u32_be(0);
+        });
+        write_full_box(buf, b"stco", 0, 0, |buf| buf.put_u32_be(0));
+    });
+}
+
+fn write_avc1(buf: &mut Vec<u8>, video: &VideoTrack) {
+    write_box(buf, b"avc1", |buf| {
+        buf.put_slice(&[0; 6]);
+        buf.put_u16_be(1); // data reference index
+        buf.put_slice(&[0; 16]);
+        buf.put_u16_be(video.width);
+        buf.put_u16_be(video.height);
+        buf.put_u32_be(0x0048_0000); // 72 dpi
+        buf.put_u32_be(0x0048_0000);
+        buf.put_u32_be(0);
+        buf.put_u16_be(1); // frame count
+        buf.put_slice(&[0; 32]); // compressor name
+        buf.put_u16_be(0x0018); // depth
+        buf.put_i16_be(-1);
+
+        // Samples are always written with 4 byte NALU lengths
+        let dcr = DecoderConfigurationRecord { nalu_size: 4, ..video.dcr.clone() };
+        write_box(buf, b"avcC", |buf| buf.put_slice(&dcr.to_bytes()));
+    });
+}
+
+fn write_mp4a(buf: &mut Vec<u8>, track_id: u32, audio: &AudioTrack) {
+    let asc = audio.asc.to_bytes();
+    let sample_rate = audio.asc.sampling_frequency().unwrap_or(0);
+
+    write_box(buf, b"mp4a", |buf| {
+        buf.put_slice(&[0; 6]);
+        buf.put_u16_be(1); // data reference index
+        buf.put_slice(&[0; 8]);
+        buf.put_u16_be(u16::from(audio.asc.channel_configuration));
+        buf.put_u16_be(16); // sample size
+        buf.put_u32_be(0);
+        buf.put_u32_be(sample_rate << 16);
+
+        write_full_box(buf, b"esds", 0, 0, |buf| {
+            // ES descriptor
+            buf.put_u8(0x03);
+            buf.put_u8((3 + 2 + 13 + 2 + asc.len() + 3) as u8);
+            buf.put_u16_be(track_id as u16);
+            buf.put_u8(0);
+
+            // Decoder configuration descriptor, MPEG-4 audio stream
+            buf.put_u8(0x04);
+            buf.put_u8((13 + 2 + asc.len()) as u8);
+            buf.put_u8(0x40);
+            buf.put_u8(0x15);
+            buf.put_uint_be(0, 3); // buffer size
+            buf.put_u32_be(0); // maximum bitrate
+            buf.put_u32_be(0); // average bitrate
+
+            // Decoder specific info
+            buf.put_u8(0x05);
+            buf.put_u8(asc.len() as u8);
+            buf.put_slice(&asc);
+
+            // Sync layer configuration descriptor
+            buf.put_u8(0x06);
+            buf.put_u8(1);
+            buf.put_u8(0x02);
+        });
+    });
+}
+
+
+/// Movie fragment header followed by the media data of all tracks
+pub fn media_segment(sequence_number: u32, tracks: &[TrackFragment]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut data_offset_positions = Vec::with_capacity(tracks.len());
+
+    write_box(&mut buf, b"moof", |buf| {
+        write_full_box(buf, b"mfhd", 0, 0, |buf| buf.put_u32_be(sequence_number));
+
+        for track in tracks {
+            let first = match track.samples.first() {
+                Some(first) => first,
+                None => continue,
+            };
+
+            write_box(buf, b"traf", |buf| {
+                // Data offsets are relative to the start of the moof box
+                write_full_box(buf, b"tfhd", 0, 0x02_0000, |buf| buf.put_u32_be(track.track_id));
+
+                write_full_box(buf, b"tfdt", 1, 0, |buf| buf.put_u64_be(first.decode_time));
+
+                // Data offset, sample duration, size, flags and composition time offset present
+                write_full_box(buf, b"trun", 0, 0x0F01, |buf| {
+                    buf.put_u32_be(track.samples.len() as u32);
+                    data_offset_positions.push(buf.len());
+                    buf.put_u32_be(0);
+
+                    for sample in track.samples {
+                        let flags = if sample.is_sync { SAMPLE_FLAGS_SYNC } else { SAMPLE_FLAGS_NON_SYNC };
+
+                        buf.put_u32_be(sample.duration);
+                        buf.put_u32_be(sample.data.len() as u32);
+                        buf.put_u32_be(flags);
+                        buf.put_u32_be(sample.composition_offset);
+                    }
+                });
+            });
+        }
+    });
+
+    // The media data of each track follows the previous one in the mdat box
+    let mut data_offset = buf.len() + 8;
+    let with_samples = tracks.iter().filter(|track| !track.samples.is_empty());
+
+    for (position, track) in data_offset_positions.into_iter().zip(with_samples) {
+        BigEndian::write_u32(&mut buf[position..position + 4], data_offset as u32);
+        data_offset += track.samples.iter().map(|sample| sample.data.len()).sum::<usize>();
+    }
+
+    write_box(&mut buf, b"mdat", |buf| {
+        for track in tracks {
+            for sample in track.samples {
+                buf.put_slice(&sample.data);
+            }
+        }
+    });
+
+    buf
+}
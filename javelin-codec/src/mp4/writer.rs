@@ -0,0 +1,268 @@
+use std::io::Write;
+use crate::{
+    avc,
+    aac,
+    Result,
+};
+use super::{
+    boxes::{self, Sample, Track, TrackFragment},
+    AudioTrack,
+    VideoTrack,
+    TIMESCALE,
+};
+
+
+/// Samples of a track that have not been written yet
+#[derive(Default)]
+struct Samples {
+    ready: Vec<Sample>,
+    /// The duration of the last sample is known once the next sample arrives
+    last: Option<Sample>,
+}
+
+impl Samples {
+    fn push(&mut self, sample: Sample) {
+        if let Some(mut last) = self.last.take() {
+            last.duration = sample.decode_time.saturating_sub(last.decode_time) as u32;
+            self.ready.push(last);
+        }
+
+        self.last = Some(sample);
+    }
+
+    /// Gives the last sample the same duration as the one before it.
+    fn close(&mut self) {
+        if let Some(mut last) = self.last.take() {
+            last.duration = self.ready.last().map_or(0, |sample| sample.duration);
+            self.ready.push(last);
+        }
+    }
+
+    fn duration(&self) -> u64 {
+        match (self.ready.first(), self.ready.last()) {
+            (Some(first), Some(last)) => last.decode_time + u64::from(last.duration) - first.decode_time,
+            _ => 0,
+        }
+    }
+}
+
+
+/// Writes a fragmented MP4 file.
+///
+/// The movie header is written up front and every GOP is written as its own
+/// fragment as soon as it is complete, so the file can be played while it is
+/// being written and stays playable if writing stops at any point.
+pub struct Writer<W> {
+    inner: W,
+    video: Option<VideoTrack>,
+    audio: Option<AudioTrack>,
+    video_samples: Samples,
+    audio_samples: Samples,
+    sequence_number: u32,
+    /// Timestamp of the first sample, the file starts at zero
+    base_timestamp: Option<u64>,
+}
+
+impl<W> Writer<W>
+    where W: Write
+{
+    const VIDEO_TRACK_ID: u32 = 1;
+    const AUDIO_TRACK_ID: u32 = 2;
+    /// Fragment duration of files without video, which have no keyframes to split at
+    const AUDIO_FRAGMENT_DURATION: u64 = 2 * TIMESCALE as u64;
+
+    /// Writes the file header describing the given tracks
+    pub fn new(mut inner: W, video: Option<VideoTrack>, audio: Option<AudioTrack>) -> Result<Self> {
+        let mut tracks = Vec::new();
+        if let Some(video) = &video {
+            tracks.push(Track::Video(Self::VIDEO_TRACK_ID, video));
+        }
+        if let Some(audio) = &audio {
+            tracks.push(Track::Audio(Self::AUDIO_TRACK_ID, audio));
+        }
+
+        inner.write_all(&boxes::init_segment(&tracks))?;
+        inner.flush()?;
+
+        Ok(Self {
+            inner,
+            video,
+            audio,
+            video_samples: Samples::default(),
+            audio_samples: Samples::default(),
+            sequence_number: 0,
+            base_timestamp: None,
+        })
+    }
+
+    fn decode_time(&mut self, timestamp: u64) -> u64 {
+        let base = *self.base_timestamp.get_or_insert(timestamp);
+        timestamp.saturating_sub(base)
+    }
+
+    /// Adds a video frame, returns the number of bytes written.
+    /// Every keyframe starts a new fragment.
+    pub fn write_video(&mut self, packet: &avc::Packet) -> Result<usize> {
+        if self.video.is_none() || packet.is_sequence_header() {
+            return Ok(0);
+        }
+
+        let sample = Sample {
+            data: packet.to_avcc_bytes(),
+            decode_time: self.decode_time(packet.timestamp()),
+            duration: 0,
+            composition_offset: packet.composition_time(),
+            is_sync: packet.is_keyframe(),
+        };
+
+        // Everything before the keyframe completes the fragment
+        let is_sync = sample.is_sync;
+        self.video_samples.push(sample);
+
+        if !is_sync {
+            return Ok(0);
+        }
+
+        self.write_fragment()
+    }
+
+    /// Adds an audio frame, returns the number of bytes written.
+    pub fn write_audio(&mut self, packet: &aac::Packet) -> Result<usize> {
+        if self.audio.is_none() || packet.is_sequence_header() {
+            return Ok(0);
+        }
+
+        let sample = Sample {
+            data: packet.payload(),
+            decode_time: self.decode_time(packet.presentation_timestamp()),
+            duration: 0,
+            composition_offset: 0,
+            is_sync: true,
+        };
+
+        self.audio_samples.push(sample);
+
+        if self.video.is_none() && self.audio_samples.duration() >= Self::AUDIO_FRAGMENT_DURATION {
+            return self.write_fragment();
+        }
+
+        Ok(0)
+    }
+
+    fn write_fragment(&mut self) -> Result<usize> {
+        if self.video_samples.ready.is_empty() && self.audio_samples.ready.is_empty() {
+            return Ok(0);
+        }
+
+        self.sequence_number += 1;
+
+        let tracks = [
+            TrackFragment { track_id: Self::VIDEO_TRACK_ID, samples: &self.video_samples.ready },
+            TrackFragment { track_id: Self::AUDIO_TRACK_ID, samples: &self.audio_samples.ready },
+        ];
+
+        let fragment = boxes::media_segment(self.sequence_number, &tracks);
+        self.inner.write_all(&fragment)?;
+        self.inner.flush()?;
+
+        self.video_samples.ready.clear();
+        self.audio_samples.ready.clear();
+
+        Ok(fragment.len())
+    }
+
+    /// Writes all remaining samples as the last fragment.
+    pub fn finish(mut self) -> Result<W> {
+        self.video_samples.close();
+        self.audio_samples.close();
+        self.write_fragment()?;
+        Ok(self.inner)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use byteorder::{ByteOrder, BigEndian};
+    use super::*;
+    use crate::SharedState;
+
+    const VIDEO_SEQ_HEADER: &[u8] = &[
+        0x17, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x42, 0xC0, 0x1E, 0xFF,
+        0xE1, 0x00, 0x05, 0x67, 0x42, 0xC0, 0x1E, 0x95,
+        0x01, 0x00, 0x04, 0x68, 0xCE, 0x3C, 0x80,
+    ];
+
+    const AUDIO_SEQ_HEADER: &[u8] = &[0xAF, 0x00, 0x12, 0x10];
+
+    fn video_frame(keyframe: bool) -> Bytes {
+        let (frame_type, nalu_type) = if keyframe { (0x17, 0x65) } else { (0x27, 0x41) };
+        Bytes::from(vec![
+            frame_type, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x06, nalu_type, 0x88, 0x84, 0x00, 0x33, 0xFF,
+        ])
+    }
+
+    /// Returns the type and content of all top level boxes
+    fn boxes(mut file: &[u8]) -> Vec<(String, &[u8])> {
+        let mut boxes = Vec::new();
+
+        while !file.is_empty() {
+            let size = BigEndian::read_u32(&file[0..4]) as usize;
+            let kind = String::from_utf8_lossy(&file[4..8]).to_string();
+            boxes.push((kind, &file[8..size]));
+            file = &file[size..];
+        }
+
+        boxes
+    }
+
+    #[test]
+    fn writes_a_fragment_per_gop() {
+        let shared = SharedState::new();
+        let seq_header = avc::Packet::try_from_buf(VIDEO_SEQ_HEADER, 0, &shared).unwrap();
+        aac::Packet::try_from_bytes(AUDIO_SEQ_HEADER, 0, &shared).unwrap();
+
+        let video = VideoTrack { dcr: shared.dcr.read().clone().unwrap(), width: 640, height: 360 };
+        let audio = AudioTrack { asc: shared.asc.read().clone().unwrap() };
+
+        let mut writer = Writer::new(Vec::new(), Some(video), Some(audio)).unwrap();
+        assert_eq!(writer.write_video(&seq_header).unwrap(), 0);
+
+        let mut written = 0;
+        for (timestamp, keyframe) in &[(0, true), (40, false), (80, true), (120, false)] {
+            let packet = avc::Packet::try_from_buf(video_frame(*keyframe), *timestamp, &shared).unwrap();
+            written += writer.write_video(&packet).unwrap();
+
+            let audio = aac::Packet::try_from_bytes(&[0xAF, 0x01, 0x21, 0x00][..], *timestamp, &shared).unwrap();
+            written += writer.write_audio(&audio).unwrap();
+        }
+
+        assert!(written > 0);
+
+        let file = writer.finish().unwrap();
+        let kinds: Vec<String> = boxes(&file).into_iter().map(|(kind, _)| kind).collect();
+
+        assert_eq!(kinds, vec!["ftyp", "moov", "moof", "mdat", "moof", "mdat"]);
+    }
+
+    #[test]
+    fn samples_are_written_with_length_prefix() {
+        let shared = SharedState::new();
+        avc::Packet::try_from_buf(VIDEO_SEQ_HEADER, 0, &shared).unwrap();
+
+        let video = VideoTrack { dcr: shared.dcr.read().clone().unwrap(), width: 640, height: 360 };
+        let mut writer = Writer::new(Vec::new(), Some(video), None).unwrap();
+
+        let packet = avc::Packet::try_from_buf(video_frame(true), 0, &shared).unwrap();
+        writer.write_video(&packet).unwrap();
+
+        let file = writer.finish().unwrap();
+        let boxes = boxes(&file);
+        let (_, mdat) = boxes.last().unwrap();
+
+        assert_eq!(*mdat, &[0x00, 0x00, 0x00, 0x06, 0x65, 0x88, 0x84, 0x00, 0x33, 0xFF][..]);
+    }
+}
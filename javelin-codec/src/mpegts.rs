@@ -0,0 +1,475 @@
+use std::collections::{HashMap, VecDeque};
+use bytes::{Bytes, BytesMut};
+use crate::{Error, Result};
+
+
+/// Elementary stream types of the program map table that can be demuxed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamType {
+    H264,
+    Aac,
+}
+
+impl StreamType {
+    pub fn try_from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x1B => Some(StreamType::H264),
+            0x0F => Some(StreamType::Aac),
+            _ => None,
+        }
+    }
+}
+
+
+/// A reassembled PES packet.
+///
+/// Timestamps are in units of 90 kHz and wrap around after 33 bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pes {
+    pub stream_type: StreamType,
+    pub pts: Option<u64>,
+    pub dts: Option<u64>,
+    pub data: Bytes,
+}
+
+
+struct PendingPes {
+    pts: Option<u64>,
+    dts: Option<u64>,
+    data: BytesMut,
+    /// Payload size announced in the header, streams of unbounded size end with the next packet
+    expected_size: Option<usize>,
+}
+
+struct ElementaryStream {
+    stream_type: StreamType,
+    continuity_counter: Option<u8>,
+    pending: Option<PendingPes>,
+}
+
+
+/// Extracts H.264 and AAC streams of an MPEG transport stream that arrives in chunks of any size.
+///
+/// Follows the program association and program map tables to find the elementary streams
+/// of the first program. Incomplete PES packets are dropped when packets are lost.
+#[derive(Default)]
+pub struct Demuxer {
+    buf: BytesMut,
+    pmt_pid: Option<u16>,
+    streams: HashMap<u16, ElementaryStream>,
+    ready: VecDeque<Pes>,
+}
+
+impl Demuxer {
+    const PACKET_SIZE: usize = 188;
+    const SYNC_BYTE: u8 = 0x47;
+    const PAT_PID: u16 = 0x0000;
+    const NULL_PID: u16 = 0x1FFF;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete PES packet, or `None` until more data was pushed.
+    pub fn next_pes(&mut self) -> Result<Option<Pes>> {
+        loop {
+            if let Some(pes) = self.ready.pop_front() {
+                return Ok(Some(pes));
+            }
+
+            // Skip garbage until the next packet starts
+            match self.buf.iter().position(|byte| *byte == Self::SYNC_BYTE) {
+                Some(offset) => self.buf.advance(offset),
+                None => self.buf.clear(),
+            }
+
+            if self.buf.len() < Self::PACKET_SIZE {
+                return Ok(None);
+            }
+
+            let packet = self.buf.split_to(Self::PACKET_SIZE).freeze();
+            self.read_packet(&packet)?;
+        }
+    }
+
+    /// Hands out the PES packets still being reassembled, once the stream ended.
+    pub fn flush(&mut self) -> Vec<Pes> {
+        let mut flushed: Vec<Pes> = self.ready.drain(..).collect();
+
+        for stream in self.streams.values_mut() {
+            if let Some(pending) = stream.pending.take() {
+                flushed.push(finish_pes(stream.stream_type, pending));
+            }
+        }
+
+        flushed
+    }
+
+    fn read_packet(&mut self, packet: &[u8]) -> Result<()> {
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = (u16::from(packet[1] & 0x1F) << 8) | u16::from(packet[2]);
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+        let continuity_counter = packet[3] & 0x0F;
+
+        if pid == Self::NULL_PID || adaptation_field_control & 0x01 == 0 {
+            return Ok(());
+        }
+
+        let mut offset = 4;
+        if adaptation_field_control & 0x02 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+
+        if offset >= Self::PACKET_SIZE {
+            return Err(Error::ParseError("Invalid MPEG-TS adaptation field length".into()));
+        }
+
+        let payload = &packet[offset..];
+
+        if pid == Self::PAT_PID {
+            if unit_start {
+                self.read_pat(section(payload)?)?;
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if unit_start {
+                self.read_pmt(section(payload)?)?;
+            }
+        } else if let Some(stream) = self.streams.get_mut(&pid) {
+            // A repeated counter marks a duplicate packet (ISO 13818-1 2.4.3.3)
+            if stream.continuity_counter == Some(continuity_counter) {
+                return Ok(());
+            }
+
+            // Lost packets leave the PES packet incomplete
+            let expected_counter = stream.continuity_counter.map(|counter| (counter + 1) & 0x0F);
+            if expected_counter.is_some() && expected_counter != Some(continuity_counter) {
+                stream.pending = None;
+            }
+            stream.continuity_counter = Some(continuity_counter);
+
+            if unit_start {
+                if let Some(pending) = stream.pending.take() {
+                    self.ready.push_back(finish_pes(stream.stream_type, pending));
+                }
+                stream.pending = Some(read_pes_header(payload)?);
+            } else if let Some(pending) = stream.pending.as_mut() {
+                pending.data.extend_from_slice(payload);
+            }
+
+            let complete = stream.pending.as_ref()
+                .and_then(|pending| pending.expected_size.map(|size| pending.data.len() >= size))
+                .unwrap_or(false);
+
+            if complete {
+                let pending = stream.pending.take().expect("BUG: pending PES missing");
+                self.ready.push_back(finish_pes(stream.stream_type, pending));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bits | Description
+    /// ---- | -----------
+    /// 16   | Program number
+    /// 3    | Reserved
+    /// 13   | Program map PID
+    fn read_pat(&mut self, section: &[u8]) -> Result<()> {
+        let entries = section.get(5..).unwrap_or_default();
+
+        let pmt_pid = entries.chunks(4)
+            .filter(|entry| entry.len() == 4)
+            .map(|entry| (u16::from(entry[0]) << 8 | u16::from(entry[1]), u16::from(entry[2] & 0x1F) << 8 | u16::from(entry[3])))
+            // Program 0 refers to the network information table
+            .find(|(program_number, _)| *program_number != 0)
+            .map(|(_, pid)| pid);
+
+        if pmt_pid.is_some() {
+            self.pmt_pid = pmt_pid;
+        }
+
+        Ok(())
+    }
+
+    /// Bits | Description
+    /// ---- | -----------
+    /// 8    | Stream type
+    /// 3    | Reserved
+    /// 13   | Elementary PID
+    /// 4    | Reserved
+    /// 12   | Descriptors length
+    /// var  | Descriptors
+    fn read_pmt(&mut self, section: &[u8]) -> Result<()> {
+        if section.len() < 9 {
+            return Err(Error::ParseError("MPEG-TS program map table is too short".into()));
+        }
+
+        let program_info_length = usize::from(section[7] & 0x0F) << 8 | usize::from(section[8]);
+        let mut entries = section.get(9 + program_info_length..).unwrap_or_default();
+
+        while entries.len() >= 5 {
+            let stream_type = entries[0];
+            let pid = u16::from(entries[1] & 0x1F) << 8 | u16::from(entries[2]);
+            let descriptors_length = usize::from(entries[3] & 0x0F) << 8 | usize::from(entries[4]);
+            entries = entries.get(5 + descriptors_length..).unwrap_or_default();
+
+            if let Some(stream_type) = StreamType::try_from_u8(stream_type) {
+                let known = self.streams.get(&pid).map(|stream| stream.stream_type) == Some(stream_type);
+                if !known {
+                    self.streams.insert(pid, ElementaryStream { stream_type, continuity_counter: None, pending: None });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/// The section of a table that starts in the payload, without its header and CRC.
+/// Only sections that fit in a single packet are supported.
+fn section(payload: &[u8]) -> Result<&[u8]> {
+    let pointer = *payload.first().ok_or_else(|| Error::ParseError("MPEG-TS table is empty".into()))? as usize;
+    let section = payload.get(1 + pointer..).unwrap_or_default();
+
+    if section.len() < 3 {
+        return Err(Error::ParseError("MPEG-TS table is too short".into()));
+    }
+
+    let section_length = usize::from(section[1] & 0x0F) << 8 | usize::from(section[2]);
+    if section_length < 4 || section.len() < 3 + section_length {
+        return Err(Error::ParseError("MPEG-TS tables spanning multiple packets are not supported".into()));
+    }
+
+    Ok(&section[3..3 + section_length - 4])
+}
+
+/// Bits | Description
+/// ---- | -----------
+/// 24   | Start code prefix (0x000001)
+/// 8    | Stream ID
+/// 16   | PES packet length
+/// 8    | Flags
+/// 2    | PTS/DTS flags
+/// 6    | Flags
+/// 8    | Header data length
+/// var  | PTS, DTS and optional fields
+fn read_pes_header(payload: &[u8]) -> Result<PendingPes> {
+    if payload.len() < 9 || payload[..3] != [0x00, 0x00, 0x01] {
+        return Err(Error::ParseError("Invalid PES start code".into()));
+    }
+
+    let packet_length = usize::from(payload[4]) << 8 | usize::from(payload[5]);
+    let pts_dts_flags = payload[7] >> 6;
+    let header_data_length = payload[8] as usize;
+
+    if payload.len() < 9 + header_data_length {
+        return Err(Error::ParseError("Invalid PES header length".into()));
+    }
+
+    let header = &payload[9..9 + header_data_length];
+    let pts = if pts_dts_flags & 0x02 != 0 { Some(read_timestamp(header.get(..5))?) } else { None };
+    let dts = if pts_dts_flags == 0x03 { Some(read_timestamp(header.get(5..10))?) } else { None };
+
+    Ok(PendingPes {
+        pts,
+        dts,
+        data: BytesMut::from(&payload[9 + header_data_length..]),
+        expected_size: packet_length.checked_sub(3 + header_data_length).filter(|_| packet_length != 0),
+    })
+}
+
+/// 33 bit timestamp, spread over 5 bytes with marker bits
+fn read_timestamp(bytes: Option<&[u8]>) -> Result<u64> {
+    let bytes = bytes.ok_or_else(|| Error::ParseError("PES header is too short for its timestamps".into()))?;
+
+    Ok(
+        u64::from(bytes[0] >> 1 & 0x07) << 30
+            | u64::from(bytes[1]) << 22
+            | u64::from(bytes[2] >> 1) << 15
+            | u64::from(bytes[3]) << 7
+            | u64::from(bytes[4] >> 1)
+    )
+}
+
+fn finish_pes(stream_type: StreamType, mut pending: PendingPes) -> Pes {
+    if let Some(size) = pending.expected_size {
+        pending.data.truncate(size);
+    }
+
+    Pes {
+        stream_type,
+        pts: pending.pts,
+        dts: pending.dts.or(pending.pts),
+        data: pending.data.freeze(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(pid: u16, unit_start: bool, counter: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![
+            Demuxer::SYNC_BYTE,
+            (if unit_start { 0x40 } else { 0x00 }) | (pid >> 8) as u8,
+            pid as u8,
+        ];
+
+        let stuffing = Demuxer::PACKET_SIZE - 4 - payload.len();
+        if stuffing > 0 {
+            packet.push(0x30 | counter);
+            packet.push((stuffing - 1) as u8);
+            if stuffing > 1 {
+                packet.push(0x00);
+                packet.resize(packet.len() + stuffing - 2, 0xFF);
+            }
+        } else {
+            packet.push(0x10 | counter);
+        }
+
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn pat() -> Vec<u8> {
+        packet(0, true, 0, &[
+            0x00, // pointer
+            0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00,
+            0x00, 0x01, 0xF0, 0x00, // program 1, PMT PID 0x1000
+            0x00, 0x00, 0x00, 0x00, // CRC
+        ])
+    }
+
+    fn pmt() -> Vec<u8> {
+        packet(0x1000, true, 0, &[
+            0x00, // pointer
+            0x02, 0xB0, 0x17, 0x00, 0x01, 0xC1, 0x00, 0x00,
+            0xE1, 0x00, 0xF0, 0x00,
+            0x1B, 0xE1, 0x00, 0xF0, 0x00, // H.264 on 0x100
+            0x0F, 0xE1, 0x01, 0xF0, 0x00, // AAC on 0x101
+            0x00, 0x00, 0x00, 0x00, // CRC
+        ])
+    }
+
+    fn pes_header(stream_id: u8, packet_length: u16, pts: u64) -> Vec<u8> {
+        vec![
+            0x00, 0x00, 0x01, stream_id, (packet_length >> 8) as u8, packet_length as u8,
+            0x80, 0x80, 0x05,
+            0x21 | ((pts >> 29) & 0x0E) as u8,
+            (pts >> 22) as u8,
+            0x01 | ((pts >> 14) & 0xFE) as u8,
+            (pts >> 7) as u8,
+            0x01 | ((pts << 1) & 0xFE) as u8,
+        ]
+    }
+
+    fn demux(stream: &[u8]) -> (Demuxer, Vec<Pes>) {
+        let mut demuxer = Demuxer::new();
+        let mut packets = Vec::new();
+
+        for chunk in stream.chunks(100) {
+            demuxer.push(chunk);
+            while let Some(pes) = demuxer.next_pes().unwrap() {
+                packets.push(pes);
+            }
+        }
+
+        (demuxer, packets)
+    }
+
+    #[test]
+    fn reassembles_pes_packets() {
+        let mut video = pes_header(0xE0, 0, 0x1_2345_6789);
+        video.extend(vec![0xAB; 200]);
+
+        let mut audio = pes_header(0xC0, 3 + 5 + 4, 9000);
+        audio.extend_from_slice(&[0xFF, 0xF1, 0x50, 0x80]);
+
+        let mut stream = pat();
+        stream.extend(pmt());
+        stream.extend(packet(0x100, true, 0, &video[..184]));
+        stream.extend(packet(0x101, true, 0, &audio));
+        stream.extend(packet(0x100, false, 1, &video[184..]));
+
+        let (mut demuxer, packets) = demux(&stream);
+
+        assert_eq!(packets, vec![Pes {
+            stream_type: StreamType::Aac,
+            pts: Some(9000),
+            dts: Some(9000),
+            data: Bytes::from_static(&[0xFF, 0xF1, 0x50, 0x80]),
+        }]);
+
+        let flushed = demuxer.flush();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].stream_type, StreamType::H264);
+        assert_eq!(flushed[0].pts, Some(0x1_2345_6789));
+        assert_eq!(flushed[0].data, Bytes::from(vec![0xAB; 200]));
+    }
+
+    #[test]
+    fn drops_pes_packets_with_lost_packets() {
+        let mut video = pes_header(0xE0, 0, 0);
+        video.extend(vec![0xAB; 400]);
+
+        let mut stream = pat();
+        stream.extend(pmt());
+        stream.extend(packet(0x100, true, 0, &video[..184]));
+        stream.extend(packet(0x100, false, 2, &video[368..]));
+
+        let (mut demuxer, packets) = demux(&stream);
+
+        assert!(packets.is_empty());
+        assert!(demuxer.flush().is_empty());
+    }
+
+    #[test]
+    fn drops_duplicate_packets() {
+        let mut video = pes_header(0xE0, 0, 0);
+        video.extend(vec![0xAB; 200]);
+
+        let mut stream = pat();
+        stream.extend(pmt());
+        stream.extend(packet(0x100, true, 0, &video[..184]));
+        stream.extend(packet(0x100, true, 0, &video[..184]));
+        stream.extend(packet(0x100, false, 1, &video[184..]));
+
+        let (mut demuxer, packets) = demux(&stream);
+
+        assert!(packets.is_empty());
+        let flushed = demuxer.flush();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].data, Bytes::from(vec![0xAB; 200]));
+    }
+
+    #[test]
+    fn rejects_truncated_pes_headers() {
+        // PTS and DTS announced without header data
+        assert!(read_pes_header(&[0x00, 0x00, 0x01, 0xE0, 0x00, 0x00, 0x80, 0xC0, 0x00]).is_err());
+
+        // Header data longer than the packet
+        let header = pes_header(0xE0, 0, 0);
+        assert!(read_pes_header(&header[..12]).is_err());
+    }
+
+    #[test]
+    fn ignores_streams_before_program_map_table() {
+        let mut audio = pes_header(0xC0, 0, 0);
+        audio.push(0xFF);
+
+        let mut stream = vec![0x00, 0x12];
+        stream.extend(packet(0x101, true, 0, &audio));
+        stream.extend(pat());
+
+        let (mut demuxer, packets) = demux(&stream);
+
+        assert!(packets.is_empty());
+        assert!(demuxer.flush().is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use clap::{
-    Arg, App, ArgMatches, AppSettings,
+    Arg, App, ArgMatches, AppSettings, SubCommand,
     crate_name,
     crate_version,
     crate_authors,
@@ -8,7 +8,17 @@ use clap::{
 
 
 pub fn build_args<'a>() -> ArgMatches<'a> {
-    let mut app = App::new(capitalize(crate_name!()))
+    app().get_matches()
+}
+
+/// Parses the given arguments instead of the ones of the process
+#[cfg(test)]
+pub fn build_args_from<'a>(args: &[&str]) -> ArgMatches<'a> {
+    app().get_matches_from(args)
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    let app = App::new(capitalize(crate_name!()))
         .version(crate_version!())
         .author(crate_authors!("\n"))
         .about(crate_description!())
@@ -38,14 +48,111 @@ pub fn build_args<'a>() -> ArgMatches<'a> {
             .multiple(true))
         .arg(Arg::with_name("republish_action")
             .long("republish-action")
-            .possible_values(&["replace", "deny"])
+            .possible_values(&["replace", "deny", "backup"])
             .default_value("replace")
             .help("The action to take when a republishing to the same application"))
+        .arg(Arg::with_name("publisher_grace_period")
+            .long("publisher-grace-period")
+            .value_name("SECONDS")
+            .default_value("0")
+            .help("Time a disconnected publisher has to reconnect before its stream ends"))
+        .arg(Arg::with_name("publisher_silence_timeout")
+            .long("publisher-silence-timeout")
+            .value_name("SECONDS")
+            .default_value("5")
+            .help("Time without media after which a publisher is replaced by its backup"))
+        .arg(Arg::with_name("max_watchers")
+            .long("max-watchers")
+            .value_name("COUNT")
+            .help("Maximum number of RTMP and FLV watchers per application"))
+        .arg(Arg::with_name("idle_watcher_timeout")
+            .long("idle-watcher-timeout")
+            .value_name("SECONDS")
+            .help("Disconnect watchers of an application that is not being published to after this time"))
+        .arg(Arg::with_name("drain_deadline")
+            .long("drain-deadline")
+            .value_name("SECONDS")
+            .default_value("10")
+            .help("Maximum time to wait for clients and HLS writers to finish on shutdown"))
+        .arg(Arg::with_name("fallback_sources")
+            .long("fallback")
+            .value_name("APP=PATH")
+            .help("Loop an FLV file on an application while nobody is publishing to it")
+            .multiple(true))
+        .arg(Arg::with_name("push_targets")
+            .long("push")
+            .value_name("APP=URL")
+            .help("Push the stream of an application to a remote RTMP server")
+            .multiple(true))
+        .arg(Arg::with_name("pull_sources")
+            .long("pull")
+            .value_name("APP=URL")
+            .help("Publish a stream of a remote RTMP server as an application")
+            .multiple(true))
+        .arg(Arg::with_name("ts_sources")
+            .long("ts")
+            .value_name("APP=URL")
+            .help("Publish an MPEG transport stream received on a udp:// (unicast or multicast) or tcp:// address as an application")
+            .multiple(true))
+        .arg(Arg::with_name("pull_on_demand")
+            .long("pull-on-demand")
+            .value_name("URL")
+            .help("Pull applications requested by watchers from a remote RTMP server, '{app}' in the URL is replaced by the application name"))
+        .arg(Arg::with_name("origins")
+            .long("origin")
+            .value_name("ADDRESS:PORT")
+            .help("Run as edge, pulling applications requested by watchers from the origin with this web API address")
+            .multiple(true))
+        .arg(Arg::with_name("record_apps")
+            .long("record")
+            .value_name("APP")
+            .help("Record every stream published to an application")
+            .multiple(true))
+        .arg(Arg::with_name("record_dir")
+            .long("record-dir")
+            .value_name("PATH")
+            .help("The directory where recordings will be placed"))
+        .arg(Arg::with_name("record_format")
+            .long("record-format")
+            .possible_values(&["flv", "mp4"])
+            .default_value("flv")
+            .help("Container of recordings, MP4 files are fragmented"))
+        .arg(Arg::with_name("record_filename_template")
+            .long("record-filename")
+            .value_name("TEMPLATE")
+            .default_value("{app}-{timestamp}")
+            .help("File name of recordings without extension, '{app}', '{key}' and '{timestamp}' are replaced"))
+        .arg(Arg::with_name("record_max_duration")
+            .long("record-max-duration")
+            .value_name("SECONDS")
+            .help("Continue recordings in a new file after this duration"))
+        .arg(Arg::with_name("record_max_size")
+            .long("record-max-size")
+            .value_name("MEGABYTES")
+            .help("Continue recordings in a new file after this size"))
+        .arg(Arg::with_name("on_record_done")
+            .long("on-record-done")
+            .value_name("COMMAND")
+            .help("Run this command with the path of every finished recording"))
         .arg(Arg::with_name("config_dir")
             .short("c")
             .long("config-dir")
             .value_name("PATH")
-            .help("The directory where all config files are located"));
+            .help("The directory where all config files are located"))
+        .subcommand(SubCommand::with_name("publish-file")
+            .about("Publishes an FLV file to an application in real time, as if it was streamed live")
+            .arg(Arg::with_name("path")
+                .value_name("PATH")
+                .required(true)
+                .help("The FLV file to publish"))
+            .arg(Arg::with_name("app")
+                .long("app")
+                .value_name("APP")
+                .required(true)
+                .help("The application to publish to"))
+            .arg(Arg::with_name("loop")
+                .long("loop")
+                .help("Start over at the end of the file instead of ending the stream")));
 
     let mut args = Vec::new();
 
@@ -79,6 +186,11 @@ pub fn build_args<'a>() -> ArgMatches<'a> {
             .value_name("PATH")
             .display_order(20)
             .help("The directory where stream output will be placed"));
+
+        args.push(Arg::with_name("hls_bitstream_keyframes")
+            .long("hls-bitstream-keyframes")
+            .display_order(20)
+            .help("Starts segments at IDR and recovery point frames of the bitstream instead of trusting the FLV frame type"));
     }
 
     if cfg!(feature = "tls") {
@@ -94,9 +206,29 @@ pub fn build_args<'a>() -> ArgMatches<'a> {
             .help("The TLS certificate to use"));
     }
 
-    app = app.args(&args);
+    if cfg!(feature = "srt") {
+        args.push(Arg::with_name("srt_port")
+            .long("srt-port")
+            .value_name("PORT")
+            .display_order(40)
+            .help("Enables the SRT listener on this port"));
+
+        args.push(Arg::with_name("srt_bind")
+            .long("srt-bind")
+            .value_name("ADDRESS")
+            .default_value("0.0.0.0")
+            .display_order(40)
+            .help("The SRT listener address"));
+
+        args.push(Arg::with_name("srt_latency")
+            .long("srt-latency")
+            .value_name("MILLISECONDS")
+            .default_value("120")
+            .display_order(40)
+            .help("The SRT receiver latency"));
+    }
 
-    app.get_matches()
+    app.args(&args)
 }
 
 fn capitalize(string: &str) -> String {
@@ -0,0 +1,876 @@
+mod failover;
+
+
+use std::{
+    mem,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use futures::Future;
+use tokio::{
+    executor::{DefaultExecutor, Executor},
+    timer::Delay,
+};
+use rml_rtmp::{
+    sessions::StreamMetadata,
+    time::RtmpTimestamp,
+};
+use chrono::prelude::{DateTime, Utc};
+use log::{info, warn, error};
+use crate::{
+    config::{RecordConfig, RecordFormat},
+    error::{Error, Result},
+    media::Media,
+    record::Recorder,
+    relay::{self, push, pull},
+    shared::Shared,
+    shutdown::Shutdown,
+    rtmp::{
+        peer,
+        fanout::{Watcher, Watchers, Fanout},
+    },
+};
+#[cfg(feature = "hls")]
+use crate::media;
+
+
+pub type Handle = Arc<Mutex<Channel>>;
+
+
+/// The client that is currently publishing to a channel
+pub struct Publisher {
+    pub id: u64,
+    stream_key: String,
+    sender: peer::Sender,
+}
+
+impl Publisher {
+    pub fn new(id: u64, stream_key: String, sender: peer::Sender) -> Self {
+        Self { id, stream_key, sender }
+    }
+
+    pub fn disconnect(&self) {
+        let _ = self.sender.unbounded_send(peer::Message::Disconnect);
+    }
+}
+
+
+/// Stream state of a source that is not on air yet
+#[derive(Default)]
+struct SourceState {
+    metadata: Option<StreamMetadata>,
+    video_seq_header: Option<Bytes>,
+    audio_seq_header: Option<Bytes>,
+}
+
+impl SourceState {
+    fn update(&mut self, media: &Media) {
+        match media {
+            Media::AAC(_, data) if media.is_sequence_header() => {
+                self.audio_seq_header = Some(data.clone());
+            },
+            Media::H264(_, data) | Media::H265(_, data) if media.is_sequence_header() => {
+                self.video_seq_header = Some(data.clone());
+            },
+            _ => (),
+        }
+    }
+}
+
+
+/// Keeps timestamps continuous when a stream is resumed by a new connection
+#[derive(Default)]
+struct Timeline {
+    offset: u32,
+    last: u32,
+    rebase: bool,
+}
+
+impl Timeline {
+    fn adjust(&mut self, timestamp: u32) -> u32 {
+        if self.rebase {
+            self.offset = self.last.wrapping_sub(timestamp);
+            self.rebase = false;
+        }
+
+        let adjusted = timestamp.wrapping_add(self.offset);
+        self.last = self.last.max(adjusted);
+        adjusted
+    }
+}
+
+
+/// State of a single application.
+///
+/// Every channel sits behind its own lock and is only reachable through
+/// the handles held by its publisher and watchers, so media of different
+/// channels is never routed through shared state.
+pub struct Channel {
+    pub app_name: String,
+    publisher: Option<Publisher>,
+    watchers: Watchers,
+    pub metadata: Option<StreamMetadata>,
+    pub video_seq_header: Option<Bytes>,
+    pub audio_seq_header: Option<Bytes>,
+    pub publish_start: Option<DateTime<Utc>>,
+    /// Stream key of a publisher that went away and may still resume the stream
+    suspended: Option<String>,
+    backup: Option<failover::Backup>,
+    /// Set when the publisher went away and the backup takes over at its next keyframe
+    failover: bool,
+    last_media: Option<Instant>,
+    /// Time without media after which a publisher is replaced by its backup
+    silence_timeout: Duration,
+    /// Set when a fallback source is configured for this channel
+    has_fallback: bool,
+    fallback_metadata: Option<StreamMetadata>,
+    /// Set while the fallback is on air, until the publisher sends its next keyframe
+    fallback_active: bool,
+    /// State of the publisher while the fallback is still on air
+    pending: SourceState,
+    /// Incremented on every change of the publisher, used to detect outdated timers
+    generation: u64,
+    timeline: Timeline,
+    grace_period: Duration,
+    idle_watcher_timeout: Option<Duration>,
+    max_watchers: Option<usize>,
+    shutdown: Shutdown,
+    this: Weak<Mutex<Channel>>,
+    push_targets: Vec<push::Target>,
+    pull: Option<pull::Target>,
+    /// Set while origins are asked for this application
+    locating: bool,
+    /// Set when every stream published to this channel is recorded
+    recording: bool,
+    record_config: RecordConfig,
+    recorder: Option<relay::Sender>,
+    #[cfg(feature = "hls")]
+    hls_writer: Option<media::Sender>,
+}
+
+impl Channel {
+    pub fn create(app_name: String, shared: &Shared) -> Handle {
+        let config = shared.config.read();
+        let push_urls = config.push_targets.get(&app_name).cloned().unwrap_or_default();
+        let record_config = config.record.clone();
+
+        let recording = record_config.apps.contains(&app_name);
+
+        let channel = Self {
+            app_name,
+            publisher: None,
+            watchers: Watchers::new(),
+            metadata: None,
+            video_seq_header: None,
+            audio_seq_header: None,
+            publish_start: None,
+            suspended: None,
+            backup: None,
+            failover: false,
+            last_media: None,
+            silence_timeout: config.publisher_silence_timeout,
+            has_fallback: false,
+            fallback_metadata: None,
+            fallback_active: false,
+            pending: SourceState::default(),
+            generation: 0,
+            timeline: Timeline::default(),
+            grace_period: config.publisher_grace_period,
+            idle_watcher_timeout: config.idle_watcher_timeout,
+            max_watchers: config.max_watchers,
+            shutdown: shared.shutdown.clone(),
+            this: Weak::new(),
+            push_targets: Vec::new(),
+            pull: None,
+            locating: false,
+            recording,
+            record_config,
+            recorder: None,
+            #[cfg(feature = "hls")]
+            hls_writer: None,
+        };
+        drop(config);
+
+        let handle = Arc::new(Mutex::new(channel));
+
+        {
+            let mut channel = handle.lock();
+            channel.this = Arc::downgrade(&handle);
+
+            for url in push_urls {
+                if let Err(why) = channel.add_push_target(&url) {
+                    error!("Failed to add push target for app '{}': {:?}", channel.app_name, why);
+                }
+            }
+        }
+
+        handle
+    }
+
+    pub fn publisher(&self) -> Option<&Publisher> {
+        self.publisher.as_ref()
+    }
+
+    #[allow(dead_code)]
+    pub fn has_publisher(&self) -> bool {
+        self.publisher.is_some()
+    }
+
+    pub fn is_publisher(&self, id: u64) -> bool {
+        self.publisher.as_ref().is_some_and(|p| p.id == id)
+    }
+
+    /// Whether the channel has a publisher or is waiting for one to resume or take over
+    pub fn is_live(&self) -> bool {
+        self.publisher.is_some() || self.suspended.is_some() || self.failover || self.fallback_active
+    }
+
+    /// Whether nothing depends on the channel anymore, so it can be removed until it is needed again
+    pub fn is_unused(&self) -> bool {
+        !self.is_live()
+            && self.backup.is_none()
+            && self.watchers.is_empty()
+            && self.pull.is_none()
+            && self.push_targets.is_empty()
+            && !self.recording
+            && !self.has_fallback
+            && !self.locating
+    }
+
+    /// Marks the channel as having a fallback source that keeps it on air without publisher.
+    pub fn enable_fallback(&mut self, metadata: Option<StreamMetadata>) {
+        self.has_fallback = true;
+        self.fallback_metadata = metadata;
+    }
+
+    /// Takes the fallback source off the channel, ending the stream if there is no publisher.
+    pub fn disable_fallback(&mut self) {
+        self.has_fallback = false;
+
+        if self.fallback_active && self.publisher.is_none() {
+            self.unpublish();
+        }
+
+        self.fallback_active = false;
+
+        #[cfg(feature = "hls")]
+        {
+            if !self.is_live() {
+                self.hls_writer = None;
+            }
+        }
+    }
+
+    /// Sets the publisher of the channel.
+    /// A publisher with the stream key of a suspended publisher resumes its stream.
+    pub fn set_publisher(&mut self, publisher: Publisher) {
+        // A publisher arriving while waiting for the backup to take over continues the stream as well
+        let resumed = self.failover || self.suspended.as_ref() == Some(&publisher.stream_key);
+
+        if resumed {
+            info!("Publisher resumed stream of app '{}'", self.app_name);
+            self.suspended = None;
+            self.failover = false;
+            self.timeline.rebase = true;
+
+            #[cfg(feature = "hls")]
+            self.send_to_hls_writer(media::Message::Discontinuity);
+        } else {
+            if self.suspended.is_some() {
+                self.unpublish();
+            }
+
+            self.publish_start = Some(Utc::now());
+
+            let fanout = self.watchers.all();
+            if let Err(why) = fanout.send_status("NetStream.Play.PublishNotify", "Stream is now published") {
+                error!("Failed to notify watchers of app '{}': {:?}", self.app_name, why);
+            }
+
+            if self.recording {
+                self.start_recorder(publisher.stream_key.clone());
+            }
+        }
+
+        self.publisher = Some(publisher);
+        self.generation += 1;
+    }
+
+    /// Removes a publisher that went away.
+    ///
+    /// With a grace period configured the channel, its watchers and the HLS playlist
+    /// stay intact for that long, so a publisher reconnecting with the same stream key
+    /// can resume the stream.
+    pub fn release_publisher(&mut self, publisher_id: u64) {
+        if self.is_backup(publisher_id) {
+            self.release_backup();
+            return;
+        }
+
+        if !self.is_publisher(publisher_id) {
+            return;
+        }
+
+        if self.backup.is_some() {
+            info!("Publisher of app '{}' went away, switching to backup", self.app_name);
+            self.fail_over();
+            return;
+        }
+
+        if self.grace_period == Duration::from_secs(0) {
+            self.unpublish();
+            return;
+        }
+
+        info!("Publisher of app '{}' went away, waiting {} seconds for it to resume",
+            self.app_name, self.grace_period.as_secs());
+
+        self.suspended = self.publisher.take().map(|p| p.stream_key);
+        self.generation += 1;
+
+        let generation = self.generation;
+        self.schedule(self.grace_period, move |channel| {
+            if channel.suspended.is_some() && channel.generation == generation {
+                info!("Publisher of app '{}' did not resume, ending stream", channel.app_name);
+                channel.unpublish();
+            }
+        });
+    }
+
+    pub fn unpublish(&mut self) {
+        if self.is_live() {
+            // With a fallback the watchers keep on receiving a stream
+            if !self.has_fallback {
+                let fanout = self.watchers.all();
+                if let Err(why) = fanout.send_status("NetStream.Play.UnpublishNotify", "Stream is now unpublished") {
+                    error!("Failed to notify watchers of app '{}': {:?}", self.app_name, why);
+                }
+                fanout.send_stream_eof();
+            }
+
+            self.watchers.reset();
+            self.generation += 1;
+            self.schedule_idle_timeout(self.watchers.peer_ids());
+        }
+
+        self.publisher = None;
+        self.suspended = None;
+        self.failover = false;
+        self.last_media = None;
+        self.pending = SourceState::default();
+        self.metadata = None;
+        self.video_seq_header = None;
+        self.audio_seq_header = None;
+
+        if let Some(backup) = self.backup.take() {
+            backup.publisher.disconnect();
+        }
+
+        // Dropping the sender finishes the recording
+        self.recorder = None;
+
+        // The fallback continues the timeline and keeps the HLS playlist and push targets going,
+        // otherwise dropping the sender finishes the HLS writer
+        if !self.has_fallback {
+            self.timeline = Timeline::default();
+            self.send_to_relays(relay::Message::End);
+
+            #[cfg(feature = "hls")]
+            {
+                self.hls_writer = None;
+            }
+        }
+    }
+
+    /// Disconnects the given watchers if the channel is still not live after the idle timeout.
+    fn schedule_idle_timeout(&mut self, peer_ids: Vec<u64>) {
+        let timeout = match self.idle_watcher_timeout {
+            Some(timeout) if !peer_ids.is_empty() && !self.has_fallback => timeout,
+            _ => return,
+        };
+
+        let generation = self.generation;
+        self.schedule(timeout, move |channel| {
+            if channel.is_live() || channel.has_fallback || channel.generation != generation {
+                return;
+            }
+
+            info!("Disconnecting idle watchers of app '{}'", channel.app_name);
+
+            for peer_id in peer_ids {
+                channel.watchers.disconnect(peer_id);
+            }
+        });
+    }
+
+    /// Runs `f` on the channel once the delay has passed or the server is shutting down.
+    fn schedule<F>(&mut self, delay: Duration, f: F)
+        where F: FnOnce(&mut Channel) + Send + 'static
+    {
+        let mut executor = DefaultExecutor::current();
+
+        if executor.status().is_err() {
+            // Only happens while the runtime is going away
+            f(self);
+            return;
+        }
+
+        let channel = self.this.clone();
+        let task = Delay::new(Instant::now() + delay)
+            .map_err(|why| error!("{:?}", why))
+            .select(self.shutdown.signal())
+            .then(move |_| {
+                if let Some(channel) = channel.upgrade() {
+                    f(&mut channel.lock());
+                }
+                Ok(())
+            });
+
+        if let Err(why) = executor.spawn(Box::new(task)) {
+            error!("Failed to schedule task for app '{}': {:?}", self.app_name, why);
+        }
+    }
+
+    #[cfg(feature = "hls")]
+    pub fn has_hls_writer(&self) -> bool {
+        self.hls_writer.is_some()
+    }
+
+    #[cfg(feature = "hls")]
+    pub fn set_hls_writer(&mut self, sender: media::Sender) {
+        self.hls_writer = Some(sender);
+    }
+
+    #[cfg(feature = "hls")]
+    fn send_to_hls_writer(&mut self, message: media::Message) {
+        if let Some(hls_writer) = &self.hls_writer {
+            if hls_writer.unbounded_send(message).is_err() {
+                warn!("HLS writer for app '{}' is gone", self.app_name);
+                self.hls_writer = None;
+            }
+        }
+    }
+
+    /// Starts pushing the stream to a remote server, immediately if the channel is live.
+    pub fn add_push_target(&mut self, url: &str) -> Result<()> {
+        let url: relay::Url = url.parse()?;
+
+        if self.push_targets.iter().any(|target| target.url == url) {
+            return Err(Error::from(format!("Already pushing to {}", url)));
+        }
+
+        let (push, target) = push::Push::create(self.app_name.clone(), url, self.shutdown.signal());
+
+        DefaultExecutor::current()
+            .spawn(Box::new(push))
+            .map_err(|why| Error::from(format!("Failed to spawn push: {:?}", why)))?;
+
+        if self.is_live() {
+            for message in self.stream_state() {
+                target.send(message);
+            }
+        }
+
+        info!("Added push target {} to app '{}'", target.url, self.app_name);
+        self.push_targets.push(target);
+
+        Ok(())
+    }
+
+    /// Stops pushing to a remote server, returns whether it was a push target.
+    #[allow(dead_code)]
+    pub fn remove_push_target(&mut self, url: &str) -> bool {
+        let count = self.push_targets.len();
+        self.push_targets.retain(|target| target.url.as_str() != url);
+        self.push_targets.len() != count
+    }
+
+    #[allow(dead_code)]
+    pub fn push_targets(&self) -> &[push::Target] {
+        &self.push_targets
+    }
+
+    /// Starts publishing a remote stream to this channel.
+    pub fn start_pull(&mut self, url: &str, on_demand: bool, shared: &Shared) -> Result<()> {
+        let url: relay::Url = url.parse()?;
+        let handle = self.this.upgrade().expect("BUG: channel handle missing");
+
+        let (pull, target) = pull::Pull::create(self.app_name.clone(), url, on_demand, handle, shared.clone());
+
+        DefaultExecutor::current()
+            .spawn(Box::new(pull))
+            .map_err(|why| Error::from(format!("Failed to spawn pull: {:?}", why)))?;
+
+        // Replacing a previous pull stops it
+        self.pull = Some(target);
+
+        Ok(())
+    }
+
+    /// Whether watchers wait for a stream only a pull could provide.
+    /// A publisher within its grace period, a backup taking over or the fallback keep the channel on air.
+    pub fn needs_pull(&self) -> bool {
+        !self.is_live() && self.pull.is_none()
+    }
+
+    #[allow(dead_code)]
+    pub fn pull(&self) -> Option<&pull::Target> {
+        self.pull.as_ref()
+    }
+
+    pub fn is_locating(&self) -> bool {
+        self.locating
+    }
+
+    pub fn set_locating(&mut self, locating: bool) {
+        self.locating = locating;
+    }
+
+    /// Records every stream published from now on, starting with the current one.
+    #[allow(dead_code)]
+    pub fn start_recording(&mut self) -> Result<()> {
+        if self.recording {
+            return Err(Error::from(format!("App '{}' is already being recorded", self.app_name)));
+        }
+
+        // MP4 recordings only hold H.264 video
+        if self.record_config.format == RecordFormat::Mp4 && self.has_h265_video() {
+            return Err(Error::UnsupportedRecording);
+        }
+
+        self.recording = true;
+
+        if self.is_live() {
+            let stream_key = self.publisher.as_ref()
+                .map(|publisher| publisher.stream_key.clone())
+                .or_else(|| self.suspended.clone())
+                .unwrap_or_default();
+
+            self.start_recorder(stream_key);
+        }
+
+        info!("Started recording app '{}'", self.app_name);
+
+        Ok(())
+    }
+
+    /// Stops recording, returns whether the channel was being recorded.
+    #[allow(dead_code)]
+    pub fn stop_recording(&mut self) -> bool {
+        if !self.recording {
+            return false;
+        }
+
+        info!("Stopped recording app '{}'", self.app_name);
+        self.recording = false;
+        self.recorder = None;
+
+        true
+    }
+
+    #[allow(dead_code)]
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    fn has_h265_video(&self) -> bool {
+        let timestamp = RtmpTimestamp::new(0);
+        let media = self.video_seq_header.clone().and_then(|data| Media::video(timestamp, data).ok());
+        matches!(media, Some(Media::H265(..)))
+    }
+
+    fn start_recorder(&mut self, stream_key: String) {
+        let token = self.shutdown.token();
+        let (recorder, sender) = Recorder::create(self.app_name.clone(), stream_key, self.record_config.clone(), token);
+
+        if let Err(why) = DefaultExecutor::current().spawn(Box::new(recorder)) {
+            error!("Failed to spawn recorder for app '{}': {:?}", self.app_name, why);
+            return;
+        }
+
+        for message in self.stream_state() {
+            let _ = sender.unbounded_send(message);
+        }
+
+        self.recorder = Some(sender);
+    }
+
+    /// Forwards the stream to push targets and the recorder.
+    fn send_to_relays(&mut self, message: relay::Message) {
+        if let Some(recorder) = &self.recorder {
+            if recorder.unbounded_send(message.clone()).is_err() {
+                warn!("Recorder for app '{}' is gone", self.app_name);
+                self.recorder = None;
+            }
+        }
+
+        let app_name = &self.app_name;
+        self.push_targets.retain(|target| {
+            let sent = target.send(message.clone());
+            if !sent {
+                warn!("Push to {} for app '{}' is gone", target.url, app_name);
+            }
+            sent
+        });
+    }
+
+    /// Everything required to start decoding the current stream
+    fn stream_state(&self) -> Vec<relay::Message> {
+        let timestamp = RtmpTimestamp::new(self.timeline.last);
+        let video_seq_header = self.video_seq_header.clone().and_then(|data| Media::video(timestamp, data).ok());
+        let audio_seq_header = self.audio_seq_header.clone().map(|data| Media::AAC(timestamp, data));
+
+        self.metadata.clone().map(relay::Message::Metadata).into_iter()
+            .chain(video_seq_header.map(relay::Message::Media))
+            .chain(audio_seq_header.map(relay::Message::Media))
+            .collect()
+    }
+
+    /// Adds a watcher and sends it everything required to start decoding.
+    /// RTMP and FLV watchers count towards the same limit.
+    pub fn add_watcher(&mut self, watcher: Watcher) -> Result<()> {
+        if self.max_watchers.is_some_and(|max| self.watchers.len() >= max) {
+            return Err(Error::WatcherLimitReached);
+        }
+
+        let fanout = Fanout::single(watcher.clone());
+
+        if let Some(ref metadata) = self.metadata {
+            fanout.send_metadata(metadata);
+        }
+
+        if let Some(ref v_seq_h) = self.video_seq_header {
+            if let Ok(media) = Media::video(RtmpTimestamp::new(0), v_seq_h.clone()) {
+                fanout.send_media(&media);
+            }
+        }
+
+        if let Some(ref a_seq_h) = self.audio_seq_header {
+            fanout.send_media(&Media::AAC(RtmpTimestamp::new(0), a_seq_h.clone()));
+        }
+
+        let peer_id = watcher.peer_id;
+        self.watchers.add(watcher);
+
+        if !self.is_live() {
+            self.schedule_idle_timeout(vec![peer_id]);
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_watcher(&mut self, watcher_id: u64) {
+        self.watchers.remove(watcher_id);
+
+        let on_demand = self.pull.as_ref().is_some_and(|pull| pull.on_demand);
+
+        if self.watchers.is_empty() && on_demand {
+            if let Some(pull) = self.pull.take() {
+                info!("Last watcher of app '{}' left, stopping pull from {}", self.app_name, pull.url);
+            }
+        }
+    }
+
+    pub fn watcher_count(&self) -> usize {
+        self.watchers.len()
+    }
+
+    /// Stores the metadata and returns all watchers that should receive it,
+    /// or `None` if the client is not the active publisher.
+    pub fn set_metadata(&mut self, publisher_id: u64, metadata: StreamMetadata) -> Option<Fanout> {
+        if let Some(backup) = self.backup.as_mut().filter(|b| b.publisher.id == publisher_id) {
+            backup.state.metadata = Some(metadata);
+            return None;
+        }
+
+        if !self.is_publisher(publisher_id) {
+            return None;
+        }
+
+        if self.fallback_active {
+            self.pending.metadata = Some(metadata);
+            return None;
+        }
+
+        self.send_to_relays(relay::Message::Metadata(metadata.clone()));
+        self.metadata = Some(metadata);
+        Some(self.watchers.all())
+    }
+
+    /// Puts a different source on air, starting with its decoder configuration.
+    fn switch_source(&mut self, state: SourceState) {
+        self.metadata = state.metadata;
+        self.video_seq_header = state.video_seq_header;
+        self.audio_seq_header = state.audio_seq_header;
+        self.timeline.rebase = true;
+
+        #[cfg(feature = "hls")]
+        self.send_to_hls_writer(media::Message::Discontinuity);
+
+        // Watchers and the HLS writer need the new decoder configuration before the first frame
+        let fanout = self.watchers.all();
+
+        if let Some(ref metadata) = self.metadata {
+            fanout.send_metadata(metadata);
+        }
+
+        let timestamp = RtmpTimestamp::new(self.timeline.last);
+        let seq_headers = vec![
+            self.video_seq_header.clone().and_then(|data| Media::video(timestamp, data).ok()),
+            self.audio_seq_header.clone().map(|data| Media::AAC(timestamp, data)),
+        ];
+
+        for seq_header in seq_headers.into_iter().flatten() {
+            fanout.send_media(&seq_header);
+
+            #[cfg(feature = "hls")]
+            self.send_to_hls_writer(media::Message::Media(seq_header));
+        }
+
+        for message in self.stream_state() {
+            self.send_to_relays(message);
+        }
+    }
+
+    /// Prepares media of the fallback source, returns `None` while the publisher is on air.
+    pub fn prepare_fallback_fanout(&mut self, media: &mut Media) -> Option<Fanout> {
+        if self.publisher.is_some() && !self.fallback_active {
+            return None;
+        }
+
+        if !self.fallback_active {
+            info!("Putting fallback of app '{}' on air", self.app_name);
+            self.fallback_active = true;
+            let state = SourceState { metadata: self.fallback_metadata.clone(), ..SourceState::default() };
+            self.switch_source(state);
+        }
+
+        Some(self.fanout(media))
+    }
+
+    /// Stores sequence headers, adjusts the timestamp of resumed streams,
+    /// forwards the media to the HLS writer and returns the watchers that should receive the media,
+    /// or `None` if the media does not come from the active publisher.
+    pub fn prepare_fanout(&mut self, publisher_id: u64, media: &mut Media) -> Option<Fanout> {
+        if self.is_backup(publisher_id) && !self.handle_backup_media(media) {
+            return None;
+        }
+
+        if !self.is_publisher(publisher_id) {
+            return None;
+        }
+
+        self.last_media = Some(Instant::now());
+
+        if self.fallback_active {
+            self.pending.update(media);
+
+            if !media.is_keyframe() {
+                return None;
+            }
+
+            info!("Taking fallback of app '{}' off air", self.app_name);
+            self.fallback_active = false;
+            let state = mem::take(&mut self.pending);
+            self.switch_source(state);
+        }
+
+        Some(self.fanout(media))
+    }
+
+    fn fanout(&mut self, media: &mut Media) -> Fanout {
+        let timestamp = self.timeline.adjust(media.timestamp());
+        media.set_timestamp(timestamp);
+
+        match &*media {
+            Media::AAC(_, data) if media.is_sequence_header() => {
+                self.audio_seq_header = Some(data.clone());
+            },
+            Media::H264(_, data) | Media::H265(_, data) if media.is_sequence_header() => {
+                self.video_seq_header = Some(data.clone());
+            },
+            _ => (),
+        }
+
+        #[cfg(feature = "hls")]
+        self.send_to_hls_writer(media::Message::Media(media.clone()));
+
+        self.send_to_relays(relay::Message::Media(media.clone()));
+
+        self.watchers.fanout_for(media)
+    }
+}
+
+
+/// Application names are used as directory names and in URL paths of origin lookups,
+/// so they are restricted to letters, digits, underscores and dashes.
+pub fn is_valid_app_name(app_name: &str) -> bool {
+    !app_name.is_empty()
+        && app_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::sync::mpsc;
+    use crate::config::Config;
+    use super::*;
+
+    #[test]
+    fn limits_watchers() {
+        let (shutdown, _) = Shutdown::new();
+        let shared = Shared::with_config(Config::from_args(&["javelin", "--max-watchers", "2"]), shutdown);
+        let channel = shared.channel_or_create("live");
+        let (sender, _receiver) = mpsc::unbounded();
+        let (media, _media_receiver) = peer::media_queue();
+        let mut channel = channel.lock();
+
+        assert!(channel.add_watcher(Watcher::new(1, 1, sender.clone(), media.clone())).is_ok());
+        assert!(channel.add_watcher(Watcher::flv(2, sender.clone(), media.clone())).is_ok());
+
+        match channel.add_watcher(Watcher::new(3, 1, sender.clone(), media.clone())) {
+            Err(Error::WatcherLimitReached) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        channel.remove_watcher(1);
+        assert!(channel.add_watcher(Watcher::new(3, 1, sender, media)).is_ok());
+    }
+
+    #[test]
+    fn refuses_mp4_recordings_of_h265() {
+        let (shutdown, _) = Shutdown::new();
+        let shared = Shared::with_config(Config::from_args(&["javelin", "--record-format", "mp4"]), shutdown);
+        let channel = shared.channel_or_create("live");
+        let mut channel = channel.lock();
+
+        channel.video_seq_header = Some(Bytes::from_static(&[0x90, b'h', b'v', b'c', b'1']));
+        match channel.start_recording() {
+            Err(Error::UnsupportedRecording) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        channel.video_seq_header = Some(Bytes::from_static(&[0x17, 0x00, 0x00, 0x00, 0x00]));
+        assert!(channel.start_recording().is_ok());
+    }
+
+    #[test]
+    fn accepts_plain_app_names() {
+        assert!(is_valid_app_name("live"));
+        assert!(is_valid_app_name("Stream_2-HD"));
+    }
+
+    #[test]
+    fn rejects_app_names_unsafe_for_paths_and_requests() {
+        assert!(!is_valid_app_name(""));
+        assert!(!is_valid_app_name(".."));
+        assert!(!is_valid_app_name("../etc"));
+        assert!(!is_valid_app_name("a\\b"));
+        assert!(!is_valid_app_name("live stream"));
+        assert!(!is_valid_app_name("live\r\nHost: evil"));
+        assert!(!is_valid_app_name("live%2F"));
+        assert!(!is_valid_app_name("straße"));
+    }
+}
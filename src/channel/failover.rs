@@ -0,0 +1,242 @@
+use log::{info, warn};
+use tokio::executor::{DefaultExecutor, Executor};
+use crate::media::Media;
+use super::{Channel, Publisher, SourceState};
+
+
+/// A hot standby publisher and the stream state it has sent so far
+pub(super) struct Backup {
+    pub(super) publisher: Publisher,
+    pub(super) state: SourceState,
+}
+
+
+impl Channel {
+    pub fn has_backup(&self) -> bool {
+        self.backup.is_some()
+    }
+
+    pub(super) fn is_backup(&self, id: u64) -> bool {
+        self.backup.as_ref().is_some_and(|b| b.publisher.id == id)
+    }
+
+    /// Sets the backup publisher, which takes over once the publisher disconnects
+    /// or has not sent any media for the silence timeout.
+    pub fn set_backup(&mut self, publisher: Publisher) {
+        info!("Client {} is now the backup publisher of app '{}'", publisher.id, self.app_name);
+        let backup_id = publisher.id;
+        self.backup = Some(Backup { publisher, state: SourceState::default() });
+        self.schedule_silence_check(backup_id);
+    }
+
+    pub(super) fn release_backup(&mut self) {
+        info!("Backup publisher of app '{}' went away", self.app_name);
+        self.backup = None;
+
+        if self.failover {
+            self.unpublish();
+        }
+    }
+
+    /// Takes the publisher off air, the backup takes over at its next keyframe.
+    pub(super) fn fail_over(&mut self) {
+        self.publisher = None;
+        self.failover = true;
+        self.generation += 1;
+    }
+
+    /// Checks the publisher for silence for as long as the backup is around.
+    ///
+    /// The check runs on a timer, so a silent publisher is noticed even while
+    /// the backup does not send keyframes.
+    fn schedule_silence_check(&mut self, backup_id: u64) {
+        // Without a runtime the check would run right away, over and over again
+        if DefaultExecutor::current().status().is_err() {
+            return;
+        }
+
+        let timeout = self.silence_timeout;
+        let delay = match (&self.publisher, self.last_media) {
+            (Some(_), Some(last)) => timeout.checked_sub(last.elapsed()).unwrap_or_default(),
+            _ => timeout,
+        };
+
+        self.schedule(delay, move |channel| {
+            // A missing token means the server is shutting down
+            if !channel.is_backup(backup_id) || channel.shutdown.token().is_none() {
+                return;
+            }
+
+            if !channel.is_publisher_silent() {
+                channel.schedule_silence_check(backup_id);
+                return;
+            }
+
+            if let Some(publisher) = channel.publisher.take() {
+                warn!("Publisher of app '{}' is silent, switching to backup", channel.app_name);
+                publisher.disconnect();
+                channel.fail_over();
+            }
+        });
+    }
+
+    /// Whether the publisher has sent media before but nothing within the silence timeout
+    fn is_publisher_silent(&self) -> bool {
+        self.publisher.is_some()
+            && self.last_media.is_some_and(|last| last.elapsed() >= self.silence_timeout)
+    }
+
+    /// Keeps the state of the backup up to date and lets it take over at a keyframe
+    /// once the publisher is gone. Returns whether the backup is now the publisher.
+    pub(super) fn handle_backup_media(&mut self, media: &Media) -> bool {
+        match self.backup.as_mut() {
+            Some(backup) => backup.state.update(media),
+            None => return false,
+        }
+
+        if !media.is_keyframe() || !self.failover {
+            return false;
+        }
+
+        let backup = self.backup.take().expect("BUG: backup missing");
+
+        info!("Backup publisher took over app '{}'", self.app_name);
+
+        self.publisher = Some(backup.publisher);
+        self.failover = false;
+        self.generation += 1;
+
+        // The fallback might have been on air while waiting for the backup
+        self.fallback_active = false;
+        self.pending = SourceState::default();
+
+        self.switch_source(backup.state);
+
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use bytes::Bytes;
+    use futures::{future, sync::mpsc, Stream};
+    use tokio::{runtime::current_thread::Runtime, timer::Delay};
+    use rml_rtmp::time::RtmpTimestamp;
+    use crate::{
+        config::Config,
+        rtmp::peer,
+        shared::Shared,
+        shutdown::Shutdown,
+    };
+    use super::*;
+
+    fn shared(args: &[&str]) -> Shared {
+        let (shutdown, _) = Shutdown::new();
+        Shared::with_config(Config::from_args(args), shutdown)
+    }
+
+    fn publisher(id: u64) -> (Publisher, mpsc::UnboundedReceiver<peer::Message>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (Publisher::new(id, "key".to_string(), sender), receiver)
+    }
+
+    fn keyframe() -> Media {
+        Media::H264(RtmpTimestamp::new(0), Bytes::from_static(&[0x17, 0x01, 0x00, 0x00, 0x00]))
+    }
+
+    fn inter_frame() -> Media {
+        Media::H264(RtmpTimestamp::new(0), Bytes::from_static(&[0x27, 0x01, 0x00, 0x00, 0x00]))
+    }
+
+    fn is_sent(channel: &mut Channel, publisher_id: u64, mut media: Media) -> bool {
+        channel.prepare_fanout(publisher_id, &mut media).is_some()
+    }
+
+    /// Runs `f` on the runtime and gives timers that are already due the chance to fire
+    fn run<F>(runtime: &mut Runtime, f: F) where F: FnOnce() {
+        runtime.block_on(future::lazy(|| -> std::result::Result<(), ()> { f(); Ok(()) })).unwrap();
+        runtime.block_on(Delay::new(Instant::now() + Duration::from_millis(20))).unwrap();
+    }
+
+    #[test]
+    fn switches_to_backup_on_silence() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin", "--publisher-silence-timeout", "1"]);
+        let handle = shared.channel_or_create("live");
+        let (primary, primary_receiver) = publisher(1);
+        let (backup, _backup_receiver) = publisher(2);
+
+        run(&mut runtime, || {
+            let mut channel = handle.lock();
+            channel.set_publisher(primary);
+            assert!(is_sent(&mut channel, 1, keyframe()));
+            channel.last_media = Some(Instant::now() - Duration::from_secs(2));
+            channel.set_backup(backup);
+        });
+
+        let mut channel = handle.lock();
+        assert!(channel.publisher().is_none());
+        assert!(channel.is_live());
+
+        match primary_receiver.wait().next() {
+            Some(Ok(peer::Message::Disconnect)) => (),
+            _ => panic!("Expected the silent publisher to be disconnected"),
+        }
+
+        // The backup only goes on air at a keyframe
+        assert!(!is_sent(&mut channel, 2, inter_frame()));
+        assert!(is_sent(&mut channel, 2, keyframe()));
+        assert!(channel.is_publisher(2));
+        assert!(!channel.has_backup());
+    }
+
+    #[test]
+    fn keeps_backup_waiting_while_publisher_sends() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin", "--publisher-silence-timeout", "10"]);
+        let handle = shared.channel_or_create("live");
+        let (primary, _primary_receiver) = publisher(1);
+        let (backup, _backup_receiver) = publisher(2);
+
+        run(&mut runtime, || {
+            let mut channel = handle.lock();
+            channel.set_publisher(primary);
+            assert!(is_sent(&mut channel, 1, keyframe()));
+            channel.set_backup(backup);
+        });
+
+        let mut channel = handle.lock();
+        assert!(channel.is_publisher(1));
+        assert!(!is_sent(&mut channel, 2, keyframe()));
+        assert!(channel.has_backup());
+    }
+
+    #[test]
+    fn switches_back_when_publisher_returns() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin"]);
+        let handle = shared.channel_or_create("live");
+        let (primary, _primary_receiver) = publisher(1);
+        let (backup, _backup_receiver) = publisher(2);
+        let (returned, _returned_receiver) = publisher(3);
+
+        run(&mut runtime, || {
+            let mut channel = handle.lock();
+            channel.set_publisher(primary);
+            channel.set_backup(backup);
+            channel.release_publisher(1);
+        });
+
+        let mut channel = handle.lock();
+        assert!(channel.is_live());
+
+        // Returning before the backup sent a keyframe continues the stream
+        channel.set_publisher(returned);
+        assert!(channel.is_publisher(3));
+        assert!(!is_sent(&mut channel, 2, keyframe()));
+        assert!(is_sent(&mut channel, 3, keyframe()));
+        assert!(channel.has_backup());
+    }
+}
@@ -1,16 +1,18 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     net::SocketAddr,
     str::FromStr,
     result,
     path::PathBuf,
+    time::Duration,
 };
 #[cfg(feature = "tls")]
 use std::{
     fs::File,
     io::Read,
-    env,
 };
+#[cfg(any(feature = "tls", feature = "srt"))]
+use std::env;
 use log::debug;
 use clap::ArgMatches;
 use crate::{args, Error};
@@ -22,6 +24,7 @@ use crate::error::Result;
 pub enum RepublishAction {
     Replace,
     Deny,
+    Backup,
 }
 
 impl FromStr for RepublishAction {
@@ -31,6 +34,7 @@ impl FromStr for RepublishAction {
         let action = match s {
             "replace" => RepublishAction::Replace,
             "deny" => RepublishAction::Deny,
+            "backup" => RepublishAction::Backup,
             _ => return Err(Error::from(format!("Failed to parse RepublishAction, '{}' not valid", s)))
         };
 
@@ -39,6 +43,36 @@ impl FromStr for RepublishAction {
 }
 
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    Flv,
+    Mp4,
+}
+
+impl RecordFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Flv => "flv",
+            RecordFormat::Mp4 => "mp4",
+        }
+    }
+}
+
+impl FromStr for RecordFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let format = match s {
+            "flv" => RecordFormat::Flv,
+            "mp4" => RecordFormat::Mp4,
+            _ => return Err(Error::from(format!("Failed to parse RecordFormat, '{}' not valid", s)))
+        };
+
+        Ok(format)
+    }
+}
+
+
 #[derive(Debug, Clone)]
 #[cfg(feature = "tls")]
 pub struct TlsConfig {
@@ -82,6 +116,8 @@ impl TlsConfig {
 pub struct HlsConfig {
     pub root_dir: PathBuf,
     pub enabled: bool,
+    /// Segment at the random access points found in the bitstream
+    pub bitstream_keyframes: bool,
 }
 
 #[cfg(feature = "hls")]
@@ -93,7 +129,9 @@ impl HlsConfig {
             .map(PathBuf::from)
             .unwrap_or_else(|| PathBuf::from("./tmp/stream"));
 
-        Self { root_dir, enabled }
+        let bitstream_keyframes = args.is_present("hls_bitstream_keyframes");
+
+        Self { root_dir, enabled, bitstream_keyframes }
     }
 }
 
@@ -119,24 +157,164 @@ impl WebConfig {
 }
 
 
+#[derive(Debug, Clone)]
+#[cfg(feature = "srt")]
+pub struct SrtConfig {
+    pub addr: SocketAddr,
+    pub passphrase: Option<String>,
+    pub latency: Duration,
+    pub enabled: bool,
+}
+
+#[cfg(feature = "srt")]
+impl SrtConfig {
+    pub fn new(args: &ArgMatches) -> Self {
+        let enabled = args.is_present("srt_port");
+
+        let host = args.value_of("srt_bind").expect("BUG: default value for 'srt_bind' missing");
+        let port = args.value_of("srt_port").unwrap_or("0");
+        let addr = format!("{}:{}", host, port).parse().expect("Invalid address or port name for SRT listener");
+
+        let latency = args.value_of("srt_latency").expect("BUG: default value for 'srt_latency' missing");
+        let latency = Self::parse_latency(latency).expect("Invalid SRT latency");
+
+        let passphrase = Self::passphrase();
+
+        Self { addr, passphrase, latency, enabled }
+    }
+
+    /// Callers have to encrypt with this passphrase, if set
+    fn passphrase() -> Option<String> {
+        let passphrase = env::var("JAVELIN_SRT_PASSPHRASE").ok()?;
+
+        if !Self::is_valid_passphrase(&passphrase) {
+            panic!("SRT passphrase has to be between 10 and 79 characters long");
+        }
+
+        Some(passphrase)
+    }
+
+    fn is_valid_passphrase(passphrase: &str) -> bool {
+        (10..=79).contains(&passphrase.len())
+    }
+
+    /// Milliseconds, libsrt takes them as a C int
+    fn parse_latency(latency: &str) -> Option<Duration> {
+        latency.parse::<u32>().ok()
+            .filter(|latency| *latency <= i32::MAX as u32)
+            .map(|latency| Duration::from_millis(u64::from(latency)))
+    }
+}
+
+
+/// A file published to an application, see `FileSource`
+#[derive(Debug, Clone)]
+pub struct FileSourceConfig {
+    pub path: PathBuf,
+    pub looped: bool,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct RecordConfig {
+    pub apps: HashSet<String>,
+    pub dir: PathBuf,
+    pub format: RecordFormat,
+    pub filename_template: String,
+    pub max_duration: Option<Duration>,
+    pub max_size: Option<u64>,
+    pub on_record_done: Option<String>,
+}
+
+impl RecordConfig {
+    pub fn new(args: &ArgMatches) -> Self {
+        let apps = args
+            .values_of("record_apps")
+            .unwrap_or_default()
+            .map(str::to_string)
+            .collect();
+
+        let dir = args.value_of("record_dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("./recordings"));
+
+        let format = args
+            .value_of("record_format")
+            .expect("BUG: default value for 'record_format' missing")
+            .parse()
+            .unwrap(); // this should be safe to unwrap
+
+        let filename_template = args
+            .value_of("record_filename_template")
+            .expect("BUG: default value for 'record_filename_template' missing")
+            .to_string();
+
+        let max_duration = args
+            .value_of("record_max_duration")
+            .map(|v| v.parse().map(Duration::from_secs).expect("Invalid maximum recording duration"));
+
+        let max_size = args
+            .value_of("record_max_size")
+            .map(|v| v.parse::<u64>().map(|mb| mb * 1024 * 1024).expect("Invalid maximum recording size"));
+
+        let on_record_done = args.value_of("on_record_done").map(str::to_string);
+
+        Self { apps, dir, format, filename_template, max_duration, max_size, on_record_done }
+    }
+}
+
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub addr: SocketAddr,
     pub permitted_stream_keys: HashSet<String>,
     pub republish_action: RepublishAction,
+    pub publisher_grace_period: Duration,
+    pub publisher_silence_timeout: Duration,
+    pub idle_watcher_timeout: Option<Duration>,
+    pub max_watchers: Option<usize>,
+    pub drain_deadline: Duration,
+    pub fallback_sources: HashMap<String, PathBuf>,
+    pub push_targets: HashMap<String, Vec<String>>,
+    pub pull_sources: HashMap<String, String>,
+    pub pull_on_demand: Option<String>,
+    pub origins: Vec<String>,
+    pub file_sources: HashMap<String, FileSourceConfig>,
+    pub ts_sources: HashMap<String, String>,
+    pub record: RecordConfig,
     #[cfg(feature = "tls")]
     pub tls: TlsConfig,
     #[cfg(feature = "hls")]
     pub hls: HlsConfig,
     #[cfg(feature = "web")]
     pub web: WebConfig,
+    #[cfg(feature = "srt")]
+    pub srt: SrtConfig,
 }
 
 impl Config {
     pub fn new() -> Self {
-        let matches = args::build_args();
+        Self::from_matches(&args::build_args())
+    }
 
-        let permitted_stream_keys = load_permitted_stream_keys(&matches);
+    #[cfg(test)]
+    pub fn from_args(args: &[&str]) -> Self {
+        Self::from_matches(&args::build_args_from(args))
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        let permitted_stream_keys = load_permitted_stream_keys(matches);
+        let fallback_sources = load_fallback_sources(matches);
+        let push_targets = load_push_targets(matches);
+        let pull_sources = load_pull_sources(matches);
+        let pull_on_demand = matches.value_of("pull_on_demand").map(str::to_string);
+        let file_sources = load_file_sources(matches);
+        let ts_sources = load_ts_sources(matches);
+        let origins = matches
+            .values_of("origins")
+            .unwrap_or_default()
+            .map(str::to_string)
+            .collect();
 
         let host = matches.value_of("bind").expect("BUG: default value for 'bind' missing");
         let port = matches.value_of("port").expect("BUG: default value for 'port' missing");
@@ -148,16 +326,62 @@ impl Config {
             .parse()
             .unwrap(); // this should be safe to unwrap
 
+        let publisher_grace_period = matches
+            .value_of("publisher_grace_period")
+            .expect("BUG: default value for 'publisher_grace_period' missing")
+            .parse()
+            .map(Duration::from_secs)
+            .expect("Invalid publisher grace period");
+
+        let publisher_silence_timeout = matches
+            .value_of("publisher_silence_timeout")
+            .expect("BUG: default value for 'publisher_silence_timeout' missing")
+            .parse()
+            .ok()
+            .filter(|seconds| *seconds > 0)
+            .map(Duration::from_secs)
+            .expect("Invalid publisher silence timeout");
+
+        let idle_watcher_timeout = matches
+            .value_of("idle_watcher_timeout")
+            .map(|v| v.parse().map(Duration::from_secs).expect("Invalid idle watcher timeout"));
+
+        let max_watchers = matches
+            .value_of("max_watchers")
+            .map(|v| v.parse().expect("Invalid maximum number of watchers"));
+
+        let drain_deadline = matches
+            .value_of("drain_deadline")
+            .expect("BUG: default value for 'drain_deadline' missing")
+            .parse()
+            .map(Duration::from_secs)
+            .expect("Invalid drain deadline");
+
         Self {
             addr,
             permitted_stream_keys,
             republish_action,
+            publisher_grace_period,
+            publisher_silence_timeout,
+            idle_watcher_timeout,
+            max_watchers,
+            drain_deadline,
+            fallback_sources,
+            push_targets,
+            pull_sources,
+            pull_on_demand,
+            origins,
+            file_sources,
+            ts_sources,
+            record: RecordConfig::new(matches),
             #[cfg(feature = "tls")]
-            tls: TlsConfig::new(&matches),
+            tls: TlsConfig::new(matches),
             #[cfg(feature = "hls")]
-            hls: HlsConfig::new(&matches),
+            hls: HlsConfig::new(matches),
             #[cfg(feature = "web")]
-            web: WebConfig::new(&matches),
+            web: WebConfig::new(matches),
+            #[cfg(feature = "srt")]
+            srt: SrtConfig::new(matches),
         }
     }
 }
@@ -165,10 +389,7 @@ impl Config {
 /// Loads all stream keys from the configuration file and then from command line arguments.
 /// Every key is only included once, even if they are specified multiple times.
 fn load_permitted_stream_keys(args: &ArgMatches) -> HashSet<String> {
-    let config_dir = args.value_of("config_dir")
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from("./config"));
-    let keys_file = config_dir.join("permitted_stream_keys.yml");
+    let keys_file = config_dir(args).join("permitted_stream_keys.yml");
     let mut permitted_stream_keys: HashSet<String> = HashSet::new();
 
     if keys_file.exists() {
@@ -190,3 +411,193 @@ fn load_permitted_stream_keys(args: &ArgMatches) -> HashSet<String> {
 
     permitted_stream_keys
 }
+
+/// Loads fallback files per application from the configuration file and then from command line arguments.
+/// Command line arguments take precedence over the configuration file.
+fn load_fallback_sources(args: &ArgMatches) -> HashMap<String, PathBuf> {
+    let sources_file = config_dir(args).join("fallback_sources.yml");
+    let mut fallback_sources: HashMap<String, PathBuf> = HashMap::new();
+
+    if sources_file.exists() {
+        debug!("Loading fallback sources from configuration file");
+        if let Ok(file) = std::fs::File::open(&sources_file) {
+            let sources: HashMap<String, PathBuf> = serde_yaml::from_reader(file)
+                .expect("Failed to read fallback sources from config file");
+            fallback_sources.extend(sources);
+        }
+    }
+
+    let sources = args
+        .values_of("fallback_sources")
+        .unwrap_or_default()
+        .map(|value| {
+            let (app_name, path) = split_app_assignment(value);
+            (app_name, PathBuf::from(path))
+        });
+
+    fallback_sources.extend(sources);
+
+    fallback_sources
+}
+
+/// Loads remote URLs per application from the configuration file and then from command line arguments.
+fn load_push_targets(args: &ArgMatches) -> HashMap<String, Vec<String>> {
+    let targets_file = config_dir(args).join("push_targets.yml");
+    let mut push_targets: HashMap<String, Vec<String>> = HashMap::new();
+
+    if targets_file.exists() {
+        debug!("Loading push targets from configuration file");
+        if let Ok(file) = std::fs::File::open(&targets_file) {
+            let targets: HashMap<String, Vec<String>> = serde_yaml::from_reader(file)
+                .expect("Failed to read push targets from config file");
+            push_targets.extend(targets);
+        }
+    }
+
+    for value in args.values_of("push_targets").unwrap_or_default() {
+        let (app_name, url) = split_app_assignment(value);
+        let urls = push_targets.entry(app_name).or_default();
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    push_targets
+}
+
+/// Loads remote sources per application from the configuration file and then from command line arguments.
+/// Command line arguments take precedence over the configuration file.
+fn load_pull_sources(args: &ArgMatches) -> HashMap<String, String> {
+    let sources_file = config_dir(args).join("pull_sources.yml");
+    let mut pull_sources: HashMap<String, String> = HashMap::new();
+
+    if sources_file.exists() {
+        debug!("Loading pull sources from configuration file");
+        if let Ok(file) = std::fs::File::open(&sources_file) {
+            let sources: HashMap<String, String> = serde_yaml::from_reader(file)
+                .expect("Failed to read pull sources from config file");
+            pull_sources.extend(sources);
+        }
+    }
+
+    let sources = args
+        .values_of("pull_sources")
+        .unwrap_or_default()
+        .map(split_app_assignment);
+
+    pull_sources.extend(sources);
+
+    pull_sources
+}
+
+/// Loads addresses to receive MPEG transport streams on per application from the configuration file
+/// and then from command line arguments. Command line arguments take precedence over the configuration file.
+fn load_ts_sources(args: &ArgMatches) -> HashMap<String, String> {
+    let sources_file = config_dir(args).join("ts_sources.yml");
+    let mut ts_sources: HashMap<String, String> = HashMap::new();
+
+    if sources_file.exists() {
+        debug!("Loading transport stream sources from configuration file");
+        if let Ok(file) = std::fs::File::open(&sources_file) {
+            let sources: HashMap<String, String> = serde_yaml::from_reader(file)
+                .expect("Failed to read transport stream sources from config file");
+            ts_sources.extend(sources);
+        }
+    }
+
+    let sources = args
+        .values_of("ts_sources")
+        .unwrap_or_default()
+        .map(split_app_assignment);
+
+    ts_sources.extend(sources);
+
+    ts_sources
+}
+
+/// Loads files to publish per application from the configuration file and then from the
+/// `publish-file` command. Entries are either a path, or a `path` and whether to `loop` the file.
+fn load_file_sources(args: &ArgMatches) -> HashMap<String, FileSourceConfig> {
+    let sources_file = config_dir(args).join("file_sources.yml");
+    let mut file_sources: HashMap<String, FileSourceConfig> = HashMap::new();
+
+    if sources_file.exists() {
+        debug!("Loading file sources from configuration file");
+        if let Ok(file) = std::fs::File::open(&sources_file) {
+            let sources: HashMap<String, serde_yaml::Value> = serde_yaml::from_reader(file)
+                .expect("Failed to read file sources from config file");
+
+            for (app_name, value) in sources {
+                let source = parse_file_source(&value)
+                    .unwrap_or_else(|| panic!("Invalid file source for app '{}'", app_name));
+                file_sources.insert(app_name, source);
+            }
+        }
+    }
+
+    if let Some(args) = args.subcommand_matches("publish-file") {
+        let app_name = args.value_of("app").expect("BUG: required argument 'app' missing");
+        let path = args.value_of("path").expect("BUG: required argument 'path' missing");
+        let looped = args.is_present("loop");
+        file_sources.insert(app_name.to_string(), FileSourceConfig { path: PathBuf::from(path), looped });
+    }
+
+    file_sources
+}
+
+fn parse_file_source(value: &serde_yaml::Value) -> Option<FileSourceConfig> {
+    use serde_yaml::Value;
+
+    match value {
+        Value::String(path) => Some(FileSourceConfig { path: PathBuf::from(path), looped: false }),
+        Value::Mapping(entry) => {
+            let path = entry.get(&Value::from("path"))?.as_str()?;
+            let looped = match entry.get(&Value::from("loop")) {
+                Some(looped) => looped.as_bool()?,
+                None => false,
+            };
+            Some(FileSourceConfig { path: PathBuf::from(path), looped })
+        },
+        _ => None,
+    }
+}
+
+/// Splits arguments of the form `APP=VALUE`.
+fn split_app_assignment(value: &str) -> (String, String) {
+    let mut parts = value.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(app_name), Some(value)) if !app_name.is_empty() && !value.is_empty() => {
+            (app_name.to_string(), value.to_string())
+        },
+        _ => panic!("Invalid argument '{}', expected APP=VALUE", value),
+    }
+}
+
+fn config_dir(args: &ArgMatches) -> PathBuf {
+    args.value_of("config_dir")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("./config"))
+}
+
+
+#[cfg(all(test, feature = "srt"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_srt_passphrases() {
+        assert!(SrtConfig::is_valid_passphrase("0123456789"));
+        assert!(SrtConfig::is_valid_passphrase(&"x".repeat(79)));
+        assert!(!SrtConfig::is_valid_passphrase("012345678"));
+        assert!(!SrtConfig::is_valid_passphrase(&"x".repeat(80)));
+    }
+
+    #[test]
+    fn validates_srt_latency() {
+        assert_eq!(SrtConfig::parse_latency("120"), Some(Duration::from_millis(120)));
+        assert_eq!(SrtConfig::parse_latency("2147483647"), Some(Duration::from_millis(2_147_483_647)));
+        assert_eq!(SrtConfig::parse_latency("2147483648"), None);
+        assert_eq!(SrtConfig::parse_latency("-1"), None);
+        assert_eq!(SrtConfig::parse_latency("fast"), None);
+    }
+}
@@ -1,8 +1,10 @@
-use std::{io, result};
-use rml_rtmp::sessions::ServerSessionError as RtmpSessionError;
+use std::{fmt, io, result};
+use rml_rtmp::sessions::{
+    ServerSessionError as RtmpSessionError,
+    ClientSessionError as RtmpClientSessionError,
+};
 #[cfg(feature = "hls")]
 use mpeg2ts::Error as TransportStreamError;
-#[cfg(feature = "hls")]
 use javelin_codec::Error as CodecError;
 
 pub type Result<T> = result::Result<T, Error>;
@@ -12,13 +14,16 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     IoError(io::Error),
     RtmpSessionError(RtmpSessionError),
+    RtmpClientSessionError(RtmpClientSessionError),
     Custom(String),
     HandshakeFailed,
     RequestError,
     SessionError(String),
+    WatcherLimitReached,
+    /// The recording format can not hold the codec of the stream
+    UnsupportedRecording,
     #[cfg(feature = "hls")]
     TransportStreamError(TransportStreamError),
-    #[cfg(feature = "hls")]
     CodecError(CodecError)
 }
 
@@ -34,6 +39,12 @@ impl From<RtmpSessionError> for Error {
     }
 }
 
+impl From<RtmpClientSessionError> for Error {
+    fn from(err: RtmpClientSessionError) -> Self {
+        Error::RtmpClientSessionError(err)
+    }
+}
+
 #[cfg(feature = "hls")]
 impl From<TransportStreamError> for Error {
     fn from(err: TransportStreamError) -> Self {
@@ -41,7 +52,6 @@ impl From<TransportStreamError> for Error {
     }
 }
 
-#[cfg(feature = "hls")]
 impl From<CodecError> for Error {
     fn from(err: CodecError) -> Self {
         Error::CodecError(err)
@@ -59,3 +69,21 @@ impl From<String> for Error {
         Error::Custom(err)
     }
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IoError(err) => write!(f, "{}", err),
+            Error::RtmpSessionError(err) => write!(f, "RTMP session: {:?}", err),
+            Error::RtmpClientSessionError(err) => write!(f, "RTMP client session: {:?}", err),
+            Error::Custom(message) | Error::SessionError(message) => write!(f, "{}", message),
+            Error::HandshakeFailed => write!(f, "Handshake failed"),
+            Error::RequestError => write!(f, "Invalid request"),
+            Error::WatcherLimitReached => write!(f, "Watcher limit reached"),
+            Error::UnsupportedRecording => write!(f, "Recording format does not support the codec"),
+            #[cfg(feature = "hls")]
+            Error::TransportStreamError(err) => write!(f, "Transport stream: {:?}", err),
+            Error::CodecError(err) => write!(f, "Codec: {:?}", err),
+        }
+    }
+}
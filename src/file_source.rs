@@ -0,0 +1,354 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use log::{debug, error, info};
+use futures::{sync::mpsc, try_ready};
+use tokio::{
+    prelude::*,
+    timer::Delay,
+};
+use javelin_codec::flv;
+use crate::{
+    channel::{self, Publisher},
+    error::{Error, Result},
+    media::{self, Media},
+    rtmp::peer,
+    shared::Shared,
+    shutdown,
+};
+#[cfg(feature = "hls")]
+use crate::hls;
+
+
+/// Publishes an FLV file to a channel like an RTMP publisher would.
+///
+/// Media is read as it is due and paced in real time. Looping files start over
+/// at the end, continuing the timestamps of the previous pass.
+pub struct FileSource {
+    id: u64,
+    app_name: String,
+    path: PathBuf,
+    looped: bool,
+    channel: channel::Handle,
+    #[cfg_attr(not(feature = "hls"), allow(dead_code))]
+    shared: Shared,
+    reader: flv::Reader<BufReader<File>>,
+    /// Position of the first tag, where looping files start over
+    start_position: u64,
+    /// Timestamp of the first media in the file
+    first_timestamp: Option<u32>,
+    /// Added to the timestamps of the current pass
+    loop_offset: u32,
+    last_timestamp: u32,
+    pending: Option<Media>,
+    started: Option<Instant>,
+    publishing: bool,
+    delay: Delay,
+    /// Receives the disconnect request when another publisher takes over
+    sender: peer::Sender,
+    receiver: mpsc::UnboundedReceiver<peer::Message>,
+    shutdown: shutdown::Signal,
+}
+
+impl FileSource {
+    /// Gap between the last tag of the file and the first tag of the next pass
+    const LOOP_GAP: u32 = 40; // milliseconds
+
+    pub fn create<P>(app_name: String, path: P, looped: bool, shared: Shared) -> Result<Self>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref().to_path_buf();
+        let reader = flv::Reader::new(BufReader::new(File::open(&path)?))?;
+        let (sender, receiver) = mpsc::unbounded();
+
+        Ok(Self {
+            id: shared.next_client_id(),
+            channel: shared.channel_or_create(&app_name),
+            app_name,
+            path,
+            looped,
+            start_position: reader.position(),
+            reader,
+            first_timestamp: None,
+            loop_offset: 0,
+            last_timestamp: 0,
+            pending: None,
+            started: None,
+            publishing: false,
+            delay: Delay::new(Instant::now()),
+            sender,
+            receiver,
+            shutdown: shared.shutdown.signal(),
+            shared,
+        })
+    }
+
+    /// Takes the place of the publisher, unless someone else is publishing.
+    fn start(&mut self) -> Result<()> {
+        {
+            let mut channel = self.channel.lock();
+
+            if channel.has_publisher() {
+                return Err(Error::from("Application is already being published to"));
+            }
+
+            let stream_key = format!("file:{}", self.path.display());
+            channel.set_publisher(Publisher::new(self.id, stream_key, self.sender.clone()));
+        }
+
+        self.publishing = true;
+
+        #[cfg(feature = "hls")]
+        hls::server::ensure_writer(&self.channel, &self.shared);
+
+        info!("Publishing '{}' to app '{}'", self.path.display(), self.app_name);
+
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        let mut channel = self.channel.lock();
+        if channel.is_publisher(self.id) {
+            channel.unpublish();
+        }
+    }
+
+    /// Reads the next media, with timestamps continuing those of previous passes.
+    /// Metadata is handed to the channel on the way.
+    fn next_media(&mut self) -> Result<Option<Media>> {
+        loop {
+            let tag = match self.reader.read_tag()? {
+                Some(tag) => tag,
+                None if self.looped && self.first_timestamp.is_some() => {
+                    debug!("Starting over with '{}'", self.path.display());
+                    self.reader.seek(self.start_position)?;
+                    self.loop_offset = self.last_timestamp + Self::LOOP_GAP;
+                    self.first_timestamp = None;
+                    continue;
+                },
+                None => return Ok(None),
+            };
+
+            if let Some(metadata) = flv::Metadata::try_from_tag(&tag)? {
+                // Later passes have the same metadata
+                if self.loop_offset > 0 {
+                    continue;
+                }
+
+                let metadata = media::metadata_from_flv(&metadata);
+                let fanout = self.channel.lock().set_metadata(self.id, metadata.clone());
+                if let Some(fanout) = fanout {
+                    fanout.send_metadata(&metadata);
+                }
+                continue;
+            }
+
+            if let Some(mut media) = Media::from_flv_tag(tag) {
+                let first_timestamp = *self.first_timestamp.get_or_insert(media.timestamp());
+                let timestamp = self.loop_offset + media.timestamp().saturating_sub(first_timestamp);
+                media.set_timestamp(timestamp);
+                return Ok(Some(media));
+            }
+        }
+    }
+
+    /// Sends all media that is due, returns `Ready` once the end of the file is reached.
+    fn publish(&mut self) -> Poll<(), Error> {
+        loop {
+            try_ready!(self.delay.poll().map_err(|why| Error::from(format!("{:?}", why))));
+
+            let mut media = match self.pending.take() {
+                Some(media) => media,
+                None => match self.next_media()? {
+                    Some(media) => media,
+                    None => return Ok(Async::Ready(())),
+                },
+            };
+
+            let started = *self.started.get_or_insert_with(Instant::now);
+            let due = started + Duration::from_millis(u64::from(media.timestamp()));
+
+            if due > Instant::now() {
+                self.pending = Some(media);
+                self.delay.reset(due);
+                continue;
+            }
+
+            self.last_timestamp = media.timestamp();
+
+            let fanout = self.channel.lock().prepare_fanout(self.id, &mut media);
+            if let Some(fanout) = fanout {
+                fanout.send_media(&media);
+            }
+        }
+    }
+}
+
+impl Future for FileSource {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            self.stop();
+            return Ok(Async::Ready(()));
+        }
+
+        while let Async::Ready(Some(message)) = self.receiver.poll()? {
+            if let peer::Message::Disconnect = message {
+                info!("Stopped publishing '{}', app '{}' was taken over", self.path.display(), self.app_name);
+                return Ok(Async::Ready(()));
+            }
+        }
+
+        if !self.publishing {
+            if let Err(why) = self.start() {
+                error!("Failed to publish '{}' to app '{}': {:?}", self.path.display(), self.app_name, why);
+                return Ok(Async::Ready(()));
+            }
+        }
+
+        match self.publish() {
+            Ok(Async::Ready(())) => {
+                info!("Finished publishing '{}' to app '{}'", self.path.display(), self.app_name);
+            },
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(why) => {
+                error!("Failed to publish '{}' to app '{}': {:?}", self.path.display(), self.app_name, why);
+            },
+        }
+
+        self.stop();
+        Ok(Async::Ready(()))
+    }
+}
+
+
+/// Starts publishing all configured files.
+pub fn spawn_all(shared: &Shared) {
+    let file_sources = shared.config.read().file_sources.clone();
+
+    for (app_name, source) in file_sources {
+        match FileSource::create(app_name.clone(), &source.path, source.looped, shared.clone()) {
+            Ok(file_source) => {
+                tokio::spawn(file_source);
+            },
+            Err(why) => error!("Failed to open '{}' for app '{}': {:?}", source.path.display(), app_name, why),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use bytes::Bytes;
+    use tokio::runtime::current_thread::Runtime;
+    use javelin_codec::flv::{Tag, TagKind};
+    use crate::{
+        config::Config,
+        rtmp::fanout::Watcher,
+        shutdown::Shutdown,
+    };
+    use super::*;
+
+    fn shared() -> Shared {
+        let (shutdown, _) = Shutdown::new();
+        Shared::with_config(Config::from_args(&["javelin"]), shutdown)
+    }
+
+    /// Writes an FLV file with video keyframes at the given timestamps
+    fn write_file(name: &str, timestamps: &[u32]) -> PathBuf {
+        let path = env::temp_dir().join(format!("javelin-file-source-{}-{}.flv", name, std::process::id()));
+        let header = flv::Header { has_audio: false, has_video: true };
+        let mut writer = flv::Writer::new(Vec::new(), header).unwrap();
+
+        for timestamp in timestamps {
+            writer.write_tag(&Tag::new(TagKind::Video, *timestamp, Bytes::from_static(&[0x17, 0x01, 0x00, 0x00, 0x00]))).unwrap();
+        }
+
+        fs::write(&path, writer.into_inner()).unwrap();
+        path
+    }
+
+    fn timestamps(source: &mut FileSource, count: usize) -> Vec<Option<u32>> {
+        (0..count)
+            .map(|_| {
+                let media = source.next_media().unwrap();
+                // Passes continue from the last media that was sent
+                if let Some(media) = &media {
+                    source.last_timestamp = media.timestamp();
+                }
+                media.map(|media| media.timestamp())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loops_with_continuing_timestamps() {
+        let path = write_file("loop", &[100, 110, 120]);
+        let mut source = FileSource::create("live".to_string(), &path, true, shared()).unwrap();
+
+        assert_eq!(timestamps(&mut source, 5), vec![Some(0), Some(10), Some(20), Some(60), Some(70)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ends_at_end_of_file() {
+        let path = write_file("end", &[0, 10]);
+        let mut source = FileSource::create("live".to_string(), &path, false, shared()).unwrap();
+
+        assert_eq!(timestamps(&mut source, 3), vec![Some(0), Some(10), None]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unpublishes_once_finished() {
+        let path = write_file("publish", &[0, 10, 20]);
+        let shared = shared();
+        let source = FileSource::create("live".to_string(), &path, false, shared.clone()).unwrap();
+        let channel = shared.channel_or_create("live");
+        let (sender, _receiver) = mpsc::unbounded();
+        let (media, media_receiver) = peer::media_queue();
+        channel.lock().add_watcher(Watcher::flv(1, sender, media)).unwrap();
+
+        Runtime::new().unwrap().block_on(source).unwrap();
+        assert!(!channel.lock().is_live());
+
+        // Every frame followed by the end of the stream
+        let mut messages = media_receiver.wait().map(|message| message.unwrap());
+        for timestamp in &[0, 10, 20] {
+            match messages.next() {
+                Some(peer::Message::Raw(bytes)) => assert_eq!(bytes[4..7], [0, 0, *timestamp]),
+                _ => panic!("Expected a frame"),
+            }
+        }
+        match messages.next() {
+            Some(peer::Message::Disconnect) => (),
+            _ => panic!("Expected the end of the stream"),
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn leaves_published_applications_alone() {
+        let path = write_file("taken", &[0]);
+        let shared = shared();
+        let channel = shared.channel_or_create("live");
+        let (sender, _receiver) = mpsc::unbounded();
+        channel.lock().set_publisher(Publisher::new(1, "key".to_string(), sender));
+
+        let source = FileSource::create("live".to_string(), &path, false, shared.clone()).unwrap();
+        Runtime::new().unwrap().block_on(source).unwrap();
+
+        assert!(channel.lock().is_publisher(1));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
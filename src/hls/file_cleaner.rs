@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     path::PathBuf,
     fs,
     time::{Instant, Duration},
@@ -9,7 +10,10 @@ use tokio::{
     prelude::*,
     timer::DelayQueue,
 };
-use crate::shared::Shared;
+use crate::{
+    shared::Shared,
+    shutdown,
+};
 
 type Batch = Vec<PathBuf>;
 type Message = (Duration, Batch);
@@ -18,8 +22,12 @@ type Receiver = UnboundedReceiver<Message>;
 
 
 pub struct FileCleaner {
-    items: DelayQueue<Batch>,
+    items: DelayQueue<u64>,
+    batches: HashMap<u64, Batch>,
+    next_batch_id: u64,
     receiver: Receiver,
+    closed: bool,
+    _token: Option<shutdown::Token>,
 }
 
 impl FileCleaner {
@@ -27,10 +35,15 @@ impl FileCleaner {
         let (sender, receiver) = mpsc::unbounded();
 
         shared.set_fcleaner_sender(sender);
+        let token = shared.shutdown.token();
 
         Self {
             items: DelayQueue::new(),
+            batches: HashMap::new(),
+            next_batch_id: 0,
             receiver,
+            closed: false,
+            _token: token,
         }
     }
 
@@ -40,12 +53,26 @@ impl FileCleaner {
                 Ok(Async::Ready(Some((duration, files)))) => {
                     let timestamp = Instant::now() + ((duration / 100) * 150);
                     debug!("{} files queued for cleanup at {:?}", files.len(), timestamp);
-                    self.items.insert_at(files, timestamp);
+                    let id = self.next_batch_id;
+                    self.next_batch_id += 1;
+                    self.batches.insert(id, files);
+                    self.items.insert_at(id, timestamp);
+                },
+                Ok(Async::Ready(None)) => {
+                    self.closed = true;
+                    break;
                 },
                 _ => break,
             }
         }
     }
+
+    /// Removes all queued files right away instead of waiting for their delay.
+    fn remove_all(&mut self) {
+        for (_, files) in self.batches.drain() {
+            remove_files(&files);
+        }
+    }
 }
 
 impl Future for FileCleaner {
@@ -55,11 +82,22 @@ impl Future for FileCleaner {
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         self.get_new();
 
+        // Only happens once every playlist is gone during shutdown
+        if self.closed {
+            debug!("File cleaner closed, removing all queued files");
+            self.remove_all();
+            return Ok(Async::Ready(()));
+        }
+
         loop {
             match self.items.poll() {
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
                 Ok(Async::Ready(None)) => return Ok(Async::NotReady),
-                Ok(Async::Ready(Some(files))) => remove_files(&files.into_inner()),
+                Ok(Async::Ready(Some(id))) => {
+                    if let Some(files) = self.batches.remove(id.get_ref()) {
+                        remove_files(&files);
+                    }
+                },
                 Err(why) => {
                     error!("{:?}", why);
                     return Err(());
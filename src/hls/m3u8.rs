@@ -1,12 +1,12 @@
 use std::{
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::Duration,
 };
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use log::error;
-use m3u8_rs::playlist::{MediaPlaylist, MediaSegment};
+use m3u8_rs::playlist::{MasterPlaylist as M3u8MasterPlaylist, MediaPlaylist, MediaSegment, VariantStream};
 use tempfile::NamedTempFile;
 use super::file_cleaner;
 use crate::{
@@ -19,6 +19,7 @@ pub struct Playlist {
     file_path: PathBuf,
     current_duration: u64,
     cleanup_started: bool,
+    discontinuity: bool,
     playlist: MediaPlaylist,
     file_cleaner: file_cleaner::Sender,
 }
@@ -41,6 +42,7 @@ impl Playlist {
             file_path: path.into(),
             current_duration: 0,
             cleanup_started: false,
+            discontinuity: false,
             playlist,
             file_cleaner,
         }
@@ -50,6 +52,11 @@ impl Playlist {
         self.playlist.target_duration = (duration as f64 / 1000.0) as f32;
     }
 
+    /// Marks the next segment as not being continuous with the previous one.
+    pub fn add_discontinuity(&mut self) {
+        self.discontinuity = true;
+    }
+
     fn schedule_for_deletion(&mut self, amount: usize, delete_after: u64) {
         let segments_to_delete: Vec<_> = self.playlist.segments.drain(..amount).collect();
         let paths: Vec<_> = segments_to_delete.iter()
@@ -60,6 +67,7 @@ impl Playlist {
             .collect();
 
         self.playlist.media_sequence += paths.len() as i32;
+        self.playlist.discontinuity_sequence += segments_to_delete.iter().filter(|seg| seg.discontinuity).count() as i32;
         self.file_cleaner.unbounded_send((Duration::from_millis(delete_after), paths)).unwrap();
     }
 
@@ -70,6 +78,8 @@ impl Playlist {
         segment.duration = (duration as f64 / 1000.0) as f32;
         segment.title = Some("".into()); // adding empty title here, because implementation is broken
         segment.uri = uri.into();
+        segment.discontinuity = self.discontinuity && !self.playlist.segments.is_empty();
+        self.discontinuity = false;
 
 
         if self.cleanup_started {
@@ -87,42 +97,99 @@ impl Playlist {
     }
 
     fn atomic_update(&mut self) -> Result<()> {
-        let mut tmp_file = tempfile::Builder::new()
-            .prefix(".playlist.m3u")
-            .suffix(".tmp")
-            .tempfile_in(&self.hls_root())?;
+        let playlist = &self.playlist;
+        write_atomically(&self.file_path, |file| playlist.write_to(file).map_err(Into::into))
+    }
+}
 
-        self.write_temporary_file(&mut tmp_file)?;
-        fs::rename(&tmp_file.path(), &self.file_path)?;
+impl Drop for Playlist {
+    fn drop(&mut self) {
+        self.schedule_for_deletion(self.playlist.segments.len(), self.current_duration);
+        self.playlist.end_list = true;
 
-        Ok(())
+        if let Err(why) = self.atomic_update() {
+            error!("Failed to write end tag to playlist: {:?}", why);
+        }
     }
+}
 
-    fn hls_root(&self) -> PathBuf {
-        self.file_path.parent().expect("No parent directory for playlist").into()
-    }
 
-    fn write_temporary_file(&mut self, tmp_file: &mut NamedTempFile) -> Result<()> {
-        self.playlist.write_to(tmp_file)?;
+/// Properties of a stream that players need to choose it, taken from the bitstream
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// Peak bit rate in bits per second
+    pub bandwidth: u64,
+    pub codecs: Vec<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<f64>,
+}
+
+
+/// Master playlist listing the media playlist of a stream as its only variant
+pub struct MasterPlaylist {
+    file_path: PathBuf,
+    media_playlist: String,
+    variant: Option<Variant>,
+}
 
-        #[cfg(unix)]
-        {
-            let mut perms = fs::metadata(&tmp_file.path())?.permissions();
-            perms.set_mode(0o644);
-            fs::set_permissions(&tmp_file.path(), perms)?;
+impl MasterPlaylist {
+    pub fn new<P, S>(path: P, media_playlist: S) -> Self
+        where P: Into<PathBuf>,
+              S: Into<String>,
+    {
+        Self { file_path: path.into(), media_playlist: media_playlist.into(), variant: None }
+    }
+
+    /// Rewrites the playlist if the properties of the stream changed.
+    pub fn update(&mut self, variant: Variant) {
+        if self.variant.as_ref() == Some(&variant) {
+            return;
         }
 
-        Ok(())
+        let variant_stream = VariantStream {
+            uri: self.media_playlist.clone(),
+            bandwidth: variant.bandwidth.to_string(),
+            codecs: variant.codecs.join(","),
+            resolution: variant.resolution.map(|(width, height)| format!("{}x{}", width, height)),
+            frame_rate: variant.frame_rate.map(|frame_rate| format!("{:.3}", frame_rate)),
+            ..VariantStream::default()
+        };
+
+        let playlist = M3u8MasterPlaylist {
+            version: 3,
+            variants: vec![variant_stream],
+            ..M3u8MasterPlaylist::default()
+        };
+
+        match write_atomically(&self.file_path, |file| playlist.write_to(file).map_err(Into::into)) {
+            Ok(()) => self.variant = Some(variant),
+            Err(why) => error!("Failed to update master playlist: {:?}", why),
+        }
     }
 }
 
-impl Drop for Playlist {
-    fn drop(&mut self) {
-        self.schedule_for_deletion(self.playlist.segments.len(), self.current_duration);
-        self.playlist.end_list = true;
 
-        if let Err(why) = self.atomic_update() {
-            error!("Failed to write end tag to playlist: {:?}", why);
-        }
+/// Replaces the file at once, so that clients never read a partially written playlist.
+fn write_atomically<F>(file_path: &Path, write: F) -> Result<()>
+    where F: FnOnce(&mut NamedTempFile) -> Result<()>
+{
+    let hls_root = file_path.parent().expect("No parent directory for playlist");
+
+    let mut tmp_file = tempfile::Builder::new()
+        .prefix(".playlist.m3u")
+        .suffix(".tmp")
+        .tempfile_in(hls_root)?;
+
+    write(&mut tmp_file)?;
+
+    #[cfg(unix)]
+    {
+        let mut perms = fs::metadata(tmp_file.path())?.permissions();
+        perms.set_mode(0o644);
+        fs::set_permissions(tmp_file.path(), perms)?;
     }
+
+    fs::rename(tmp_file.path(), file_path)?;
+
+    Ok(())
 }
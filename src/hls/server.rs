@@ -6,8 +6,10 @@ use futures::{
     sync::{mpsc, oneshot},
 };
 use crate::{
+    channel,
     media,
     shared::Shared,
+    shutdown,
     Result,
 };
 use super::writer::Writer;
@@ -22,6 +24,7 @@ pub struct Server {
     sender: Sender,
     receiver: Receiver,
     shared: Shared,
+    shutdown: shutdown::Signal,
 }
 
 
@@ -36,7 +39,9 @@ impl Server {
         directory_cleanup(hls_root).expect("Failed to clean up HLS directory");
         info!("HLS directory purged");
 
-        Self { sender, receiver, shared }
+        let shutdown = shared.shutdown.signal();
+
+        Self { sender, receiver, shared, shutdown }
     }
 
     pub fn sender(&self) -> Sender {
@@ -49,6 +54,10 @@ impl Future for Server {
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+
         while let Some((app_name, request)) = try_ready!(self.receiver.poll()) {
             let (sender, receiver) = mpsc::unbounded();
             request.send(sender).unwrap();
@@ -64,6 +73,39 @@ impl Future for Server {
 }
 
 
+/// Requests a new HLS writer for an application.
+/// Returns `None` if HLS is not running.
+pub fn register_writer(app_name: String, shared: &Shared) -> Option<media::Sender> {
+    let sender = shared.hls_sender()?;
+    let (request, response) = oneshot::channel();
+
+    if let Err(why) = sender.unbounded_send((app_name, request)) {
+        error!("{:?}", why);
+        return None;
+    }
+
+    response.wait().map_err(|why| error!("{:?}", why)).ok()
+}
+
+/// Gives the channel an HLS writer, unless it already has one.
+pub fn ensure_writer(handle: &channel::Handle, shared: &Shared) {
+    let app_name = {
+        let channel = handle.lock();
+        if channel.has_hls_writer() {
+            return;
+        }
+        channel.app_name.clone()
+    };
+
+    if let Some(hls_writer) = register_writer(app_name, shared) {
+        let mut channel = handle.lock();
+        if !channel.has_hls_writer() {
+            channel.set_hls_writer(hls_writer);
+        }
+    }
+}
+
+
 fn directory_cleanup<P: AsRef<Path>>(path: P) -> Result<()> {
     let path = path.as_ref();
 
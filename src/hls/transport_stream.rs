@@ -9,8 +9,9 @@ use mpeg2ts::{
         Pid,
     },
     pes::PesHeader,
+    es::StreamType,
 };
-use javelin_codec::{avc, aac};
+use javelin_codec::{avc, aac, hevc};
 use crate::Result;
 
 
@@ -19,11 +20,14 @@ const VIDEO_ES_PID: u16 = 257;
 const AUDIO_ES_PID: u16 = 258;
 const PES_VIDEO_STREAM_ID: u8 = 224;
 const PES_AUDIO_STREAM_ID: u8 = 192;
+const TS_PACKET_SIZE: usize = 188;
 
 
 pub struct Buffer {
     video_continuity_counter: ContinuityCounter,
     audio_continuity_counter: ContinuityCounter,
+    /// Stream type of the last video frame, announced in the program map table
+    video_stream_type: StreamType,
     packets: Vec<TsPacket>,
 }
 
@@ -32,11 +36,17 @@ impl Buffer {
         Self {
             video_continuity_counter: ContinuityCounter::new(),
             audio_continuity_counter: ContinuityCounter::new(),
+            video_stream_type: StreamType::H264,
             packets: Vec::new(),
         }
     }
 
-    pub fn write_to_file<P>(&mut self, filename: P) -> Result<()>
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Writes all buffered packets and returns the size of the file.
+    pub fn write_to_file<P>(&mut self, filename: P) -> Result<usize>
         where P: AsRef<Path>
     {
         use mpeg2ts::ts::{TsPacketWriter, WriteTsPacket};
@@ -46,16 +56,28 @@ impl Buffer {
         let mut writer = TsPacketWriter::new(file);
 
         writer.write_ts_packet(&default_pat_packet())?;
-        writer.write_ts_packet(&default_pmt_packet())?;
+        writer.write_ts_packet(&default_pmt_packet(self.video_stream_type))?;
 
         for packet in &packets {
             writer.write_ts_packet(packet)?;
         }
 
-        Ok(())
+        Ok((packets.len() + 2) * TS_PACKET_SIZE)
+    }
+
+    /// Keyframes are marked as random access points.
+    pub fn push_video(&mut self, video: &avc::Packet, keyframe: bool) -> Result<()> {
+        self.video_stream_type = StreamType::H264;
+        self.push_video_frame(video.try_as_bytes()?, video.timestamp(), video.presentation_timestamp(), keyframe)
+    }
+
+    /// Keyframes are marked as random access points.
+    pub fn push_hevc_video(&mut self, video: &hevc::Packet, keyframe: bool) -> Result<()> {
+        self.video_stream_type = StreamType::H265;
+        self.push_video_frame(video.try_as_bytes()?, video.timestamp(), video.presentation_timestamp(), keyframe)
     }
 
-    pub fn push_video(&mut self, video: &avc::Packet) -> Result<()> {
+    fn push_video_frame(&mut self, data: Bytes, timestamp: u64, presentation_timestamp: u64, keyframe: bool) -> Result<()> {
         use mpeg2ts::{
             ts::{AdaptationField, payload},
             es::StreamId,
@@ -65,11 +87,11 @@ impl Buffer {
         let mut header = default_ts_header(VIDEO_ES_PID)?;
         header.continuity_counter = self.video_continuity_counter;
 
-        let mut buf = video.try_as_bytes()?.into_buf();
+        let mut buf = data.into_buf();
         let pes_data: Bytes = buf.by_ref().take(153).collect();
-        let pcr = ClockReference::new(video.timestamp() * 90)?;
+        let pcr = ClockReference::new(timestamp * 90)?;
 
-        let adaptation_field = if video.is_keyframe() {
+        let adaptation_field = if keyframe {
             Some(AdaptationField {
                 discontinuity_indicator: false,
                 random_access_indicator: true,
@@ -84,8 +106,8 @@ impl Buffer {
             None
         };
 
-        let pts = Timestamp::new(video.presentation_timestamp() * 90)?;
-        let dts = Timestamp::new(video.timestamp() * 90)?;
+        let pts = Timestamp::new(presentation_timestamp * 90)?;
+        let dts = Timestamp::new(timestamp * 90)?;
 
         let packet = TsPacket {
             header: header.clone(),
@@ -214,11 +236,8 @@ fn default_pat_packet() -> TsPacket {
     }
 }
 
-fn default_pmt_packet() -> TsPacket {
-    use mpeg2ts::{
-        ts::{VersionNumber, payload::Pmt, EsInfo},
-        es::StreamType,
-    };
+fn default_pmt_packet(video_stream_type: StreamType) -> TsPacket {
+    use mpeg2ts::ts::{VersionNumber, payload::Pmt, EsInfo};
 
     TsPacket {
         header: default_ts_header(PMT_PID).unwrap(),
@@ -230,7 +249,7 @@ fn default_pmt_packet() -> TsPacket {
                 version_number: VersionNumber::default(),
                 table: vec![
                     EsInfo {
-                        stream_type: StreamType::H264,
+                        stream_type: video_stream_type,
                         elementary_pid: Pid::new(VIDEO_ES_PID).unwrap(),
                         descriptors: vec![],
                     },
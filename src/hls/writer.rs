@@ -5,13 +5,18 @@ use tokio::prelude::*;
 use bytes::Bytes;
 use chrono::Utc;
 #[cfg(feature = "hls")]
-use javelin_codec::{avc, aac};
+use javelin_codec::{
+    avc::{self, frame::{FrameNumbers, ParameterSets}, slice::SliceType},
+    hevc::{self, sps::SequenceParameterSet as HevcSequenceParameterSet},
+    aac,
+};
 use super::{
     transport_stream::Buffer as TsBuffer,
-    m3u8::Playlist,
+    m3u8::{MasterPlaylist, Playlist, Variant},
 };
 use crate::{
     shared::Shared,
+    shutdown,
     media::{self, Media},
     error::{Error, Result},
 };
@@ -23,10 +28,26 @@ pub struct Writer {
     next_write: u64,
     last_keyframe: u64,
     keyframe_counter: usize,
+    segment_start: u64,
+    last_timestamp: u64,
+    waiting_for_keyframe: bool,
     buffer: TsBuffer,
     shared_state: javelin_codec::SharedState,
     playlist: Playlist,
+    master_playlist: MasterPlaylist,
+    parameter_sets: Option<ParameterSets>,
+    /// Sequence parameter set of HEVC streams, whose frames are classified by their NAL unit types
+    hevc_sps: Option<HevcSequenceParameterSet>,
+    frame_numbers: FrameNumbers,
+    /// Segment at the random access points of the bitstream instead of the FLV frame type
+    bitstream_keyframes: bool,
+    frame_type_mismatch_reported: bool,
+    /// B-frames of the current segment
+    b_frames: usize,
+    /// Highest bit rate of all segments so far, in bits per second
+    peak_bandwidth: u64,
     stream_path: PathBuf,
+    _token: Option<shutdown::Token>,
 }
 
 impl Writer {
@@ -34,9 +55,13 @@ impl Writer {
         let write_interval = 2000; // milliseconds
         let next_write = write_interval; // milliseconds
 
-        let hls_root = shared.config.read().hls.root_dir.clone();
+        let (hls_root, bitstream_keyframes) = {
+            let config = shared.config.read();
+            (config.hls.root_dir.clone(), config.hls.bitstream_keyframes)
+        };
         let stream_path = hls_root.join(app_name);
         let playlist_path = stream_path.join("playlist.m3u8");
+        let master_playlist_path = stream_path.join("master.m3u8");
 
         if stream_path.exists() && !stream_path.is_dir() {
             return Err(Error::from(format!("Path '{}' exists, but is not a directory", stream_path.display())));
@@ -52,13 +77,80 @@ impl Writer {
             next_write,
             last_keyframe: 0,
             keyframe_counter: 0,
+            segment_start: 0,
+            last_timestamp: 0,
+            waiting_for_keyframe: true,
             buffer: TsBuffer::new(),
             shared_state: javelin_codec::SharedState::new(),
             playlist: Playlist::new(playlist_path, shared),
+            master_playlist: MasterPlaylist::new(master_playlist_path, "playlist.m3u8"),
+            parameter_sets: None,
+            hevc_sps: None,
+            frame_numbers: FrameNumbers::new(),
+            bitstream_keyframes,
+            frame_type_mismatch_reported: false,
+            b_frames: 0,
+            peak_bandwidth: 0,
             stream_path,
+            _token: shared.shutdown.token(),
         })
     }
 
+    fn write_segment(&mut self, end_timestamp: u64) -> Result<()> {
+        let filename = format!("{}-{}.ts", Utc::now().timestamp(), self.keyframe_counter);
+        let path = self.stream_path.join(&filename);
+        let size = self.buffer.write_to_file(&path)?;
+        let duration = end_timestamp - self.segment_start;
+        self.playlist.add_media_segment(filename, duration);
+        self.segment_start = end_timestamp;
+
+        debug!("Wrote segment '{}' with {} B-frames", path.display(), self.b_frames);
+        self.b_frames = 0;
+
+        if let Some(bandwidth) = (size as u64 * 8 * 1000).checked_div(duration) {
+            self.peak_bandwidth = self.peak_bandwidth.max(bandwidth);
+        }
+        self.update_master_playlist();
+
+        Ok(())
+    }
+
+    /// Describes the stream by its parameter sets, the publisher's metadata is not trusted.
+    ///
+    /// HEVC variants have no `FRAME-RATE`, as the frame rate is only known from the
+    /// VUI of the sequence parameter set and parsing stops before it.
+    fn update_master_playlist(&mut self) {
+        let avc_sps = self.parameter_sets.as_ref().and_then(|parameter_sets| parameter_sets.sps.first());
+
+        let (video_codec, resolution, frame_rate) = match (avc_sps, &self.hevc_sps) {
+            (Some(sps), _) => (sps.codec_string(), (sps.width(), sps.height()), sps.frame_rate()),
+            (None, Some(sps)) => (sps.codec_string(), (sps.width(), sps.height()), None),
+            (None, None) => return,
+        };
+
+        let mut codecs = vec![video_codec];
+        if let Some(asc) = &*self.shared_state.asc.read() {
+            codecs.push(asc.codec_string());
+        }
+
+        self.master_playlist.update(Variant {
+            bandwidth: self.peak_bandwidth,
+            codecs,
+            resolution: Some(resolution),
+            frame_rate,
+        });
+    }
+
+    /// Writes everything still buffered as the final segment.
+    fn finish(&mut self) -> Result<()> {
+        if !self.waiting_for_keyframe && !self.buffer.is_empty() {
+            debug!("Writing final segment to '{}'", self.stream_path.display());
+            self.write_segment(self.last_timestamp)?;
+        }
+
+        Ok(())
+    }
+
     fn handle_h264<T>(&mut self, timestamp: T, bytes: Bytes) -> Result<()>
         where T: Into<u64>
     {
@@ -67,10 +159,71 @@ impl Writer {
         let packet = avc::Packet::try_from_buf(bytes, timestamp, &self.shared_state)?;
 
         if packet.is_sequence_header() {
+            self.parameter_sets = self.shared_state.dcr.read().as_ref()
+                .and_then(|dcr| ParameterSets::try_from_dcr(dcr).ok());
+            self.hevc_sps = None;
+            self.frame_numbers = FrameNumbers::new();
+            return Ok(());
+        }
+
+        let keyframe = self.is_keyframe(&packet);
+        self.advance(timestamp, keyframe)?;
+
+        if let Err(why) = self.buffer.push_video(&packet, keyframe) {
+            warn!("Failed to put data into buffer: {:?}", why);
+        }
+
+        Ok(())
+    }
+
+    fn handle_h265<T>(&mut self, timestamp: T, bytes: Bytes) -> Result<()>
+        where T: Into<u64>
+    {
+        let timestamp: u64 = timestamp.into();
+
+        let packet = hevc::Packet::try_from_buf(bytes, timestamp, &self.shared_state)?;
+
+        if packet.is_sequence_header() {
+            self.hevc_sps = self.shared_state.hevc_dcr.read().as_ref()
+                .and_then(|dcr| dcr.sps.first())
+                .and_then(|sps| HevcSequenceParameterSet::try_from_unit(sps).ok());
+            self.parameter_sets = None;
             return Ok(());
         }
 
-        if packet.is_keyframe() {
+        if !packet.has_coded_frames() {
+            return Ok(());
+        }
+
+        // IRAP pictures are told apart by their NAL unit type, without parsing slice headers
+        if packet.is_random_access() != packet.is_keyframe() {
+            self.report_frame_type_mismatch();
+        }
+
+        let keyframe = if self.bitstream_keyframes {
+            packet.is_random_access()
+        } else {
+            packet.is_keyframe()
+        };
+        self.advance(timestamp, keyframe)?;
+
+        if let Err(why) = self.buffer.push_hevc_video(&packet, keyframe) {
+            warn!("Failed to put data into buffer: {:?}", why);
+        }
+
+        Ok(())
+    }
+
+    /// Moves on to the next video frame, a keyframe may end the current segment.
+    fn advance(&mut self, timestamp: u64, keyframe: bool) -> Result<()> {
+        if keyframe {
+            if self.waiting_for_keyframe {
+                self.segment_start = timestamp;
+                self.last_keyframe = timestamp;
+                self.next_write = timestamp + self.write_interval;
+                self.waiting_for_keyframe = false;
+            }
+
             let keyframe_duration = timestamp - self.last_keyframe;
 
             if self.keyframe_counter == 1 {
@@ -78,10 +231,7 @@ impl Writer {
             }
 
             if timestamp >= self.next_write {
-                let filename = format!("{}-{}.ts", Utc::now().timestamp(), self.keyframe_counter);
-                let path = self.stream_path.join(&filename);
-                self.buffer.write_to_file(&path)?;
-                self.playlist.add_media_segment(filename, keyframe_duration);
+                self.write_segment(timestamp)?;
                 self.next_write += self.write_interval;
             }
 
@@ -89,13 +239,57 @@ impl Writer {
             self.last_keyframe = timestamp;
         }
 
-        if let Err(why) = self.buffer.push_video(&packet) {
-            warn!("Failed to put data into buffer: {:?}", why);
-        }
+        self.last_timestamp = self.last_timestamp.max(timestamp);
 
         Ok(())
     }
 
+    /// Whether a segment can start with this frame.
+    ///
+    /// Frames are classified by their slices if the parameter sets are known,
+    /// the FLV frame type is only trusted if segmenting by the bitstream is disabled.
+    fn is_keyframe(&mut self, packet: &avc::Packet) -> bool {
+        let frame = match &self.parameter_sets {
+            Some(parameter_sets) => packet.frame_info(parameter_sets).unwrap_or_else(|why| {
+                debug!("Failed to parse slice header: {:?}", why);
+                None
+            }),
+            None => None,
+        };
+
+        let frame = match frame {
+            Some(frame) => frame,
+            None => return packet.is_keyframe(),
+        };
+
+        if frame.slice_type == SliceType::B {
+            self.b_frames += 1;
+        }
+
+        if !self.frame_numbers.check(&frame) {
+            debug!("Unexpected frame number {} in '{}', frames were lost or reordered", frame.frame_num, self.stream_path.display());
+        }
+
+        if frame.is_random_access() != packet.is_keyframe() {
+            self.report_frame_type_mismatch();
+        }
+
+        if self.bitstream_keyframes {
+            frame.is_random_access()
+        } else {
+            packet.is_keyframe()
+        }
+    }
+
+    fn report_frame_type_mismatch(&mut self) {
+        if !self.frame_type_mismatch_reported {
+            warn!("Frame type of '{}' does not match the bitstream, segmenting by {}",
+                self.stream_path.display(),
+                if self.bitstream_keyframes { "the bitstream" } else { "the frame type" });
+            self.frame_type_mismatch_reported = true;
+        }
+    }
+
     fn handle_aac<T>(&mut self, timestamp: T, bytes: Bytes) -> Result<()>
         where T: Into<u64>
     {
@@ -103,10 +297,12 @@ impl Writer {
 
         let packet = aac::Packet::try_from_bytes(bytes, timestamp, &self.shared_state)?;
 
-        if self.keyframe_counter == 0 || packet.is_sequence_header() {
+        if self.waiting_for_keyframe || packet.is_sequence_header() {
             return Ok(());
         }
 
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+
         if let Err(why) = self.buffer.push_audio(&packet) {
             warn!("Failed to put data into buffer: {:?}", why);
         }
@@ -114,10 +310,20 @@ impl Writer {
         Ok(())
     }
 
-    fn handle(&mut self, media: Media) -> Result<()> {
-        match media {
-            Media::H264(timestamp, bytes) => self.handle_h264(timestamp.value, bytes),
-            Media::AAC(timestamp, bytes) => self.handle_aac(timestamp.value, bytes),
+    /// Ends the current segment and starts a new one with the next keyframe.
+    fn handle_discontinuity(&mut self) -> Result<()> {
+        self.finish()?;
+        self.playlist.add_discontinuity();
+        self.waiting_for_keyframe = true;
+        Ok(())
+    }
+
+    fn handle(&mut self, message: media::Message) -> Result<()> {
+        match message {
+            media::Message::Media(Media::H264(timestamp, bytes)) => self.handle_h264(timestamp.value, bytes),
+            media::Message::Media(Media::H265(timestamp, bytes)) => self.handle_h265(timestamp.value, bytes),
+            media::Message::Media(Media::AAC(timestamp, bytes)) => self.handle_aac(timestamp.value, bytes),
+            media::Message::Discontinuity => self.handle_discontinuity(),
         }
     }
 }
@@ -128,10 +334,13 @@ impl Future for Writer {
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        while let Some(media) = try_ready!(self.receiver.poll()) {
-            self.handle(media).map_err(|why| error!("{:?}", why))?;
+        while let Some(message) = try_ready!(self.receiver.poll()) {
+            self.handle(message).map_err(|why| error!("{:?}", why))?;
         }
 
+        // The stream ended, dropping the playlist afterwards marks it as finished
+        self.finish().map_err(|why| error!("{:?}", why))?;
+
         Ok(Async::Ready(()))
     }
 }
@@ -2,9 +2,17 @@
 
 mod error;
 mod shared;
+mod channel;
+mod shutdown;
 mod config;
 mod media;
 mod rtmp;
+mod slate;
+mod file_source;
+mod ts_ingest;
+mod relay;
+mod record;
+mod vod;
 mod args;
 
 #[cfg(feature = "hls")]
@@ -13,13 +21,26 @@ mod hls;
 #[cfg(feature = "web")]
 mod web;
 
+#[cfg(feature = "srt")]
+mod srt;
 
+#[cfg(any(feature = "web", feature = "srt"))]
+mod publish;
+
+
+use log::{info, warn};
 use futures::future::lazy;
+use tokio::{
+    prelude::*,
+    runtime::Runtime,
+    timer::Timeout,
+};
 use simplelog::{Config, SimpleLogger, TermLogger, LevelFilter};
 
 #[allow(unused_imports)]
 use self::{
     shared::Shared,
+    shutdown::{Shutdown, Drained},
     error::{Error, Result},
 };
 
@@ -34,19 +55,56 @@ fn main() {
         init_logger!(SimpleLogger).unwrap_or_else(|err|
             eprintln!("Failed to initialize logger: {}", err)));
 
-    let shared = Shared::new();
+    let (shutdown, drained) = Shutdown::new();
+    let shared = Shared::new(shutdown);
 
     #[cfg(feature = "web")]
     spawn_web_server(shared.clone());
 
-    tokio::run(lazy(move || {
-        #[cfg(feature = "hls")]
-        spawn_hls_server(shared.clone());
+    let mut runtime = Runtime::new().expect("Failed to create runtime");
+
+    {
+        let shared = shared.clone();
+        runtime.spawn(lazy(move || {
+            #[cfg(feature = "hls")]
+            spawn_hls_server(shared.clone());
+
+            slate::spawn_all(&shared);
+            relay::pull::spawn_all(&shared);
+            file_source::spawn_all(&shared);
+            ts_ingest::spawn_all(&shared);
+            #[cfg(feature = "srt")]
+            srt::spawn(&shared);
+
+            tokio::spawn(rtmp::Server::new(shared.clone()));
+
+            Ok(())
+        }));
+    }
+
+    let _ = runtime.block_on(lazy(shutdown::os_signal));
+
+    graceful_shutdown(runtime, &shared, drained);
+}
+
+/// Stops accepting clients, notifies all connected clients
+/// and waits for them and all HLS writers to finish.
+fn graceful_shutdown(mut runtime: Runtime, shared: &Shared, drained: Drained) {
+    let drain_deadline = shared.config.read().drain_deadline;
 
-        tokio::spawn(rtmp::Server::new(shared.clone()));
+    info!("Shutting down, waiting up to {} seconds for tasks to finish", drain_deadline.as_secs());
+
+    shared.shutdown.trigger();
+
+    #[cfg(feature = "hls")]
+    shared.unset_fcleaner_sender();
+
+    match runtime.block_on(Timeout::new(drained, drain_deadline)) {
+        Ok(()) => info!("All tasks finished"),
+        Err(_) => warn!("Drain deadline exceeded, terminating remaining tasks"),
+    }
 
-        Ok(())
-    }));
+    let _ = runtime.shutdown_now().wait();
 }
 
 #[cfg(feature = "hls")]
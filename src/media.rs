@@ -1,27 +1,107 @@
-use std::collections::HashSet;
 use bytes::Bytes;
 use rml_rtmp::{
     sessions::StreamMetadata,
     time::RtmpTimestamp,
 };
+use javelin_codec::{
+    flv::{self, Tag, TagKind},
+    Error as CodecError,
+};
+use crate::error::Result;
 #[cfg(feature = "hls")]
 use futures::sync::mpsc;
-use chrono::prelude::{DateTime, Utc};
 
 
 #[cfg(feature = "hls")]
-pub type Receiver = mpsc::UnboundedReceiver<Media>;
+pub type Receiver = mpsc::UnboundedReceiver<Message>;
 #[cfg(feature = "hls")]
-pub type Sender = mpsc::UnboundedSender<Media>;
+pub type Sender = mpsc::UnboundedSender<Message>;
+
+
+/// Messages consumed by media writers
+#[cfg(feature = "hls")]
+#[derive(Debug, Clone)]
+pub enum Message {
+    Media(Media),
+    /// The following media does not continue the previous media, e.g. after a publisher reconnected
+    Discontinuity,
+}
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Media {
     AAC(RtmpTimestamp, Bytes),
     H264(RtmpTimestamp, Bytes),
+    /// HEVC in Enhanced RTMP video packets
+    H265(RtmpTimestamp, Bytes),
 }
 
 impl Media {
+    const FLV_CODEC_AVC: u8 = 7;
+    const FLV_SOUND_FORMAT_AAC: u8 = 10;
+    /// Enhanced RTMP packets set the highest bit and name their codec by FourCC
+    const FLV_EX_HEADER: u8 = 0x80;
+    const FOURCC_HEVC: &'static [u8] = b"hvc1";
+    const EX_PACKET_SEQUENCE_START: u8 = 0;
+    const EX_PACKET_CODED_FRAMES: u8 = 1;
+    const EX_PACKET_CODED_FRAMES_X: u8 = 3;
+
+    /// Wraps the data of a video message, H.264 (`avc1`) with the FLV codec ID
+    /// or HEVC (`hvc1`) in Enhanced RTMP packets.
+    pub fn video(timestamp: RtmpTimestamp, data: Bytes) -> Result<Self> {
+        let first = *data.first().ok_or(CodecError::NotEnoughData)?;
+
+        let codec = if first & Self::FLV_EX_HEADER != 0 {
+            match data.get(1..5) {
+                Some(fourcc) if fourcc == Self::FOURCC_HEVC => return Ok(Media::H265(timestamp, data)),
+                Some(fourcc) => String::from_utf8_lossy(fourcc).into_owned(),
+                None => return Err(CodecError::NotEnoughData.into()),
+            }
+        } else {
+            match first & 0x0F {
+                Self::FLV_CODEC_AVC => return Ok(Media::H264(timestamp, data)),
+                codec_id => format!("FLV video codec {}", codec_id),
+            }
+        };
+
+        Err(CodecError::UnsupportedCodec(codec).into())
+    }
+
+    /// Converts an FLV tag, returns `None` for script data and unsupported codecs.
+    pub fn from_flv_tag(tag: Tag) -> Option<Self> {
+        let timestamp = RtmpTimestamp::new(tag.timestamp);
+        let first = *tag.data.first()?;
+
+        match tag.kind {
+            TagKind::Video => Self::video(timestamp, tag.data).ok(),
+            TagKind::Audio if first >> 4 == Self::FLV_SOUND_FORMAT_AAC => Some(Media::AAC(timestamp, tag.data)),
+            _ => None,
+        }
+    }
+
+    pub fn to_flv_tag(&self) -> Tag {
+        match self {
+            Media::AAC(timestamp, data) => Tag::new(TagKind::Audio, timestamp.value, data.clone()),
+            Media::H264(timestamp, data) | Media::H265(timestamp, data) => {
+                Tag::new(TagKind::Video, timestamp.value, data.clone())
+            },
+        }
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        match self {
+            Media::AAC(timestamp, _) | Media::H264(timestamp, _) | Media::H265(timestamp, _) => timestamp.value,
+        }
+    }
+
+    pub fn set_timestamp(&mut self, value: u32) {
+        match self {
+            Media::AAC(timestamp, _) | Media::H264(timestamp, _) | Media::H265(timestamp, _) => {
+                *timestamp = RtmpTimestamp::new(value)
+            },
+        }
+    }
+
     pub fn is_sequence_header(&self) -> bool {
         match self {
             Media::AAC(_, ref bytes) => {
@@ -30,6 +110,9 @@ impl Media {
             Media::H264(_, ref bytes) => {
                 bytes.len() >= 2 && bytes[0] == 0x17 && bytes[1] == 0x00
             },
+            Media::H265(_, ref bytes) => {
+                bytes.len() >= 5 && bytes[0] & 0x0f == Self::EX_PACKET_SEQUENCE_START
+            },
         }
     }
 
@@ -37,7 +120,12 @@ impl Media {
         match self {
             Media::H264(_, bytes) => {
                 bytes.len() >= 2 && bytes[0] == 0x17 && bytes[1] != 0x00
-            }
+            },
+            Media::H265(_, bytes) if bytes.len() >= 5 => {
+                let packet_type = bytes[0] & 0x0f;
+                (bytes[0] >> 4) & 0x07 == 1
+                    && (packet_type == Self::EX_PACKET_CODED_FRAMES || packet_type == Self::EX_PACKET_CODED_FRAMES_X)
+            },
             _ => false
         }
     }
@@ -48,53 +136,112 @@ impl Media {
 }
 
 
-pub struct Channel {
-    pub publisher: Option<u64>,
-    pub stream_key: Option<String>,
-    pub watchers: HashSet<u64>,
-    pub metadata: Option<StreamMetadata>,
-    pub video_seq_header: Option<Bytes>,
-    pub audio_seq_header: Option<Bytes>,
-    pub publish_start: Option<DateTime<Utc>>,
-}
+/// Converts stream metadata into the properties of an `onMetaData` script tag.
+pub fn metadata_to_flv(metadata: &StreamMetadata) -> flv::Metadata {
+    let mut properties = flv::Metadata::new();
 
-impl Channel {
-    pub fn new() -> Self {
-        Self {
-            publisher: None,
-            stream_key: None,
-            watchers: HashSet::new(),
-            metadata: None,
-            video_seq_header: None,
-            audio_seq_header: None,
-            publish_start: None,
+    let mut number = |key: &str, value: Option<f64>| {
+        if let Some(value) = value {
+            properties.set(key, flv::Value::Number(value));
         }
+    };
+
+    number("width", metadata.video_width.map(f64::from));
+    number("height", metadata.video_height.map(f64::from));
+    number("framerate", metadata.video_frame_rate.map(f64::from));
+    number("videodatarate", metadata.video_bitrate_kbps.map(f64::from));
+    number("audiodatarate", metadata.audio_bitrate_kbps.map(f64::from));
+    number("audiosamplerate", metadata.audio_sample_rate.map(f64::from));
+    number("audiochannels", metadata.audio_channels.map(f64::from));
+
+    // Codecs are identified by their FLV codec ID or by name
+    let codec_id = |codec: &String| {
+        codec.parse::<f64>()
+            .map(flv::Value::Number)
+            .unwrap_or_else(|_| flv::Value::String(codec.clone()))
+    };
+
+    if let Some(codec) = &metadata.video_codec {
+        properties.set("videocodecid", codec_id(codec));
+    }
+
+    if let Some(codec) = &metadata.audio_codec {
+        properties.set("audiocodecid", codec_id(codec));
     }
 
-    pub fn set_publisher(&mut self, publisher_id: u64, stream_key: String) {
-        self.publisher = Some(publisher_id);
-        self.stream_key = Some(stream_key);
-        self.publish_start = Some(Utc::now());
+    if let Some(stereo) = metadata.audio_is_stereo {
+        properties.set("stereo", flv::Value::Boolean(stereo));
     }
 
-    #[allow(dead_code)]
-    pub fn has_publisher(&self) -> bool {
-        self.publisher != None
+    if let Some(encoder) = &metadata.encoder {
+        properties.set("encoder", flv::Value::String(encoder.clone()));
     }
 
-    pub fn add_watcher(&mut self, watcher_id: u64) {
-        self.watchers.insert(watcher_id);
+    properties
+}
+
+/// Converts the properties of an `onMetaData` script tag, unknown properties are dropped.
+pub fn metadata_from_flv(properties: &flv::Metadata) -> StreamMetadata {
+    let number = |key: &str| properties.number(key);
+
+    let codec_id = |key: &str| match properties.get(key) {
+        Some(flv::Value::Number(id)) => Some(id.to_string()),
+        Some(flv::Value::String(name)) => Some(name.clone()),
+        _ => None,
+    };
+
+    StreamMetadata {
+        video_width: number("width").map(|v| v as u32),
+        video_height: number("height").map(|v| v as u32),
+        video_codec: codec_id("videocodecid"),
+        video_frame_rate: number("framerate").map(|v| v as f32),
+        video_bitrate_kbps: number("videodatarate").map(|v| v as u32),
+        audio_codec: codec_id("audiocodecid"),
+        audio_bitrate_kbps: number("audiodatarate").map(|v| v as u32),
+        audio_sample_rate: number("audiosamplerate").map(|v| v as u32),
+        audio_channels: number("audiochannels").map(|v| v as u32),
+        audio_is_stereo: properties.boolean("stereo"),
+        encoder: properties.string("encoder").map(str::to_string),
     }
+}
+
 
-    pub fn unpublish(&mut self) {
-        self.publisher = None;
-        self.stream_key = None;
-        self.metadata = None;
-        self.video_seq_header = None;
-        self.audio_seq_header = None;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_survives_flv_script_tag() {
+        let metadata = StreamMetadata {
+            video_width: Some(1280),
+            video_height: Some(720),
+            video_codec: Some("7".to_string()),
+            video_frame_rate: Some(30.0),
+            video_bitrate_kbps: Some(2500),
+            audio_codec: Some("mp4a".to_string()),
+            audio_bitrate_kbps: Some(128),
+            audio_sample_rate: Some(44100),
+            audio_channels: Some(2),
+            audio_is_stereo: Some(true),
+            encoder: Some("obs-output module".to_string()),
+        };
+
+        let tag = metadata_to_flv(&metadata).to_tag(0);
+        let properties = flv::Metadata::try_from_tag(&tag).unwrap().unwrap();
+
+        assert_eq!(metadata_from_flv(&properties), metadata);
     }
 
-    pub fn set_metadata(&mut self, metadata: StreamMetadata) {
-        self.metadata = Some(metadata)
+    #[test]
+    fn classifies_video_codecs() {
+        let video = |data: &'static [u8]| Media::video(RtmpTimestamp::new(0), Bytes::from_static(data));
+
+        assert!(matches!(video(&[0x17, 0x00, 0x00, 0x00, 0x00]), Ok(Media::H264(..))));
+        assert!(matches!(video(&[0x90, b'h', b'v', b'c', b'1']), Ok(Media::H265(..))));
+
+        // VP6, Enhanced RTMP AV1 and an empty message
+        for data in [&[0x14, 0x00][..], &[0x90, b'a', b'v', b'0', b'1'][..], &[][..]].iter() {
+            assert!(video(data).is_err());
+        }
     }
 }
@@ -0,0 +1,137 @@
+use log::info;
+use crate::{
+    channel::{self, is_valid_app_name, Publisher},
+    config::RepublishAction,
+    rtmp::peer,
+    shared::Shared,
+};
+#[cfg(feature = "hls")]
+use crate::hls;
+
+
+/// Why publishing was not allowed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Denied {
+    InvalidAppName,
+    StreamKeyNotPermitted,
+    AlreadyPublished,
+}
+
+
+/// Lets a publisher that is not an RTMP client publish to an application.
+///
+/// The stream key has to be permitted, and a channel that is already being published to
+/// is handled according to the republish action, just like for RTMP publishers.
+/// The publisher either becomes the publisher or the backup of the channel.
+pub fn claim(app_name: &str, stream_key: String, publisher_id: u64, sender: peer::Sender, shared: &Shared) -> Result<channel::Handle, Denied> {
+    if !is_valid_app_name(app_name) {
+        return Err(Denied::InvalidAppName);
+    }
+
+    let republish_action = {
+        let config = shared.config.read();
+        if stream_key.is_empty() || !config.permitted_stream_keys.contains(&stream_key) {
+            return Err(Denied::StreamKeyNotPermitted);
+        }
+        config.republish_action
+    };
+
+    let handle = shared.channel_or_create(app_name);
+
+    {
+        let mut channel = handle.lock();
+        let publisher = Publisher::new(publisher_id, stream_key, sender);
+
+        match (channel.publisher(), republish_action) {
+            (None, _) => channel.set_publisher(publisher),
+            (Some(current), RepublishAction::Replace) => {
+                info!("Another client is already publishing to this app, removing client");
+                current.disconnect();
+                channel.unpublish();
+                channel.set_publisher(publisher);
+            },
+            (Some(_), RepublishAction::Backup) if !channel.has_backup() => channel.set_backup(publisher),
+            (Some(_), _) => return Err(Denied::AlreadyPublished),
+        }
+    }
+
+    #[cfg(feature = "hls")]
+    {
+        if handle.lock().is_publisher(publisher_id) {
+            hls::server::ensure_writer(&handle, shared);
+        }
+    }
+
+    Ok(handle)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::{sync::mpsc, Stream};
+    use crate::{
+        config::Config,
+        shutdown::Shutdown,
+    };
+    use super::*;
+
+    fn shared(republish_action: &str) -> Shared {
+        let (shutdown, _) = Shutdown::new();
+        let args = ["javelin", "--permit-stream-key", "key", "--republish-action", republish_action];
+        Shared::with_config(Config::from_args(&args), shutdown)
+    }
+
+    fn claim_as(id: u64, shared: &Shared) -> (Result<channel::Handle, Denied>, mpsc::UnboundedReceiver<peer::Message>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (claim("live", "key".to_string(), id, sender, shared), receiver)
+    }
+
+    #[test]
+    fn requires_valid_app_and_permitted_key() {
+        let shared = shared("replace");
+        let (sender, _receiver) = mpsc::unbounded();
+
+        assert_eq!(claim("../live", "key".to_string(), 1, sender.clone(), &shared).err(), Some(Denied::InvalidAppName));
+        assert_eq!(claim("live", "other".to_string(), 1, sender.clone(), &shared).err(), Some(Denied::StreamKeyNotPermitted));
+        assert_eq!(claim("live", String::new(), 1, sender, &shared).err(), Some(Denied::StreamKeyNotPermitted));
+    }
+
+    #[test]
+    fn replaces_publisher() {
+        let shared = shared("replace");
+        let (first, first_receiver) = claim_as(1, &shared);
+        let (second, _second_receiver) = claim_as(2, &shared);
+
+        assert!(first.is_ok());
+        assert!(second.unwrap().lock().is_publisher(2));
+
+        match first_receiver.wait().next() {
+            Some(Ok(peer::Message::Disconnect)) => (),
+            _ => panic!("Expected the replaced publisher to be disconnected"),
+        }
+    }
+
+    #[test]
+    fn denies_second_publisher() {
+        let shared = shared("deny");
+        let (first, _first_receiver) = claim_as(1, &shared);
+        let (second, _second_receiver) = claim_as(2, &shared);
+
+        assert!(first.unwrap().lock().is_publisher(1));
+        assert_eq!(second.err(), Some(Denied::AlreadyPublished));
+    }
+
+    #[test]
+    fn keeps_single_backup() {
+        let shared = shared("backup");
+        let (first, _first_receiver) = claim_as(1, &shared);
+        let (second, _second_receiver) = claim_as(2, &shared);
+        let (third, _third_receiver) = claim_as(3, &shared);
+
+        let channel = first.unwrap();
+        assert!(second.is_ok());
+        assert!(channel.lock().is_publisher(1));
+        assert!(channel.lock().has_backup());
+        assert_eq!(third.err(), Some(Denied::AlreadyPublished));
+    }
+}
@@ -0,0 +1,451 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process::Command,
+    thread,
+};
+use log::{debug, error, info, warn};
+use futures::{sync::mpsc, try_ready};
+use tokio::prelude::*;
+use bytes::Bytes;
+use chrono::Utc;
+use rml_rtmp::sessions::StreamMetadata;
+use javelin_codec::{
+    avc,
+    aac,
+    hevc,
+    flv::{self, Tag, TagKind},
+    mp4,
+    SharedState,
+};
+use crate::{
+    config::{RecordConfig, RecordFormat},
+    error::{Error, Result},
+    media::{self, Media},
+    relay::{self, Message},
+    shutdown,
+};
+
+
+enum Output {
+    Flv(flv::Writer<BufWriter<File>>),
+    Mp4(Box<mp4::Writer<BufWriter<File>>>),
+}
+
+
+/// The file a recording is currently written to
+struct Segment {
+    path: PathBuf,
+    output: Output,
+    /// Timestamp of the stream at the start of the file
+    start: u32,
+    size: u64,
+}
+
+impl Segment {
+    /// Writes an FLV tag, MP4 files get their metadata from the decoder configuration.
+    fn write_tag(&mut self, tag: &Tag) -> Result<()> {
+        if let Output::Flv(writer) = &mut self.output {
+            self.size += writer.write_tag(tag)? as u64;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<PathBuf> {
+        match self.output {
+            Output::Flv(mut writer) => writer.flush()?,
+            Output::Mp4(writer) => writer.finish()?.flush()?,
+        }
+
+        Ok(self.path)
+    }
+}
+
+
+/// Writes the stream of a channel to FLV or fragmented MP4 files.
+///
+/// Every file starts with the metadata and the decoder configuration,
+/// so it can be played on its own. Files are split at keyframes once they exceed
+/// the maximum duration or size.
+pub struct Recorder {
+    app_name: String,
+    stream_key: String,
+    config: RecordConfig,
+    receiver: relay::Receiver,
+    metadata: Option<StreamMetadata>,
+    video_seq_header: Option<Bytes>,
+    audio_seq_header: Option<Bytes>,
+    /// Parsed decoder configuration, required for MP4 files
+    codec_state: SharedState,
+    last_timestamp: u32,
+    segment: Option<Segment>,
+    /// Set once H.265 video arrived for MP4 files, which are recorded without video then
+    skips_video: bool,
+    /// Message waiting for the blocking file I/O to be allowed
+    pending: Option<Message>,
+    _token: Option<shutdown::Token>,
+}
+
+impl Recorder {
+    /// Attempts to find a free file name before giving up on a segment
+    const MAX_FILE_NAME_ATTEMPTS: usize = 1000;
+
+    /// Creates a recorder, the stream ends once the returned sender is dropped.
+    pub fn create(app_name: String, stream_key: String, config: RecordConfig, token: Option<shutdown::Token>) -> (Self, relay::Sender) {
+        let (sender, receiver) = mpsc::unbounded();
+
+        let recorder = Self {
+            app_name,
+            stream_key,
+            config,
+            receiver,
+            metadata: None,
+            video_seq_header: None,
+            audio_seq_header: None,
+            codec_state: SharedState::new(),
+            last_timestamp: 0,
+            segment: None,
+            skips_video: false,
+            pending: None,
+            _token: token,
+        };
+
+        (recorder, sender)
+    }
+
+    fn filename(&self) -> String {
+        // Values must not escape the recording directory
+        let sanitize = |value: &str| value.replace(&['/', '\\'][..], "_");
+
+        self.config.filename_template
+            .replace("{app}", &sanitize(&self.app_name))
+            .replace("{key}", &sanitize(&self.stream_key))
+            .replace("{timestamp}", &Utc::now().format("%Y%m%d-%H%M%S").to_string())
+    }
+
+    /// Creates the file of a new segment without replacing existing recordings.
+    ///
+    /// Names that are taken, e.g. by a segment started in the same second, get a sequence number.
+    fn create_file(&self) -> Result<(PathBuf, File)> {
+        let name = self.filename();
+        let extension = self.config.format.extension();
+
+        for sequence in 0..Self::MAX_FILE_NAME_ATTEMPTS {
+            let filename = match sequence {
+                0 => format!("{}.{}", name, extension),
+                _ => format!("{}-{}.{}", name, sequence, extension),
+            };
+            let path = self.config.dir.join(filename);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((path, file)),
+                Err(ref why) if why.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(why) => return Err(why.into()),
+            }
+        }
+
+        Err(Error::from(format!("No free file name for recording '{}'", name)))
+    }
+
+    /// Starts a new file with everything required to decode it.
+    fn open(&mut self, start: u32) -> Result<()> {
+        fs::create_dir_all(&self.config.dir)?;
+
+        let (path, file) = self.create_file()?;
+        debug!("Recording app '{}' to '{}'", self.app_name, path.display());
+
+        let file = BufWriter::new(file);
+
+        let output = match self.config.format {
+            RecordFormat::Flv => {
+                let header = flv::Header {
+                    has_audio: self.audio_seq_header.is_some(),
+                    has_video: self.video_seq_header.is_some(),
+                };
+                Output::Flv(flv::Writer::new(file, header)?)
+            },
+            RecordFormat::Mp4 => {
+                let (video, audio) = self.mp4_tracks()?;
+                Output::Mp4(Box::new(mp4::Writer::new(file, video, audio)?))
+            },
+        };
+
+        let mut segment = Segment { path, output, start, size: 0 };
+
+        if let Some(metadata) = &self.metadata {
+            segment.write_tag(&media::metadata_to_flv(metadata).to_tag(0))?;
+        }
+
+        if let Some(data) = &self.video_seq_header {
+            segment.write_tag(&Tag::new(TagKind::Video, 0, data.clone()))?;
+        }
+
+        if let Some(data) = &self.audio_seq_header {
+            segment.write_tag(&Tag::new(TagKind::Audio, 0, data.clone()))?;
+        }
+
+        self.segment = Some(segment);
+
+        Ok(())
+    }
+
+    fn mp4_tracks(&self) -> Result<(Option<mp4::VideoTrack>, Option<mp4::AudioTrack>)> {
+        let (width, height) = self.metadata.as_ref().map_or((0, 0), |metadata| {
+            (metadata.video_width.unwrap_or(0) as u16, metadata.video_height.unwrap_or(0) as u16)
+        });
+
+        let video = self.codec_state.dcr.read().clone()
+            .map(|dcr| mp4::VideoTrack { dcr, width, height });
+
+        let audio = self.codec_state.asc.read().clone()
+            .map(|asc| mp4::AudioTrack { asc });
+
+        if video.is_none() && audio.is_none() {
+            return Err(Error::from("Decoder configuration missing"));
+        }
+
+        Ok((video, audio))
+    }
+
+    /// Finishes the current file and runs the hook.
+    fn close(&mut self) -> Result<()> {
+        let path = match self.segment.take() {
+            Some(segment) => segment.finish()?,
+            None => return Ok(()),
+        };
+
+        info!("Finished recording of app '{}' at '{}'", self.app_name, path.display());
+
+        if let Some(command) = &self.config.on_record_done {
+            run_hook(command, path);
+        }
+
+        Ok(())
+    }
+
+    fn is_full(&self, segment: &Segment, timestamp: u32) -> bool {
+        let duration = u64::from(timestamp.saturating_sub(segment.start));
+        let too_long = self.config.max_duration.is_some_and(|max| duration >= max.as_secs() * 1000);
+        let too_large = self.config.max_size.is_some_and(|max| segment.size >= max);
+
+        too_long || too_large
+    }
+
+    fn handle_sequence_header(&mut self, media: &Media) -> Result<()> {
+        let changed = match media {
+            Media::H264(_, data) => {
+                avc::Packet::try_from_buf(data.clone(), 0, &self.codec_state)?;
+                self.video_seq_header.replace(data.clone()).as_ref() != Some(data)
+            },
+            Media::H265(_, data) => {
+                hevc::Packet::try_from_buf(data.clone(), 0, &self.codec_state)?;
+                self.video_seq_header.replace(data.clone()).as_ref() != Some(data)
+            },
+            Media::AAC(_, data) => {
+                aac::Packet::try_from_bytes(data.clone(), 0, &self.codec_state)?;
+                self.audio_seq_header.replace(data.clone()).as_ref() != Some(data)
+            },
+        };
+
+        // The tracks of an MP4 file are only described at its start
+        if changed && self.config.format == RecordFormat::Mp4 {
+            self.close()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_media(&mut self, media: Media) -> Result<()> {
+        if let Media::H265(..) = media {
+            if self.config.format == RecordFormat::Mp4 {
+                if !self.skips_video {
+                    warn!("MP4 recordings only support H.264, video of app '{}' is not recorded", self.app_name);
+                    self.skips_video = true;
+                }
+                return Ok(());
+            }
+        }
+
+        if media.is_sequence_header() {
+            self.handle_sequence_header(&media)?;
+        }
+
+        let timestamp = media.timestamp();
+        self.last_timestamp = timestamp;
+
+        // Files of streams with video start with a keyframe, audio only streams may start anywhere
+        let can_start = media.is_keyframe() || (self.video_seq_header.is_none() && !media.is_sequence_header());
+
+        if can_start && self.segment.as_ref().is_some_and(|segment| self.is_full(segment, timestamp)) {
+            self.close()?;
+        }
+
+        if self.segment.is_none() {
+            if !can_start {
+                return Ok(());
+            }
+            self.open(timestamp)?;
+        }
+
+        self.write_media(media)
+    }
+
+    fn write_media(&mut self, mut media: Media) -> Result<()> {
+        let segment = match self.segment.as_mut() {
+            Some(segment) => segment,
+            None => return Ok(()),
+        };
+
+        let timestamp = media.timestamp().saturating_sub(segment.start);
+        media.set_timestamp(timestamp);
+
+        let written = match (&mut segment.output, media) {
+            (Output::Flv(writer), media) => writer.write_tag(&media.to_flv_tag())?,
+            (Output::Mp4(writer), Media::H264(_, data)) => {
+                let packet = avc::Packet::try_from_buf(data, u64::from(timestamp), &self.codec_state)?;
+                writer.write_video(&packet)?
+            },
+            // Left out before, see `handle_media`
+            (Output::Mp4(_), Media::H265(..)) => 0,
+            (Output::Mp4(writer), Media::AAC(_, data)) => {
+                let packet = aac::Packet::try_from_bytes(data, u64::from(timestamp), &self.codec_state)?;
+                writer.write_audio(&packet)?
+            },
+        };
+
+        segment.size += written as u64;
+
+        Ok(())
+    }
+
+    fn handle_metadata(&mut self, metadata: StreamMetadata) -> Result<()> {
+        if let Some(segment) = self.segment.as_mut() {
+            let timestamp = self.last_timestamp.saturating_sub(segment.start);
+            segment.write_tag(&media::metadata_to_flv(&metadata).to_tag(timestamp))?;
+        }
+
+        self.metadata = Some(metadata);
+
+        Ok(())
+    }
+
+    fn handle(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::Metadata(metadata) => self.handle_metadata(metadata),
+            Message::Media(media) => self.handle_media(media),
+            Message::End => self.close(),
+        }
+    }
+
+    fn handle_pending(&mut self) {
+        let message = match self.pending.take() {
+            Some(message) => message,
+            None => return,
+        };
+
+        if let Err(why) = self.handle(message) {
+            error!("Failed to record app '{}': {:?}", self.app_name, why);
+            // Continue in a new file, the current one might be incomplete
+            self.segment = None;
+        }
+    }
+}
+
+impl Future for Recorder {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if self.pending.is_none() {
+                match try_ready!(self.receiver.poll()) {
+                    Some(message) => self.pending = Some(message),
+                    None => break,
+                }
+            }
+
+            try_ready!(blocking(|| self.handle_pending()));
+        }
+
+        try_ready!(blocking(|| self.close()))
+            .map_err(|why| error!("Failed to finish recording of app '{}': {:?}", self.app_name, why))?;
+
+        Ok(Async::Ready(()))
+    }
+}
+
+
+/// Runs file I/O on the blocking pool, so it does not hold up the other tasks of the worker.
+///
+/// Outside of the thread pool, e.g. on a current thread runtime, it runs right away.
+fn blocking<F, T>(mut f: F) -> Poll<T, ()> where F: FnMut() -> T {
+    match tokio_threadpool::blocking(&mut f) {
+        Ok(result) => Ok(result),
+        Err(_) => Ok(Async::Ready(f())),
+    }
+}
+
+
+/// Runs the hook for a finished recording without blocking the runtime.
+fn run_hook(command: &str, path: PathBuf) {
+    let mut child = match Command::new(command).arg(&path).spawn() {
+        Ok(child) => child,
+        Err(why) => {
+            error!("Failed to run '{}' for recording '{}': {:?}", command, path.display(), why);
+            return;
+        },
+    };
+
+    let command = command.to_string();
+    thread::spawn(move || {
+        match child.wait() {
+            Ok(status) if !status.success() => error!("'{}' failed for recording '{}': {}", command, path.display(), status),
+            Err(why) => error!("Failed to wait for '{}': {:?}", command, why),
+            _ => (),
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use rml_rtmp::time::RtmpTimestamp;
+    use crate::config::Config;
+    use super::*;
+
+    #[test]
+    fn keeps_existing_recordings() {
+        let dir = env::temp_dir().join(format!("javelin-record-{}", std::process::id()));
+        let config = Config::from_args(&["javelin", "--record-dir", dir.to_str().unwrap(), "--record-filename", "{app}"]);
+        let (recorder, _sender) = Recorder::create("live".to_string(), "key".to_string(), config.record, None);
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("live.flv"), b"first").unwrap();
+
+        let (path, _) = recorder.create_file().unwrap();
+        assert_eq!(path, dir.join("live-1.flv"));
+        let (path, _) = recorder.create_file().unwrap();
+        assert_eq!(path, dir.join("live-2.flv"));
+
+        assert_eq!(fs::read(dir.join("live.flv")).unwrap(), b"first");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_h265_out_of_mp4_recordings() {
+        let config = Config::from_args(&["javelin", "--record-format", "mp4"]);
+        let (mut recorder, _sender) = Recorder::create("live".to_string(), "key".to_string(), config.record, None);
+        let seq_header = Bytes::from_static(&[0x90, b'h', b'v', b'c', b'1']);
+
+        for _ in 0..2 {
+            let media = Media::video(RtmpTimestamp::new(0), seq_header.clone()).unwrap();
+            recorder.handle_media(media).unwrap();
+        }
+
+        assert!(recorder.skips_video);
+        assert!(recorder.video_seq_header.is_none());
+        assert!(recorder.segment.is_none());
+    }
+}
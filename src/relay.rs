@@ -0,0 +1,121 @@
+mod url;
+mod connection;
+pub mod push;
+pub mod pull;
+pub mod edge;
+
+
+use std::{
+    cmp,
+    fmt,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    time::Duration,
+};
+use parking_lot::RwLock;
+use futures::{future, sync::mpsc, Async, Future};
+use rml_rtmp::sessions::StreamMetadata;
+use crate::{
+    error::{Error, Result},
+    media::Media,
+};
+
+pub use self::url::Url;
+
+
+/// Messages consumed by relays
+#[derive(Debug, Clone)]
+pub enum Message {
+    Metadata(StreamMetadata),
+    Media(Media),
+    /// The stream ended, the relay stops until the next stream starts
+    End,
+}
+
+pub type Sender = mpsc::UnboundedSender<Message>;
+pub type Receiver = mpsc::UnboundedReceiver<Message>;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    Connecting,
+    Active,
+    Retrying,
+}
+
+impl State {
+    #[allow(dead_code)]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Connecting => "connecting",
+            State::Active => "active",
+            State::Retrying => "retrying",
+        }
+    }
+}
+
+
+/// Connection state of a relay as reported by the API
+#[derive(Clone, Debug)]
+pub struct Status {
+    pub state: State,
+    pub retries: u32,
+    pub last_error: Option<String>,
+}
+
+impl Status {
+    fn new() -> Self {
+        Self { state: State::Idle, retries: 0, last_error: None }
+    }
+}
+
+pub type StatusHandle = Arc<RwLock<Status>>;
+
+
+/// Exponentially growing delay between reconnection attempts
+struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    const INITIAL_DELAY: u64 = 1; // seconds
+    const MAX_DELAY: u64 = 60; // seconds
+
+    fn new() -> Self {
+        Self { attempt: 0 }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = Self::INITIAL_DELAY << cmp::min(self.attempt, 6);
+        self.attempt += 1;
+        Duration::from_secs(cmp::min(delay, Self::MAX_DELAY))
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+
+/// Resolves the address without blocking the worker thread,
+/// as the system resolver may take seconds to answer.
+fn resolve<A>(addr: A) -> impl Future<Item = SocketAddr, Error = Error> + Send
+    where A: ToSocketAddrs + fmt::Debug + Send + 'static
+{
+    let lookup = move || -> Result<SocketAddr> {
+        addr.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::from(format!("Failed to resolve {:?}", addr)))
+    };
+
+    future::poll_fn(move || {
+        match tokio_threadpool::blocking(&lookup) {
+            Ok(result) => Ok(result),
+            // Outside of the thread pool, e.g. on a current thread runtime
+            Err(_) => Ok(Async::Ready(lookup())),
+        }
+    })
+    .and_then(future::result)
+}
@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use log::debug;
+use futures::{future, try_ready};
+use tokio::{
+    prelude::*,
+    net::TcpStream,
+};
+use rml_rtmp::{
+    handshake::{
+        Handshake,
+        HandshakeProcessResult,
+        PeerType,
+    },
+    sessions::{
+        ClientSession,
+        ClientSessionConfig,
+        ClientSessionEvent,
+        ClientSessionResult,
+    },
+};
+use crate::{
+    error::{Error, Result},
+    rtmp::BytesStream,
+};
+use super::Url;
+
+
+pub trait Socket: AsyncRead + AsyncWrite + Send {}
+
+impl<T> Socket for T where T: AsyncRead + AsyncWrite + Send {}
+
+
+pub type Connect = Box<dyn Future<Item = Connection, Error = Error> + Send>;
+
+
+/// An outgoing connection to a remote RTMP server.
+///
+/// Performs the handshake and requests a connection to the application of the URL,
+/// everything after that is up to the owner reacting to the raised session events.
+pub struct Connection {
+    url: Url,
+    bytes_stream: BytesStream<Box<dyn Socket>>,
+    handshake: Handshake,
+    session: Option<ClientSession>,
+    events: VecDeque<ClientSessionEvent>,
+}
+
+impl Connection {
+    fn new(url: Url, socket: Box<dyn Socket>) -> Result<Self> {
+        let mut handshake = Handshake::new(PeerType::Client);
+        let p0_and_p1 = handshake.generate_outbound_p0_and_p1()
+            .map_err(|_| Error::HandshakeFailed)?;
+
+        let mut bytes_stream = BytesStream::new(socket);
+        bytes_stream.fill_write_buffer(&p0_and_p1);
+
+        Ok(Self {
+            url,
+            bytes_stream,
+            handshake,
+            session: None,
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn session(&mut self) -> Result<&mut ClientSession> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| Error::SessionError("Session not established yet".into()))
+    }
+
+    pub fn send(&mut self, result: ClientSessionResult) -> Result<()> {
+        self.handle_results(vec![result])
+    }
+
+    pub fn handle_results(&mut self, results: Vec<ClientSessionResult>) -> Result<()> {
+        for result in results {
+            match result {
+                ClientSessionResult::OutboundResponse(packet) => {
+                    self.bytes_stream.fill_write_buffer(&packet.bytes);
+                },
+                ClientSessionResult::RaisedEvent(event) => {
+                    self.events.push_back(event);
+                },
+                ClientSessionResult::UnhandleableMessageReceived(_) => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn poll_flush(&mut self) -> Poll<(), Error> {
+        self.bytes_stream.poll_flush().map_err(Error::from)
+    }
+
+    /// Returns the next session event, or `None` once the remote closed the connection.
+    pub fn poll_event(&mut self) -> Poll<Option<ClientSessionEvent>, Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+
+            let _ = self.poll_flush()?;
+
+            match try_ready!(self.bytes_stream.poll()) {
+                Some(data) => self.handle_input(&data)?,
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+
+    fn handle_input(&mut self, data: &[u8]) -> Result<()> {
+        let results = match self.session {
+            Some(ref mut session) => session.handle_input(data)?,
+            None => return self.handle_handshake(data),
+        };
+
+        self.handle_results(results)
+    }
+
+    fn handle_handshake(&mut self, data: &[u8]) -> Result<()> {
+        match self.handshake.process_bytes(data).map_err(|_| Error::HandshakeFailed)? {
+            HandshakeProcessResult::InProgress { response_bytes } => {
+                self.bytes_stream.fill_write_buffer(&response_bytes);
+            },
+            HandshakeProcessResult::Completed { response_bytes, remaining_bytes } => {
+                debug!("Handshake with {} successful", self.url);
+                self.bytes_stream.fill_write_buffer(&response_bytes);
+
+                let mut session = ClientSession::new(ClientSessionConfig::new());
+                let request = session.request_connection(self.url.app.clone())?;
+                self.session = Some(session);
+                self.send(request)?;
+
+                if !remaining_bytes.is_empty() {
+                    self.handle_input(&remaining_bytes)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Connects to the server of the URL, using TLS for `rtmps` URLs.
+pub fn connect(url: &Url) -> Connect {
+    let url = url.clone();
+    let stream = super::resolve((url.host.clone(), url.port))
+        .and_then(|addr| TcpStream::connect(&addr).map_err(Error::from));
+
+    if url.secure {
+        let host = url.host.clone();
+        Box::new(stream
+            .and_then(move |stream| secure(host, stream))
+            .and_then(move |socket| Connection::new(url, socket)))
+    } else {
+        Box::new(stream.and_then(move |stream| Connection::new(url, Box::new(stream))))
+    }
+}
+
+#[cfg(feature = "tls")]
+fn secure(host: String, stream: TcpStream) -> Box<dyn Future<Item = Box<dyn Socket>, Error = Error> + Send> {
+    let connector = match native_tls::TlsConnector::new() {
+        Ok(connector) => tokio_tls::TlsConnector::from(connector),
+        Err(why) => return Box::new(future::err(Error::from(format!("TLS error: {:?}", why)))),
+    };
+
+    Box::new(connector
+        .connect(&host, stream)
+        .map(|stream| Box::new(stream) as Box<dyn Socket>)
+        .map_err(|why| Error::from(format!("TLS error: {:?}", why))))
+}
+
+#[cfg(not(feature = "tls"))]
+fn secure(_host: String, _stream: TcpStream) -> Box<dyn Future<Item = Box<dyn Socket>, Error = Error> + Send> {
+    Box::new(future::err(Error::from("RTMPS requires TLS support")))
+}
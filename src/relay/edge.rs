@@ -0,0 +1,167 @@
+use std::time::Duration;
+use log::{debug, error, info};
+use futures::{future, stream};
+use tokio::{
+    prelude::*,
+    io,
+    net::TcpStream,
+    timer::Timeout,
+};
+use serde_json::Value as JsonValue;
+use crate::{
+    channel::{self, is_valid_app_name},
+    error::{Error, Result},
+    shared::Shared,
+};
+use super::Url;
+
+
+/// Maximum time an origin has to answer a lookup
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+
+/// Looks up which origin has the application of the channel and pulls it from there.
+/// The pull ends once the last watcher left.
+pub fn pull_from_origins(handle: &channel::Handle, shared: &Shared) {
+    let origins = shared.config.read().origins.clone();
+
+    let app_name = {
+        let mut channel = handle.lock();
+
+        if !channel.needs_pull() || channel.is_locating() {
+            return;
+        }
+
+        channel.set_locating(true);
+        channel.app_name.clone()
+    };
+
+    debug!("Looking up app '{}' on {} origins", app_name, origins.len());
+
+    let handle = handle.clone();
+    let shared = shared.clone();
+
+    let task = locate(app_name, origins).then(move |result| {
+        let mut channel = handle.lock();
+        channel.set_locating(false);
+
+        match result {
+            Ok(Some(url)) => {
+                if channel.watcher_count() == 0 || !channel.needs_pull() {
+                    debug!("App '{}' no longer needs to be pulled", channel.app_name);
+                } else if let Err(why) = channel.start_pull(url.as_str(), true, &shared) {
+                    error!("Failed to pull app '{}' from origin: {:?}", channel.app_name, why);
+                }
+            },
+            Ok(None) => info!("No origin has app '{}'", channel.app_name),
+            Err(why) => error!("Failed to look up app '{}': {:?}", channel.app_name, why),
+        }
+
+        Ok(())
+    });
+
+    tokio::spawn(task);
+}
+
+/// Asks the origins in order, resolves to the URL of the first one that has the application.
+fn locate(app_name: String, origins: Vec<String>) -> impl Future<Item = Option<Url>, Error = Error> {
+    stream::iter_ok(origins)
+        .and_then(move |origin| {
+            lookup(&origin, &app_name).or_else(move |why| {
+                debug!("Lookup on origin {} failed: {:?}", origin, why);
+                Ok(None)
+            })
+        })
+        .filter_map(|url| url)
+        .into_future()
+        .map(|(url, _)| url)
+        .map_err(|(why, _)| why)
+}
+
+/// Queries the locate endpoint of an origin's API.
+fn lookup(origin: &str, app_name: &str) -> Box<dyn Future<Item = Option<Url>, Error = Error> + Send> {
+    let request = match lookup_request(origin, app_name) {
+        Ok(request) => request,
+        Err(why) => return Box::new(future::err(why)),
+    };
+
+    let host = origin.rsplitn(2, ':').last().unwrap_or(origin).to_string();
+    let app_name = app_name.to_string();
+
+    let response = super::resolve(origin.to_string())
+        .and_then(|addr| TcpStream::connect(&addr).map_err(Error::from))
+        .and_then(move |stream| io::write_all(stream, request).map_err(Error::from))
+        .and_then(|(stream, _)| io::read_to_end(stream, Vec::new()).map_err(Error::from))
+        .and_then(move |(_, response)| parse_response(&response, &host, &app_name));
+
+    Box::new(Timeout::new(response, LOOKUP_TIMEOUT).map_err(|why| {
+        why.into_inner().unwrap_or_else(|| Error::from("Lookup timed out"))
+    }))
+}
+
+/// The application name ends up in the request line, so anything that could
+/// change the request is refused.
+fn lookup_request(origin: &str, app_name: &str) -> Result<String> {
+    if !is_valid_app_name(app_name) {
+        return Err(Error::from(format!("Invalid application name {:?}", app_name)));
+    }
+
+    Ok(format!("GET /api/locate/{} HTTP/1.0\r\nHost: {}\r\n\r\n", app_name, origin))
+}
+
+fn parse_response(response: &[u8], host: &str, app_name: &str) -> Result<Option<Url>> {
+    let header_end = response.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| Error::from("Invalid HTTP response"))?;
+
+    let (header, body) = response.split_at(header_end + 4);
+
+    // Anything but a success means the origin does not have the stream
+    if !header.starts_with(b"HTTP/1.1 200") && !header.starts_with(b"HTTP/1.0 200") {
+        return Ok(None);
+    }
+
+    let json: JsonValue = serde_json::from_slice(body)
+        .map_err(|why| Error::from(format!("Invalid lookup response: {:?}", why)))?;
+
+    if json["live"].as_bool() != Some(true) {
+        return Ok(None);
+    }
+
+    let rtmp_port = json["rtmp_port"].as_u64()
+        .ok_or_else(|| Error::from("Lookup response is missing the RTMP port"))?;
+
+    // Javelin only looks at the application name of watchers, the stream key is arbitrary
+    let url = format!("rtmp://{}:{}/{}/{}", host, rtmp_port, app_name, app_name).parse()?;
+
+    Ok(Some(url))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_location_of_app() {
+        assert_eq!(
+            lookup_request("origin:8080", "live").unwrap(),
+            "GET /api/locate/live HTTP/1.0\r\nHost: origin:8080\r\n\r\n");
+    }
+
+    #[test]
+    fn refuses_app_names_that_change_the_request() {
+        assert!(lookup_request("origin:8080", "live HTTP/1.0\r\nX-Injected: 1\r\n").is_err());
+        assert!(lookup_request("origin:8080", "../admin").is_err());
+        assert!(lookup_request("origin:8080", "live?x=1").is_err());
+    }
+
+    #[test]
+    fn parses_lookup_response() {
+        let response = b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\r\n{\"app_name\":\"live\",\"live\":true,\"rtmp_port\":1935}";
+        let url = parse_response(response, "origin", "live").unwrap().unwrap();
+
+        assert_eq!(url.as_str(), "rtmp://origin:1935/live/live");
+        assert_eq!(parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n", "origin", "live").unwrap(), None);
+    }
+}
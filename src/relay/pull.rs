@@ -0,0 +1,451 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use log::{debug, error, info, warn};
+use parking_lot::RwLock;
+use futures::sync::{mpsc, oneshot};
+use tokio::{
+    prelude::*,
+    timer::Delay,
+};
+use rml_rtmp::sessions::ClientSessionEvent;
+use crate::{
+    channel::{self, Publisher},
+    error::{Error, Result},
+    media::Media,
+    rtmp::peer,
+    shared::Shared,
+    shutdown,
+};
+#[cfg(feature = "hls")]
+use crate::hls;
+use super::{
+    connection::{self, Connect, Connection},
+    edge,
+    Backoff,
+    State as Status,
+    StatusHandle,
+    Url,
+};
+
+
+/// A remote stream a channel gets its media from
+pub struct Target {
+    pub url: Url,
+    #[allow(dead_code)]
+    pub status: StatusHandle,
+    /// Pulls started by a watcher end once the last watcher left
+    pub on_demand: bool,
+    /// Dropping the sender stops the pull
+    _stop: oneshot::Sender<()>,
+}
+
+
+enum State {
+    Idle,
+    Waiting(Delay),
+    Connecting(Connect),
+    Connected {
+        connection: Box<Connection>,
+        playing: bool,
+        timeout: Delay,
+    },
+}
+
+
+/// Plays a stream of a remote RTMP server and publishes it to a channel,
+/// like a local publisher would.
+///
+/// Keeps on reconnecting with increasing delays until it is stopped.
+pub struct Pull {
+    id: u64,
+    app_name: String,
+    url: Url,
+    channel: channel::Handle,
+    #[cfg_attr(not(feature = "hls"), allow(dead_code))]
+    shared: Shared,
+    status: StatusHandle,
+    state: State,
+    /// Receives the disconnect request when the channel replaces the pull
+    sender: peer::Sender,
+    receiver: mpsc::UnboundedReceiver<peer::Message>,
+    stop: oneshot::Receiver<()>,
+    backoff: Backoff,
+    shutdown: shutdown::Signal,
+}
+
+impl Pull {
+    /// Maximum time from connecting until the remote started playback
+    const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn create(app_name: String, url: Url, on_demand: bool, channel: channel::Handle, shared: Shared) -> (Self, Target) {
+        let (sender, receiver) = mpsc::unbounded();
+        let (stop_sender, stop) = oneshot::channel();
+        let status = Arc::new(RwLock::new(super::Status::new()));
+
+        let target = Target { url: url.clone(), status: status.clone(), on_demand, _stop: stop_sender };
+
+        let pull = Self {
+            id: shared.next_client_id(),
+            app_name,
+            url,
+            channel,
+            status,
+            // Connecting is left to the task, resolving the host name blocks
+            state: State::Waiting(Delay::new(Instant::now())),
+            sender,
+            receiver,
+            stop,
+            backoff: Backoff::new(),
+            shutdown: shared.shutdown.signal(),
+            shared,
+        };
+
+        info!("Pulling app '{}' from {}", pull.app_name, pull.url);
+        pull.set_status(Status::Connecting);
+
+        (pull, target)
+    }
+
+    fn set_status(&self, state: Status) {
+        self.status.write().state = state;
+    }
+
+    /// Gives up the publisher slot, keeping the stream resumable within the grace period.
+    fn release(&mut self) {
+        self.channel.lock().release_publisher(self.id);
+    }
+
+    fn stop(&mut self) {
+        info!("Stopped pulling app '{}' from {}", self.app_name, self.url);
+
+        let mut channel = self.channel.lock();
+        if channel.is_publisher(self.id) {
+            channel.unpublish();
+        }
+
+        self.state = State::Idle;
+        self.set_status(Status::Idle);
+    }
+
+    fn retry(&mut self, why: Error) {
+        warn!("Pulling app '{}' from {} failed: {:?}", self.app_name, self.url, why);
+
+        self.release();
+
+        {
+            let mut status = self.status.write();
+            status.retries += 1;
+            status.last_error = Some(format!("{:?}", why));
+        }
+
+        let delay = self.backoff.next_delay();
+        debug!("Reconnecting to {} in {} seconds", self.url, delay.as_secs());
+
+        self.state = State::Waiting(Delay::new(Instant::now() + delay));
+        self.set_status(Status::Retrying);
+    }
+
+    /// Takes the place of the publisher, unless a local publisher is on air.
+    fn start_playing(&mut self) -> Result<()> {
+        {
+            let mut channel = self.channel.lock();
+
+            if channel.has_publisher() {
+                return Err(Error::from("Application is already being published to"));
+            }
+
+            // Reconnecting pulls share the stream key and resume the stream
+            channel.set_publisher(Publisher::new(self.id, self.url.as_str().to_string(), self.sender.clone()));
+        }
+
+        #[cfg(feature = "hls")]
+        hls::server::ensure_writer(&self.channel, &self.shared);
+
+        info!("Remote {} started playback of app '{}'", self.url, self.app_name);
+        self.backoff.reset();
+        self.set_status(Status::Active);
+
+        Ok(())
+    }
+
+    fn handle_media(&mut self, mut media: Media) {
+        let fanout = self.channel.lock().prepare_fanout(self.id, &mut media);
+
+        if let Some(fanout) = fanout {
+            fanout.send_media(&media);
+        }
+    }
+
+    /// Drives the connection, returns whether the state changed and has to be polled again.
+    fn poll_state(&mut self) -> Result<bool> {
+        let mut started = false;
+
+        let next_state = match self.state {
+            State::Idle => return Ok(false),
+            State::Waiting(ref mut delay) => {
+                match delay.poll().map_err(|why| Error::from(format!("{:?}", why)))? {
+                    Async::Ready(()) => State::Connecting(connection::connect(&self.url)),
+                    Async::NotReady => return Ok(false),
+                }
+            },
+            State::Connecting(ref mut connect) => {
+                match connect.poll()? {
+                    Async::Ready(connection) => State::Connected {
+                        connection: Box::new(connection),
+                        playing: false,
+                        timeout: Delay::new(Instant::now() + Self::SESSION_TIMEOUT),
+                    },
+                    Async::NotReady => return Ok(false),
+                }
+            },
+            State::Connected { ref mut connection, ref mut playing, ref mut timeout } => {
+                if !*playing && timeout.poll().map_err(|why| Error::from(format!("{:?}", why)))?.is_ready() {
+                    return Err(Error::from("Timed out while waiting for playback to start"));
+                }
+
+                let mut media = Vec::new();
+
+                while let Async::Ready(event) = connection.poll_event()? {
+                    match event.ok_or_else(|| Error::from("Connection closed by remote"))? {
+                        ClientSessionEvent::ConnectionRequestAccepted => {
+                            let stream_key = self.url.stream_key.clone();
+                            let result = connection.session()?.request_playback(stream_key)?;
+                            connection.send(result)?;
+                        },
+                        ClientSessionEvent::ConnectionRequestRejected { description } => {
+                            return Err(Error::from(format!("Connection rejected: {}", description)));
+                        },
+                        ClientSessionEvent::PlaybackRequestAccepted => {
+                            *playing = true;
+                            started = true;
+                            break;
+                        },
+                        ClientSessionEvent::StreamMetadataReceived { metadata } if *playing => {
+                            let fanout = self.channel.lock().set_metadata(self.id, metadata.clone());
+                            if let Some(fanout) = fanout {
+                                fanout.send_metadata(&metadata);
+                            }
+                        },
+                        ClientSessionEvent::VideoDataReceived { data, timestamp } if *playing => {
+                            media.push(Media::video(timestamp, data)?);
+                        },
+                        ClientSessionEvent::AudioDataReceived { data, timestamp } if *playing => {
+                            media.push(Media::AAC(timestamp, data));
+                        },
+                        ClientSessionEvent::UnhandleableOnStatusCode { ref code } if code == "NetStream.Play.UnpublishNotify" => {
+                            return Err(Error::from("Remote stream was unpublished"));
+                        },
+                        ClientSessionEvent::UnhandleableOnStatusCode { code } => {
+                            debug!("Remote {} sent status {}", self.url, code);
+                        },
+                        _ => (),
+                    }
+                }
+
+                let _ = connection.poll_flush()?;
+
+                for media in media {
+                    self.handle_media(media);
+                }
+
+                if !started {
+                    return Ok(false);
+                }
+
+                self.start_playing()?;
+
+                // Events after the start of the playback still have to be handled
+                return Ok(true);
+            },
+        };
+
+        self.state = next_state;
+        Ok(true)
+    }
+}
+
+impl Future for Pull {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() || self.stop.poll() != Ok(Async::NotReady) {
+            self.stop();
+            return Ok(Async::Ready(()));
+        }
+
+        while let Async::Ready(Some(message)) = self.receiver.poll()? {
+            if let peer::Message::Disconnect = message {
+                self.retry(Error::from("Replaced by another publisher"));
+            }
+        }
+
+        loop {
+            match self.poll_state() {
+                Ok(true) => continue,
+                Ok(false) => return Ok(Async::NotReady),
+                Err(why) => self.retry(why),
+            }
+        }
+    }
+}
+
+
+/// Starts pulling all applications configured with a remote source.
+pub fn spawn_all(shared: &Shared) {
+    let pull_sources = shared.config.read().pull_sources.clone();
+
+    for (app_name, url) in pull_sources {
+        let channel = shared.channel_or_create(&app_name);
+        let result = channel.lock().start_pull(&url, false, shared);
+
+        if let Err(why) = result {
+            error!("Failed to pull app '{}': {:?}", app_name, why);
+        }
+    }
+}
+
+/// Starts pulling an application requested by a watcher from the configured origins
+/// or upstream, unless the application is already on air.
+pub fn on_demand(handle: &channel::Handle, shared: &Shared) {
+    let (template, is_edge) = {
+        let config = shared.config.read();
+        (config.pull_on_demand.clone(), !config.origins.is_empty())
+    };
+
+    if is_edge {
+        return edge::pull_from_origins(handle, shared);
+    }
+
+    let template = match template {
+        Some(template) => template,
+        None => return,
+    };
+
+    let mut channel = handle.lock();
+
+    if !channel.needs_pull() {
+        return;
+    }
+
+    let url = template.replace("{app}", &channel.app_name);
+
+    if let Err(why) = channel.start_pull(&url, true, shared) {
+        error!("Failed to pull app '{}' on demand: {:?}", channel.app_name, why);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::future;
+    use tokio::runtime::current_thread::Runtime;
+    use rml_rtmp::time::RtmpTimestamp;
+    use crate::{
+        config::Config,
+        rtmp::fanout::Watcher,
+        shutdown::Shutdown,
+    };
+    use super::*;
+
+    fn shared(args: &[&str]) -> Shared {
+        let (shutdown, _) = Shutdown::new();
+        Shared::with_config(Config::from_args(args), shutdown)
+    }
+
+    fn publisher(id: u64) -> Publisher {
+        let (sender, _) = mpsc::unbounded();
+        Publisher::new(id, "key".to_string(), sender)
+    }
+
+    /// Runs `f` on the runtime, so pulls and timers can be spawned
+    fn run<F>(runtime: &mut Runtime, f: F) where F: FnOnce() {
+        runtime.block_on(future::lazy(|| -> std::result::Result<(), ()> { f(); Ok(()) })).unwrap();
+    }
+
+    fn pulls_on_demand(runtime: &mut Runtime, shared: &Shared, handle: &channel::Handle) -> bool {
+        run(runtime, || on_demand(handle, shared));
+        let mut channel = handle.lock();
+        let pulls = channel.pull().is_some_and(|pull| pull.on_demand);
+        // Without watchers this drops the pull again
+        channel.remove_watcher(0);
+        pulls
+    }
+
+    #[test]
+    fn pulls_on_demand_without_stream() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin", "--pull-on-demand", "rtmp://127.0.0.1:1/{app}/key"]);
+        let handle = shared.channel_or_create("live");
+
+        assert!(pulls_on_demand(&mut runtime, &shared, &handle));
+
+        run(&mut runtime, || handle.lock().set_publisher(publisher(1)));
+        assert!(!pulls_on_demand(&mut runtime, &shared, &handle));
+    }
+
+    #[test]
+    fn waits_for_publishers_to_resume() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin", "--pull-on-demand", "rtmp://127.0.0.1:1/{app}/key", "--publisher-grace-period", "10"]);
+        let handle = shared.channel_or_create("live");
+
+        run(&mut runtime, || {
+            let mut channel = handle.lock();
+            channel.set_publisher(publisher(1));
+            channel.release_publisher(1);
+        });
+        assert!(!pulls_on_demand(&mut runtime, &shared, &handle));
+
+        run(&mut runtime, || handle.lock().unpublish());
+        assert!(pulls_on_demand(&mut runtime, &shared, &handle));
+    }
+
+    #[test]
+    fn leaves_fallback_on_air() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin", "--pull-on-demand", "rtmp://127.0.0.1:1/{app}/key"]);
+        let handle = shared.channel_or_create("live");
+
+        run(&mut runtime, || {
+            let mut channel = handle.lock();
+            channel.enable_fallback(None);
+            let mut media = Media::AAC(RtmpTimestamp::new(0), Bytes::from_static(&[0xAF, 0x01, 0x21]));
+            channel.prepare_fallback_fanout(&mut media);
+        });
+        assert!(!pulls_on_demand(&mut runtime, &shared, &handle));
+
+        run(&mut runtime, || handle.lock().disable_fallback());
+        assert!(pulls_on_demand(&mut runtime, &shared, &handle));
+    }
+
+    #[test]
+    fn pulls_only_with_template() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin"]);
+        let handle = shared.channel_or_create("live");
+
+        assert!(!pulls_on_demand(&mut runtime, &shared, &handle));
+    }
+
+    #[test]
+    fn stops_on_demand_pulls_with_last_watcher() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin", "--pull-on-demand", "rtmp://127.0.0.1:1/{app}/key"]);
+        let handle = shared.channel_or_create("live");
+        let (sender, _receiver) = mpsc::unbounded();
+        let (media, _media_receiver) = peer::media_queue();
+
+        run(&mut runtime, || {
+            handle.lock().add_watcher(Watcher::new(1, 1, sender, media)).unwrap();
+            on_demand(&handle, &shared);
+        });
+        assert!(handle.lock().pull().is_some());
+
+        handle.lock().remove_watcher(1);
+        assert!(handle.lock().pull().is_none());
+    }
+}
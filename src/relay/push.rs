@@ -0,0 +1,417 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use log::{debug, info, warn};
+use parking_lot::RwLock;
+use futures::sync::mpsc;
+use tokio::{
+    prelude::*,
+    timer::Delay,
+};
+use rml_rtmp::sessions::{
+    ClientSessionEvent,
+    PublishRequestType,
+    StreamMetadata,
+};
+use crate::{
+    error::{Error, Result},
+    media::Media,
+    shutdown,
+};
+use super::{
+    connection::{self, Connect, Connection},
+    Backoff,
+    Message,
+    Receiver,
+    Sender,
+    State as Status,
+    StatusHandle,
+    Url,
+};
+
+
+/// A remote server the media of a channel gets pushed to
+pub struct Target {
+    pub url: Url,
+    #[allow(dead_code)]
+    pub status: StatusHandle,
+    sender: Sender,
+}
+
+impl Target {
+    pub fn send(&self, message: Message) -> bool {
+        self.sender.unbounded_send(message).is_ok()
+    }
+}
+
+
+enum State {
+    Idle,
+    Waiting(Delay),
+    Connecting(Connect),
+    Connected {
+        connection: Box<Connection>,
+        publishing: bool,
+        timeout: Delay,
+    },
+}
+
+
+/// Publishes the media of a channel to a remote RTMP server.
+///
+/// Connects as soon as the channel goes live and reconnects with increasing
+/// delays when the connection fails, until the stream ends.
+pub struct Push {
+    app_name: String,
+    url: Url,
+    receiver: Receiver,
+    status: StatusHandle,
+    state: State,
+    live: bool,
+    waiting_for_keyframe: bool,
+    metadata: Option<StreamMetadata>,
+    video_seq_header: Option<Media>,
+    audio_seq_header: Option<Media>,
+    backoff: Backoff,
+    shutdown: shutdown::Signal,
+}
+
+impl Push {
+    /// Maximum time from connecting until the remote accepted publishing
+    const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn create(app_name: String, url: Url, shutdown: shutdown::Signal) -> (Self, Target) {
+        let (sender, receiver) = mpsc::unbounded();
+        let status = Arc::new(RwLock::new(super::Status::new()));
+
+        let target = Target { url: url.clone(), status: status.clone(), sender };
+
+        let push = Self {
+            app_name,
+            url,
+            receiver,
+            status,
+            state: State::Idle,
+            live: false,
+            waiting_for_keyframe: true,
+            metadata: None,
+            video_seq_header: None,
+            audio_seq_header: None,
+            backoff: Backoff::new(),
+            shutdown,
+        };
+
+        (push, target)
+    }
+
+    fn set_status(&self, state: Status) {
+        self.status.write().state = state;
+    }
+
+    fn handle_message(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::Metadata(metadata) => {
+                self.start();
+
+                if let State::Connected { ref mut connection, publishing: true, .. } = self.state {
+                    let result = connection.session()?.publish_metadata(&metadata)?;
+                    connection.send(result)?;
+                }
+
+                self.metadata = Some(metadata);
+            },
+            Message::Media(media) => {
+                self.start();
+
+                if media.is_sequence_header() {
+                    match media {
+                        Media::H264(..) | Media::H265(..) => self.video_seq_header = Some(media.clone()),
+                        Media::AAC(..) => self.audio_seq_header = Some(media.clone()),
+                    }
+                } else if self.waiting_for_keyframe {
+                    if !media.is_keyframe() {
+                        return Ok(());
+                    }
+                    self.waiting_for_keyframe = false;
+                }
+
+                if let State::Connected { ref mut connection, publishing: true, .. } = self.state {
+                    send_media(connection, &media)?;
+                }
+            },
+            Message::End => self.stop(),
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) {
+        self.live = true;
+
+        if let State::Idle = self.state {
+            info!("Pushing app '{}' to {}", self.app_name, self.url);
+            self.state = State::Connecting(connection::connect(&self.url));
+            self.set_status(Status::Connecting);
+        }
+    }
+
+    fn stop(&mut self) {
+        if let State::Connected { ref mut connection, publishing: true, .. } = self.state {
+            info!("Stopped pushing app '{}' to {}", self.app_name, self.url);
+
+            // Best effort, the connection gets closed right away
+            let _ = stop_publishing(connection);
+        }
+
+        self.state = State::Idle;
+        self.live = false;
+        self.waiting_for_keyframe = true;
+        self.metadata = None;
+        self.video_seq_header = None;
+        self.audio_seq_header = None;
+        self.backoff.reset();
+        self.set_status(Status::Idle);
+    }
+
+    fn retry(&mut self, why: Error) {
+        warn!("Pushing app '{}' to {} failed: {:?}", self.app_name, self.url, why);
+
+        {
+            let mut status = self.status.write();
+            status.retries += 1;
+            status.last_error = Some(format!("{:?}", why));
+        }
+
+        if !self.live {
+            self.state = State::Idle;
+            self.set_status(Status::Idle);
+            return;
+        }
+
+        let delay = self.backoff.next_delay();
+        debug!("Reconnecting to {} in {} seconds", self.url, delay.as_secs());
+
+        self.state = State::Waiting(Delay::new(Instant::now() + delay));
+        self.waiting_for_keyframe = true;
+        self.set_status(Status::Retrying);
+    }
+
+    /// Drives the connection, returns whether the state changed and has to be polled again.
+    fn poll_state(&mut self) -> Result<bool> {
+        let next_state = match self.state {
+            State::Idle => return Ok(false),
+            State::Waiting(ref mut delay) => {
+                match delay.poll().map_err(|why| Error::from(format!("{:?}", why)))? {
+                    Async::Ready(()) => State::Connecting(connection::connect(&self.url)),
+                    Async::NotReady => return Ok(false),
+                }
+            },
+            State::Connecting(ref mut connect) => {
+                match connect.poll()? {
+                    Async::Ready(connection) => State::Connected {
+                        connection: Box::new(connection),
+                        publishing: false,
+                        timeout: Delay::new(Instant::now() + Self::SESSION_TIMEOUT),
+                    },
+                    Async::NotReady => return Ok(false),
+                }
+            },
+            State::Connected { ref mut connection, ref mut publishing, ref mut timeout } => {
+                if !*publishing && timeout.poll().map_err(|why| Error::from(format!("{:?}", why)))?.is_ready() {
+                    return Err(Error::from("Timed out while waiting for publish request to be accepted"));
+                }
+
+                while let Async::Ready(event) = connection.poll_event()? {
+                    match event.ok_or_else(|| Error::from("Connection closed by remote"))? {
+                        ClientSessionEvent::ConnectionRequestAccepted => {
+                            let stream_key = self.url.stream_key.clone();
+                            let result = connection.session()?.request_publishing(stream_key, PublishRequestType::Live)?;
+                            connection.send(result)?;
+                        },
+                        ClientSessionEvent::ConnectionRequestRejected { description } => {
+                            return Err(Error::from(format!("Connection rejected: {}", description)));
+                        },
+                        ClientSessionEvent::PublishRequestAccepted => {
+                            info!("Remote {} accepted app '{}'", self.url, self.app_name);
+                            *publishing = true;
+                            self.backoff.reset();
+                            self.status.write().state = Status::Active;
+
+                            // The remote needs the decoder configuration before the next keyframe
+                            if let Some(ref metadata) = self.metadata {
+                                let result = connection.session()?.publish_metadata(metadata)?;
+                                connection.send(result)?;
+                            }
+
+                            let seq_headers = self.video_seq_header.iter().chain(self.audio_seq_header.iter());
+                            for seq_header in seq_headers {
+                                send_media(connection, seq_header)?;
+                            }
+
+                            self.waiting_for_keyframe = self.video_seq_header.is_some();
+                        },
+                        ClientSessionEvent::UnhandleableOnStatusCode { code } => {
+                            debug!("Remote {} sent status {}", self.url, code);
+                        },
+                        _ => (),
+                    }
+                }
+
+                let _ = connection.poll_flush()?;
+
+                return Ok(false);
+            },
+        };
+
+        self.state = next_state;
+        Ok(true)
+    }
+}
+
+impl Future for Push {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            self.stop();
+            return Ok(Async::Ready(()));
+        }
+
+        while let Async::Ready(message) = self.receiver.poll()? {
+            match message {
+                Some(message) => {
+                    if let Err(why) = self.handle_message(message) {
+                        self.retry(why);
+                    }
+                },
+                None => {
+                    debug!("Push target {} of app '{}' removed", self.url, self.app_name);
+                    self.stop();
+                    return Ok(Async::Ready(()));
+                },
+            }
+        }
+
+        loop {
+            match self.poll_state() {
+                Ok(true) => continue,
+                Ok(false) => return Ok(Async::NotReady),
+                Err(why) => self.retry(why),
+            }
+        }
+    }
+}
+
+
+fn stop_publishing(connection: &mut Connection) -> Result<()> {
+    let results = connection.session()?.stop_publishing()?;
+    connection.handle_results(results)?;
+    connection.poll_flush()?;
+    Ok(())
+}
+
+fn send_media(connection: &mut Connection, media: &Media) -> Result<()> {
+    let result = match media {
+        Media::H264(timestamp, data) | Media::H265(timestamp, data) => {
+            connection.session()?.publish_video_data(data.clone(), *timestamp, !media.is_sendable())?
+        },
+        Media::AAC(timestamp, data) => {
+            connection.session()?.publish_audio_data(data.clone(), *timestamp, !media.is_sequence_header())?
+        },
+    };
+
+    connection.send(result)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::future;
+    use tokio::runtime::current_thread::Runtime;
+    use rml_rtmp::time::RtmpTimestamp;
+    use crate::shutdown::Shutdown;
+    use super::*;
+
+    fn push() -> (Push, Target) {
+        let (shutdown, _) = Shutdown::new();
+        let url = "rtmp://127.0.0.1:1/live/key".parse().unwrap();
+        Push::create("live".to_string(), url, shutdown.signal())
+    }
+
+    fn video(data: &'static [u8]) -> Message {
+        Message::Media(Media::H264(RtmpTimestamp::new(0), Bytes::from_static(data)))
+    }
+
+    fn state(target: &Target) -> Status {
+        target.status.read().state
+    }
+
+    #[test]
+    fn connects_once_live() {
+        let (mut push, target) = push();
+        assert_eq!(state(&target), Status::Idle);
+
+        let audio_seq_header = Media::AAC(RtmpTimestamp::new(0), Bytes::from_static(&[0xAF, 0x00, 0x12, 0x10]));
+        push.handle_message(Message::Media(audio_seq_header)).unwrap();
+        assert_eq!(state(&target), Status::Connecting);
+        assert!(push.live);
+        assert!(push.audio_seq_header.is_some());
+
+        push.handle_message(Message::End).unwrap();
+        assert_eq!(state(&target), Status::Idle);
+        assert!(!push.live);
+        assert!(push.audio_seq_header.is_none());
+    }
+
+    #[test]
+    fn keeps_decoder_configuration_until_keyframe() {
+        let (mut push, _target) = push();
+
+        push.handle_message(video(&[0x17, 0x00, 0x00, 0x00, 0x00, 0x01])).unwrap();
+        assert!(push.video_seq_header.is_some());
+        assert!(push.waiting_for_keyframe);
+
+        push.handle_message(video(&[0x27, 0x01, 0x00, 0x00, 0x00])).unwrap();
+        assert!(push.waiting_for_keyframe);
+
+        push.handle_message(video(&[0x17, 0x01, 0x00, 0x00, 0x00])).unwrap();
+        assert!(!push.waiting_for_keyframe);
+
+        push.handle_message(Message::End).unwrap();
+        assert!(push.video_seq_header.is_none());
+        assert!(push.waiting_for_keyframe);
+    }
+
+    #[test]
+    fn retries_only_while_live() {
+        let mut runtime = Runtime::new().unwrap();
+        let (mut push, target) = push();
+
+        push.retry(Error::from("Refused"));
+        assert_eq!(state(&target), Status::Idle);
+
+        runtime.block_on(future::lazy(|| -> std::result::Result<(), ()> {
+            push.start();
+            push.retry(Error::from("Refused"));
+            Ok(())
+        })).unwrap();
+
+        let status = target.status.read();
+        assert_eq!(status.state, Status::Retrying);
+        assert_eq!(status.retries, 2);
+        assert!(status.last_error.is_some());
+    }
+
+    #[test]
+    fn stops_once_removed() {
+        let mut runtime = Runtime::new().unwrap();
+        let (mut push, target) = push();
+        drop(target);
+
+        let result = runtime.block_on(future::lazy(|| push.poll()));
+        assert_eq!(result, Ok(Async::Ready(())));
+    }
+}
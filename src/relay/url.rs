@@ -0,0 +1,77 @@
+use std::{
+    fmt,
+    str::FromStr,
+    result,
+};
+use crate::error::Error;
+
+
+/// Location of a stream on a remote RTMP server, e.g. `rtmp://host:1935/app/stream_key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    raw: String,
+    pub secure: bool,
+    pub host: String,
+    pub port: u16,
+    pub app: String,
+    pub stream_key: String,
+}
+
+impl Url {
+    const DEFAULT_PORT: u16 = 1935;
+    const DEFAULT_SECURE_PORT: u16 = 443;
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+}
+
+impl FromStr for Url {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let invalid = || Error::from(format!("Invalid RTMP URL '{}'", s));
+
+        let (secure, rest) = if let Some(rest) = s.strip_prefix("rtmp://") {
+            (false, rest)
+        } else if let Some(rest) = s.strip_prefix("rtmps://") {
+            (true, rest)
+        } else {
+            return Err(invalid());
+        };
+
+        let mut parts = rest.splitn(2, '/');
+        let authority = parts.next().ok_or_else(invalid)?;
+        let path = parts.next().ok_or_else(invalid)?;
+
+        let mut parts = authority.splitn(2, ':');
+        let host = parts.next().filter(|host| !host.is_empty()).ok_or_else(invalid)?;
+        let port = match parts.next() {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None if secure => Self::DEFAULT_SECURE_PORT,
+            None => Self::DEFAULT_PORT,
+        };
+
+        // The application name may contain slashes, the stream key is always the last segment
+        let mut parts = path.rsplitn(2, '/');
+        let stream_key = parts.next().filter(|key| !key.is_empty()).ok_or_else(invalid)?;
+        let app = parts.next().filter(|app| !app.is_empty()).ok_or_else(invalid)?;
+
+        Ok(Self {
+            raw: s.to_string(),
+            secure,
+            host: host.to_string(),
+            port,
+            app: app.to_string(),
+            stream_key: stream_key.to_string(),
+        })
+    }
+}
+
+/// Stream keys are secrets, so they are never displayed.
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = if self.secure { "rtmps" } else { "rtmp" };
+        write!(f, "{}://{}:{}/{}/****", scheme, self.host, self.port, self.app)
+    }
+}
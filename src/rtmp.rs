@@ -1,14 +1,15 @@
 mod bytes_stream;
+mod chunk_encoder;
 mod event;
+pub mod fanout;
 pub mod peer;
 pub mod client;
 pub mod server;
 
 
-use self::{
-    peer::Peer,
-    bytes_stream::BytesStream,
-};
+use self::peer::Peer;
+
+pub use self::bytes_stream::BytesStream;
 
 pub use self::client::Client;
 pub use self::server::Server;
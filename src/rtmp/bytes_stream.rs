@@ -32,6 +32,11 @@ impl<S> BytesStream<S>
         self.buf_out.put(data);
     }
 
+    /// Number of bytes still waiting to be written
+    pub fn buffered_len(&self) -> usize {
+        self.buf_out.len()
+    }
+
     pub fn poll_flush(&mut self) -> Poll<(), io::Error> {
         while !self.buf_out.is_empty() {
             let bytes_written = try_ready!(self.socket.poll_write(&self.buf_out));
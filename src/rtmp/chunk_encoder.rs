@@ -0,0 +1,191 @@
+use bytes::{Bytes, BytesMut, BufMut};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Audio,
+    Video,
+    Data,
+    Command,
+    UserControl,
+}
+
+impl MessageKind {
+    fn type_id(self) -> u8 {
+        match self {
+            MessageKind::Audio => 8,
+            MessageKind::Video => 9,
+            MessageKind::Data => 18,
+            MessageKind::Command => 20,
+            MessageKind::UserControl => 4,
+        }
+    }
+
+    /// Chunk stream IDs that are never used by the RTMP session itself,
+    /// so the session's header compression state can not be disturbed.
+    ///
+    /// User control messages are protocol control messages, which have to be sent on
+    /// chunk stream 2. The session only sends control messages on it, which ignore the
+    /// timestamp, so the full header written here can only shift their timestamps.
+    fn chunk_stream_id(self) -> u8 {
+        match self {
+            MessageKind::UserControl => 2,
+            MessageKind::Audio => 20,
+            MessageKind::Video => 21,
+            MessageKind::Data => 22,
+            MessageKind::Command => 23,
+        }
+    }
+}
+
+
+/// Serializes outgoing messages into RTMP chunks.
+///
+/// Every message starts with a full (type 0) chunk header, which makes the
+/// result independent of any previously sent message. The same bytes can
+/// therefore be shared by all watchers with the same stream ID.
+///
+/// Bits | Name
+/// ---- | ----
+/// 2    | Format (0)
+/// 6    | Chunk Stream ID
+/// 24   | Timestamp
+/// 24   | Message Length
+/// 8    | Message Type ID
+/// 32   | Message Stream ID (little endian)
+/// 32   | Extended Timestamp (only if timestamp >= 0xFFFFFF)
+/// var  | Payload (up to chunk size)
+///
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkEncoder {
+    chunk_size: usize,
+}
+
+impl ChunkEncoder {
+    const MAX_TIMESTAMP: u32 = 0x00_FF_FF_FF;
+
+    pub fn new(chunk_size: u32) -> Self {
+        assert!(chunk_size > 0, "Chunk size must not be zero");
+        Self { chunk_size: chunk_size as usize }
+    }
+
+    pub fn encode(&self, kind: MessageKind, timestamp: u32, stream_id: u32, payload: &[u8]) -> Bytes {
+        debug_assert!(kind != MessageKind::UserControl || stream_id == 0, "User control messages belong to message stream 0");
+
+        let csid = kind.chunk_stream_id();
+        let extended = timestamp >= Self::MAX_TIMESTAMP;
+        let chunk_count = (payload.len() / self.chunk_size) + 1;
+        let mut tmp = BytesMut::with_capacity(payload.len() + 16 + (chunk_count * 5));
+
+        tmp.put_u8(csid);
+        tmp.put_uint_be(u64::from(timestamp.min(Self::MAX_TIMESTAMP)), 3);
+        tmp.put_uint_be(payload.len() as u64, 3);
+        tmp.put_u8(kind.type_id());
+        tmp.put_u32_le(stream_id);
+
+        if extended {
+            tmp.put_u32_be(timestamp);
+        }
+
+        for (i, chunk) in payload.chunks(self.chunk_size).enumerate() {
+            if i > 0 {
+                tmp.put_u8(0b1100_0000 | csid);
+
+                if extended {
+                    tmp.put_u32_be(timestamp);
+                }
+            }
+
+            tmp.put_slice(chunk);
+        }
+
+        tmp.freeze()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_with_full_header() {
+        let bytes = ChunkEncoder::new(128).encode(MessageKind::Video, 0x01_02_03, 1, &[0xAA, 0xBB, 0xCC]);
+
+        assert_eq!(bytes[..], [
+            0x15, // Format 0, chunk stream 21
+            0x01, 0x02, 0x03,
+            0x00, 0x00, 0x03,
+            0x09,
+            0x01, 0x00, 0x00, 0x00,
+            0xAA, 0xBB, 0xCC,
+        ][..]);
+    }
+
+    #[test]
+    fn never_compresses_headers() {
+        // A type 1 header would depend on the message before, which not every watcher received
+        let encoder = ChunkEncoder::new(128);
+        let first = encoder.encode(MessageKind::Audio, 1000, 1, &[0xAF, 0x01]);
+        let second = encoder.encode(MessageKind::Audio, 1023, 1, &[0xAF, 0x01]);
+
+        assert_eq!(first[..], [0x14, 0x00, 0x03, 0xE8, 0x00, 0x00, 0x02, 0x08, 0x01, 0x00, 0x00, 0x00, 0xAF, 0x01][..]);
+        assert_eq!(second[..], [0x14, 0x00, 0x03, 0xFF, 0x00, 0x00, 0x02, 0x08, 0x01, 0x00, 0x00, 0x00, 0xAF, 0x01][..]);
+    }
+
+    #[test]
+    fn splits_payload_at_chunk_size() {
+        let payload: Vec<u8> = (0..10).collect();
+        let bytes = ChunkEncoder::new(4).encode(MessageKind::Data, 0, 1, &payload);
+
+        assert_eq!(bytes[..], [
+            0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0A, 0x12, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x01, 0x02, 0x03,
+            0xD6, // Format 3, chunk stream 22
+            0x04, 0x05, 0x06, 0x07,
+            0xD6,
+            0x08, 0x09,
+        ][..]);
+    }
+
+    #[test]
+    fn payload_of_exactly_one_chunk_is_not_continued() {
+        let bytes = ChunkEncoder::new(4).encode(MessageKind::Command, 0, 0, &[1, 2, 3, 4]);
+
+        assert_eq!(bytes.len(), 12 + 4);
+        assert_eq!(bytes[0], 0x17);
+    }
+
+    #[test]
+    fn repeats_extended_timestamp_in_every_chunk() {
+        let bytes = ChunkEncoder::new(2).encode(MessageKind::Video, 0x01_00_00_00, 1, &[0x17, 0x01, 0x00]);
+
+        assert_eq!(bytes[..], [
+            0x15, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x03, 0x09, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x00, 0x00, 0x00,
+            0x17, 0x01,
+            0xD5,
+            0x01, 0x00, 0x00, 0x00,
+            0x00,
+        ][..]);
+    }
+
+    #[test]
+    fn sends_user_control_on_protocol_control_stream() {
+        let bytes = ChunkEncoder::new(128).encode(MessageKind::UserControl, 0, 0, &[0x00, 0x01, 0x00, 0x00, 0x00, 0x01]);
+
+        assert_eq!(bytes[..], [
+            0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+        ][..]);
+    }
+
+    #[test]
+    fn uses_extended_timestamp_from_maximum() {
+        let bytes = ChunkEncoder::new(128).encode(MessageKind::Audio, 0x00_FF_FF_FF, 1, &[0xAF]);
+
+        assert_eq!(bytes[1..4], [0xFF, 0xFF, 0xFF]);
+        assert_eq!(bytes[12..16], [0x00, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(bytes[16..], [0xAF]);
+    }
+}
@@ -1,3 +1,7 @@
+use std::path::PathBuf;
+use log::debug;
+use bytes::Bytes;
+use tokio::executor::{DefaultExecutor, Executor};
 use rml_rtmp::sessions::{
     ServerSession,
     ServerSessionConfig,
@@ -5,16 +9,27 @@ use rml_rtmp::sessions::{
 };
 use crate::{
     error::{Error, Result},
-    media::Channel,
-    shared::Shared,
+    channel::{self, Publisher},
+    shutdown,
+    vod::{self, Vod},
 };
+use super::{
+    peer,
+    fanout::{self, Watcher},
+};
+
+
+/// Outgoing chunk size announced to every client.
+/// Media for watchers is encoded with this size once and then shared.
+pub const CHUNK_SIZE: u32 = 4096;
 
 
-#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ClientState {
     Waiting,
-    Publishing(String, String),
-    Watching(String, u32),
+    Publishing(channel::Handle),
+    Watching(channel::Handle, u32),
+    /// Playing a recording, controlled through the sender
+    Replaying(vod::Sender),
 }
 
 
@@ -22,23 +37,24 @@ pub enum ClientState {
 pub struct Client {
     peer_id: u64,
     state: ClientState,
-    shared: Shared,
+    sender: peer::Sender,
+    media: peer::MediaSender,
     pub session: ServerSession,
-    pub received_video_keyframe: bool,
 }
 
 impl Client {
     #[allow(clippy::new_ret_no_self)]
-    pub fn new(peer_id: u64, shared: Shared) -> Result<(Self, Vec<ServerSessionResult>)> {
-        let session_config = ServerSessionConfig::new();
+    pub fn new(peer_id: u64, sender: peer::Sender, media: peer::MediaSender) -> Result<(Self, Vec<ServerSessionResult>)> {
+        let mut session_config = ServerSessionConfig::new();
+        session_config.chunk_size = CHUNK_SIZE;
         let (session, results) = ServerSession::new(session_config)?;
 
         let this = Self {
             peer_id,
-            shared,
+            sender,
+            media,
             session,
             state: ClientState::Waiting,
-            received_video_keyframe: false,
         };
 
         Ok((this, results))
@@ -48,31 +64,108 @@ impl Client {
         self.session.accept_request(request_id).map_err(|_| Error::RequestError)
     }
 
-    pub fn publish(&mut self, channel: &mut Channel, app_name: String, stream_key: String) {
-        channel.set_publisher(self.peer_id, stream_key.clone());
-        self.state = ClientState::Publishing(app_name, stream_key);
+    /// Answers a request with an `onStatus` error on the given message stream,
+    /// the session itself has no way to refuse a request.
+    pub fn reject_request(&self, stream_id: u32, code: &str, description: &str) -> Result<()> {
+        self.send(fanout::encode_status(stream_id, "error", code, description)?);
+        Ok(())
+    }
+
+    /// Answers the connect request with an `_error` result.
+    pub fn reject_connection(&self, code: &str, description: &str) -> Result<()> {
+        self.send(fanout::encode_connect_error(code, description)?);
+        Ok(())
+    }
+
+    pub fn publisher(&self, stream_key: String) -> Publisher {
+        Publisher::new(self.peer_id, stream_key, self.sender.clone())
+    }
+
+    pub fn publish(&mut self, channel: channel::Handle) {
+        self.state = ClientState::Publishing(channel);
+    }
+
+    pub fn watch(&mut self, channel: channel::Handle, stream_id: u32) -> Result<()> {
+        let watcher = Watcher::new(self.peer_id, stream_id, self.sender.clone(), self.media.clone());
+        channel.lock().add_watcher(watcher)?;
+        self.state = ClientState::Watching(channel, stream_id);
+        Ok(())
+    }
+
+    /// Plays a recording on the given message stream instead of a live channel.
+    pub fn replay(&mut self, path: PathBuf, stream_id: u32, shutdown: shutdown::Signal) -> Result<()> {
+        let (vod, sender) = Vod::create(path, self.peer_id, stream_id, self.sender.clone(), self.media.clone(), shutdown)?;
+
+        DefaultExecutor::current()
+            .spawn(Box::new(vod))
+            .map_err(|why| Error::from(format!("Failed to spawn playback: {:?}", why)))?;
+
+        self.state = ClientState::Replaying(sender);
+
+        Ok(())
     }
 
-    pub fn watch(&mut self, channel: &mut Channel, stream_id: u32, app_name: String) {
-        channel.add_watcher(self.peer_id);
-        self.state = ClientState::Watching(app_name, stream_id);
+    /// Forwards a seek or pause request, only recordings can be controlled.
+    pub fn control_replay(&self, command: vod::Command) {
+        if let ClientState::Replaying(ref sender) = self.state {
+            let _ = sender.unbounded_send(command);
+        }
     }
 
-    pub fn watched_stream(&self) -> Option<u32> {
+    pub fn published_channel(&self) -> Option<&channel::Handle> {
         match self.state {
-            ClientState::Watching(_, stream_id) => Some(stream_id),
+            ClientState::Publishing(ref channel) => Some(channel),
             _ => None,
         }
     }
+
+    /// Tells the client that the server is going away and disconnects it.
+    pub fn shutdown(&mut self) -> Result<()> {
+        match self.state {
+            ClientState::Watching(ref channel, stream_id) => {
+                channel.lock().remove_watcher(self.peer_id);
+                self.send(fanout::encode_status(stream_id, "status", "NetStream.Play.UnpublishNotify", "Server is shutting down")?);
+                self.send(fanout::encode_stream_eof(stream_id));
+            },
+            ClientState::Publishing(ref channel) => {
+                {
+                    let mut channel = channel.lock();
+                    if channel.is_publisher(self.peer_id) {
+                        debug!("Unpublishing app '{}'", channel.app_name);
+                        channel.unpublish();
+                    } else {
+                        // Only removes this client in case it is the backup
+                        channel.release_publisher(self.peer_id);
+                    }
+                }
+                // Sent on the connection's control stream, as the publishing stream is not tracked
+                self.send(fanout::encode_status(0, "status", "NetConnection.Connect.Closed", "Server is shutting down")?);
+            },
+            ClientState::Replaying(_) | ClientState::Waiting => (),
+        }
+
+        self.state = ClientState::Waiting;
+        let _ = self.sender.unbounded_send(peer::Message::Disconnect);
+
+        Ok(())
+    }
+
+    fn send(&self, bytes: Bytes) {
+        let _ = self.sender.unbounded_send(peer::Message::Raw(bytes));
+    }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
-        if let ClientState::Watching(ref app_name, _) = self.state {
-            let mut streams = self.shared.streams.write();
-            if let Some(stream) = streams.get_mut(app_name) {
-                stream.watchers.remove(&self.peer_id);
-            }
+        match self.state {
+            ClientState::Watching(ref channel, _) => {
+                channel.lock().remove_watcher(self.peer_id);
+            },
+            ClientState::Publishing(ref channel) => {
+                channel.lock().release_publisher(self.peer_id);
+            },
+            // Dropping the sender stops the playback
+            ClientState::Replaying(_) | ClientState::Waiting => (),
         }
     }
 }
@@ -1,29 +1,26 @@
 use std::{
     collections::VecDeque,
-    rc::Rc,
+    path::PathBuf,
 };
-use::log::{debug, error, info};
-#[cfg(feature = "hls")]
-use futures::{sync::oneshot, Future};
-use rml_rtmp::{
-    sessions::{
-        ServerSessionResult,
-        ServerSessionEvent as Event,
-        StreamMetadata,
-    },
-    chunk_io::Packet,
-    time::RtmpTimestamp
+use::log::{debug, error, info, warn};
+use bytes::Bytes;
+use rml_amf0::Amf0Value;
+use rml_rtmp::sessions::{
+    ServerSessionResult,
+    ServerSessionEvent as Event,
+    StreamMetadata,
 };
 use crate::{
+    channel::is_valid_app_name,
     error::{Error, Result},
     config::RepublishAction,
     shared::Shared,
-    media::{Media, Channel},
+    media::Media,
+    relay,
+    vod,
 };
 #[cfg(feature = "hls")]
-use crate::{
-    media,
-};
+use crate::hls;
 use super::{
     Client,
     peer,
@@ -32,35 +29,29 @@ use super::{
 
 #[derive(Debug)]
 pub enum EventResult {
-    Outbound(u64, Packet),
     Disconnect,
 }
 
 
 pub struct Handler {
     peer_id: u64,
+    sender: peer::Sender,
+    client: Client,
     results: VecDeque<EventResult>,
     shared: Shared,
-    #[cfg(feature = "hls")]
-    media_sender: Option<media::Sender>,
 }
 
 impl Handler {
     #[allow(clippy::new_ret_no_self)]
-    pub fn new(peer_id: u64, shared: Shared) -> Result<Self> {
-        let results = {
-            let mut clients = shared.clients.lock();
-            let (client, results) = Client::new(peer_id, shared.clone())?;
-            clients.insert(peer_id, client);
-            results
-        };
+    pub fn new(peer_id: u64, sender: peer::Sender, media: peer::MediaSender, shared: Shared) -> Result<Self> {
+        let (client, results) = Client::new(peer_id, sender.clone(), media)?;
 
         let mut this = Self {
             peer_id,
+            sender,
+            client,
             results: VecDeque::new(),
             shared,
-            #[cfg(feature = "hls")]
-            media_sender: None,
         };
 
         this.handle_server_session_results(results)?;
@@ -68,12 +59,14 @@ impl Handler {
         Ok(this)
     }
 
+    /// Notifies the client about the shutdown and disconnects it.
+    pub fn shutdown(&mut self) -> Result<()> {
+        info!("Disconnecting client {} due to shutdown", self.peer_id);
+        self.client.shutdown()
+    }
+
     pub fn handle(&mut self, bytes: &[u8]) -> Result<Vec<EventResult>> {
-        let results = {
-            let mut clients = self.shared.clients.lock();
-            let client = clients.get_mut(&self.peer_id).unwrap();
-            client.session.handle_input(bytes)?
-        };
+        let results = self.client.session.handle_input(bytes)?;
 
         self.handle_server_session_results(results)?;
 
@@ -86,7 +79,10 @@ impl Handler {
         for result in results {
             match result {
                 OutboundResponse(packet) => {
-                    self.results.push_back(EventResult::Outbound(self.peer_id, packet));
+                    // Sent right away, so responses always precede any media fanned out to this peer
+                    self.sender
+                        .unbounded_send(peer::Message::Raw(Bytes::from(packet.bytes)))
+                        .map_err(|_| Error::SessionError("Failed to send response".into()))?;
                 },
                 RaisedEvent(event) => {
                     self.handle_event(event)?;
@@ -110,20 +106,23 @@ impl Handler {
             PublishStreamRequested { request_id, app_name, stream_key, .. } => {
                 self.publish_requested(request_id, app_name, stream_key)?;
             }
-            PlayStreamRequested { request_id, app_name, stream_id, .. } => {
-                self.play_requested(request_id, &app_name, stream_id)?;
+            PlayStreamRequested { request_id, app_name, stream_key, stream_id, .. } => {
+                self.play_requested(request_id, &app_name, &stream_key, stream_id)?;
+            },
+            UnhandleableAmf0Command { command_name, additional_values, .. } => {
+                self.command_received(&command_name, &additional_values);
             },
             StreamMetadataChanged { app_name, metadata, .. } => {
-                self.metadata_received(&app_name, &metadata)?;
+                self.metadata_received(&app_name, metadata)?;
             },
-            VideoDataReceived { stream_key, data, timestamp, .. } => {
-                self.multimedia_data_received(&stream_key, &Media::H264(timestamp, data))?;
+            VideoDataReceived { data, timestamp, .. } => {
+                self.multimedia_data_received(Media::video(timestamp, data)?)?;
             },
-            AudioDataReceived { stream_key, data, timestamp, .. } => {
-                self.multimedia_data_received(&stream_key, &Media::AAC(timestamp, data))?;
+            AudioDataReceived { data, timestamp, .. } => {
+                self.multimedia_data_received(Media::AAC(timestamp, data))?;
             },
-            PublishStreamFinished { app_name, stream_key } => {
-                self.publish_stream_finished(&app_name, &stream_key)?;
+            PublishStreamFinished { app_name, .. } => {
+                self.publish_stream_finished(&app_name)?;
             },
             _ => {
                 debug!("Event: {:?}", event);
@@ -137,15 +136,10 @@ impl Handler {
         info!("Connection request from client {} for app '{}'", self.peer_id, app_name);
 
         if app_name.is_empty() {
-            return Err(Error::from("Application name can not be empty"));
+            return self.reject_connection(app_name, "Application name can not be empty");
         }
 
-        let results = {
-            let mut clients = self.shared.clients.lock();
-            let client = clients.get_mut(&self.peer_id).unwrap();
-            client.accept_request(request_id)?
-        };
-
+        let results = self.client.accept_request(request_id)?;
         self.handle_server_session_results(results)?;
 
         Ok(())
@@ -154,54 +148,73 @@ impl Handler {
     fn publish_requested(&mut self, request_id: u32, app_name: String, stream_key: String) -> Result<()> {
         info!("Client {} requested publishing to app '{}' using stream key {}", self.peer_id, app_name, stream_key);
 
-        {
+        if !is_valid_app_name(&app_name) {
+            return self.reject(0, &app_name, "NetStream.Publish.BadName", "Invalid application name");
+        }
+
+        let republish_action = {
             let config = self.shared.config.read();
             if stream_key.is_empty() || !config.permitted_stream_keys.contains(&stream_key) {
-                return Err(Error::SessionError(format!("Stream key '{}' is not permitted", stream_key)));
+                drop(config);
+                return self.reject(0, &app_name, "NetStream.Publish.BadName", "Stream key is not permitted");
             }
-        }
+            config.republish_action
+        };
 
         debug!("Stream key '{}' permitted", stream_key);
 
+        let handle = self.shared.channel_or_create(&app_name);
+
         {
-            let mut streams = self.shared.streams.write();
-            let config = self.shared.config.read();
-            if let Some(stream) = streams.get_mut(&app_name) {
-                if let Some(publisher) = &stream.publisher {
-                    match config.republish_action {
-                        RepublishAction::Replace => {
-                            info!("Another client is already publishing to this app, removing client");
-                            let peers = self.shared.peers.write();
-                            let peer = peers.get(publisher).unwrap();
-                            peer.unbounded_send(peer::Message::Disconnect).unwrap();
-                            stream.unpublish();
-                        },
-                        RepublishAction::Deny => {
-                            return Err(Error::SessionError(format!("App '{}' is already being published to", app_name)));
-                        }
-                    }
+            let mut channel = handle.lock();
+
+            if let Some(publisher) = channel.publisher() {
+                match republish_action {
+                    RepublishAction::Replace => {
+                        info!("Another client is already publishing to this app, removing client");
+                        publisher.disconnect();
+                        channel.unpublish();
+                    },
+                    RepublishAction::Deny => {
+                        drop(channel);
+                        return self.reject(0, &app_name, "NetStream.Publish.Denied", "Application is already being published to");
+                    },
+                    RepublishAction::Backup if channel.has_backup() => {
+                        drop(channel);
+                        return self.reject(0, &app_name, "NetStream.Publish.Denied", "Application already has a backup publisher");
+                    },
+                    RepublishAction::Backup => {
+                        channel.set_backup(self.client.publisher(stream_key));
+                        drop(channel);
+                        self.client.publish(handle);
+                        return self.accept_publish_request(request_id);
+                    },
                 }
             }
+
+            channel.set_publisher(self.client.publisher(stream_key));
         }
 
-        #[cfg(feature = "hls")]
-        self.register_on_hls_server(app_name.clone());
-
-        let result = {
-            let mut clients = self.shared.clients.lock();
-            let client = clients.get_mut(&self.peer_id).unwrap();
-            let mut streams = self.shared.streams.write();
-            let mut stream = streams.entry(app_name.clone()).or_insert_with(Channel::new);
-            client.publish(&mut stream, app_name.clone(), stream_key.clone());
-            client.accept_request(request_id)
-        };
+        self.client.publish(handle.clone());
 
+        // A resumed stream or a fallback keeps writing to the existing playlist
+        #[cfg(feature = "hls")]
         {
-            let mut app_names = self.shared.app_names.write();
-            app_names.insert(stream_key, app_name);
+            if !handle.lock().has_hls_writer() {
+                if let Some(hls_writer) = hls::server::register_writer(app_name, &self.shared) {
+                    let mut channel = handle.lock();
+                    if channel.is_publisher(self.peer_id) && !channel.has_hls_writer() {
+                        channel.set_hls_writer(hls_writer);
+                    }
+                }
+            }
         }
 
-        match result {
+        self.accept_publish_request(request_id)
+    }
+
+    fn accept_publish_request(&mut self, request_id: u32) -> Result<()> {
+        match self.client.accept_request(request_id) {
             Err(why) => {
                 error!("Error while accepting publishing request: {:?}", why);
                 return Err(Error::SessionError("Publish request failed".into()));
@@ -212,18 +225,11 @@ impl Handler {
         Ok(())
     }
 
-    fn publish_stream_finished(&mut self, app_name: &str, stream_key: &str) -> Result<()> {
+    fn publish_stream_finished(&mut self, app_name: &str) -> Result<()> {
         info!("Publishing of app '{}' finished", app_name);
 
-        {
-            let mut streams = self.shared.streams.write();
-            let stream = streams.get_mut(app_name).unwrap();
-            stream.unpublish();
-        }
-
-        {
-            let mut app_names = self.shared.app_names.write();
-            app_names.remove(stream_key);
+        if let Some(channel) = self.client.published_channel() {
+            channel.lock().release_publisher(self.peer_id);
         }
 
         self.results.push_back(EventResult::Disconnect);
@@ -231,158 +237,121 @@ impl Handler {
         Ok(())
     }
 
-    fn play_requested(&mut self, request_id: u32, app_name: &str, stream_id: u32) -> Result<()> {
+    fn play_requested(&mut self, request_id: u32, app_name: &str, stream_key: &str, stream_id: u32) -> Result<()> {
         info!("Client {} requested playback of app '{}'", self.peer_id, app_name);
 
-        let results = {
-            let mut clients = self.shared.clients.lock();
-            let client = clients.get_mut(&self.peer_id).unwrap();
+        if !is_valid_app_name(app_name) {
+            return self.reject(stream_id, app_name, "NetStream.Play.StreamNotFound", "Invalid application name");
+        }
 
-            {
-                let mut streams = self.shared.streams.write();
-                let mut stream = streams.entry(app_name.to_string()).or_insert_with(Channel::new);
-                client.watch(&mut stream, stream_id, app_name.to_string());
-            }
+        let results = self.client.accept_request(request_id)?;
+        self.handle_server_session_results(results)?;
 
-            client.accept_request(request_id)?
-        };
+        if let Some(path) = self.recording(app_name, stream_key) {
+            info!("Client {} plays recording '{}'", self.peer_id, path.display());
 
-        {
-            let mut clients = self.shared.clients.lock();
-            let client = clients.get_mut(&self.peer_id).unwrap();
-            let streams = self.shared.streams.read();
-
-            if let Some(ref metadata) = streams.get(app_name).unwrap().metadata {
-                let packet = client.session.send_metadata(stream_id, Rc::new(metadata.clone()))
-                    .map_err(|_| Error::SessionError("Failed to send metadata".into()))?;
-                self.results.push_back(EventResult::Outbound(self.peer_id, packet));
+            match self.client.replay(path, stream_id, self.shared.shutdown.signal()) {
+                Ok(()) => return Ok(()),
+                Err(why) => error!("Failed to play recording to client {}: {:?}", self.peer_id, why),
             }
+        }
 
-            if let Some(ref v_seq_h) = streams.get(app_name).unwrap().video_seq_header {
-                let packet = client.session.send_video_data(stream_id, v_seq_h.clone(), RtmpTimestamp::new(0), false)
-                    .map_err(|_| Error::SessionError("Failed to send video data".into()))?;
-                self.results.push_back(EventResult::Outbound(self.peer_id, packet));
-            }
+        let channel = match self.shared.channel_to_watch(app_name) {
+            Some(channel) => channel,
+            None => return self.reject(stream_id, app_name, "NetStream.Play.StreamNotFound", "Stream is not published"),
+        };
 
-            if let Some(ref a_seq_h) = streams.get(app_name).unwrap().audio_seq_header {
-                let packet = client.session.send_audio_data(stream_id, a_seq_h.clone(), RtmpTimestamp::new(0), false)
-                    .map_err(|_| Error::SessionError("Failed to send audio data".into()))?;
-                self.results.push_back(EventResult::Outbound(self.peer_id, packet));
-            }
+        match self.client.watch(channel.clone(), stream_id) {
+            Err(Error::WatcherLimitReached) => {
+                return self.reject(stream_id, app_name, "NetStream.Play.Failed", "Application reached its limit of watchers");
+            },
+            result => result?,
         }
 
-        self.handle_server_session_results(results)?;
+        relay::pull::on_demand(&channel, &self.shared);
 
         Ok(())
     }
 
-    fn metadata_received(&mut self, app_name: &str, metadata: &StreamMetadata) -> Result<()> {
-        debug!("Received stream metadata for app '{}'", app_name);
+    /// Looks up a recording to play, recordings are only played while the application is not live.
+    fn recording(&self, app_name: &str, stream_key: &str) -> Option<PathBuf> {
+        let live = self.shared.channel(app_name).is_some_and(|channel| channel.lock().is_live());
 
-        let mut streams = self.shared.streams.write();
-        if let Some(stream) = streams.get_mut(app_name) {
-            stream.set_metadata(metadata.clone());
-            let mut clients = self.shared.clients.lock();
+        if live {
+            return None;
+        }
 
-            for client_id in &stream.watchers {
-                let client = clients.get_mut(client_id).unwrap();
+        vod::find_recording(&self.shared.config.read().record, app_name, stream_key)
+    }
 
-                if let Some(watched_stream) = client.watched_stream() {
-                    let packet = client.session
-                        .send_metadata(watched_stream, Rc::new(metadata.clone()))
-                        .map_err(|_| Error::SessionError("Failed to send metadata".into()))?;
+    /// Handles seeking and pausing of recordings, other commands are ignored.
+    fn command_received(&mut self, name: &str, values: &[Amf0Value]) {
+        let command = match (name, values) {
+            ("seek", [Amf0Value::Number(position), ..]) => vod::Command::Seek(*position as u32),
+            ("pause", [Amf0Value::Boolean(pause), ..]) => vod::Command::Pause(*pause),
+            _ => {
+                debug!("Unhandled command '{}' of client {}", name, self.peer_id);
+                return;
+            },
+        };
 
-                    self.results.push_back(EventResult::Outbound(self.peer_id, packet));
-                }
-            }
-        }
+        self.client.control_replay(command);
+    }
+
+    /// Refuses the connection with an `_error` result of the connect command,
+    /// the client gets disconnected once the response has been sent.
+    fn reject_connection(&mut self, app_name: &str, description: &str) -> Result<()> {
+        const CODE: &str = "NetConnection.Connect.Rejected";
+
+        warn!("Request rejected: client={} app={:?} code={} reason={:?}", self.peer_id, app_name, CODE, description);
+
+        self.client.reject_connection(CODE, description)?;
+        self.results.push_back(EventResult::Disconnect);
 
         Ok(())
     }
 
-    fn multimedia_data_received(&mut self, stream_key: &str, media: &Media) -> Result<()> {
-        // debug!("Received video data for stream with key {}", stream_key);
+    /// Refuses a request with the given status code and disconnects the client
+    /// once the response has been sent.
+    ///
+    /// Publish requests are refused on the control stream,
+    /// as the session does not expose the stream a client publishes to.
+    fn reject(&mut self, stream_id: u32, app_name: &str, code: &str, description: &str) -> Result<()> {
+        warn!("Request rejected: client={} app={:?} code={} reason={:?}", self.peer_id, app_name, code, description);
 
-        #[cfg(feature = "hls")]
-        self.send_to_hls_writer(media.clone());
-
-        let app_name = self.shared
-            .app_name_from_stream_key(&stream_key)
-            .ok_or_else(|| Error::SessionError("No app for stream key".into()))?;
+        self.client.reject_request(stream_id, code, description)?;
+        self.results.push_back(EventResult::Disconnect);
 
-        let mut streams = self.shared.streams.write();
-        if let Some(stream) = streams.get_mut(&app_name) {
-            match &media {
-                Media::AAC(_, ref data) if media.is_sequence_header() => {
-                    stream.audio_seq_header = Some(data.clone());
-                },
-                Media::H264(_, ref data) if media.is_sequence_header() => {
-                    stream.video_seq_header = Some(data.clone());
-                },
-                _ => (),
-            }
+        Ok(())
+    }
 
-            for client_id in &stream.watchers {
-                let mut clients = self.shared.clients.lock();
-                let client = match clients.get_mut(&client_id) {
-                    Some(client) => client,
-                    None => continue,
-                };
+    fn metadata_received(&mut self, app_name: &str, metadata: StreamMetadata) -> Result<()> {
+        debug!("Received stream metadata for app '{}'", app_name);
 
-                if !(client.received_video_keyframe || media.is_sendable()) {
-                    continue;
-                }
+        let fanout = match self.client.published_channel() {
+            Some(channel) => channel.lock().set_metadata(self.peer_id, metadata.clone()),
+            None => return Ok(()),
+        };
 
-                if let Some(active_stream) = client.watched_stream() {
-                    let packet = match &media {
-                        Media::AAC(timestamp, bytes) => {
-                            client.session.send_audio_data(active_stream, bytes.clone(), timestamp.clone(), true)?
-                        }
-                        Media::H264(timestamp, ref bytes) => {
-                            if media.is_keyframe() {
-                                client.received_video_keyframe = true;
-                            }
-                            client.session.send_video_data(active_stream, bytes.clone(), timestamp.clone(), true)?
-                        },
-                    };
-
-                    self.results.push_back(EventResult::Outbound(*client_id, packet));
-                }
-            }
+        if let Some(fanout) = fanout {
+            fanout.send_metadata(&metadata);
         }
 
         Ok(())
     }
 
-    #[cfg(feature = "hls")]
-    fn register_on_hls_server(&mut self, app_name: String) {
-        if let Some(sender) = self.shared.hls_sender() {
-            let (request, response) = oneshot::channel();
-            sender.unbounded_send((app_name, request))
-                .map_err(|err| error!("{:?}", err))
-                .map(|_| {
-                    response.map(|hls_writer_handle| {
-                        self.media_sender = Some(hls_writer_handle);
-                    })
-                    .wait().unwrap()
-                })
-                .unwrap();
-        }
-    }
+    fn multimedia_data_received(&mut self, mut media: Media) -> Result<()> {
+        let channel = self.client
+            .published_channel()
+            .ok_or_else(|| Error::SessionError("Client is not publishing".into()))?;
 
+        let fanout = channel.lock().prepare_fanout(self.peer_id, &mut media);
 
-    #[cfg(feature = "hls")]
-    fn send_to_hls_writer(&self, media: Media) {
-        if let Some(media_sender) = &self.media_sender {
-            media_sender.unbounded_send(media).unwrap();
+        if let Some(fanout) = fanout {
+            fanout.send_media(&media);
         }
-    }
-}
 
-impl Drop for Handler {
-    fn drop(&mut self) {
-        let mut clients = self.shared.clients.lock();
-        clients.remove(&self.peer_id);
+        Ok(())
     }
 }
 
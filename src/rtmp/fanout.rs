@@ -0,0 +1,422 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use bytes::{Bytes, BytesMut, BufMut};
+use parking_lot::Mutex;
+use rml_amf0::Amf0Value;
+use rml_rtmp::sessions::StreamMetadata;
+use javelin_codec::flv::{Tag, TagKind};
+use log::{error, warn};
+use crate::{
+    error::{Error, Result},
+    media::{self, Media},
+};
+use super::{
+    peer,
+    client::CHUNK_SIZE,
+    chunk_encoder::{ChunkEncoder, MessageKind},
+};
+
+
+/// How the stream is encoded for a watcher
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// RTMP messages on the given message stream
+    Rtmp(u32),
+    /// FLV tags, following an FLV header sent by the watcher itself
+    Flv,
+}
+
+
+/// A peer that is watching a channel.
+///
+/// Media is queued with a limit, a watcher that falls too far behind is
+/// disconnected instead of buffering the stream without bounds.
+#[derive(Clone)]
+pub struct Watcher {
+    pub peer_id: u64,
+    pub format: Format,
+    sender: peer::Sender,
+    // Shared by every copy, a bounded sender only gets its own slot per clone
+    media: Arc<Mutex<peer::MediaSender>>,
+    lagging: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    pub fn new(peer_id: u64, stream_id: u32, sender: peer::Sender, media: peer::MediaSender) -> Self {
+        Self::with_format(peer_id, Format::Rtmp(stream_id), sender, media)
+    }
+
+    pub fn flv(peer_id: u64, sender: peer::Sender, media: peer::MediaSender) -> Self {
+        Self::with_format(peer_id, Format::Flv, sender, media)
+    }
+
+    fn with_format(peer_id: u64, format: Format, sender: peer::Sender, media: peer::MediaSender) -> Self {
+        Self {
+            peer_id,
+            format,
+            sender,
+            media: Arc::new(Mutex::new(media)),
+            lagging: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn send(&self, bytes: Bytes) {
+        if self.lagging.load(Ordering::Relaxed) {
+            return;
+        }
+
+        match self.media.lock().try_send(peer::Message::Raw(bytes)) {
+            Err(ref why) if why.is_full() => {
+                warn!("Watcher {} fell behind, disconnecting", self.peer_id);
+                self.lagging.store(true, Ordering::Relaxed);
+                let _ = self.sender.unbounded_send(peer::Message::Disconnect);
+            },
+            // A failed send means the peer is already gone and will remove itself
+            _ => (),
+        }
+    }
+
+    pub fn disconnect(&self) {
+        // Queued behind the media, unless there is no room left for it
+        if self.media.lock().try_send(peer::Message::Disconnect).is_err() {
+            let _ = self.sender.unbounded_send(peer::Message::Disconnect);
+        }
+    }
+}
+
+
+/// All watchers of a channel.
+///
+/// Watchers start out waiting and only get promoted once a video keyframe
+/// arrives, so no one receives media they are not able to decode.
+#[derive(Default)]
+pub struct Watchers {
+    ready: Arc<Vec<Watcher>>,
+    waiting: Vec<Watcher>,
+}
+
+impl Watchers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, watcher: Watcher) {
+        self.waiting.push(watcher);
+    }
+
+    pub fn remove(&mut self, peer_id: u64) {
+        self.waiting.retain(|w| w.peer_id != peer_id);
+
+        if self.ready.iter().any(|w| w.peer_id == peer_id) {
+            let ready = self.ready.iter()
+                .filter(|w| w.peer_id != peer_id)
+                .cloned()
+                .collect();
+            self.ready = Arc::new(ready);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ready.len() + self.waiting.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Selects the watchers that should receive the given media.
+    pub fn fanout_for(&mut self, media: &Media) -> Fanout {
+        if media.is_keyframe() && !self.waiting.is_empty() {
+            let mut ready = Vec::with_capacity(self.len());
+            ready.extend(self.ready.iter().cloned());
+            ready.append(&mut self.waiting);
+            self.ready = Arc::new(ready);
+        }
+
+        let waiting = if media.is_sendable() {
+            self.waiting.clone()
+        } else {
+            Vec::new()
+        };
+
+        Fanout { ready: Arc::clone(&self.ready), waiting }
+    }
+
+    pub fn all(&self) -> Fanout {
+        Fanout { ready: Arc::clone(&self.ready), waiting: self.waiting.clone() }
+    }
+
+    /// Lets every watcher wait for the next keyframe again, e.g. after the publisher changed.
+    pub fn reset(&mut self) {
+        let mut waiting = Vec::with_capacity(self.len());
+        waiting.extend(self.ready.iter().cloned());
+        waiting.append(&mut self.waiting);
+        self.ready = Arc::new(Vec::new());
+        self.waiting = waiting;
+    }
+
+    pub fn peer_ids(&self) -> Vec<u64> {
+        self.ready.iter().chain(self.waiting.iter()).map(|w| w.peer_id).collect()
+    }
+
+    pub fn disconnect(&self, peer_id: u64) {
+        if let Some(watcher) = self.ready.iter().chain(self.waiting.iter()).find(|w| w.peer_id == peer_id) {
+            watcher.disconnect();
+        }
+    }
+}
+
+
+/// A snapshot of watchers that can be served without holding the channel lock.
+pub struct Fanout {
+    ready: Arc<Vec<Watcher>>,
+    waiting: Vec<Watcher>,
+}
+
+impl Fanout {
+    pub fn single(watcher: Watcher) -> Self {
+        Self { ready: Arc::new(Vec::new()), waiting: vec![watcher] }
+    }
+
+    pub fn send_media(&self, media: &Media) {
+        match media {
+            Media::AAC(timestamp, bytes) => self.send(MessageKind::Audio, TagKind::Audio, timestamp.value, bytes),
+            Media::H264(timestamp, bytes) | Media::H265(timestamp, bytes) => self.send(MessageKind::Video, TagKind::Video, timestamp.value, bytes),
+        }
+    }
+
+    pub fn send_metadata(&self, metadata: &StreamMetadata) {
+        let payload = media::metadata_to_flv(metadata).to_bytes();
+        self.send(MessageKind::Data, TagKind::Script, 0, &payload);
+    }
+
+    /// Only sent to RTMP watchers, FLV has no equivalent.
+    pub fn send_status(&self, code: &str, description: &str) -> Result<()> {
+        let payload = command_payload("onStatus", 0.0, "status", code, description)?;
+        let encoder = ChunkEncoder::new(CHUNK_SIZE);
+
+        self.send_encoded(|format| match format {
+            Format::Rtmp(stream_id) => Some(encoder.encode(MessageKind::Command, 0, stream_id, &payload)),
+            Format::Flv => None,
+        });
+
+        Ok(())
+    }
+
+    /// Tells watchers that the stream ended.
+    /// FLV streams can only be ended by closing them, so FLV watchers get disconnected.
+    pub fn send_stream_eof(&self) {
+        self.send_encoded(|format| match format {
+            Format::Rtmp(stream_id) => Some(encode_stream_eof(stream_id)),
+            Format::Flv => None,
+        });
+
+        for watcher in self.watchers().filter(|w| w.format == Format::Flv) {
+            watcher.disconnect();
+        }
+    }
+
+    fn send(&self, kind: MessageKind, tag_kind: TagKind, timestamp: u32, payload: &Bytes) {
+        let encoder = ChunkEncoder::new(CHUNK_SIZE);
+
+        self.send_encoded(|format| match format {
+            Format::Rtmp(stream_id) => Some(encoder.encode(kind, timestamp, stream_id, payload)),
+            Format::Flv => Tag::new(tag_kind, timestamp, payload.clone()).to_bytes()
+                .map_err(|why| error!("Failed to encode FLV tag: {:?}", why))
+                .ok(),
+        });
+    }
+
+    fn watchers(&self) -> impl Iterator<Item = &Watcher> {
+        self.ready.iter().chain(self.waiting.iter())
+    }
+
+    /// Encodes the message once per format and shares the bytes with every watcher.
+    /// Watchers of formats without an encoding of the message are skipped.
+    fn send_encoded<F>(&self, encode: F)
+        where F: Fn(Format) -> Option<Bytes>
+    {
+        let mut encoded: Vec<(Format, Option<Bytes>)> = Vec::with_capacity(1);
+
+        for watcher in self.watchers() {
+            let bytes = match encoded.iter().find(|(format, _)| *format == watcher.format) {
+                Some((_, bytes)) => bytes.clone(),
+                None => {
+                    let bytes = encode(watcher.format);
+                    encoded.push((watcher.format, bytes.clone()));
+                    bytes
+                }
+            };
+
+            if let Some(bytes) = bytes {
+                watcher.send(bytes);
+            }
+        }
+    }
+}
+
+
+/// Encodes an `onStatus` command for the given message stream.
+pub fn encode_status(stream_id: u32, level: &str, code: &str, description: &str) -> Result<Bytes> {
+    let payload = command_payload("onStatus", 0.0, level, code, description)?;
+    Ok(ChunkEncoder::new(CHUNK_SIZE).encode(MessageKind::Command, 0, stream_id, &payload))
+}
+
+/// Encodes an `_error` result of the `connect` command, which clients always send as transaction 1.
+pub fn encode_connect_error(code: &str, description: &str) -> Result<Bytes> {
+    const CONNECT_TRANSACTION_ID: f64 = 1.0;
+
+    let payload = command_payload("_error", CONNECT_TRANSACTION_ID, "error", code, description)?;
+    Ok(ChunkEncoder::new(CHUNK_SIZE).encode(MessageKind::Command, 0, 0, &payload))
+}
+
+/// Encodes a `StreamEOF` user control event for the given message stream.
+pub fn encode_stream_eof(stream_id: u32) -> Bytes {
+    const STREAM_EOF: u16 = 1;
+
+    let mut payload = BytesMut::with_capacity(6);
+    payload.put_u16_be(STREAM_EOF);
+    payload.put_u32_be(stream_id);
+
+    // User control messages are always sent on chunk stream 2 and message stream 0
+    ChunkEncoder::new(CHUNK_SIZE).encode(MessageKind::UserControl, 0, 0, &payload)
+}
+
+
+fn command_payload(name: &str, transaction_id: f64, level: &str, code: &str, description: &str) -> Result<Vec<u8>> {
+    let mut properties = HashMap::new();
+    properties.insert("level".to_string(), Amf0Value::Utf8String(level.to_string()));
+    properties.insert("code".to_string(), Amf0Value::Utf8String(code.to_string()));
+    properties.insert("description".to_string(), Amf0Value::Utf8String(description.to_string()));
+
+    let values = vec![
+        Amf0Value::Utf8String(name.to_string()),
+        Amf0Value::Number(transaction_id),
+        Amf0Value::Null,
+        Amf0Value::Object(properties),
+    ];
+
+    rml_amf0::serialize(&values)
+        .map_err(|why| Error::SessionError(format!("Failed to serialize status: {:?}", why)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::{sync::mpsc, Stream};
+    use rml_rtmp::time::RtmpTimestamp;
+    use super::*;
+
+    fn watchers(count: u64) -> (Watchers, Vec<peer::MediaReceiver>) {
+        let mut watchers = Watchers::new();
+        let mut receivers = Vec::new();
+
+        for peer_id in 0..count {
+            let (sender, _) = mpsc::unbounded();
+            let (media, receiver) = peer::media_queue();
+            watchers.add(Watcher::new(peer_id, 1, sender, media));
+            receivers.push(receiver);
+        }
+
+        (watchers, receivers)
+    }
+
+    fn received(receiver: peer::MediaReceiver) -> Vec<Bytes> {
+        receiver.wait()
+            .map(|message| match message.unwrap() {
+                peer::Message::Raw(bytes) => bytes,
+                peer::Message::Disconnect => panic!("Unexpected disconnect"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shares_encoded_media_with_many_watchers() {
+        let (mut watchers, receivers) = watchers(1000);
+        let keyframe = Media::H264(RtmpTimestamp::new(40), Bytes::from_static(&[0x17, 0x01, 0x00, 0x00, 0x00]));
+        let inter_frame = Media::H264(RtmpTimestamp::new(80), Bytes::from_static(&[0x27, 0x01, 0x00, 0x00, 0x00]));
+
+        watchers.fanout_for(&keyframe).send_media(&keyframe);
+        watchers.fanout_for(&inter_frame).send_media(&inter_frame);
+        drop(watchers);
+
+        let expected = ChunkEncoder::new(CHUNK_SIZE).encode(MessageKind::Video, 40, 1, &[0x17, 0x01, 0x00, 0x00, 0x00]);
+        let mut first = None;
+
+        for receiver in receivers {
+            let messages = received(receiver);
+            assert_eq!(messages.len(), 2);
+            assert_eq!(messages[0], expected);
+
+            // Encoded once, every watcher gets the same buffer
+            let ptr = messages[0].as_ptr();
+            assert_eq!(*first.get_or_insert(ptr), ptr);
+        }
+    }
+
+    #[test]
+    fn waiting_watchers_start_with_keyframe() {
+        let (mut watchers, mut receivers) = watchers(1);
+        let inter_frame = Media::H264(RtmpTimestamp::new(0), Bytes::from_static(&[0x27, 0x01, 0x00, 0x00, 0x00]));
+        let keyframe = Media::H264(RtmpTimestamp::new(40), Bytes::from_static(&[0x17, 0x01, 0x00, 0x00, 0x00]));
+
+        watchers.fanout_for(&inter_frame).send_media(&inter_frame);
+        watchers.fanout_for(&keyframe).send_media(&keyframe);
+        drop(watchers);
+
+        let messages = received(receivers.remove(0));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0][1..4], [0x00, 0x00, 0x28]);
+    }
+
+    #[test]
+    fn disconnects_watchers_that_fall_behind() {
+        let (sender, receiver) = mpsc::unbounded();
+        let (media, media_receiver) = peer::media_queue();
+        let watcher = Watcher::new(1, 1, sender, media);
+        let frame = Bytes::from_static(&[0x27, 0x01, 0x00, 0x00, 0x00]);
+
+        for _ in 0..1000 {
+            watcher.send(frame.clone());
+        }
+        drop(watcher);
+
+        let queued = media_receiver.wait().count();
+        assert!(queued > 0 && queued < 1000);
+
+        let control: Vec<_> = receiver.wait().collect();
+        assert_eq!(control.len(), 1);
+        match control[0] {
+            Ok(peer::Message::Disconnect) => (),
+            _ => panic!("Expected a disconnect"),
+        }
+    }
+
+    #[test]
+    fn rejects_connections_with_error_result() {
+        let bytes = encode_connect_error("NetConnection.Connect.Rejected", "Denied").unwrap();
+
+        // Command message on message stream 0
+        assert_eq!(bytes[0], 23);
+        assert_eq!(bytes[7], 20);
+        assert_eq!(bytes[8..12], [0, 0, 0, 0]);
+
+        let values = rml_amf0::deserialize(&mut &bytes[12..]).unwrap();
+        assert_eq!(values[0], Amf0Value::Utf8String("_error".to_string()));
+        assert_eq!(values[1], Amf0Value::Number(1.0));
+
+        match &values[3] {
+            Amf0Value::Object(properties) => {
+                assert_eq!(properties["level"], Amf0Value::Utf8String("error".to_string()));
+                assert_eq!(properties["code"], Amf0Value::Utf8String("NetConnection.Connect.Rejected".to_string()));
+            },
+            other => panic!("Unexpected value {:?}", other),
+        }
+    }
+}
@@ -15,6 +15,7 @@ use rml_rtmp::{
 use crate::{
     error::{Error, Result},
     shared::Shared,
+    shutdown,
 };
 use super::{
     BytesStream,
@@ -33,6 +34,21 @@ pub enum Message {
 pub type Sender = mpsc::UnboundedSender<Message>;
 type Receiver = mpsc::UnboundedReceiver<Message>;
 
+/// Media for a watcher, which is only queued up to a limit
+pub type MediaSender = mpsc::Sender<Message>;
+pub type MediaReceiver = mpsc::Receiver<Message>;
+
+/// Messages a watcher may fall behind before it gets disconnected
+const MEDIA_QUEUE_SIZE: usize = 512;
+
+/// Bytes waiting for the socket before no more media is taken from the queue
+const MAX_BUFFERED_BYTES: usize = 256 * 1024;
+
+
+pub fn media_queue() -> (MediaSender, MediaReceiver) {
+    mpsc::channel(MEDIA_QUEUE_SIZE)
+}
+
 
 /// Represents an incoming connection
 pub struct Peer<S>
@@ -42,12 +58,15 @@ pub struct Peer<S>
     bytes_stream: BytesStream<S>,
     sender: Sender,
     receiver: Receiver,
-    shared: Shared,
+    media: MediaReceiver,
     buffer: BytesMut,
     event_handler: EventHandler,
     disconnecting: bool,
     handshake_completed: bool,
     handshake: RtmpHandshake,
+    shutdown: shutdown::Signal,
+    shutdown_handled: bool,
+    _token: Option<shutdown::Token>,
 }
 
 impl<S> Peer<S>
@@ -55,27 +74,28 @@ impl<S> Peer<S>
 {
     pub fn new(id: u64, bytes_stream: BytesStream<S>, shared: Shared) -> Self {
         let (sender, receiver) = mpsc::unbounded();
-        let event_handler = EventHandler::new(id, shared.clone())
+        let (media_sender, media) = media_queue();
+        let shutdown = shared.shutdown.signal();
+        let token = shared.shutdown.token();
+        let event_handler = EventHandler::new(id, sender.clone(), media_sender, shared)
             .unwrap_or_else(|_| {
                 panic!("Failed to create event handler for peer {}", id)
             });
 
-        {
-            let mut peers = shared.peers.write();
-            peers.insert(id, sender.clone());
-        }
-
         Self {
             id,
             bytes_stream,
             sender,
             receiver,
-            shared,
+            media,
             buffer: BytesMut::with_capacity(4096),
             event_handler,
             handshake_completed: false,
             disconnecting: false,
             handshake: RtmpHandshake::new(PeerType::Server),
+            shutdown,
+            shutdown_handled: false,
+            _token: token,
         }
     }
 
@@ -122,22 +142,55 @@ impl<S> Peer<S>
 
         let event_results = self.event_handler.handle(&data)?;
 
-        for result in event_results {
-            match result {
-                EventResult::Outbound(target_peer_id, packet) => {
-                    let peers = self.shared.peers.read();
-                    let peer = peers.get(&target_peer_id).unwrap();
-                    // debug!("Packet from {} to {} with {:?} bytes", self.id, target_peer_id, packet.bytes.len());
-                    peer.unbounded_send(Message::Raw(Bytes::from(packet.bytes))).unwrap();
+        if let Some(EventResult::Disconnect) = event_results.into_iter().next() {
+            self.disconnecting = true;
+        }
+
+        Ok(())
+    }
+
+    fn handle_shutdown(&mut self) -> Result<()> {
+        if !self.shutdown_handled && self.shutdown.poll() == Ok(Async::Ready(())) {
+            self.shutdown_handled = true;
+            self.event_handler.shutdown()?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves queued messages into the write buffer.
+    /// Returns false if media was left in its queue because the client is not reading fast enough.
+    fn write_outgoing(&mut self) -> bool {
+        while let Async::Ready(Some(msg)) = self.receiver.poll().unwrap() {
+            if !self.write_message(msg) {
+                return true;
+            }
+        }
+
+        while self.bytes_stream.buffered_len() < MAX_BUFFERED_BYTES {
+            match self.media.poll().unwrap() {
+                Async::Ready(Some(msg)) => if !self.write_message(msg) {
+                    return true;
                 },
-                EventResult::Disconnect => {
-                    self.disconnecting = true;
-                    break;
-                }
+                _ => return true,
             }
         }
 
-        Ok(())
+        false
+    }
+
+    /// Returns false once the peer should disconnect.
+    fn write_message(&mut self, msg: Message) -> bool {
+        match msg {
+            Message::Raw(val) => {
+                self.bytes_stream.fill_write_buffer(&val);
+                true
+            },
+            Message::Disconnect => {
+                self.disconnecting = true;
+                false
+            }
+        }
     }
 }
 
@@ -145,9 +198,6 @@ impl<S> Drop for Peer<S>
     where S: AsyncRead + AsyncWrite
 {
     fn drop(&mut self) {
-        let mut peers = self.shared.peers.write();
-        peers.remove(&self.id);
-
         info!("Closing connection: {}", self.id);
     }
 }
@@ -159,41 +209,38 @@ impl<S> Future for Peer<S>
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // FIXME: potential starvation of socket stream?
-        while let Async::Ready(Some(msg)) = self.receiver.poll().unwrap() {
-            match msg {
-                Message::Raw(val) => {
-                    self.bytes_stream.fill_write_buffer(&val);
-                },
-                Message::Disconnect => {
-                    self.disconnecting = true;
-                    break;
-                }
+        loop {
+            self.handle_shutdown()?;
+            let drained = self.write_outgoing();
+
+            if self.disconnecting {
+                // Everything queued before the disconnect still has to reach the client
+                try_ready!(self.bytes_stream.poll_flush());
+                return Ok(Async::Ready(()));
             }
-        }
 
-        let _ = self.bytes_stream.poll_flush()?;
+            let flushed = self.bytes_stream.poll_flush()?.is_ready();
 
-        match try_ready!(self.bytes_stream.poll()) {
-            Some(data) => {
-                self.buffer.reserve(data.len());
-                self.buffer.put(data);
+            // Nothing else wakes the peer up for the media still queued
+            if flushed && !drained {
+                continue;
+            }
 
-                if self.handshake_completed {
-                    self.handle_incoming_bytes()?;
-                } else {
-                    try_ready!(self.handle_handshake());
-                }
-            },
-            None => {
-                return Ok(Async::Ready(()));
-            },
-        }
+            match try_ready!(self.bytes_stream.poll()) {
+                Some(data) => {
+                    self.buffer.reserve(data.len());
+                    self.buffer.put(data);
 
-        if self.disconnecting {
-            Ok(Async::Ready(()))
-        } else {
-            Ok(Async::NotReady)
+                    if self.handshake_completed {
+                        self.handle_incoming_bytes()?;
+                    } else {
+                        try_ready!(self.handle_handshake());
+                    }
+                },
+                None => {
+                    return Ok(Async::Ready(()));
+                },
+            }
         }
     }
 }
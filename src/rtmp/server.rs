@@ -1,7 +1,6 @@
 use std::{
     net::SocketAddr,
     io::ErrorKind as IoErrorKind,
-    sync::atomic::{AtomicUsize, Ordering},
     time::Duration,
 };
 use log::{info, error};
@@ -17,19 +16,17 @@ use tokio_tls::TlsAcceptor;
 use crate::{
     error::Error,
     shared::Shared,
+    shutdown,
 };
 
 use super::{Peer, BytesStream};
 
 
-type ClientId = u64;
-
-
 pub struct Server {
     shared: Shared,
     _addr: SocketAddr,
     listener: Incoming,
-    client_id: AtomicUsize,
+    shutdown: shutdown::Signal,
 }
 
 impl Server {
@@ -39,21 +36,15 @@ impl Server {
 
         info!("Starting up Javelin RTMP server on {}", addr);
 
+        let shutdown = shared.shutdown.signal();
+
         Self {
             shared,
             _addr: addr,
             listener: listener.incoming(),
-            client_id: AtomicUsize::default(),
+            shutdown,
         }
     }
-
-    fn client_id(&self) -> ClientId {
-        self.client_id.load(Ordering::SeqCst) as ClientId
-    }
-
-    fn increment_client_id(&mut self) {
-        self.client_id.fetch_add(1, Ordering::SeqCst);
-    }
 }
 
 impl Future for Server {
@@ -61,9 +52,13 @@ impl Future for Server {
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            info!("RTMP server stopped accepting connections");
+            return Ok(Async::Ready(()));
+        }
+
         while let Some(tcp_stream) = try_ready!(self.listener.poll().map_err(|err| error!("{}", err))) {
-            spawner(self.client_id(), tcp_stream, self.shared.clone());
-            self.increment_client_id();
+            spawner(self.shared.next_client_id(), tcp_stream, self.shared.clone());
         }
 
         Ok(Async::Ready(()))
@@ -1,15 +1,15 @@
 use std::{
     collections::HashMap,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
-use parking_lot::{RwLock, Mutex};
+use parking_lot::RwLock;
 use crate::{
-    media::Channel,
-    rtmp::{
-        Client,
-        peer,
-    },
+    channel::{self, Channel},
     config::Config,
+    shutdown::Shutdown,
 };
 #[cfg(feature = "hls")]
 use crate::hls;
@@ -18,10 +18,9 @@ use crate::hls;
 #[derive(Clone)]
 pub struct Shared {
     pub config: Arc<RwLock<Config>>,
-    pub peers: Arc<RwLock<HashMap<u64, peer::Sender>>>,
-    pub clients: Arc<Mutex<HashMap<u64, Client>>>,
-    pub streams: Arc<RwLock<HashMap<String, Channel>>>,
-    pub app_names: Arc<RwLock<HashMap<String, String>>>,
+    pub channels: Arc<RwLock<HashMap<String, channel::Handle>>>,
+    pub shutdown: Shutdown,
+    client_id: Arc<AtomicUsize>,
     #[cfg(feature = "hls")]
     hls_sender: Arc<RwLock<Option<hls::server::Sender>>>,
     #[cfg(feature = "hls")]
@@ -29,13 +28,16 @@ pub struct Shared {
 }
 
 impl Shared {
-    pub fn new() -> Self {
+    pub fn new(shutdown: Shutdown) -> Self {
+        Self::with_config(Config::new(), shutdown)
+    }
+
+    pub fn with_config(config: Config, shutdown: Shutdown) -> Self {
         Self {
-            config: Arc::new(RwLock::new(Config::new())),
-            peers: Arc::new(RwLock::new(HashMap::new())),
-            clients: Arc::new(Mutex::new(HashMap::new())),
-            streams: Arc::new(RwLock::new(HashMap::new())),
-            app_names: Arc::new(RwLock::new(HashMap::new())),
+            config: Arc::new(RwLock::new(config)),
+            channels: Arc::new(RwLock::new(HashMap::new())),
+            shutdown,
+            client_id: Arc::new(AtomicUsize::default()),
             #[cfg(feature = "hls")]
             hls_sender: Arc::new(RwLock::new(None)),
             #[cfg(feature = "hls")]
@@ -43,6 +45,11 @@ impl Shared {
         }
     }
 
+    /// Hands out unique ids for RTMP clients and relays, which can both act as publisher.
+    pub fn next_client_id(&self) -> u64 {
+        self.client_id.fetch_add(1, Ordering::SeqCst) as u64
+    }
+
     #[cfg(feature = "hls")]
     pub fn set_hls_sender(&mut self, sender: hls::server::Sender) {
         let mut hls_sender = self.hls_sender.write();
@@ -60,14 +67,109 @@ impl Shared {
         *fcleaner_sender = Some(sender);
     }
 
+    /// Drops the shared file cleaner sender, so the cleaner can finish
+    /// as soon as all playlists are gone.
+    #[cfg(feature = "hls")]
+    pub fn unset_fcleaner_sender(&self) {
+        let mut fcleaner_sender = self.fcleaner_sender.write();
+        *fcleaner_sender = None;
+    }
+
     #[cfg(feature = "hls")]
     pub fn fcleaner_sender(&self) -> Option<hls::file_cleaner::Sender> {
         self.fcleaner_sender.read().clone()
     }
 
-    pub fn app_name_from_stream_key(&self, stream_key: &str) -> Option<String> {
-        let app_names = self.app_names.read();
-        let app_name = app_names.get(stream_key)?;
-        Some(app_name.to_string())
+    #[allow(dead_code)]
+    pub fn channel(&self, app_name: &str) -> Option<channel::Handle> {
+        self.channels.read().get(app_name).cloned()
+    }
+
+    /// Looks up the channel of an application, creating it if necessary.
+    /// This is only needed when a client starts publishing,
+    /// afterwards the client keeps its own handle to the channel.
+    ///
+    /// Channels nothing uses anymore are removed whenever a channel is created,
+    /// so there are never more channels than applications in use.
+    pub fn channel_or_create(&self, app_name: &str) -> channel::Handle {
+        if let Some(channel) = self.channels.read().get(app_name) {
+            return channel.clone();
+        }
+
+        let mut channels = self.channels.write();
+        channels.retain(|name, channel| name == app_name || !is_unused(channel));
+        channels.entry(app_name.to_string())
+            .or_insert_with(|| Channel::create(app_name.to_string(), self))
+            .clone()
+    }
+
+    /// Looks up the channel of an application a client wants to watch.
+    ///
+    /// Applications that are not published are only created when they can be pulled on demand,
+    /// watching anything else fails right away.
+    pub fn channel_to_watch(&self, app_name: &str) -> Option<channel::Handle> {
+        if let Some(channel) = self.channel(app_name) {
+            return Some(channel);
+        }
+
+        let on_demand = {
+            let config = self.config.read();
+            config.pull_on_demand.is_some() || !config.origins.is_empty()
+        };
+
+        if on_demand {
+            Some(self.channel_or_create(app_name))
+        } else {
+            None
+        }
+    }
+}
+
+
+/// Clients, relays and timers keep their own handle while they use a channel,
+/// so a channel only referred to by the map is unused once it holds no state either.
+fn is_unused(channel: &channel::Handle) -> bool {
+    Arc::strong_count(channel) == 1 && channel.try_lock().is_some_and(|channel| channel.is_unused())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use super::*;
+
+    fn shared(args: &[&str]) -> Shared {
+        let (shutdown, _) = Shutdown::new();
+        Shared::with_config(Config::from_args(args), shutdown)
+    }
+
+    #[test]
+    fn removes_unused_channels() {
+        let shared = shared(&["javelin"]);
+
+        let watched = shared.channel_or_create("watched");
+        shared.channel_or_create("recorded").lock().start_recording().unwrap();
+        shared.channel_or_create("unused");
+        shared.channel_or_create("other");
+
+        assert!(shared.channel("watched").is_some());
+        assert!(shared.channel("recorded").is_some());
+        assert!(shared.channel("unused").is_none());
+        assert!(shared.channel("other").is_some());
+
+        drop(watched);
+        shared.channel_or_create("another");
+        assert!(shared.channel("watched").is_none());
+        assert!(shared.channel("other").is_none());
+    }
+
+    #[test]
+    fn creates_channels_to_watch_only_on_demand() {
+        let shared = shared(&["javelin"]);
+        assert!(shared.channel_to_watch("live").is_none());
+        assert!(shared.channel("live").is_none());
+
+        let published = shared.channel_or_create("live");
+        assert!(shared.channel_to_watch("live").is_some_and(|channel| Arc::ptr_eq(&channel, &published)));
     }
 }
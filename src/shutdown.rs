@@ -0,0 +1,116 @@
+use std::sync::Arc;
+use log::info;
+use parking_lot::Mutex;
+use futures::{
+    try_ready,
+    future::{self, Shared as SharedFuture},
+    sync::{mpsc, oneshot},
+};
+use tokio::prelude::*;
+
+
+/// Resolves once a shutdown has been triggered.
+#[derive(Clone)]
+pub struct Signal(SharedFuture<oneshot::Receiver<()>>);
+
+impl Future for Signal {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // A dropped trigger is treated like a triggered shutdown
+            _ => Ok(Async::Ready(())),
+        }
+    }
+}
+
+
+/// Held by every task that has to finish before the process may exit.
+pub struct Token {
+    _sender: mpsc::UnboundedSender<()>,
+}
+
+
+/// Resolves once every handed out token has been dropped.
+pub struct Drained(mpsc::UnboundedReceiver<()>);
+
+impl Future for Drained {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while try_ready!(self.0.poll()).is_some() {}
+        Ok(Async::Ready(()))
+    }
+}
+
+
+/// Coordinates the shutdown of all running tasks.
+#[derive(Clone)]
+pub struct Shutdown {
+    signal: Signal,
+    trigger: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    drain: Arc<Mutex<Option<mpsc::UnboundedSender<()>>>>,
+}
+
+impl Shutdown {
+    pub fn new() -> (Self, Drained) {
+        let (trigger, signal) = oneshot::channel();
+        let (drain, drained) = mpsc::unbounded();
+
+        let this = Self {
+            signal: Signal(signal.shared()),
+            trigger: Arc::new(Mutex::new(Some(trigger))),
+            drain: Arc::new(Mutex::new(Some(drain))),
+        };
+
+        (this, Drained(drained))
+    }
+
+    pub fn signal(&self) -> Signal {
+        self.signal.clone()
+    }
+
+    /// Returns a token that delays the shutdown until dropped,
+    /// or `None` if the shutdown is already in progress.
+    pub fn token(&self) -> Option<Token> {
+        self.drain.lock().as_ref().map(|sender| Token { _sender: sender.clone() })
+    }
+
+    pub fn trigger(&self) {
+        if let Some(trigger) = self.trigger.lock().take() {
+            info!("Shutdown triggered");
+            let _ = trigger.send(());
+        }
+
+        self.drain.lock().take();
+    }
+}
+
+
+/// Resolves on the first SIGINT or SIGTERM.
+pub fn os_signal() -> Box<dyn Future<Item = (), Error = ()> + Send> {
+    let ctrl_c = future::lazy(tokio_signal::ctrl_c)
+        .flatten_stream()
+        .into_future()
+        .map(|_| ())
+        .map_err(|_| ());
+
+    #[cfg(unix)]
+    {
+        use tokio_signal::unix::{Signal as UnixSignal, SIGTERM};
+
+        let terminate = UnixSignal::new(SIGTERM)
+            .flatten_stream()
+            .into_future()
+            .map(|_| ())
+            .map_err(|_| ());
+
+        Box::new(ctrl_c.select(terminate).map(|_| ()).map_err(|_| ()))
+    }
+
+    #[cfg(not(unix))]
+    Box::new(ctrl_c)
+}
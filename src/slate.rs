@@ -0,0 +1,309 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use log::{debug, error, info};
+use futures::try_ready;
+use tokio::{
+    prelude::*,
+    timer::Delay,
+};
+use rml_rtmp::sessions::StreamMetadata;
+use javelin_codec::flv;
+use crate::{
+    channel,
+    error::{Error, Result},
+    media::{self, Media},
+    shared::Shared,
+    shutdown,
+};
+#[cfg(feature = "hls")]
+use crate::hls;
+
+
+/// Loops an FLV file on a channel while nobody is publishing to it.
+///
+/// Media is read from the file as it is due and paced in real time. As soon as
+/// the publisher is back on air the slate idles and starts from the beginning
+/// of the file next time.
+pub struct Slate {
+    app_name: String,
+    path: PathBuf,
+    channel: channel::Handle,
+    #[cfg_attr(not(feature = "hls"), allow(dead_code))]
+    shared: Shared,
+    reader: flv::Reader<BufReader<File>>,
+    /// Position of the first tag, where every loop starts over
+    start_position: u64,
+    /// Timestamp of the first media in the file
+    first_timestamp: Option<u32>,
+    /// Added to the timestamps of the current loop
+    loop_offset: u32,
+    last_timestamp: u32,
+    pending: Option<Media>,
+    started: Option<Instant>,
+    delay: Delay,
+    shutdown: shutdown::Signal,
+}
+
+impl Slate {
+    /// Time between checks whether the publisher went away
+    const IDLE_INTERVAL: Duration = Duration::from_millis(500);
+    /// Gap between the last tag of the file and the first tag of the next loop
+    const LOOP_GAP: u32 = 40; // milliseconds
+
+    pub fn create<P>(app_name: String, path: P, shared: Shared) -> Result<Self>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref().to_path_buf();
+        let mut reader = flv::Reader::new(BufReader::new(File::open(&path)?))?;
+        let start_position = reader.position();
+        let metadata = read_metadata(&mut reader, &path)?;
+        reader.seek(start_position)?;
+
+        let channel = shared.channel_or_create(&app_name);
+        channel.lock().enable_fallback(metadata);
+
+        Ok(Self {
+            app_name,
+            path,
+            channel,
+            reader,
+            start_position,
+            first_timestamp: None,
+            loop_offset: 0,
+            last_timestamp: 0,
+            pending: None,
+            started: None,
+            delay: Delay::new(Instant::now()),
+            shutdown: shared.shutdown.signal(),
+            shared,
+        })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.reader.seek(self.start_position)?;
+        self.first_timestamp = None;
+        self.loop_offset = 0;
+        self.last_timestamp = 0;
+        self.pending = None;
+        self.started = None;
+        Ok(())
+    }
+
+    /// Reads the next media, starting over at the end of the file.
+    /// Timestamps continue those of previous loops.
+    fn next_media(&mut self) -> Result<Media> {
+        loop {
+            let tag = match self.reader.read_tag()? {
+                Some(tag) => tag,
+                None if self.first_timestamp.is_some() => {
+                    debug!("Starting over with '{}'", self.path.display());
+                    self.reader.seek(self.start_position)?;
+                    self.loop_offset = self.last_timestamp + Self::LOOP_GAP;
+                    self.first_timestamp = None;
+                    continue;
+                },
+                None => return Err(Error::from(format!("No supported media in '{}'", self.path.display()))),
+            };
+
+            if let Some(mut media) = Media::from_flv_tag(tag) {
+                let first_timestamp = *self.first_timestamp.get_or_insert(media.timestamp());
+                let timestamp = self.loop_offset + media.timestamp().saturating_sub(first_timestamp);
+                media.set_timestamp(timestamp);
+                self.last_timestamp = timestamp;
+                return Ok(media);
+            }
+        }
+    }
+
+    /// Reads the next media on the blocking pool
+    fn poll_next_media(&mut self) -> Poll<Media, Error> {
+        match tokio_threadpool::blocking(|| self.next_media()) {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // Outside of the thread pool, e.g. on a current thread runtime
+            Err(_) => self.next_media().map(Async::Ready),
+        }
+    }
+
+    /// Sends all media that is due, until the publisher comes back.
+    fn play(&mut self) -> Poll<(), Error> {
+        loop {
+            try_ready!(self.delay.poll().map_err(|why| Error::from(format!("{:?}", why))));
+
+            let mut media = match self.pending.take() {
+                Some(media) => media,
+                None => try_ready!(self.poll_next_media()),
+            };
+
+            let started = *self.started.get_or_insert_with(Instant::now);
+            let due = started + Duration::from_millis(u64::from(media.timestamp()));
+
+            if due > Instant::now() {
+                self.pending = Some(media);
+                self.delay.reset(due);
+                continue;
+            }
+
+            // The fallback might be the first to put the application on air
+            #[cfg(feature = "hls")]
+            {
+                if media.timestamp() == 0 {
+                    hls::server::ensure_writer(&self.channel, &self.shared);
+                }
+            }
+
+            let fanout = self.channel.lock().prepare_fallback_fanout(&mut media);
+
+            match fanout {
+                Some(fanout) => fanout.send_media(&media),
+                None => {
+                    // The publisher is on air
+                    self.reset()?;
+                    self.delay.reset(Instant::now() + Self::IDLE_INTERVAL);
+                },
+            }
+        }
+    }
+}
+
+impl Future for Slate {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            debug!("Stopping fallback of app '{}'", self.app_name);
+            self.channel.lock().disable_fallback();
+            return Ok(Async::Ready(()));
+        }
+
+        match self.play() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(())) => (),
+            Err(why) => error!("Failed to loop '{}' for app '{}': {:?}", self.path.display(), self.app_name, why),
+        }
+
+        self.channel.lock().disable_fallback();
+        Ok(Async::Ready(()))
+    }
+}
+
+
+/// Reads the metadata preceding the first supported media of an FLV file.
+/// Files without any supported media are refused.
+fn read_metadata<R>(reader: &mut flv::Reader<R>, path: &Path) -> Result<Option<StreamMetadata>>
+    where R: Read
+{
+    let mut metadata = None;
+
+    while let Some(tag) = reader.read_tag()? {
+        if metadata.is_none() {
+            if let Some(properties) = flv::Metadata::try_from_tag(&tag)? {
+                metadata = Some(media::metadata_from_flv(&properties));
+                continue;
+            }
+        }
+
+        if Media::from_flv_tag(tag).is_some() {
+            return Ok(metadata);
+        }
+    }
+
+    Err(Error::from(format!("No supported media in '{}'", path.display())))
+}
+
+
+pub fn spawn_all(shared: &Shared) {
+    let fallback_sources = shared.config.read().fallback_sources.clone();
+
+    for (app_name, path) in fallback_sources {
+        match Slate::create(app_name.clone(), &path, shared.clone()) {
+            Ok(slate) => {
+                info!("Fallback for app '{}' is '{}'", app_name, path.display());
+                tokio::spawn(slate);
+            },
+            Err(why) => error!("Failed to load fallback for app '{}': {:?}", app_name, why),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use bytes::Bytes;
+    use rml_rtmp::time::RtmpTimestamp;
+    use javelin_codec::flv::{Tag, TagKind};
+    use crate::{
+        config::Config,
+        shutdown::Shutdown,
+    };
+    use super::*;
+
+    fn shared() -> Shared {
+        let (shutdown, _) = Shutdown::new();
+        Shared::with_config(Config::from_args(&["javelin"]), shutdown)
+    }
+
+    /// Writes an audio only FLV file with media at the given timestamps
+    fn write_file(name: &str, timestamps: &[u32]) -> PathBuf {
+        let path = env::temp_dir().join(format!("javelin-slate-{}-{}.flv", name, std::process::id()));
+        let header = flv::Header { has_audio: true, has_video: false };
+        let mut writer = flv::Writer::new(Vec::new(), header).unwrap();
+
+        writer.write_tag(&Tag::new(TagKind::Script, 0, Bytes::from_static(&[0x02, 0x00, 0x00]))).unwrap();
+        for timestamp in timestamps {
+            writer.write_tag(&Tag::new(TagKind::Audio, *timestamp, Bytes::from_static(&[0xAF, 0x01, 0x21]))).unwrap();
+        }
+
+        fs::write(&path, writer.into_inner()).unwrap();
+        path
+    }
+
+    fn timestamps(slate: &mut Slate, count: usize) -> Vec<u32> {
+        (0..count).map(|_| slate.next_media().unwrap().timestamp()).collect()
+    }
+
+    #[test]
+    fn loops_with_continuing_timestamps() {
+        let path = write_file("loop", &[100, 110, 120]);
+        let mut slate = Slate::create("live".to_string(), &path, shared()).unwrap();
+
+        assert_eq!(timestamps(&mut slate, 7), vec![0, 10, 20, 60, 70, 80, 120]);
+
+        // The next time the fallback goes on air it starts over
+        slate.reset().unwrap();
+        assert_eq!(timestamps(&mut slate, 2), vec![0, 10]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keeps_application_on_air() {
+        let path = write_file("fallback", &[0]);
+        let shared = shared();
+        let _slate = Slate::create("live".to_string(), &path, shared.clone()).unwrap();
+        let channel = shared.channel_or_create("live");
+        let mut channel = channel.lock();
+
+        assert!(!channel.is_unused());
+        assert!(!channel.is_live());
+
+        let mut media = Media::AAC(RtmpTimestamp::new(0), Bytes::from_static(&[0xAF, 0x01, 0x21]));
+        assert!(channel.prepare_fallback_fanout(&mut media).is_some());
+        assert!(channel.is_live());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_files_without_media() {
+        let path = write_file("empty", &[]);
+        assert!(Slate::create("live".to_string(), &path, shared()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+mod ffi;
+mod socket;
+mod stream_id;
+
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+use log::{debug, error, info, warn};
+use futures::{sync::mpsc, try_ready};
+use tokio::prelude::*;
+use bytes::Bytes;
+use crate::{
+    error::Result,
+    shared::Shared,
+    shutdown,
+    ts_ingest::publishing::Publishing,
+};
+use self::socket::{Received, Socket};
+
+
+/// A caller that connected to the listener
+struct Caller {
+    socket: Socket,
+    addr: Option<SocketAddr>,
+}
+
+
+/// Starts listening for SRT callers, if enabled.
+pub fn spawn(shared: &Shared) {
+    let config = shared.config.read().srt.clone();
+
+    if !config.enabled {
+        return;
+    }
+
+    let listener = match Socket::listen(config.addr, config.passphrase.as_deref(), config.latency) {
+        Ok(listener) => listener,
+        Err(why) => return error!("Failed to listen for SRT callers on {}: {:?}", config.addr, why),
+    };
+
+    info!("Listening for SRT callers on {}", config.addr);
+
+    // Accepting blocks, so it is done on a thread of its own
+    let (sender, receiver) = mpsc::unbounded();
+    thread::spawn(move || loop {
+        match listener.accept() {
+            Ok((socket, addr)) => {
+                if sender.unbounded_send(Caller { socket, addr }).is_err() {
+                    break;
+                }
+            },
+            Err(why) => {
+                error!("Failed to accept SRT caller: {:?}", why);
+                break;
+            },
+        }
+    });
+
+    tokio::spawn(Listener { callers: receiver, shared: shared.clone(), shutdown: shared.shutdown.signal() });
+}
+
+
+/// Lets accepted callers publish.
+struct Listener {
+    callers: mpsc::UnboundedReceiver<Caller>,
+    shared: Shared,
+    shutdown: shutdown::Signal,
+}
+
+impl Listener {
+    fn accept(&mut self, caller: Caller) -> Result<()> {
+        let peer = caller.addr.map(|addr| addr.to_string()).unwrap_or_else(|| "unknown".to_string());
+        let stream_id = caller.socket.stream_id()?;
+
+        let (app_name, stream_key) = match stream_id::parse(&stream_id) {
+            Some(target) => target,
+            None => {
+                warn!("Rejected SRT caller {} with invalid stream ID '{}'", peer, stream_id);
+                return Ok(());
+            },
+        };
+
+        let publishing = match Publishing::claim(&app_name, stream_key, &self.shared) {
+            Ok(publishing) => publishing,
+            Err(denied) => {
+                warn!("Rejected SRT caller {} publishing to app '{}': {:?}", peer, app_name, denied);
+                return Ok(());
+            },
+        };
+
+        info!("SRT caller {} is publishing to app '{}'", peer, app_name);
+
+        let (sender, receiver) = mpsc::unbounded();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        {
+            let stop = Arc::clone(&stop);
+            let socket = caller.socket;
+            thread::spawn(move || receive(&socket, &sender, &stop));
+        }
+
+        tokio::spawn(Connection {
+            app_name,
+            data: receiver,
+            publishing,
+            stop,
+            shutdown: self.shared.shutdown.signal(),
+        });
+
+        Ok(())
+    }
+}
+
+impl Future for Listener {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+
+        while let Some(caller) = try_ready!(self.callers.poll()) {
+            if let Err(why) = self.accept(caller) {
+                warn!("Failed to accept SRT caller: {:?}", why);
+            }
+        }
+
+        Ok(Async::Ready(()))
+    }
+}
+
+
+/// Receives the messages of a caller until it disconnects or the connection is no longer needed.
+fn receive(socket: &Socket, sender: &mpsc::UnboundedSender<Bytes>, stop: &AtomicBool) {
+    // Wakes up regularly to notice when the connection is no longer needed
+    const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+    // Live mode messages carry up to seven transport stream packets
+    const MAX_MESSAGE_SIZE: usize = 1500;
+
+    if let Err(why) = socket.set_receive_timeout(RECEIVE_TIMEOUT) {
+        return warn!("Failed to set SRT receive timeout: {:?}", why);
+    }
+
+    let mut buf = [0; MAX_MESSAGE_SIZE];
+
+    while !stop.load(Ordering::Relaxed) {
+        match socket.receive(&mut buf) {
+            Ok(Received::Data(size)) => {
+                if sender.unbounded_send(Bytes::from(&buf[..size])).is_err() {
+                    break;
+                }
+            },
+            Ok(Received::TimedOut) => (),
+            Ok(Received::Closed) => break,
+            Err(why) => {
+                debug!("SRT connection failed: {:?}", why);
+                break;
+            },
+        }
+    }
+}
+
+
+/// Publishes the transport stream of a caller, ends with the connection.
+struct Connection {
+    app_name: String,
+    data: mpsc::UnboundedReceiver<Bytes>,
+    publishing: Publishing,
+    stop: Arc<AtomicBool>,
+    shutdown: shutdown::Signal,
+}
+
+impl Future for Connection {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() || self.publishing.is_replaced() {
+            return Ok(Async::Ready(()));
+        }
+
+        while let Some(bytes) = try_ready!(self.data.poll()) {
+            if let Err(why) = self.publishing.push(&bytes) {
+                warn!("Invalid transport stream for app '{}': {:?}", self.app_name, why);
+                return Ok(Async::Ready(()));
+            }
+        }
+
+        info!("SRT caller of app '{}' disconnected", self.app_name);
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,40 @@
+//! Bindings of the parts of libsrt that are needed to receive streams.
+//!
+//! See `srt/srt.h` of the [SRT library][srt] for documentation.
+//!
+//! [srt]: https://github.com/Haivision/srt
+
+#![allow(non_camel_case_types, clippy::upper_case_acronyms)]
+
+use libc::{c_char, c_int, c_void, sockaddr};
+
+
+pub type SRTSOCKET = c_int;
+pub type SRT_SOCKOPT = c_int;
+
+pub const SRT_INVALID_SOCK: SRTSOCKET = -1;
+pub const SRT_ERROR: c_int = -1;
+
+pub const SRTO_RCVTIMEO: SRT_SOCKOPT = 14;
+pub const SRTO_LATENCY: SRT_SOCKOPT = 23;
+pub const SRTO_PASSPHRASE: SRT_SOCKOPT = 26;
+pub const SRTO_STREAMID: SRT_SOCKOPT = 46;
+
+/// Error code of a receive that timed out
+pub const SRT_EASYNCRCV: c_int = 6002;
+
+
+#[link(name = "srt")]
+extern "C" {
+    pub fn srt_startup() -> c_int;
+    pub fn srt_create_socket() -> SRTSOCKET;
+    pub fn srt_setsockflag(socket: SRTSOCKET, option: SRT_SOCKOPT, value: *const c_void, length: c_int) -> c_int;
+    pub fn srt_getsockflag(socket: SRTSOCKET, option: SRT_SOCKOPT, value: *mut c_void, length: *mut c_int) -> c_int;
+    pub fn srt_bind(socket: SRTSOCKET, name: *const sockaddr, length: c_int) -> c_int;
+    pub fn srt_listen(socket: SRTSOCKET, backlog: c_int) -> c_int;
+    pub fn srt_accept(socket: SRTSOCKET, addr: *mut sockaddr, length: *mut c_int) -> SRTSOCKET;
+    pub fn srt_recvmsg(socket: SRTSOCKET, buf: *mut c_char, length: c_int) -> c_int;
+    pub fn srt_close(socket: SRTSOCKET) -> c_int;
+    pub fn srt_getlasterror(errno: *mut c_int) -> c_int;
+    pub fn srt_getlasterror_str() -> *const c_char;
+}
@@ -0,0 +1,177 @@
+use std::{
+    ffi::CStr,
+    mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::raw::{c_char, c_int, c_void},
+    ptr,
+    sync::Once,
+    time::Duration,
+};
+use crate::error::{Error, Result};
+use super::ffi;
+
+
+fn last_error() -> Error {
+    let message = unsafe { CStr::from_ptr(ffi::srt_getlasterror_str()) };
+    Error::from(format!("SRT: {}", message.to_string_lossy()))
+}
+
+fn check(result: c_int) -> Result<c_int> {
+    if result == ffi::SRT_ERROR {
+        Err(last_error())
+    } else {
+        Ok(result)
+    }
+}
+
+
+/// The outcome of waiting for data
+pub enum Received {
+    Data(usize),
+    TimedOut,
+    Closed,
+}
+
+
+/// An SRT socket in blocking mode, closed once dropped
+pub struct Socket {
+    id: ffi::SRTSOCKET,
+}
+
+impl Socket {
+    fn create() -> Result<Self> {
+        static STARTUP: Once = Once::new();
+        STARTUP.call_once(|| unsafe {
+            ffi::srt_startup();
+        });
+
+        let id = unsafe { ffi::srt_create_socket() };
+        if id == ffi::SRT_INVALID_SOCK {
+            return Err(last_error());
+        }
+
+        Ok(Self { id })
+    }
+
+    /// Creates a socket listening for callers. Accepted sockets inherit the passphrase and latency.
+    pub fn listen(addr: SocketAddr, passphrase: Option<&str>, latency: Duration) -> Result<Self> {
+        let socket = Self::create()?;
+
+        let latency = latency.as_millis() as c_int;
+        socket.set_option(ffi::SRTO_LATENCY, &latency as *const c_int as *const c_void, mem::size_of::<c_int>())?;
+
+        if let Some(passphrase) = passphrase {
+            socket.set_option(ffi::SRTO_PASSPHRASE, passphrase.as_ptr() as *const c_void, passphrase.len())?;
+        }
+
+        let (storage, length) = to_sockaddr(addr);
+        check(unsafe { ffi::srt_bind(socket.id, &storage as *const _ as *const libc::sockaddr, length) })?;
+        check(unsafe { ffi::srt_listen(socket.id, 16) })?;
+
+        Ok(socket)
+    }
+
+    /// Blocks until a caller connected.
+    pub fn accept(&self) -> Result<(Socket, Option<SocketAddr>)> {
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut length = mem::size_of::<libc::sockaddr_storage>() as c_int;
+
+        let id = unsafe { ffi::srt_accept(self.id, &mut storage as *mut _ as *mut libc::sockaddr, &mut length) };
+        if id == ffi::SRT_INVALID_SOCK {
+            return Err(last_error());
+        }
+
+        Ok((Socket { id }, from_sockaddr(&storage)))
+    }
+
+    /// The stream ID the caller connected with
+    pub fn stream_id(&self) -> Result<String> {
+        // Stream IDs are limited to 512 bytes
+        let mut buf = [0u8; 512];
+        let mut length = buf.len() as c_int;
+        check(unsafe { ffi::srt_getsockflag(self.id, ffi::SRTO_STREAMID, buf.as_mut_ptr() as *mut c_void, &mut length) })?;
+
+        Ok(String::from_utf8_lossy(&buf[..length as usize]).into_owned())
+    }
+
+    pub fn set_receive_timeout(&self, timeout: Duration) -> Result<()> {
+        let timeout = timeout.as_millis() as c_int;
+        self.set_option(ffi::SRTO_RCVTIMEO, &timeout as *const c_int as *const c_void, mem::size_of::<c_int>())
+    }
+
+    /// Blocks until a message arrived, the receive timeout passed or the connection closed.
+    pub fn receive(&self, buf: &mut [u8]) -> Result<Received> {
+        let result = unsafe { ffi::srt_recvmsg(self.id, buf.as_mut_ptr() as *mut c_char, buf.len() as c_int) };
+
+        if result == ffi::SRT_ERROR {
+            let code = unsafe { ffi::srt_getlasterror(ptr::null_mut()) };
+            return match code {
+                ffi::SRT_EASYNCRCV => Ok(Received::TimedOut),
+                _ => Err(last_error()),
+            };
+        }
+
+        match result {
+            0 => Ok(Received::Closed),
+            size => Ok(Received::Data(size as usize)),
+        }
+    }
+
+    fn set_option(&self, option: ffi::SRT_SOCKOPT, value: *const c_void, length: usize) -> Result<()> {
+        check(unsafe { ffi::srt_setsockflag(self.id, option, value, length as c_int) }).map(|_| ())
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::srt_close(self.id);
+        }
+    }
+}
+
+// libsrt sockets can be used from any thread
+unsafe impl Send for Socket {}
+
+
+fn to_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, c_int) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+    let length = match addr {
+        SocketAddr::V4(addr) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+            sockaddr.sin_port = addr.port().to_be();
+            sockaddr.sin_addr = libc::in_addr { s_addr: u32::from(*addr.ip()).to_be() };
+            mem::size_of::<libc::sockaddr_in>()
+        },
+        SocketAddr::V6(addr) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sockaddr.sin6_port = addr.port().to_be();
+            sockaddr.sin6_addr = libc::in6_addr { s6_addr: addr.ip().octets() };
+            sockaddr.sin6_flowinfo = addr.flowinfo();
+            sockaddr.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        },
+    };
+
+    (storage, length as c_int)
+}
+
+fn from_sockaddr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match c_int::from(storage.ss_family) {
+        libc::AF_INET => {
+            let sockaddr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(sockaddr.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sockaddr.sin_port))))
+        },
+        libc::AF_INET6 => {
+            let sockaddr = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sockaddr.sin6_addr.s6_addr);
+            let port = u16::from_be(sockaddr.sin6_port);
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, port, sockaddr.sin6_flowinfo, sockaddr.sin6_scope_id)))
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,64 @@
+/// Splits a stream ID into application name and stream key.
+///
+/// Both `app/key` and the access control syntax `#!::r=app/key,m=publish` are understood,
+/// callers can only publish.
+pub fn parse(stream_id: &str) -> Option<(String, String)> {
+    let resource = match stream_id.trim_end_matches('\0') {
+        id if id.starts_with("#!::") => {
+            let mut resource = None;
+
+            for pair in id[4..].split(',') {
+                let mut parts = pair.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("r"), Some(value)) => resource = Some(value),
+                    (Some("m"), Some(mode)) if mode != "publish" => return None,
+                    _ => (),
+                }
+            }
+
+            resource?
+        },
+        id => id,
+    };
+
+    let mut parts = resource.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(app_name), Some(stream_key)) if !app_name.is_empty() => {
+            Some((app_name.to_string(), stream_key.to_string()))
+        },
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(app_name: &str, stream_key: &str) -> Option<(String, String)> {
+        Some((app_name.to_string(), stream_key.to_string()))
+    }
+
+    #[test]
+    fn parses_plain_stream_ids() {
+        assert_eq!(parse("live/key"), target("live", "key"));
+        assert_eq!(parse("live/key/with/slashes"), target("live", "key/with/slashes"));
+        assert_eq!(parse("live/"), target("live", ""));
+        assert_eq!(parse("live/key\0\0"), target("live", "key"));
+    }
+
+    #[test]
+    fn parses_access_control_stream_ids() {
+        assert_eq!(parse("#!::r=live/key"), target("live", "key"));
+        assert_eq!(parse("#!::u=admin,r=live/key,m=publish"), target("live", "key"));
+    }
+
+    #[test]
+    fn rejects_invalid_stream_ids() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("live"), None);
+        assert_eq!(parse("/key"), None);
+        assert_eq!(parse("#!::u=admin"), None);
+        assert_eq!(parse("#!::r=live/key,m=request"), None);
+    }
+}
@@ -0,0 +1,65 @@
+mod convert;
+pub mod publishing;
+mod udp;
+mod tcp;
+
+
+use std::net::{SocketAddr, ToSocketAddrs};
+use log::error;
+use crate::{
+    error::{Error, Result},
+    shared::Shared,
+};
+use self::{
+    tcp::TcpSource,
+    udp::UdpSource,
+};
+
+
+/// Where a transport stream is received, given as `udp://host:port` or `tcp://host:port`.
+/// UDP addresses can be multicast groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Listen {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+}
+
+impl Listen {
+    fn parse(url: &str) -> Result<Self> {
+        let (scheme, host) = match url.find("://") {
+            Some(offset) => (&url[..offset], &url[offset + 3..]),
+            None => return Err(Error::from(format!("Missing scheme in '{}'", url))),
+        };
+
+        let addr = host.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::from(format!("Failed to resolve '{}'", host)))?;
+
+        match scheme {
+            "udp" => Ok(Listen::Udp(addr)),
+            "tcp" => Ok(Listen::Tcp(addr)),
+            _ => Err(Error::from(format!("Unsupported scheme '{}', expected udp or tcp", scheme))),
+        }
+    }
+}
+
+
+/// Starts receiving all configured transport streams.
+pub fn spawn_all(shared: &Shared) {
+    let ts_sources = shared.config.read().ts_sources.clone();
+
+    for (app_name, url) in ts_sources {
+        if let Err(why) = spawn(app_name.clone(), &url, shared) {
+            error!("Failed to receive transport stream for app '{}' on {}: {:?}", app_name, url, why);
+        }
+    }
+}
+
+fn spawn(app_name: String, url: &str, shared: &Shared) -> Result<()> {
+    match Listen::parse(url)? {
+        Listen::Udp(addr) => tokio::spawn(UdpSource::bind(app_name, addr, shared.clone())?),
+        Listen::Tcp(addr) => tokio::spawn(TcpSource::bind(app_name, addr, shared.clone())?),
+    };
+
+    Ok(())
+}
@@ -0,0 +1,286 @@
+use bytes::{Bytes, BufMut};
+use rml_rtmp::time::RtmpTimestamp;
+use javelin_codec::{
+    aac::adts::AudioDataTransportStream,
+    avc::{annexb::AccessUnit, dcr::DecoderConfigurationRecord, nal},
+    mpegts::{Pes, StreamType},
+};
+use crate::{
+    error::{Error, Result},
+    media::Media,
+};
+
+
+/// Continuous timestamps of a transport stream, which starts anywhere and wraps after 33 bits.
+#[derive(Default)]
+struct Clock {
+    last: Option<u64>,
+    /// Ticks of 90 kHz since the first timestamp, can go below zero for streams that start late
+    elapsed: i64,
+}
+
+impl Clock {
+    const WRAP: i64 = 1 << 33;
+
+    fn ticks(&mut self, timestamp: u64) -> i64 {
+        if let Some(last) = self.last {
+            self.elapsed += Self::delta(last, timestamp);
+        }
+
+        self.last = Some(timestamp);
+        self.elapsed
+    }
+
+    /// Signed distance between two timestamps, taking the shorter way around the wrap
+    fn delta(from: u64, to: u64) -> i64 {
+        let delta = (to.wrapping_sub(from) & (Self::WRAP as u64 - 1)) as i64;
+        if delta >= Self::WRAP / 2 { delta - Self::WRAP } else { delta }
+    }
+}
+
+fn ticks_to_millis(ticks: i64) -> u32 {
+    (ticks.max(0) / 90) as u32
+}
+
+/// Offset of the presentation from the decoding time in milliseconds, as far as FLV can carry it.
+///
+/// Streams with a presentation time before the decoding time are broken, their frames are shown right away.
+fn composition_time(pts: Option<u64>, dts: u64) -> u32 {
+    const MAX: i64 = 0x00FF_FFFF;
+
+    pts.map(|pts| (Clock::delta(dts, pts) / 90).clamp(0, MAX) as u32)
+        .unwrap_or(0)
+}
+
+
+/// Turns the elementary streams of a transport stream into the media RTMP publishers send.
+///
+/// H.264 access units in Annex-B format become length prefixed NAL units,
+/// ADTS frames become raw AAC frames. Sequence headers are sent whenever
+/// the parameter sets or the audio configuration change.
+#[derive(Default)]
+pub struct Converter {
+    clock: Clock,
+    sps: Vec<nal::Unit>,
+    pps: Vec<nal::Unit>,
+    dcr: Option<DecoderConfigurationRecord>,
+    audio_config: Option<[u8; 2]>,
+}
+
+impl Converter {
+    const AAC_SAMPLES_PER_FRAME: i64 = 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn convert(&mut self, pes: Pes) -> Result<Vec<Media>> {
+        let dts = match pes.dts {
+            Some(dts) => dts,
+            None => return Err(Error::from("PES packet without timestamp")),
+        };
+
+        let dts_ticks = self.clock.ticks(dts);
+
+        match pes.stream_type {
+            StreamType::H264 => {
+                let composition_time = composition_time(pes.pts, dts);
+                self.video(&pes.data, ticks_to_millis(dts_ticks), composition_time)
+            },
+            StreamType::Aac => self.audio(pes.data, dts_ticks),
+        }
+    }
+
+    fn video(&mut self, data: &Bytes, timestamp: u32, composition_time: u32) -> Result<Vec<Media>> {
+        let mut media = Vec::with_capacity(2);
+        let access_unit = AccessUnit::try_from_bytes(data)?;
+
+        // Parameter sets can be repeated with every keyframe, only changes are announced
+        if !access_unit.sps.is_empty() {
+            self.sps = access_unit.sps.clone();
+        }
+        if !access_unit.pps.is_empty() {
+            self.pps = access_unit.pps.clone();
+        }
+
+        if let Ok(dcr) = DecoderConfigurationRecord::from_parameter_sets(self.sps.clone(), self.pps.clone()) {
+            if self.dcr.as_ref() != Some(&dcr) {
+                let mut payload = vec![0x17, 0x00, 0x00, 0x00, 0x00];
+                payload.extend_from_slice(&dcr.to_bytes());
+                media.push(Media::H264(RtmpTimestamp::new(timestamp), Bytes::from(payload)));
+                self.dcr = Some(dcr);
+            }
+        }
+
+        // Nothing can be decoded before the parameter sets are known
+        if access_unit.nal_units.is_empty() || self.dcr.is_none() {
+            return Ok(media);
+        }
+
+        let nal_units = access_unit.to_avcc_bytes();
+        let mut payload = Vec::with_capacity(5 + nal_units.len());
+        payload.put_u8(if access_unit.keyframe { 0x17 } else { 0x27 });
+        payload.put_u8(0x01);
+        payload.put_uint_be(u64::from(composition_time), 3);
+        payload.put_slice(&nal_units);
+
+        media.push(Media::H264(RtmpTimestamp::new(timestamp), Bytes::from(payload)));
+
+        Ok(media)
+    }
+
+    fn audio(&mut self, mut data: Bytes, dts_ticks: i64) -> Result<Vec<Media>> {
+        let mut media = Vec::new();
+        let mut ticks = dts_ticks;
+
+        while let Some(frame) = AudioDataTransportStream::try_from_bytes(&mut data)? {
+            let config = frame.audio_specific_configuration()?;
+            let timestamp = ticks_to_millis(ticks);
+
+            let config_bytes = config.to_bytes();
+            if self.audio_config != Some(config_bytes) {
+                let mut seq_header = vec![0xAF, 0x00];
+                seq_header.extend_from_slice(&config_bytes);
+                media.push(Media::AAC(RtmpTimestamp::new(timestamp), Bytes::from(seq_header)));
+                self.audio_config = Some(config_bytes);
+            }
+
+            let mut payload = Vec::with_capacity(2 + frame.payload().len());
+            payload.put_slice(&[0xAF, 0x01]);
+            payload.put_slice(frame.payload());
+            media.push(Media::AAC(RtmpTimestamp::new(timestamp), Bytes::from(payload)));
+
+            // Frames following the first one of a PES packet have no timestamp of their own
+            let sample_rate = config.sampling_frequency()
+                .ok_or_else(|| Error::from("Unsupported AAC sampling frequency"))?;
+            ticks += Self::AAC_SAMPLES_PER_FRAME * 90_000 / i64::from(sample_rate);
+        }
+
+        Ok(media)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRAP: u64 = 1 << 33;
+
+    fn pes(stream_type: StreamType, pts: u64, dts: u64, data: &[u8]) -> Pes {
+        Pes { stream_type, pts: Some(pts), dts: Some(dts), data: Bytes::from(data) }
+    }
+
+    fn video_access_unit() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x1F]);
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x68, 0xEE, 0x3C, 0x80]);
+        data.extend_from_slice(&[0x00, 0x00, 0x01, 0x65, 0x88, 0x84]);
+        data
+    }
+
+    fn adts_frame(payload: &[u8]) -> Vec<u8> {
+        // AAC LC, 44.1 kHz, stereo, no CRC
+        let length = 7 + payload.len();
+        let mut frame = vec![
+            0xFF, 0xF1, 0x50, 0x80,
+            (length >> 3) as u8, ((length & 0x07) << 5) as u8 | 0x1F, 0xFC,
+        ];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn video_frames(media: &[Media]) -> Vec<(u32, Bytes)> {
+        media.iter()
+            .filter_map(|media| match media {
+                Media::H264(timestamp, data) if data[1] == 0x01 => Some((timestamp.value, data.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn computes_signed_composition_times() {
+        assert_eq!(composition_time(None, 9000), 0);
+        assert_eq!(composition_time(Some(12_600), 9000), 40);
+        assert_eq!(composition_time(Some(9000), 12_600), 0);
+        assert_eq!(composition_time(Some(3600), WRAP - 3600), 80);
+        assert_eq!(composition_time(Some(WRAP - 3600), 3600), 0);
+        assert_eq!(composition_time(Some(WRAP / 2 - 1), 0), 0x00FF_FFFF);
+    }
+
+    #[test]
+    fn converts_access_units() {
+        let mut converter = Converter::new();
+
+        let media = converter.convert(pes(StreamType::H264, 12_600, 9000, &video_access_unit())).unwrap();
+        assert_eq!(media.len(), 2);
+        match &media[0] {
+            Media::H264(timestamp, data) => {
+                assert_eq!(timestamp.value, 0);
+                assert_eq!(&data[..5], &[0x17, 0x00, 0x00, 0x00, 0x00]);
+            },
+            other => panic!("Expected a sequence header, got {:?}", other),
+        }
+
+        let frames = video_frames(&media);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(&frames[0].1[..5], &[0x17, 0x01, 0x00, 0x00, 40]);
+        assert_eq!(&frames[0].1[5..], &[0x00, 0x00, 0x00, 0x03, 0x65, 0x88, 0x84]);
+
+        // Repeated parameter sets are not announced again, B-frames are shown right away
+        let media = converter.convert(pes(StreamType::H264, 9000, 12_600, &video_access_unit())).unwrap();
+        assert_eq!(media.len(), 1);
+        let frames = video_frames(&media);
+        assert_eq!(frames[0].0, 40);
+        assert_eq!(&frames[0].1[2..5], &[0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn waits_for_parameter_sets() {
+        let mut converter = Converter::new();
+        let media = converter.convert(pes(StreamType::H264, 0, 0, &[0x00, 0x00, 0x01, 0x41, 0x9A])).unwrap();
+        assert!(media.is_empty());
+    }
+
+    #[test]
+    fn follows_timestamps_across_the_wrap() {
+        let mut converter = Converter::new();
+        converter.convert(pes(StreamType::H264, WRAP - 9000, WRAP - 9000, &video_access_unit())).unwrap();
+        let media = converter.convert(pes(StreamType::H264, 9000, 9000, &video_access_unit())).unwrap();
+
+        assert_eq!(video_frames(&media)[0].0, 200);
+    }
+
+    #[test]
+    fn splits_adts_frames() {
+        let mut converter = Converter::new();
+        let mut data = adts_frame(&[0x01, 0x02]);
+        data.extend(adts_frame(&[0x03]));
+
+        let media = converter.convert(pes(StreamType::Aac, 0, 0, &data)).unwrap();
+        let tags: Vec<_> = media.iter()
+            .map(|media| match media {
+                Media::AAC(timestamp, data) => (timestamp.value, data.to_vec()),
+                other => panic!("Expected AAC, got {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(tags, vec![
+            (0, vec![0xAF, 0x00, 0x12, 0x10]),
+            (0, vec![0xAF, 0x01, 0x01, 0x02]),
+            (23, vec![0xAF, 0x01, 0x03]),
+        ]);
+
+        let media = converter.convert(pes(StreamType::Aac, 90_000, 90_000, &adts_frame(&[0x04]))).unwrap();
+        assert_eq!(media.len(), 1);
+    }
+
+    #[test]
+    fn rejects_packets_without_timestamps() {
+        let mut converter = Converter::new();
+        let pes = Pes { stream_type: StreamType::Aac, pts: None, dts: None, data: Bytes::new() };
+        assert!(converter.convert(pes).is_err());
+    }
+}
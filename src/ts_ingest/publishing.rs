@@ -0,0 +1,119 @@
+use log::{debug, info};
+use futures::sync::mpsc;
+use tokio::prelude::*;
+use javelin_codec::mpegts;
+use crate::{
+    channel::{self, Publisher},
+    error::{Error, Result},
+    rtmp::peer,
+    shared::Shared,
+};
+#[cfg(feature = "hls")]
+use crate::hls;
+#[cfg(feature = "srt")]
+use crate::publish;
+use super::convert::Converter;
+
+
+/// A transport stream that is published to a channel, like an RTMP publisher would.
+///
+/// The channel is released once this is dropped.
+pub struct Publishing {
+    id: u64,
+    app_name: String,
+    channel: channel::Handle,
+    demuxer: mpegts::Demuxer,
+    converter: Converter,
+    /// Receives the disconnect request when another publisher takes over
+    receiver: mpsc::UnboundedReceiver<peer::Message>,
+}
+
+impl Publishing {
+    /// Takes the place of the publisher, unless someone else is publishing.
+    pub fn start(app_name: &str, stream_key: String, shared: &Shared) -> Result<Self> {
+        let id = shared.next_client_id();
+        let (sender, receiver) = mpsc::unbounded();
+        let channel = shared.channel_or_create(app_name);
+
+        {
+            let mut channel = channel.lock();
+
+            if channel.has_publisher() {
+                return Err(Error::from("Application is already being published to"));
+            }
+
+            channel.set_publisher(Publisher::new(id, stream_key, sender));
+        }
+
+        #[cfg(feature = "hls")]
+        hls::server::ensure_writer(&channel, shared);
+
+        Ok(Self::new(id, app_name, channel, receiver))
+    }
+
+    /// Publishes with a stream key, subject to the same rules as RTMP publishers.
+    #[cfg(feature = "srt")]
+    pub fn claim(app_name: &str, stream_key: String, shared: &Shared) -> std::result::Result<Self, publish::Denied> {
+        let id = shared.next_client_id();
+        let (sender, receiver) = mpsc::unbounded();
+        let channel = publish::claim(app_name, stream_key, id, sender, shared)?;
+
+        Ok(Self::new(id, app_name, channel, receiver))
+    }
+
+    fn new(id: u64, app_name: &str, channel: channel::Handle, receiver: mpsc::UnboundedReceiver<peer::Message>) -> Self {
+        info!("Publishing transport stream to app '{}'", app_name);
+
+        Self {
+            id,
+            app_name: app_name.to_string(),
+            channel,
+            demuxer: mpegts::Demuxer::new(),
+            converter: Converter::new(),
+            receiver,
+        }
+    }
+
+    /// Demuxes the received data and publishes all complete frames.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<()> {
+        self.demuxer.push(bytes);
+
+        while let Some(pes) = self.demuxer.next_pes()? {
+            let media = match self.converter.convert(pes) {
+                Ok(media) => media,
+                Err(why) => {
+                    // Damaged frames are dropped, the stream can recover with the next one
+                    debug!("Dropped frame of app '{}': {:?}", self.app_name, why);
+                    continue;
+                },
+            };
+
+            for mut media in media {
+                let fanout = self.channel.lock().prepare_fanout(self.id, &mut media);
+                if let Some(fanout) = fanout {
+                    fanout.send_media(&media);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether another publisher took over the channel
+    pub fn is_replaced(&mut self) -> bool {
+        loop {
+            match self.receiver.poll() {
+                Ok(Async::Ready(Some(peer::Message::Raw(_)))) => continue,
+                Ok(Async::NotReady) => return false,
+                _ => return true,
+            }
+        }
+    }
+}
+
+impl Drop for Publishing {
+    fn drop(&mut self) {
+        info!("Stopped publishing transport stream to app '{}'", self.app_name);
+        self.channel.lock().release_publisher(self.id);
+    }
+}
@@ -0,0 +1,126 @@
+use std::net::SocketAddr;
+use log::{debug, error, info, warn};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    prelude::*,
+};
+use crate::{
+    error::Result,
+    shared::Shared,
+    shutdown,
+};
+use super::publishing::Publishing;
+
+
+/// Accepts connections sending a transport stream, publishing one at a time.
+pub struct TcpSource {
+    app_name: String,
+    listener: TcpListener,
+    shared: Shared,
+    shutdown: shutdown::Signal,
+}
+
+impl TcpSource {
+    pub fn bind(app_name: String, addr: SocketAddr, shared: Shared) -> Result<Self> {
+        let listener = TcpListener::bind(&addr)?;
+
+        info!("Receiving transport stream for app '{}' on tcp://{}", app_name, addr);
+
+        Ok(Self { app_name, listener, shutdown: shared.shutdown.signal(), shared })
+    }
+
+    fn accept(&mut self, socket: TcpStream) {
+        let peer_addr = match socket.peer_addr() {
+            Ok(addr) => addr,
+            Err(why) => return debug!("Failed to get address of transport stream sender: {:?}", why),
+        };
+
+        match Publishing::start(&self.app_name, format!("tcp://{}", peer_addr), &self.shared) {
+            Ok(publishing) => {
+                info!("Transport stream sender {} connected to app '{}'", peer_addr, self.app_name);
+                tokio::spawn(TcpConnection::new(self.app_name.clone(), socket, publishing, &self.shared));
+            },
+            Err(why) => warn!("Rejected transport stream sender {} of app '{}': {:?}", peer_addr, self.app_name, why),
+        }
+    }
+}
+
+impl Future for TcpSource {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+
+        loop {
+            match self.listener.poll_accept() {
+                Ok(Async::Ready((socket, _))) => self.accept(socket),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(why) => {
+                    error!("Accepting transport stream senders for app '{}' failed: {:?}", self.app_name, why);
+                    return Ok(Async::Ready(()));
+                },
+            }
+        }
+    }
+}
+
+
+/// A connected sender, the stream ends with the connection.
+struct TcpConnection {
+    app_name: String,
+    socket: TcpStream,
+    buf: Vec<u8>,
+    publishing: Publishing,
+    shutdown: shutdown::Signal,
+}
+
+impl TcpConnection {
+    const READ_SIZE: usize = 16 * 1024;
+
+    fn new(app_name: String, socket: TcpStream, publishing: Publishing, shared: &Shared) -> Self {
+        Self {
+            app_name,
+            socket,
+            buf: vec![0; Self::READ_SIZE],
+            publishing,
+            shutdown: shared.shutdown.signal(),
+        }
+    }
+
+    /// Publishes everything received, returns `Ready` once the connection closed.
+    fn receive(&mut self) -> Poll<(), crate::error::Error> {
+        loop {
+            match self.socket.poll_read(&mut self.buf)? {
+                Async::Ready(0) => return Ok(Async::Ready(())),
+                Async::Ready(size) => self.publishing.push(&self.buf[..size])?,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl Future for TcpConnection {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() || self.publishing.is_replaced() {
+            return Ok(Async::Ready(()));
+        }
+
+        match self.receive() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => {
+                info!("Transport stream sender of app '{}' disconnected", self.app_name);
+                Ok(Async::Ready(()))
+            },
+            Err(why) => {
+                warn!("Invalid transport stream for app '{}': {:?}", self.app_name, why);
+                Ok(Async::Ready(()))
+            },
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+use log::{error, info, warn};
+use tokio::{
+    net::UdpSocket,
+    prelude::*,
+    timer::Delay,
+};
+use crate::{
+    error::{Error, Result},
+    shared::Shared,
+    shutdown,
+};
+use super::publishing::Publishing;
+
+
+/// Receives a transport stream sent to a unicast or multicast address.
+///
+/// UDP has no notion of a stream ending, so the application is published
+/// on the first datagram and released once no data arrived for a while.
+pub struct UdpSource {
+    app_name: String,
+    addr: SocketAddr,
+    socket: UdpSocket,
+    buf: Vec<u8>,
+    shared: Shared,
+    publishing: Option<Publishing>,
+    /// Set while the channel is taken, so it is only logged once
+    denied: bool,
+    idle: Delay,
+    shutdown: shutdown::Signal,
+}
+
+impl UdpSource {
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+    const MAX_DATAGRAM_SIZE: usize = 65_536;
+
+    pub fn bind(app_name: String, addr: SocketAddr, shared: Shared) -> Result<Self> {
+        let socket = match addr {
+            SocketAddr::V4(v4) if v4.ip().is_multicast() => {
+                let socket = UdpSocket::bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port()))?;
+                socket.join_multicast_v4(v4.ip(), &Ipv4Addr::UNSPECIFIED)?;
+                socket
+            },
+            SocketAddr::V6(v6) if v6.ip().is_multicast() => {
+                return Err(Error::from(format!("IPv6 multicast is not supported, got {}", v6)));
+            },
+            _ => UdpSocket::bind(&addr)?,
+        };
+
+        info!("Receiving transport stream for app '{}' on udp://{}", app_name, addr);
+
+        Ok(Self {
+            app_name,
+            addr,
+            socket,
+            buf: vec![0; Self::MAX_DATAGRAM_SIZE],
+            shutdown: shared.shutdown.signal(),
+            shared,
+            publishing: None,
+            denied: false,
+            idle: Delay::new(Instant::now() + Self::IDLE_TIMEOUT),
+        })
+    }
+
+    fn handle_datagram(&mut self, size: usize) -> Result<()> {
+        self.idle.reset(Instant::now() + Self::IDLE_TIMEOUT);
+
+        if self.publishing.as_mut().is_some_and(|publishing| publishing.is_replaced()) {
+            self.publishing = None;
+        }
+
+        if self.publishing.is_none() {
+            let stream_key = format!("udp://{}", self.addr);
+            match Publishing::start(&self.app_name, stream_key, &self.shared) {
+                Ok(publishing) => {
+                    self.publishing = Some(publishing);
+                    self.denied = false;
+                },
+                Err(why) => {
+                    if !self.denied {
+                        warn!("Dropping transport stream for app '{}': {:?}", self.app_name, why);
+                        self.denied = true;
+                    }
+                    return Ok(());
+                },
+            }
+        }
+
+        if let Some(publishing) = self.publishing.as_mut() {
+            if let Err(why) = publishing.push(&self.buf[..size]) {
+                // Corrupt data ends the stream, the next datagram starts a new one
+                warn!("Invalid transport stream for app '{}': {:?}", self.app_name, why);
+                self.publishing = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn poll_idle(&mut self) -> Result<()> {
+        if self.idle.poll().map_err(|why| Error::from(format!("{:?}", why)))?.is_ready() {
+            if self.publishing.take().is_some() {
+                info!("No transport stream received for app '{}' in {} seconds", self.app_name, Self::IDLE_TIMEOUT.as_secs());
+            }
+            self.idle.reset(Instant::now() + Self::IDLE_TIMEOUT);
+            // Registers the new deadline
+            self.idle.poll().map_err(|why| Error::from(format!("{:?}", why)))?;
+        }
+
+        Ok(())
+    }
+
+    fn poll_socket(&mut self) -> Result<()> {
+        loop {
+            match self.socket.poll_recv(&mut self.buf)? {
+                Async::Ready(size) => self.handle_datagram(size)?,
+                Async::NotReady => return Ok(()),
+            }
+        }
+    }
+}
+
+impl Future for UdpSource {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+
+        if let Err(why) = self.poll_socket().and_then(|()| self.poll_idle()) {
+            error!("Receiving transport stream for app '{}' failed: {:?}", self.app_name, why);
+            return Ok(Async::Ready(()));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
@@ -0,0 +1,547 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use log::{debug, error, info};
+use futures::sync::mpsc;
+use tokio::{
+    prelude::*,
+    timer::Delay,
+};
+use javelin_codec::flv::{self, TagKind};
+use crate::{
+    config::RecordConfig,
+    error::{Error, Result},
+    media::{self, Media},
+    rtmp::{peer, fanout::{Fanout, Watcher}},
+    shutdown,
+};
+
+
+/// Requests of a client playing a recording
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    /// Continue at the keyframe before the given position in milliseconds
+    Seek(u32),
+    Pause(bool),
+}
+
+pub type Sender = mpsc::UnboundedSender<Command>;
+type Receiver = mpsc::UnboundedReceiver<Command>;
+
+
+/// Finds the recording a watcher asked for by its file name, the stream key.
+///
+/// Only exact matches are played, and only if the recorder could have named them for the application,
+/// other keys are watched live instead. Recordings named without the application are never played.
+pub fn find_recording(config: &RecordConfig, app_name: &str, stream_key: &str) -> Option<PathBuf> {
+    // Stream keys must not be able to leave the recording directory
+    if !is_valid_file_name(stream_key) {
+        return None;
+    }
+
+    let name = stream_key.trim_end_matches(".flv");
+
+    if !config.filename_template.contains("{app}") || !is_recording_of(&config.filename_template, app_name, name) {
+        return None;
+    }
+
+    let path = config.dir.join(format!("{}.flv", name));
+
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(|c: char| c == '/' || c == '\\' || c.is_control())
+}
+
+
+/// Piece of a recording's file name template
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Timestamp,
+    Key,
+}
+
+impl Part {
+    const TIMESTAMP_LENGTH: usize = 15;
+
+    /// Splits a file name template, with the application name filled in.
+    fn parse(template: &str, app_name: &str) -> Vec<Part> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            parts.push(Part::Literal(rest[..start].to_string()));
+            rest = &rest[start..];
+
+            let (part, length) = if rest.starts_with("{app}") {
+                (Part::Literal(app_name.to_string()), "{app}".len())
+            } else if rest.starts_with("{key}") {
+                (Part::Key, "{key}".len())
+            } else if rest.starts_with("{timestamp}") {
+                (Part::Timestamp, "{timestamp}".len())
+            } else {
+                (Part::Literal("{".to_string()), 1)
+            };
+
+            parts.push(part);
+            rest = &rest[length..];
+        }
+
+        parts.push(Part::Literal(rest.to_string()));
+        parts.retain(|part| *part != Part::Literal(String::new()));
+        parts
+    }
+
+    fn matches(parts: &[Part], name: &str) -> bool {
+        match parts.split_first() {
+            None => name.is_empty(),
+            Some((Part::Literal(literal), rest)) => {
+                name.starts_with(literal.as_str()) && Self::matches(rest, &name[literal.len()..])
+            },
+            Some((Part::Timestamp, rest)) => {
+                // Written as `%Y%m%d-%H%M%S`
+                let is_timestamp = name.len() >= Self::TIMESTAMP_LENGTH && name.bytes()
+                    .take(Self::TIMESTAMP_LENGTH)
+                    .enumerate()
+                    .all(|(i, byte)| if i == 8 { byte == b'-' } else { byte.is_ascii_digit() });
+
+                is_timestamp && Self::matches(rest, &name[Self::TIMESTAMP_LENGTH..])
+            },
+            Some((Part::Key, rest)) => {
+                name.char_indices()
+                    .map(|(i, _)| i)
+                    .chain(Some(name.len()))
+                    .any(|i| Self::matches(rest, &name[i..]))
+            },
+        }
+    }
+}
+
+/// Whether the recorder could have named a file like this for the application,
+/// names that were taken get a sequence number appended.
+fn is_recording_of(template: &str, app_name: &str, name: &str) -> bool {
+    let parts = Part::parse(template, app_name);
+
+    let unnumbered = name.rfind('-')
+        .filter(|&i| i + 1 < name.len() && name[i + 1..].bytes().all(|byte| byte.is_ascii_digit()))
+        .map(|i| &name[..i]);
+
+    Part::matches(&parts, name) || unnumbered.is_some_and(|name| Part::matches(&parts, name))
+}
+
+
+/// Where a recording can be entered, a video keyframe or any frame of recordings without video
+#[derive(Clone, Copy)]
+struct Keyframe {
+    timestamp: u32,
+    position: u64,
+}
+
+
+/// Decoder configuration and seek points of a recording, collected up front
+#[derive(Default)]
+struct Index {
+    metadata: Option<flv::Metadata>,
+    video_seq_header: Option<Media>,
+    audio_seq_header: Option<Media>,
+    keyframes: Vec<Keyframe>,
+    first_timestamp: Option<u32>,
+}
+
+impl Index {
+    fn build(path: &Path) -> Result<Self> {
+        let mut reader = flv::Reader::new(BufReader::new(File::open(path)?))?;
+        let mut index = Self::default();
+        let mut audio_frames = Vec::new();
+
+        loop {
+            let position = reader.position();
+
+            let tag = match reader.read_tag()? {
+                Some(tag) => tag,
+                None => break,
+            };
+
+            if tag.kind == TagKind::Script {
+                if index.metadata.is_none() {
+                    index.metadata = flv::Metadata::try_from_tag(&tag)?;
+                }
+                continue;
+            }
+
+            let media = match Media::from_flv_tag(tag) {
+                Some(media) => media,
+                None => continue,
+            };
+
+            let first_timestamp = *index.first_timestamp.get_or_insert(media.timestamp());
+
+            if media.is_sequence_header() {
+                match media {
+                    Media::H264(..) | Media::H265(..) => index.video_seq_header.get_or_insert(media),
+                    Media::AAC(..) => index.audio_seq_header.get_or_insert(media),
+                };
+                continue;
+            }
+
+            let keyframe = Keyframe { timestamp: media.timestamp().saturating_sub(first_timestamp), position };
+
+            match media {
+                Media::H264(..) | Media::H265(..) if media.is_keyframe() => index.keyframes.push(keyframe),
+                Media::AAC(..) => audio_frames.push(keyframe),
+                _ => (),
+            }
+        }
+
+        if index.keyframes.is_empty() {
+            index.keyframes = audio_frames;
+        }
+
+        if index.first_timestamp.is_none() {
+            return Err(Error::from(format!("No supported media in '{}'", path.display())));
+        }
+
+        Ok(index)
+    }
+
+    /// The keyframe at or before the given timestamp, the first one if there is none
+    fn keyframe_before(&self, timestamp: u32) -> Option<Keyframe> {
+        self.keyframes.iter()
+            .take_while(|keyframe| keyframe.timestamp <= timestamp)
+            .last()
+            .or_else(|| self.keyframes.first())
+            .cloned()
+    }
+}
+
+
+/// Plays a recorded FLV file to a single RTMP client.
+///
+/// Media is paced in real time relative to where playback was started, seeked to
+/// or resumed. At the end of the file the client is told that playback is complete
+/// and can still seek back.
+pub struct Vod {
+    path: PathBuf,
+    fanout: Fanout,
+    /// Opened on the first poll, together with the index
+    reader: Option<flv::Reader<BufReader<File>>>,
+    /// Built on the first poll, as indexing reads the whole file
+    index: Option<Index>,
+    /// Media read ahead, so the file is not read on the worker thread for every tag
+    buffer: VecDeque<Media>,
+    commands: Receiver,
+    /// Media that is not due yet
+    pending: Option<Media>,
+    /// Wall clock time at which `start_timestamp` was played
+    started: Option<Instant>,
+    start_timestamp: u32,
+    last_timestamp: u32,
+    paused: bool,
+    finished: bool,
+    delay: Delay,
+    shutdown: shutdown::Signal,
+}
+
+impl Vod {
+    /// Media read from the file at once
+    const READ_AHEAD: usize = 64;
+
+    /// Creates the playback, it stops once the returned sender is dropped.
+    pub fn create(path: PathBuf, peer_id: u64, stream_id: u32, sender: peer::Sender, media: peer::MediaSender, shutdown: shutdown::Signal) -> Result<(Self, Sender)> {
+        let (command_sender, commands) = mpsc::unbounded();
+
+        let fanout = Fanout::single(Watcher::new(peer_id, stream_id, sender, media));
+
+        let vod = Self {
+            path,
+            fanout,
+            reader: None,
+            index: None,
+            buffer: VecDeque::new(),
+            commands,
+            pending: None,
+            started: None,
+            start_timestamp: 0,
+            last_timestamp: 0,
+            paused: false,
+            finished: false,
+            delay: Delay::new(Instant::now()),
+            shutdown,
+        };
+
+        Ok((vod, command_sender))
+    }
+
+    /// Opens and indexes the file without stalling the worker thread, file access may be slow.
+    fn poll_index(&mut self) -> Poll<(), Error> {
+        if self.index.is_some() {
+            return Ok(Async::Ready(()));
+        }
+
+        let path = &self.path;
+        let open = || -> Result<_> {
+            let reader = flv::Reader::new(BufReader::new(File::open(path)?))?;
+            Ok((reader, Index::build(path)?))
+        };
+
+        let (reader, index) = match tokio_threadpool::blocking(open) {
+            Ok(Async::Ready(result)) => result?,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            // Outside of the thread pool, e.g. on a current thread runtime
+            Err(_) => open()?,
+        };
+
+        if let Some(metadata) = &index.metadata {
+            self.fanout.send_metadata(&media::metadata_from_flv(metadata));
+        }
+
+        self.reader = Some(reader);
+        self.index = Some(index);
+
+        Ok(Async::Ready(()))
+    }
+
+    fn index(&self) -> Result<&Index> {
+        self.index.as_ref().ok_or_else(|| Error::from("Recording is not indexed yet"))
+    }
+
+    fn reader(&mut self) -> Result<&mut flv::Reader<BufReader<File>>> {
+        self.reader.as_mut().ok_or_else(|| Error::from("Recording is not opened yet"))
+    }
+
+    /// Reads the next media of the file on the blocking pool, `None` at the end of the file
+    fn poll_next_media(&mut self) -> Poll<Option<Media>, Error> {
+        if self.buffer.is_empty() {
+            match tokio_threadpool::blocking(|| self.read_ahead()) {
+                Ok(Async::Ready(result)) => result?,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(_) => self.read_ahead()?,
+            }
+        }
+
+        Ok(Async::Ready(self.buffer.pop_front()))
+    }
+
+    /// Fills the buffer with the upcoming media of the file, with timestamps starting at zero
+    fn read_ahead(&mut self) -> Result<()> {
+        let first_timestamp = self.index()?.first_timestamp.unwrap_or(0);
+
+        while self.buffer.len() < Self::READ_AHEAD {
+            let tag = match self.reader()?.read_tag()? {
+                Some(tag) => tag,
+                None => break,
+            };
+
+            if let Some(mut media) = Media::from_flv_tag(tag) {
+                let timestamp = media.timestamp().saturating_sub(first_timestamp);
+                media.set_timestamp(timestamp);
+                self.buffer.push_back(media);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn seek(&mut self, timestamp: u32) -> Result<()> {
+        let index = self.index()?;
+        // Players might reset their decoders on seek
+        let seq_headers = vec![index.video_seq_header.clone(), index.audio_seq_header.clone()];
+        let keyframe = match index.keyframe_before(timestamp) {
+            Some(keyframe) => keyframe,
+            None => return Err(Error::from("Recording has no keyframes to seek to")),
+        };
+
+        debug!("Seeking to {} ms in '{}'", keyframe.timestamp, self.path.display());
+
+        self.reader()?.seek(keyframe.position)?;
+        self.buffer.clear();
+        self.pending = None;
+        self.started = None;
+        self.start_timestamp = keyframe.timestamp;
+        self.last_timestamp = keyframe.timestamp;
+        self.finished = false;
+
+        self.fanout.send_status("NetStream.Seek.Notify", "Seeking")?;
+        self.fanout.send_status("NetStream.Play.Start", "Playing recording")?;
+
+        for mut seq_header in seq_headers.into_iter().flatten() {
+            seq_header.set_timestamp(keyframe.timestamp);
+            self.fanout.send_media(&seq_header);
+        }
+
+        Ok(())
+    }
+
+    fn pause(&mut self, pause: bool) -> Result<()> {
+        if pause == self.paused {
+            return Ok(());
+        }
+
+        self.paused = pause;
+
+        if pause {
+            self.fanout.send_status("NetStream.Pause.Notify", "Paused")
+        } else {
+            // Pacing continues from where playback was paused
+            self.started = None;
+            self.start_timestamp = self.last_timestamp;
+            self.fanout.send_status("NetStream.Unpause.Notify", "Unpaused")
+        }
+    }
+
+    fn handle(&mut self, command: Command) -> Result<()> {
+        match command {
+            Command::Seek(timestamp) => self.seek(timestamp),
+            Command::Pause(pause) => self.pause(pause),
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        info!("Finished playing '{}'", self.path.display());
+        self.finished = true;
+        self.fanout.send_status("NetStream.Play.Complete", "Playback of recording is complete")?;
+        self.fanout.send_stream_eof();
+        Ok(())
+    }
+
+    /// Sends all media that is due, returns once waiting for the next one.
+    fn play(&mut self) -> Result<()> {
+        loop {
+            if self.delay.poll().map_err(|why| Error::from(format!("{:?}", why)))?.is_not_ready() {
+                return Ok(());
+            }
+
+            let media = match self.pending.take() {
+                Some(media) => media,
+                None => match self.poll_next_media()? {
+                    Async::Ready(Some(media)) => media,
+                    Async::Ready(None) => return self.finish(),
+                    // Woken up once the blocking pool has room
+                    Async::NotReady => return Ok(()),
+                },
+            };
+
+            let started = *self.started.get_or_insert_with(Instant::now);
+            let offset = media.timestamp().saturating_sub(self.start_timestamp);
+            let due = started + Duration::from_millis(u64::from(offset));
+
+            if due > Instant::now() {
+                self.pending = Some(media);
+                self.delay.reset(due);
+                continue;
+            }
+
+            self.last_timestamp = media.timestamp();
+            self.fanout.send_media(&media);
+        }
+    }
+}
+
+impl Future for Vod {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll()?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+
+        match self.poll_index() {
+            Ok(Async::Ready(())) => (),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(why) => {
+                error!("Failed to index '{}': {:?}", self.path.display(), why);
+                let _ = self.fanout.send_status("NetStream.Play.Failed", "Recording can not be played");
+                self.fanout.send_stream_eof();
+                return Ok(Async::Ready(()));
+            },
+        }
+
+        loop {
+            match self.commands.poll()? {
+                Async::Ready(Some(command)) => {
+                    if let Err(why) = self.handle(command) {
+                        error!("Failed to handle {:?} for '{}': {:?}", command, self.path.display(), why);
+                    }
+                },
+                // The client is gone once the sender is dropped
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::NotReady => break,
+            }
+        }
+
+        if self.paused || self.finished {
+            return Ok(Async::NotReady);
+        }
+
+        if let Err(why) = self.play() {
+            error!("Failed to play '{}': {:?}", self.path.display(), why);
+            self.finished = true;
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use crate::config::Config;
+    use super::*;
+
+    #[test]
+    fn finds_only_the_requested_recording() {
+        let dir = env::temp_dir().join(format!("javelin-vod-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("live-20240101-120000.flv"), b"").unwrap();
+        fs::write(dir.join("live-20240101-120000-1.flv"), b"").unwrap();
+        fs::write(dir.join("live-hd-20240101-120000.flv"), b"").unwrap();
+
+        let config = Config::from_args(&["javelin", "--record-dir", dir.to_str().unwrap()]).record;
+
+        let expected = Some(dir.join("live-20240101-120000.flv"));
+        assert_eq!(find_recording(&config, "live", "live-20240101-120000"), expected);
+        assert_eq!(find_recording(&config, "live", "live-20240101-120000.flv"), expected);
+        assert_eq!(find_recording(&config, "live", "live-20240101-120000-1"), Some(dir.join("live-20240101-120000-1.flv")));
+        assert_eq!(find_recording(&config, "live", "live"), None);
+        assert_eq!(find_recording(&config, "live", "../live-20240101-120000"), None);
+
+        // Recordings of other applications
+        assert_eq!(find_recording(&config, "other", "live-20240101-120000"), None);
+        assert_eq!(find_recording(&config, "live", "live-hd-20240101-120000"), None);
+        assert_eq!(find_recording(&config, "live-hd", "live-hd-20240101-120000"), Some(dir.join("live-hd-20240101-120000.flv")));
+
+        let config = Config::from_args(&["javelin", "--record-dir", dir.to_str().unwrap(), "--record-filename", "{timestamp}"]).record;
+        assert_eq!(find_recording(&config, "live", "live-20240101-120000"), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn matches_file_name_templates() {
+        assert!(is_recording_of("{app}-{timestamp}", "live", "live-20240101-120000"));
+        assert!(is_recording_of("{app}-{timestamp}", "live", "live-20240101-120000-12"));
+        assert!(!is_recording_of("{app}-{timestamp}", "live", "live-2024010-1120000"));
+        assert!(!is_recording_of("{app}-{timestamp}", "live", "live-20240101-120000-"));
+
+        assert!(is_recording_of("{key}_{app}", "live", "secret_key_live"));
+        assert!(!is_recording_of("{key}_{app}", "live", "secret_key_live_hd"));
+
+        assert!(is_recording_of("{app}", "live", "live"));
+        assert!(is_recording_of("{app}", "live", "live-1"));
+        assert!(is_recording_of("rec_{x}_{app}", "live", "rec_{x}_live"));
+    }
+}
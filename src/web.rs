@@ -1,4 +1,9 @@
 mod server;
 mod api;
+mod live;
+mod watcher;
+mod ingest;
+mod auth;
+mod publisher;
 
 pub use self::server::Server;
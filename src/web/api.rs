@@ -8,28 +8,52 @@ use warp::{
     Rejection,
     filters::BoxedFilter,
 };
-use serde_json::json;
-use crate::Shared;
+use serde_json::{json, Value as JsonValue};
+use bytes::{Bytes, IntoBuf};
+use rml_rtmp::time::RtmpTimestamp;
+use javelin_codec::{avc, hevc};
+use crate::{
+    channel::{self, is_valid_app_name, Channel},
+    media::Media,
+    Shared,
+};
+use super::auth::permitted;
 
 
 #[derive(Clone, Debug)]
 pub enum Error {
     NoSuchResource,
     StreamNotFound,
+    InvalidPushTarget,
+    PushTargetNotFound,
+    AlreadyRecording,
+    UnsupportedRecording,
+    NotRecording,
+    StreamKeyNotPermitted,
+    AlreadyPublished,
+    InvalidMedia,
+    WatcherLimitReached,
 }
 
-impl StdError for Error {
-    fn description(&self) -> &str {
-        match *self {
-            Error::NoSuchResource => "No such resource",
-            Error::StreamNotFound => "Stream could not be found"
-        }
-    }
-}
+impl StdError for Error {}
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description())
+        let message = match *self {
+            Error::NoSuchResource => "No such resource",
+            Error::StreamNotFound => "Stream could not be found",
+            Error::InvalidPushTarget => "Push target is invalid or already exists",
+            Error::PushTargetNotFound => "Push target could not be found",
+            Error::AlreadyRecording => "Stream is already being recorded",
+            Error::UnsupportedRecording => "Recording format does not support the video codec of the stream",
+            Error::NotRecording => "Stream is not being recorded",
+            Error::StreamKeyNotPermitted => "Stream key is not permitted",
+            Error::AlreadyPublished => "Application is already being published to",
+            Error::InvalidMedia => "Request body is not a valid FLV stream",
+            Error::WatcherLimitReached => "Application reached its limit of watchers",
+        };
+
+        write!(f, "{}", message)
     }
 }
 
@@ -37,6 +61,11 @@ impl Display for Error {
 pub(crate) fn api(shared: Shared) -> BoxedFilter<(impl Reply,)> {
     active_streams(shared.clone())
         .or(stream_stats(shared.clone()))
+        .or(add_push_target(shared.clone()))
+        .or(remove_push_target(shared.clone()))
+        .or(start_recording(shared.clone()))
+        .or(stop_recording(shared.clone()))
+        .or(locate(shared.clone()))
         .or(server_info())
         .or_else(|err: Rejection| {
             if err.is_not_found() {
@@ -51,10 +80,10 @@ pub(crate) fn api(shared: Shared) -> BoxedFilter<(impl Reply,)> {
 fn active_streams(shared: Shared) -> BoxedFilter<(impl Reply,)> {
     warp::path("active-streams")
         .map(move || {
-            let streams = shared.streams.read();
-            let active = streams.iter()
+            let channels = shared.channels.read();
+            let active = channels.iter()
                 .filter_map(|(k, v)| {
-                    if v.has_publisher() {
+                    if v.lock().has_publisher() {
                         Some(k.clone())
                     } else {
                         None
@@ -74,9 +103,9 @@ fn active_streams(shared: Shared) -> BoxedFilter<(impl Reply,)> {
 fn stream_stats(shared: Shared) -> BoxedFilter<(impl Reply,)> {
     warp::path("stream-stats").and(warp::path::param())
         .and_then(move |app_name: String| {
-            let streams = shared.streams.read();
-            match streams.get(&app_name) {
-                Some(stream) => {
+            match shared.channel(&app_name) {
+                Some(channel) => {
+                    let stream = channel.lock();
                     let metadata = stream.metadata.clone()
                         .map(|m| json!({
                             "video": {
@@ -94,10 +123,17 @@ fn stream_stats(shared: Shared) -> BoxedFilter<(impl Reply,)> {
                             }
                         }));
 
+                    let video = stream.video_seq_header.as_ref().and_then(video_stats);
+
                     let json = json!({
                         "app_name": app_name,
                         "start_time": stream.publish_start,
-                        "metadata": metadata
+                        "watchers": stream.watcher_count(),
+                        "metadata": metadata,
+                        "video": video,
+                        "push_targets": push_targets_json(&stream),
+                        "pull": pull_json(&stream),
+                        "recording": stream.is_recording()
                     });
                     Ok(warp::reply::json(&json))
                 },
@@ -109,6 +145,207 @@ fn stream_stats(shared: Shared) -> BoxedFilter<(impl Reply,)> {
         .boxed()
 }
 
+/// Parses the parameters of the video from the sequence header instead of trusting the metadata
+fn video_stats(seq_header: &Bytes) -> Option<JsonValue> {
+    // Skips frame type, packet type and composition time, or the FourCC of Enhanced RTMP
+    let mut buf = seq_header.slice_from(5.min(seq_header.len())).into_buf();
+
+    let stats = match Media::video(RtmpTimestamp::new(0), seq_header.clone()).ok()? {
+        Media::H265(..) => {
+            let dcr = hevc::dcr::DecoderConfigurationRecord::try_from_buf(&mut buf).ok()?;
+            let sps = hevc::sps::SequenceParameterSet::try_from_unit(dcr.sps.first()?).ok()?;
+
+            json!({
+                "codec": sps.codec_string(),
+                "profile": sps.profile_name(),
+                "level": sps.level_name(),
+                "chroma_format": sps.chroma_format_name(),
+                "width": sps.width(),
+                "height": sps.height(),
+                "framerate": null,
+                "max_reorder_frames": null
+            })
+        },
+        _ => {
+            let dcr = avc::dcr::DecoderConfigurationRecord::try_from_buf(&mut buf).ok()?;
+            let sps = avc::sps::SequenceParameterSet::try_from_unit(dcr.sps.first()?).ok()?;
+
+            json!({
+                "codec": sps.codec_string(),
+                "profile": sps.profile_name(),
+                "level": sps.level_name(),
+                "chroma_format": sps.chroma_format_name(),
+                "width": sps.width(),
+                "height": sps.height(),
+                "framerate": sps.frame_rate(),
+                "max_reorder_frames": sps.max_reorder_frames()
+            })
+        },
+    };
+
+    Some(stats)
+}
+
+/// Tells edges whether this server has a stream and where to pull it from
+fn locate(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::path("locate").and(warp::path::param())
+        .and_then(move |app_name: String| -> Result<_, Rejection> {
+            location(&shared, &app_name)
+                .map(|json| warp::reply::json(&json))
+                .ok_or_else(|| warp::reject::custom(Error::StreamNotFound))
+        })
+        .boxed()
+}
+
+/// Answers the lookup of an edge, only live applications can be pulled from here.
+fn location(shared: &Shared, app_name: &str) -> Option<JsonValue> {
+    // Same as for RTMP playback, a stream waiting for its publisher to resume
+    // or for the backup to take over is still live
+    let live = shared.channel(app_name)
+        .is_some_and(|channel| channel.lock().is_live());
+
+    if !live {
+        return None;
+    }
+
+    Some(json!({
+        "app_name": app_name,
+        "live": true,
+        "rtmp_port": shared.config.read().addr.port()
+    }))
+}
+
+/// Channel of an application the API changes, which has to exist already
+fn existing_channel(shared: &Shared, app_name: &str) -> Result<channel::Handle, Rejection> {
+    if !is_valid_app_name(app_name) {
+        return Err(warp::reject::custom(Error::NoSuchResource));
+    }
+
+    shared.channel(app_name).ok_or_else(|| warp::reject::custom(Error::StreamNotFound))
+}
+
+/// Expects a JSON body like `{ "url": "rtmp://host/app/key" }` and a permitted stream key
+fn add_push_target(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::post2()
+        .and(warp::path("push"))
+        .and(warp::path::param())
+        .and(permitted(shared.clone()))
+        .and(warp::body::json())
+        .and_then(move |app_name: String, body: JsonValue| -> Result<_, Rejection> {
+            let channel = existing_channel(&shared, &app_name)?;
+            let url = body["url"].as_str().ok_or_else(|| warp::reject::custom(Error::InvalidPushTarget))?;
+            let mut channel = channel.lock();
+
+            channel.add_push_target(url).map_err(|_| warp::reject::custom(Error::InvalidPushTarget))?;
+
+            Ok(warp::reply::json(&json!({
+                "app_name": app_name,
+                "push_targets": push_targets_json(&channel)
+            })))
+        })
+        .boxed()
+}
+
+/// Expects the same JSON body the push target was added with
+fn remove_push_target(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::delete2()
+        .and(warp::path("push"))
+        .and(warp::path::param())
+        .and(permitted(shared.clone()))
+        .and(warp::body::json())
+        .and_then(move |app_name: String, body: JsonValue| -> Result<_, Rejection> {
+            let channel = existing_channel(&shared, &app_name)?;
+            let url = body["url"].as_str().ok_or_else(|| warp::reject::custom(Error::PushTargetNotFound))?;
+            let mut channel = channel.lock();
+
+            if !channel.remove_push_target(url) {
+                return Err(warp::reject::custom(Error::PushTargetNotFound));
+            }
+
+            Ok(warp::reply::json(&json!({
+                "app_name": app_name,
+                "push_targets": push_targets_json(&channel)
+            })))
+        })
+        .boxed()
+}
+
+/// Records the application until recording is stopped, including streams published later on
+fn start_recording(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::post2()
+        .and(warp::path("record"))
+        .and(warp::path::param())
+        .and(permitted(shared.clone()))
+        .and_then(move |app_name: String| -> Result<_, Rejection> {
+            let channel = existing_channel(&shared, &app_name)?;
+            let mut channel = channel.lock();
+
+            channel.start_recording().map_err(|why| match why {
+                crate::error::Error::UnsupportedRecording => warp::reject::custom(Error::UnsupportedRecording),
+                _ => warp::reject::custom(Error::AlreadyRecording),
+            })?;
+
+            Ok(warp::reply::json(&json!({
+                "app_name": app_name,
+                "recording": true
+            })))
+        })
+        .boxed()
+}
+
+fn stop_recording(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::delete2()
+        .and(warp::path("record"))
+        .and(warp::path::param())
+        .and(permitted(shared.clone()))
+        .and_then(move |app_name: String| -> Result<_, Rejection> {
+            let channel = existing_channel(&shared, &app_name)?;
+
+            if !channel.lock().stop_recording() {
+                return Err(warp::reject::custom(Error::NotRecording));
+            }
+
+            Ok(warp::reply::json(&json!({
+                "app_name": app_name,
+                "recording": false
+            })))
+        })
+        .boxed()
+}
+
+/// Stream keys are left out, targets are listed by their masked URL
+fn push_targets_json(channel: &Channel) -> JsonValue {
+    let targets = channel.push_targets().iter()
+        .map(|target| {
+            let status = target.status.read();
+            json!({
+                "url": target.url.to_string(),
+                "status": status.state.as_str(),
+                "retries": status.retries,
+                "last_error": status.last_error
+            })
+        })
+        .collect::<Vec<_>>();
+
+    JsonValue::Array(targets)
+}
+
+fn pull_json(channel: &Channel) -> JsonValue {
+    match channel.pull() {
+        Some(pull) => {
+            let status = pull.status.read();
+            json!({
+                "url": pull.url.to_string(),
+                "on_demand": pull.on_demand,
+                "status": status.state.as_str(),
+                "retries": status.retries,
+                "last_error": status.last_error
+            })
+        },
+        None => JsonValue::Null,
+    }
+}
+
 fn server_info() -> BoxedFilter<(impl Reply,)> {
     warp::path("server-info")
         .map(|| {
@@ -122,3 +359,85 @@ fn server_info() -> BoxedFilter<(impl Reply,)> {
         })
         .boxed()
 }
+
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, sync::mpsc};
+    use tokio::runtime::current_thread::Runtime;
+    use crate::{
+        channel::{Channel, Publisher},
+        config::Config,
+        shutdown::Shutdown,
+    };
+    use super::*;
+
+    fn shared(args: &[&str]) -> Shared {
+        let (shutdown, _) = Shutdown::new();
+        Shared::with_config(Config::from_args(args), shutdown)
+    }
+
+    fn publisher(id: u64) -> Publisher {
+        let (sender, _) = mpsc::unbounded();
+        Publisher::new(id, "key".to_string(), sender)
+    }
+
+    fn is_located(shared: &Shared, app_name: &str) -> bool {
+        location(shared, app_name).is_some()
+    }
+
+    /// Changes the channel on the runtime, so it is able to schedule its timers
+    fn update<F>(runtime: &mut Runtime, shared: &Shared, f: F) where F: FnOnce(&mut Channel) {
+        let channel = shared.channel_or_create("live");
+        runtime
+            .block_on(future::lazy(|| -> Result<(), ()> { f(&mut channel.lock()); Ok(()) }))
+            .unwrap();
+    }
+
+    fn rejection(result: Result<channel::Handle, Rejection>) -> Option<Error> {
+        result.err().and_then(|rejection| rejection.find_cause::<Error>().cloned())
+    }
+
+    #[test]
+    fn changes_only_existing_applications() {
+        let shared = shared(&["javelin"]);
+        shared.channel_or_create("live");
+
+        assert!(existing_channel(&shared, "live").is_ok());
+        assert!(matches!(rejection(existing_channel(&shared, "other")), Some(Error::StreamNotFound)));
+        assert!(matches!(rejection(existing_channel(&shared, "a.b")), Some(Error::NoSuchResource)));
+        assert!(matches!(rejection(existing_channel(&shared, "")), Some(Error::NoSuchResource)));
+
+        assert!(shared.channel("other").is_none());
+    }
+
+    #[test]
+    fn locates_streams_in_grace_period() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin", "--publisher-grace-period", "10"]);
+
+        assert!(!is_located(&shared, "live"));
+
+        update(&mut runtime, &shared, |channel| channel.set_publisher(publisher(1)));
+        assert!(is_located(&shared, "live"));
+
+        update(&mut runtime, &shared, |channel| channel.release_publisher(1));
+        assert!(is_located(&shared, "live"));
+    }
+
+    #[test]
+    fn locates_streams_in_failover() {
+        let mut runtime = Runtime::new().unwrap();
+        let shared = shared(&["javelin"]);
+
+        update(&mut runtime, &shared, |channel| {
+            channel.set_publisher(publisher(1));
+            channel.set_backup(publisher(2));
+            channel.release_publisher(1);
+        });
+        assert!(is_located(&shared, "live"));
+
+        update(&mut runtime, &shared, |channel| channel.release_publisher(2));
+        assert!(!is_located(&shared, "live"));
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use warp::{
+    Filter,
+    Rejection,
+    filters::BoxedFilter,
+    http::HeaderMap,
+};
+use crate::Shared;
+use super::api::Error as ApiError;
+
+
+/// Stream key of a request, as bearer token or `key` query parameter.
+pub(super) fn stream_key() -> BoxedFilter<(String,)> {
+    bearer_token()
+        .or(key_param())
+        .unify()
+        .boxed()
+}
+
+/// Only lets requests pass that carry a permitted stream key, like publishers have to.
+pub(super) fn permitted(shared: Shared) -> BoxedFilter<()> {
+    stream_key()
+        .and_then(move |stream_key: String| -> Result<_, Rejection> {
+            if !is_permitted(&shared, &stream_key) {
+                return Err(warp::reject::custom(ApiError::StreamKeyNotPermitted));
+            }
+            Ok(())
+        })
+        .untuple_one()
+        .boxed()
+}
+
+fn is_permitted(shared: &Shared, stream_key: &str) -> bool {
+    !stream_key.is_empty() && shared.config.read().permitted_stream_keys.contains(stream_key)
+}
+
+fn bearer_token() -> BoxedFilter<(String,)> {
+    warp::header::headers_cloned()
+        .and_then(|headers: HeaderMap| -> Result<_, Rejection> {
+            headers.get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| value.starts_with("Bearer "))
+                .map(|value| value["Bearer ".len()..].trim().to_string())
+                .ok_or_else(warp::reject::not_found)
+        })
+        .boxed()
+}
+
+fn key_param() -> BoxedFilter<(String,)> {
+    warp::query::<HashMap<String, String>>()
+        .and_then(|mut query: HashMap<String, String>| -> Result<_, Rejection> {
+            query.remove("key").ok_or_else(|| warp::reject::custom(ApiError::StreamKeyNotPermitted))
+        })
+        .boxed()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::Config, shutdown::Shutdown};
+    use super::*;
+
+    #[test]
+    fn permits_configured_stream_keys() {
+        let (shutdown, _) = Shutdown::new();
+        let shared = Shared::with_config(Config::from_args(&["javelin", "--permit-stream-key", "secret"]), shutdown);
+
+        assert!(is_permitted(&shared, "secret"));
+        assert!(!is_permitted(&shared, "guess"));
+        assert!(!is_permitted(&shared, ""));
+    }
+}
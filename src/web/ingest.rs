@@ -0,0 +1,54 @@
+use log::{error, warn};
+use tokio::prelude::*;
+use warp::{
+    Filter,
+    Reply,
+    filters::BoxedFilter,
+    http::StatusCode,
+};
+use crate::{
+    publish::Denied,
+    Shared,
+};
+use super::{
+    api::Error as ApiError,
+    auth::stream_key,
+    publisher::HttpPublisher,
+};
+
+
+/// Publishing of applications by posting an FLV stream to `/ingest/<app>`.
+///
+/// The stream key is either passed as `key` query parameter
+/// or as bearer token in the `Authorization` header.
+pub(crate) fn ingest(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::post2()
+        .and(warp::path("ingest"))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(stream_key())
+        .and(warp::body::stream())
+        .and_then(move |app_name: String, stream_key: String, body| {
+            let publisher = match HttpPublisher::create(app_name.clone(), stream_key, body, &shared) {
+                Ok(publisher) => publisher,
+                Err(denied) => {
+                    warn!("Rejected HTTP publishing to app '{}': {:?}", app_name, denied);
+                    let rejection = warp::reject::custom(match denied {
+                        Denied::InvalidAppName => ApiError::NoSuchResource,
+                        Denied::StreamKeyNotPermitted => ApiError::StreamKeyNotPermitted,
+                        Denied::AlreadyPublished => ApiError::AlreadyPublished,
+                    });
+                    return future::Either::A(future::err(rejection));
+                },
+            };
+
+            future::Either::B(publisher.then(move |result| match result {
+                Ok(()) => Ok(StatusCode::NO_CONTENT),
+                Err(why) => {
+                    error!("Failed to publish request body to app '{}': {:?}", app_name, why);
+                    Err(warp::reject::custom(ApiError::InvalidMedia))
+                },
+            }))
+        })
+        .boxed()
+}
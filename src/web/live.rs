@@ -0,0 +1,195 @@
+use std::io;
+use log::{debug, warn};
+use tokio::prelude::*;
+use warp::{
+    Filter,
+    Reply,
+    Rejection,
+    filters::BoxedFilter,
+    http::Response,
+    ws::{Message, WebSocket, Ws2},
+};
+use hyper::Body;
+use crate::{
+    error::Error,
+    Shared,
+};
+use super::{
+    api::Error as ApiError,
+    watcher::{End, FlvWatcher},
+};
+
+
+/// Live playback of applications as FLV, e.g. for flv.js
+pub(crate) fn live(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::path("live")
+        .and(ws_flv(shared.clone()).or(http_flv(shared)))
+        .boxed()
+}
+
+/// Serves `/live/<app>.flv` as a never-ending chunked response
+fn http_flv(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::get2()
+        .and(flv_app_name())
+        .and_then(move |app_name: String| -> Result<_, Rejection> {
+            let watcher = create_watcher(&app_name, &shared)?;
+            let body = watcher.map_err(|_| io::Error::other("FLV stream failed"));
+
+            Ok(Response::builder()
+                .header("Content-Type", "video/x-flv")
+                .header("Cache-Control", "no-cache")
+                // Players are usually embedded in pages served from elsewhere
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::wrap_stream(body)))
+        })
+        .boxed()
+}
+
+/// Serves `/live/<app>.flv` to WebSocket clients, as binary messages
+fn ws_flv(shared: Shared) -> BoxedFilter<(impl Reply,)> {
+    warp::ws2()
+        .and(flv_app_name())
+        .and_then(move |ws: Ws2, app_name: String| -> Result<_, Rejection> {
+            let watcher = create_watcher(&app_name, &shared)?;
+            Ok(ws.on_upgrade(move |socket| WsFlv::new(socket, watcher)))
+        })
+        .boxed()
+}
+
+fn create_watcher(app_name: &str, shared: &Shared) -> Result<FlvWatcher, Rejection> {
+    FlvWatcher::create(app_name, shared).map_err(|why| {
+        warn!("Rejected FLV playback of app '{}': {:?}", app_name, why);
+        match why {
+            Error::WatcherLimitReached => warp::reject::custom(ApiError::WatcherLimitReached),
+            _ => warp::reject::custom(ApiError::StreamNotFound),
+        }
+    })
+}
+
+/// Extracts the application name of a path segment like `<app>.flv`
+fn flv_app_name() -> BoxedFilter<(String,)> {
+    warp::path::param()
+        .and(warp::path::end())
+        .and_then(|segment: String| -> Result<_, Rejection> {
+            if segment.len() > 4 && segment.ends_with(".flv") {
+                Ok(segment[..segment.len() - 4].to_string())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .boxed()
+}
+
+
+/// Sends the FLV stream of a watcher over a WebSocket and closes it
+/// once the stream ends.
+///
+/// The WebSocket of warp 0.1 can not send a close code or reason,
+/// so the reason is sent as a final text message before closing.
+struct WsFlv {
+    socket: WebSocket,
+    watcher: FlvWatcher,
+    /// Message the socket was not ready to accept yet
+    pending: Option<Message>,
+    closing: bool,
+}
+
+impl WsFlv {
+    /// Tags queued up to this size are sent as a single message
+    const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+    fn new(socket: WebSocket, watcher: FlvWatcher) -> Self {
+        Self { socket, watcher, pending: None, closing: false }
+    }
+
+    /// Whether the client closed the connection, anything else it sends is ignored
+    fn client_closed(&mut self) -> bool {
+        loop {
+            match self.socket.poll() {
+                // Close frames end the stream instead of being passed on
+                Ok(Async::Ready(Some(_))) => continue,
+                Ok(Async::NotReady) => return false,
+                _ => return true,
+            }
+        }
+    }
+
+    /// Gathers the tags that are queued into one message.
+    ///
+    /// Messages of warp 0.1 own their payload, so the tags shared by all watchers have to be
+    /// copied. Copying everything available at once keeps it to one allocation per wakeup
+    /// instead of one per tag.
+    fn next_message(&mut self) -> Poll<Option<Message>, ()> {
+        let mut data = Vec::new();
+
+        while data.len() < Self::MAX_MESSAGE_SIZE {
+            match self.watcher.poll()? {
+                Async::Ready(Some(bytes)) => data.extend_from_slice(&bytes),
+                // The end is reported again by the next poll, after the data is sent
+                Async::Ready(None) if data.is_empty() => return Ok(Async::Ready(None)),
+                Async::Ready(None) | Async::NotReady => break,
+            }
+        }
+
+        if data.is_empty() {
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(Some(Message::binary(data))))
+        }
+    }
+}
+
+impl Future for WsFlv {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.client_closed() {
+            return Ok(Async::Ready(()));
+        }
+
+        loop {
+            if let Some(message) = self.pending.take() {
+                match self.socket.start_send(message) {
+                    Ok(AsyncSink::Ready) => (),
+                    Ok(AsyncSink::NotReady(message)) => {
+                        self.pending = Some(message);
+                        break;
+                    },
+                    Err(why) => {
+                        debug!("Failed to send to WebSocket client: {:?}", why);
+                        return Ok(Async::Ready(()));
+                    },
+                }
+            }
+
+            if self.closing {
+                break;
+            }
+
+            match self.next_message()? {
+                Async::Ready(Some(message)) => self.pending = Some(message),
+                Async::Ready(None) => {
+                    let reason = self.watcher.end().map_or("Stream ended", End::reason);
+                    debug!("Closing WebSocket: {}", reason);
+                    self.pending = Some(Message::text(reason));
+                    self.closing = true;
+                },
+                Async::NotReady => break,
+            }
+        }
+
+        if self.closing && self.pending.is_none() {
+            // Sends the close frame after everything queued before it
+            return match Sink::close(&mut self.socket) {
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                _ => Ok(Async::Ready(())),
+            };
+        }
+
+        match self.socket.poll_complete() {
+            Ok(_) => Ok(Async::NotReady),
+            Err(_) => Ok(Async::Ready(())),
+        }
+    }
+}
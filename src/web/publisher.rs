@@ -0,0 +1,213 @@
+use std::fmt::Debug;
+use log::{debug, info};
+use futures::sync::mpsc;
+use tokio::prelude::*;
+use bytes::Buf;
+use javelin_codec::flv;
+use crate::{
+    channel,
+    error::{Error, Result},
+    media::{self, Media},
+    publish::{self, Denied},
+    rtmp::peer,
+    shared::Shared,
+    shutdown,
+};
+
+
+/// Publishes an FLV stream of an HTTP request body to a channel,
+/// the same way an RTMP publisher does.
+///
+/// Resolves once the body ended, the publisher was replaced or the server shuts down.
+pub struct HttpPublisher<S> {
+    id: u64,
+    app_name: String,
+    channel: channel::Handle,
+    body: S,
+    demuxer: flv::Demuxer,
+    receiver: mpsc::UnboundedReceiver<peer::Message>,
+    shutdown: shutdown::Signal,
+}
+
+impl<S> HttpPublisher<S>
+    where S: Stream,
+          S::Item: Buf,
+          S::Error: Debug,
+{
+    /// Takes the place of the publisher, subject to the same rules as RTMP publishers.
+    pub fn create(app_name: String, stream_key: String, body: S, shared: &Shared) -> std::result::Result<Self, Denied> {
+        let id = shared.next_client_id();
+        let (sender, receiver) = mpsc::unbounded();
+        let handle = publish::claim(&app_name, stream_key, id, sender, shared)?;
+
+        info!("HTTP client {} is publishing to app '{}'", id, app_name);
+
+        Ok(Self {
+            id,
+            app_name,
+            channel: handle,
+            body,
+            demuxer: flv::Demuxer::new(),
+            receiver,
+            shutdown: shared.shutdown.signal(),
+        })
+    }
+
+    fn handle_tag(&mut self, tag: flv::Tag) -> Result<()> {
+        if let Some(metadata) = flv::Metadata::try_from_tag(&tag)? {
+            debug!("Received stream metadata for app '{}'", self.app_name);
+            let metadata = media::metadata_from_flv(&metadata);
+            let fanout = self.channel.lock().set_metadata(self.id, metadata.clone());
+            if let Some(fanout) = fanout {
+                fanout.send_metadata(&metadata);
+            }
+            return Ok(());
+        }
+
+        if let Some(mut media) = Media::from_flv_tag(tag) {
+            let fanout = self.channel.lock().prepare_fanout(self.id, &mut media);
+            if let Some(fanout) = fanout {
+                fanout.send_media(&media);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publishes everything received so far, returns `Ready` once the body ended.
+    fn publish(&mut self) -> Poll<(), Error> {
+        loop {
+            while let Some(tag) = self.demuxer.next_tag()? {
+                self.handle_tag(tag)?;
+            }
+
+            let mut chunk = match self.body.poll() {
+                Ok(Async::Ready(Some(chunk))) => chunk,
+                Ok(Async::Ready(None)) => {
+                    if self.demuxer.has_remaining() {
+                        debug!("Request body for app '{}' ended with an incomplete tag", self.app_name);
+                    }
+                    return Ok(Async::Ready(()));
+                },
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(why) => return Err(Error::from(format!("Failed to read request body: {:?}", why))),
+            };
+
+            while chunk.has_remaining() {
+                let size = {
+                    let bytes = chunk.bytes();
+                    self.demuxer.push(bytes);
+                    bytes.len()
+                };
+                chunk.advance(size);
+            }
+        }
+    }
+}
+
+impl<S> Future for HttpPublisher<S>
+    where S: Stream,
+          S::Item: Buf,
+          S::Error: Debug,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.shutdown.poll().map_err(|_| Error::from("Shutdown signal failed"))?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+
+        while let Ok(Async::Ready(Some(message))) = self.receiver.poll() {
+            if let peer::Message::Disconnect = message {
+                info!("HTTP client {} was removed from app '{}'", self.id, self.app_name);
+                return Ok(Async::Ready(()));
+            }
+        }
+
+        self.publish()
+    }
+}
+
+impl<S> Drop for HttpPublisher<S> {
+    fn drop(&mut self) {
+        info!("HTTP client {} stopped publishing to app '{}'", self.id, self.app_name);
+        self.channel.lock().release_publisher(self.id);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use bytes::Bytes;
+    use futures::stream;
+    use tokio::runtime::current_thread::Runtime;
+    use javelin_codec::flv::{Tag, TagKind};
+    use crate::{
+        config::Config,
+        rtmp::fanout::Watcher,
+        shutdown::Shutdown,
+    };
+    use super::*;
+
+    fn shared() -> Shared {
+        let (shutdown, _) = Shutdown::new();
+        Shared::with_config(Config::from_args(&["javelin", "--permit-stream-key", "key"]), shutdown)
+    }
+
+    fn body(chunks: Vec<Vec<u8>>) -> impl Stream<Item = Cursor<Vec<u8>>, Error = ()> {
+        stream::iter_ok(chunks.into_iter().map(Cursor::new))
+    }
+
+    fn flv_stream() -> Vec<u8> {
+        let header = flv::Header { has_audio: false, has_video: true };
+        let mut writer = flv::Writer::new(Vec::new(), header).unwrap();
+        let keyframe = Tag::new(TagKind::Video, 0, Bytes::from_static(&[0x17, 0x01, 0x00, 0x00, 0x00]));
+        writer.write_tag(&keyframe).unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn publishes_chunked_body() {
+        let shared = shared();
+        let stream = flv_stream();
+        let chunks = stream.chunks(5).map(<[u8]>::to_vec).collect();
+        let publisher = HttpPublisher::create("live".to_string(), "key".to_string(), body(chunks), &shared).unwrap();
+        let channel = shared.channel_or_create("live");
+        let (sender, _receiver) = mpsc::unbounded();
+        let (media, media_receiver) = peer::media_queue();
+        channel.lock().add_watcher(Watcher::flv(1, sender, media)).unwrap();
+
+        assert!(Runtime::new().unwrap().block_on(publisher).is_ok());
+
+        match media_receiver.wait().next() {
+            Some(Ok(peer::Message::Raw(bytes))) => assert_eq!(bytes[11..], [0x17, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10]),
+            _ => panic!("Expected the keyframe"),
+        }
+
+        // Releasing the publisher ends the stream
+        assert!(!channel.lock().is_live());
+    }
+
+    #[test]
+    fn rejects_invalid_flv_header() {
+        let shared = shared();
+        let mut stream = flv_stream();
+        stream[2] = b'X';
+        let publisher = HttpPublisher::create("live".to_string(), "key".to_string(), body(vec![stream]), &shared).unwrap();
+
+        assert!(Runtime::new().unwrap().block_on(publisher).is_err());
+        assert!(!shared.channel_or_create("live").lock().is_live());
+    }
+
+    #[test]
+    fn requires_permitted_stream_key() {
+        let shared = shared();
+
+        match HttpPublisher::create("live".to_string(), "other".to_string(), body(vec![flv_stream()]), &shared) {
+            Err(Denied::StreamKeyNotPermitted) => (),
+            _ => panic!("Expected the stream key to be refused"),
+        }
+    }
+}
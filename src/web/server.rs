@@ -1,7 +1,4 @@
-use std::{
-    error::Error as StdError,
-    thread,
-};
+use std::thread;
 use warp::{
     Filter,
     Reply,
@@ -9,9 +6,13 @@ use warp::{
     http::StatusCode,
 };
 use serde_json::json;
-use super::api::{
-    api,
-    Error as ApiError,
+use super::{
+    api::{
+        api,
+        Error as ApiError,
+    },
+    live::live,
+    ingest::ingest,
 };
 use crate::Shared;
 
@@ -58,8 +59,14 @@ fn server(shared: Shared) {
     let streams_api = warp::path("api")
         .and(api(shared.clone()));
 
+    let live_streams = live(shared.clone());
+
+    let ingest_streams = ingest(shared.clone());
+
     let routes = hls_files
         .or(streams_api)
+        .or(live_streams)
+        .or(ingest_streams)
         .recover(error_handler);
 
     warp::serve(routes).run(addr);
@@ -68,8 +75,25 @@ fn server(shared: Shared) {
 fn error_handler(err: Rejection) -> Result<impl Reply, Rejection> {
     match err.find_cause() {
         | Some(e @ ApiError::NoSuchResource)
-        | Some(e @ ApiError::StreamNotFound) => {
-            json_error_response!(StatusCode::NOT_FOUND, e.description())
+        | Some(e @ ApiError::StreamNotFound)
+        | Some(e @ ApiError::PushTargetNotFound)
+        | Some(e @ ApiError::NotRecording) => {
+            json_error_response!(StatusCode::NOT_FOUND, e.to_string())
+        },
+        | Some(e @ ApiError::InvalidPushTarget)
+        | Some(e @ ApiError::AlreadyRecording)
+        | Some(e @ ApiError::UnsupportedRecording)
+        | Some(e @ ApiError::InvalidMedia) => {
+            json_error_response!(StatusCode::BAD_REQUEST, e.to_string())
+        },
+        Some(e @ ApiError::StreamKeyNotPermitted) => {
+            json_error_response!(StatusCode::FORBIDDEN, e.to_string())
+        },
+        Some(e @ ApiError::AlreadyPublished) => {
+            json_error_response!(StatusCode::CONFLICT, e.to_string())
+        },
+        Some(e @ ApiError::WatcherLimitReached) => {
+            json_error_response!(StatusCode::SERVICE_UNAVAILABLE, e.to_string())
         },
         None => Err(err)
     }
@@ -0,0 +1,139 @@
+use log::info;
+use futures::{sync::mpsc, try_ready};
+use tokio::prelude::*;
+use bytes::Bytes;
+use javelin_codec::flv;
+use crate::{
+    channel::{self, is_valid_app_name},
+    error::{Error, Result},
+    relay,
+    rtmp::{peer, fanout::Watcher},
+    shared::Shared,
+    shutdown,
+};
+
+
+/// Why the stream of a watcher ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum End {
+    /// The channel stopped being published, or was not published in time
+    Unpublished,
+    /// The client did not read the stream fast enough
+    Lagging,
+    Shutdown,
+}
+
+impl End {
+    pub fn reason(self) -> &'static str {
+        match self {
+            End::Unpublished => "Stream is not published",
+            End::Lagging => "Client fell behind the stream",
+            End::Shutdown => "Server is shutting down",
+        }
+    }
+}
+
+
+/// The stream of a channel as FLV, for a single HTTP or WebSocket client.
+///
+/// Starts with the FLV header, followed by the metadata and sequence headers
+/// the channel sends every new watcher. The watcher leaves the channel once
+/// the stream is dropped.
+pub struct FlvWatcher {
+    id: u64,
+    channel: channel::Handle,
+    header: Option<Bytes>,
+    receiver: mpsc::UnboundedReceiver<peer::Message>,
+    media: peer::MediaReceiver,
+    shutdown: shutdown::Signal,
+    end: Option<End>,
+}
+
+impl FlvWatcher {
+    pub fn create(app_name: &str, shared: &Shared) -> Result<Self> {
+        if !is_valid_app_name(app_name) {
+            return Err(Error::from(format!("Invalid application name '{}'", app_name)));
+        }
+
+        let channel = shared.channel_to_watch(app_name)
+            .ok_or_else(|| Error::from(format!("Application '{}' is not published", app_name)))?;
+        let id = shared.next_client_id();
+        let (sender, receiver) = mpsc::unbounded();
+        let (media_sender, media) = peer::media_queue();
+
+        let header = {
+            let mut channel = channel.lock();
+
+            // Players only set up the tracks announced in the header,
+            // so both are announced while the stream is still unknown
+            let has_video = channel.video_seq_header.is_some();
+            let has_audio = channel.audio_seq_header.is_some();
+            let unknown = !has_video && !has_audio;
+            let header = flv::Header { has_audio: has_audio || unknown, has_video: has_video || unknown };
+
+            channel.add_watcher(Watcher::flv(id, sender, media_sender))?;
+
+            header
+        };
+
+        info!("FLV client {} is watching app '{}'", id, app_name);
+
+        relay::pull::on_demand(&channel, shared);
+
+        Ok(Self {
+            id,
+            channel,
+            header: Some(header.to_bytes()),
+            receiver,
+            media,
+            shutdown: shared.shutdown.signal(),
+            end: None,
+        })
+    }
+
+    /// Set once the stream is over
+    pub fn end(&self) -> Option<End> {
+        self.end
+    }
+}
+
+impl Stream for FlvWatcher {
+    type Item = Bytes;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(header) = self.header.take() {
+            return Ok(Async::Ready(Some(header)));
+        }
+
+        if self.end.is_some() {
+            return Ok(Async::Ready(None));
+        }
+
+        if self.shutdown.poll()?.is_ready() {
+            self.end = Some(End::Shutdown);
+            return Ok(Async::Ready(None));
+        }
+
+        // Only used to disconnect watchers that fell behind
+        if let Async::Ready(Some(peer::Message::Disconnect)) = self.receiver.poll()? {
+            self.end = Some(End::Lagging);
+            return Ok(Async::Ready(None));
+        }
+
+        match try_ready!(self.media.poll()) {
+            Some(peer::Message::Raw(bytes)) => Ok(Async::Ready(Some(bytes))),
+            Some(peer::Message::Disconnect) | None => {
+                self.end = Some(End::Unpublished);
+                Ok(Async::Ready(None))
+            },
+        }
+    }
+}
+
+impl Drop for FlvWatcher {
+    fn drop(&mut self) {
+        info!("FLV client {} stopped watching", self.id);
+        self.channel.lock().remove_watcher(self.id);
+    }
+}